@@ -7,11 +7,21 @@ fn main() -> Result<()> {
     // they should as `in`.
     config.field_attribute("in", "#[serde(rename = \"in\")]");
 
+    // Bytes are encoded as base64 in JSON, rather than as an array of numbers.
+    for field in ["v0.gateway.Attachment.data", "v1.gateway.Attachment.data"] {
+        config.field_attribute(field, "#[serde(with = \"crate::proto::base64_bytes\")]");
+        config.field_attribute(field, "#[schemars(with = \"String\")]");
+    }
+
     // Add the serde serialization attributes so messages can easily be transcoded to JSON.
     config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
     config.type_attribute(".", "#[serde(rename_all = \"snake_case\")]");
     config.type_attribute(".", "#[derive(schemars::JsonSchema)]");
 
+    // Fields missing from JSON take their Protobuf default, as they do in the
+    // binary encoding, so clients don't have to send fields added later.
+    config.message_attribute(".", "#[serde(default)]");
+
     // Encode enums to json with a "type" field instead of a nested object.
     config.type_attribute(
         "v0.gateway.GatewayServerEvent.event",
@@ -21,14 +31,29 @@ fn main() -> Result<()> {
         "v0.gateway.GatewayClientEvent.event",
         "#[serde(tag = \"type\")]",
     );
+    config.type_attribute(
+        "v1.gateway.GatewayServerEvent.event",
+        "#[serde(tag = \"type\")]",
+    );
+    config.type_attribute(
+        "v1.gateway.GatewayClientEvent.event",
+        "#[serde(tag = \"type\")]",
+    );
 
     let out_dir: PathBuf = std::env::var("OUT_DIR").unwrap().into();
 
     // Generate the descriptor path so we can use it for API docs generation.
-    config.file_descriptor_set_path(&out_dir.join("proto_file_descriptor_set.pb"));
+    config.file_descriptor_set_path(out_dir.join("proto_file_descriptor_set.pb"));
 
     // Compile the specified protobuf files into Rust code.
-    config.compile_protos(&["src/proto/v0/gateway.proto"], &["src/proto"])?;
+    config.compile_protos(
+        &[
+            "src/proto/v0/gateway.proto",
+            "src/proto/v0/api.proto",
+            "src/proto/v1/gateway.proto",
+        ],
+        &["src/proto"],
+    )?;
 
     Ok(())
 }
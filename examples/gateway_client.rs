@@ -0,0 +1,33 @@
+//! Connects to a gateway and prints the events it sends.
+//!
+//! ```sh
+//! cargo run --example gateway_client --features client -- ws://localhost:8080/gateway <token>
+//! ```
+
+use bonfire::client::{ClientOptions, GatewayClient};
+use futures::StreamExt;
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let (Some(url), Some(token)) = (args.next(), args.next()) else {
+        eprintln!("usage: gateway_client <url> <token>");
+        std::process::exit(2);
+    };
+
+    let mut client = GatewayClient::connect(ClientOptions::new(url, token))
+        .await
+        .expect("failed to connect to the gateway");
+
+    println!("connected: {:?}", client.handshake());
+
+    while let Some(event) = client.events().next().await {
+        match event {
+            Ok(event) => println!("{event:?}"),
+            Err(err) => {
+                eprintln!("gateway error: {err:?}");
+                break;
+            }
+        }
+    }
+}
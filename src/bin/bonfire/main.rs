@@ -1,10 +1,10 @@
-use std::sync::{Arc, RwLock};
+use std::{io::IsTerminal, sync::Arc};
 
-use bonfire::{
-    http,
-    server::{self, auth},
-};
-use clap::{Parser, Subcommand, builder::Styles, crate_description, crate_version};
+#[cfg(feature = "server")]
+use bonfire::server::channel::Channel;
+use bonfire::{http, server, user::UserId};
+use clap::{Parser, Subcommand, ValueEnum, builder::Styles, crate_description, crate_version};
+use tracing_subscriber::EnvFilter;
 
 /// Clap v3 style (approximate)
 /// See https://stackoverflow.com/a/75343828
@@ -39,39 +39,200 @@ fn style() -> clap::builder::Styles {
 struct CliArguments {
     #[command(subcommand)]
     pub subcommand: ToplevelCommmands,
+
+    /// Format that log events are written in.
+    ///
+    /// Defaults to pretty when writing to a terminal, and JSON otherwise.
+    #[arg(long, global = true, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Filter for the log events that are written, in the `RUST_LOG` syntax.
+    ///
+    /// Overrides the `RUST_LOG` environment variable, defaults to `info`.
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+}
+
+/// Formats that log events can be written in.
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    /// Multi-line human-readable events.
+    Pretty,
+    /// Single-line human-readable events.
+    Compact,
+    /// Newline-delimited JSON objects, for log aggregation systems.
+    Json,
 }
 
 /// The top-level commands available to the CLI.
 #[derive(Subcommand)]
 enum ToplevelCommmands {
     Server,
+    /// Manage channels without a running server.
+    #[cfg(feature = "server")]
+    Channel {
+        #[command(subcommand)]
+        action: ChannelCommands,
+    },
+    /// Manage authentication without a running server.
+    #[cfg(feature = "server")]
+    Auth {
+        #[command(subcommand)]
+        action: AuthCommands,
+    },
+}
+
+/// Actions for managing channels offline.
+#[cfg(feature = "server")]
+#[derive(Subcommand)]
+enum ChannelCommands {
+    /// Create a new text channel and print it's ID.
+    Create {
+        /// User-facing label for the channel.
+        #[arg(long)]
+        label: String,
+    },
+    /// List the persisted text channels.
+    List,
+}
+
+/// Actions for managing authentication offline.
+#[cfg(feature = "server")]
+#[derive(Subcommand)]
+enum AuthCommands {
+    /// Mint a bot token that authenticates as a user.
+    ///
+    /// The token is written to the server's data directory. The database
+    /// can only be opened by one process at a time, so stop the server
+    /// before minting, the token is accepted once it's started again.
+    MintToken {
+        /// ID of the user the token authenticates as.
+        #[arg(long)]
+        user: UserId,
+        /// Label identifying what the token is used for.
+        #[arg(long)]
+        label: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
 
-    tracing_subscriber::fmt::init();
-
     let cli_args = CliArguments::parse();
 
+    init_logging(cli_args.log_format, cli_args.log_level.as_deref());
+
     match &cli_args.subcommand {
         ToplevelCommmands::Server => {
-            let config = server::Config {
-                data_dir: "data/".into(),
-                auth: auth::AuthConfig {
-                    oauth2_clients: vec![],
-                },
-            };
+            let config = server_config();
 
-            let srv = Arc::new(RwLock::new(server::Server::new(config).unwrap()));
+            let bind_addr = config.bind_addr;
 
-            let app = http::make_app_router(srv);
+            let srv = Arc::new(server::Server::new(config).unwrap());
 
-            // run our app with hyper, listening globally on port 3000
-            let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+            let app = http::make_app_router(Arc::clone(&srv));
 
-            axum::serve(listener, app).await.unwrap();
+            // run our app with hyper, listening on the configured address
+            let listener = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
+
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+
+            // Commit the channels' pending messages before exiting.
+            srv.shutdown().await;
         }
+        #[cfg(feature = "server")]
+        ToplevelCommmands::Channel { action } => {
+            let srv = server::Server::new(server_config()).unwrap();
+
+            match action {
+                ChannelCommands::Create { label } => {
+                    match srv.create_text_channel(label.clone(), Default::default()) {
+                        Ok(channel) => println!("{}", channel.channel_id()),
+                        Err(_) => {
+                            eprintln!("failed to create channel");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                ChannelCommands::List => {
+                    for channel in srv.text_channels() {
+                        println!("{}\t{}", channel.channel_id(), channel.get_label());
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "server")]
+        ToplevelCommmands::Auth { action } => {
+            let srv = server::Server::new(server_config()).unwrap();
+
+            match action {
+                AuthCommands::MintToken { user, label } => {
+                    match srv.auth().read().mint_bot_token(*user, label.clone()) {
+                        Ok(minted) => {
+                            eprintln!(
+                                "warning: this token is only shown once, store it somewhere safe"
+                            );
+                            println!("{}", minted.token);
+                        }
+                        Err(server::bot_token::BotTokenError::ReservedUser) => {
+                            eprintln!("user {user} is reserved for messages posted by the server");
+                            std::process::exit(1);
+                        }
+                        Err(err) => {
+                            eprintln!("failed to mint bot token: {err:?}");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Installs the subscriber that writes log events to stdout.
+fn init_logging(format: Option<LogFormat>, level: Option<&str>) {
+    // People watching a terminal get readable logs, log pipelines get JSON.
+    let format = format.unwrap_or(if std::io::stdout().is_terminal() {
+        LogFormat::Pretty
+    } else {
+        LogFormat::Json
+    });
+
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level).unwrap_or_else(|err| {
+            eprintln!("invalid log level: {err}");
+            std::process::exit(2);
+        }),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Json => subscriber.json().init(),
     }
 }
+
+/// Builds the server config shared by the commands that open the server.
+fn server_config() -> server::Config {
+    server::Config::builder()
+        .data_dir("data/")
+        .build()
+        .expect("invalid server config")
+}
+
+/// Resolves once the process is asked to stop with Ctrl+C.
+async fn shutdown_signal() {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        tracing::error!(%err, "failed to listen for the shutdown signal");
+        // Without the signal the server can only be killed, so keep serving.
+        std::future::pending::<()>().await;
+    }
+
+    tracing::info!("shutting down");
+}
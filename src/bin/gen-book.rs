@@ -1,11 +1,10 @@
 use std::{
     collections::HashSet,
-    io::Bytes,
+    fs,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
-use bonfire::proto::v0;
+use bonfire::{http::openapi::openapi_document, proto::v0};
 use color_eyre::eyre::eyre;
 use log::info;
 use prost::Message;
@@ -21,16 +20,35 @@ fn main() -> color_eyre::Result<()> {
     let mut md = MDBook::load(root_dir).map_err(|err| eyre!("failed to load book: {err}"))?;
 
     add_proto_sections_v0(&mut md)?;
-    add_json_sections_v0(&mut md)?;
 
     // Attempt to build the book.
     md.build()
         .map_err(|err| eyre!("failed to build book: {err}"))?;
 
+    // Building the book clears the build directory,
+    // so the artifacts have to be written afterwards.
+    write_proto_artifacts_v0(&build_dir(&md))?;
+    write_openapi_document(&md)?;
+
     Ok(())
 }
 
-fn add_proto_sections_v0(book: &mut MDBook) -> color_eyre::Result<()> {
+/// Writes the OpenAPI document for the REST API next to the built book.
+fn write_openapi_document(book: &MDBook) -> color_eyre::Result<()> {
+    let path = build_dir(book).join("openapi.json");
+
+    let document = serde_json::to_string_pretty(&openapi_document())
+        .map_err(|err| eyre!("failed to serialize OpenAPI document: {err}"))?;
+
+    write_file(&path, document.as_bytes())?;
+
+    info!("wrote OpenAPI document to {}", path.display());
+
+    Ok(())
+}
+
+// TODO: add a section to the book for each protobuf package.
+fn add_proto_sections_v0(_book: &mut MDBook) -> color_eyre::Result<()> {
     // Descriptor is generated by the build.rs script.
     let descriptor_bytes =
         include_bytes!(concat!(env!("OUT_DIR"), "/proto_file_descriptor_set.pb"));
@@ -48,17 +66,97 @@ fn add_proto_sections_v0(book: &mut MDBook) -> color_eyre::Result<()> {
         .map(|f| f.package().to_string())
         .collect();
 
+    info!("found proto packages {packages:?}");
+
     Ok(())
 }
 
-fn add_json_sections_v0(book: &mut MDBook) -> color_eyre::Result<()> {
-    // Schema of the JSON message used to send data
-    // from the gateway server to the client.
-    let gateway_server_event = schema_for!(v0::gateway_server_event::Event);
+/// Writes the protobuf descriptor and the gateway JSON schemas to the `proto/` build directory.
+///
+/// Each schema is written to `<version>.<name>.schema.json`, along with an
+/// `index.json` listing every schema so tooling doesn't have to guess the names.
+fn write_proto_artifacts_v0(build_dir: &Path) -> color_eyre::Result<()> {
+    let proto_dir = build_dir.join("proto");
+    fs::create_dir_all(&proto_dir)
+        .map_err(|err| eyre!("failed to create {}: {err}", proto_dir.display()))?;
+
+    write_file(
+        &proto_dir.join("descriptor_set.pb"),
+        include_bytes!(concat!(env!("OUT_DIR"), "/proto_file_descriptor_set.pb")),
+    )?;
+
+    let schemas = [
+        // Schema of the JSON message used to send data
+        // from the gateway server to the client.
+        (
+            "gateway_server_event",
+            schema_for!(v0::gateway_server_event::Event),
+        ),
+        // Schema of the JSON message used to send data
+        // from the client to the gateway server.
+        (
+            "gateway_client_event",
+            schema_for!(v0::gateway_client_event::Event),
+        ),
+    ];
+
+    let mut index = Vec::with_capacity(schemas.len());
+    for (name, schema) in schemas {
+        let file = format!("v0.{name}.schema.json");
+
+        let json = serde_json::to_string_pretty(&schema)
+            .map_err(|err| eyre!("failed to serialize {name} schema: {err}"))?;
+        write_file(&proto_dir.join(&file), json.as_bytes())?;
 
-    // Schema of the JSON message used to send data
-    // from the client to the gateway server.
-    let gateway_client_event = schema_for!(v0::gateway_client_event::Event);
+        index.push(serde_json::json!({
+            "version": "v0",
+            "name": name,
+            "file": file,
+        }));
+    }
+
+    let index = serde_json::to_string_pretty(&serde_json::json!({ "schemas": index }))
+        .map_err(|err| eyre!("failed to serialize schema index: {err}"))?;
+    write_file(&proto_dir.join("index.json"), index.as_bytes())?;
+
+    info!("wrote gateway schemas to {}", proto_dir.display());
 
     Ok(())
 }
+
+/// Returns the directory the book is built into.
+fn build_dir(book: &MDBook) -> PathBuf {
+    book.root.join(&book.config.build.build_dir)
+}
+
+/// Writes a build artifact, reporting which file failed.
+fn write_file(path: &Path, contents: &[u8]) -> color_eyre::Result<()> {
+    fs::write(path, contents).map_err(|err| eyre!("failed to write {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proto_artifacts_are_written_as_json() {
+        let dir = tempfile::tempdir().unwrap();
+        write_proto_artifacts_v0(dir.path()).unwrap();
+
+        let proto_dir = dir.path().join("proto");
+        assert!(proto_dir.join("descriptor_set.pb").is_file());
+
+        let read_json = |file: &str| -> serde_json::Value {
+            serde_json::from_slice(&fs::read(proto_dir.join(file)).unwrap()).unwrap()
+        };
+
+        // Every schema in the index was written, and parses as JSON.
+        let index = read_json("index.json");
+        let schemas = index["schemas"].as_array().unwrap();
+        assert_eq!(schemas.len(), 2);
+        for schema in schemas {
+            let file = schema["file"].as_str().unwrap();
+            assert!(read_json(file).is_object(), "{file}");
+        }
+    }
+}
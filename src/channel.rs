@@ -7,6 +7,7 @@ use std::{
     str::FromStr,
 };
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use snowflaked::Snowflake;
 
 /// Concrete type for channel ID's.
@@ -54,3 +55,99 @@ impl FromStr for ChannelId {
         Ok(ChannelId(u64::from_str(s)?))
     }
 }
+
+/// Serializes IDs as strings for API payloads.
+impl Serialize for ChannelId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::id::serialize_id(self.0, serializer)
+    }
+}
+
+/// Deserializes IDs from either strings or integers.
+impl<'de> Deserialize<'de> for ChannelId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::id::deserialize_id(deserializer).map(ChannelId)
+    }
+}
+
+/// Describes IDs as strings in generated schemas.
+impl schemars::JsonSchema for ChannelId {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "ChannelId".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        crate::id::id_schema()
+    }
+}
+
+/// Concrete type for channel category ID's.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct CategoryId(pub u64);
+
+/// Enables for using the ID's for keys in HashMaps.
+impl hash::Hash for CategoryId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0);
+    }
+}
+
+/// Enables extracting the snowflake ID components from IDs.
+impl Snowflake for CategoryId {
+    fn from_parts(timestamp: u64, instance: u64, sequence: u64) -> Self {
+        Self(u64::from_parts(timestamp, instance, sequence))
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.0.timestamp()
+    }
+
+    fn instance(&self) -> u64 {
+        self.0.instance()
+    }
+
+    fn sequence(&self) -> u64 {
+        self.0.sequence()
+    }
+}
+
+/// Display IDs in logs and formatting strings.
+impl Display for CategoryId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+/// Allows for calling parse() on strings to convert them to a category ID.
+impl FromStr for CategoryId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(CategoryId(u64::from_str(s)?))
+    }
+}
+
+/// Serializes IDs as strings for API payloads.
+impl Serialize for CategoryId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::id::serialize_id(self.0, serializer)
+    }
+}
+
+/// Deserializes IDs from either strings or integers.
+impl<'de> Deserialize<'de> for CategoryId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::id::deserialize_id(deserializer).map(CategoryId)
+    }
+}
+
+/// Describes IDs as strings in generated schemas.
+impl schemars::JsonSchema for CategoryId {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "CategoryId".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        crate::id::id_schema()
+    }
+}
@@ -0,0 +1,300 @@
+//! A typed client for the gateway.
+//!
+//! Handles connecting to the gateway WebSocket, the handshake and
+//! identify exchange, encoding selection, and heartbeats, so bots
+//! only have to deal with the [`v0`] event types.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream,
+    tungstenite::{
+        self, Message,
+        client::IntoClientRequest,
+        http::{HeaderValue, header},
+    },
+};
+
+use crate::proto::v0;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Number of decoded events buffered before the connection stops reading.
+const EVENT_BUFFER: usize = 64;
+
+/// The encoding used for messages sent over the gateway connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    /// Binary Protobuf messages.
+    #[default]
+    Protobuf,
+
+    /// JSON text messages.
+    Json,
+}
+
+impl Encoding {
+    /// Returns the WebSocket subprotocol used to request the encoding.
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "protobuf",
+            Encoding::Json => "json",
+        }
+    }
+}
+
+/// Errors that can occur connecting to or talking with the gateway.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The gateway URL couldn't be turned into a WebSocket request.
+    InvalidUrl(tungstenite::Error),
+    /// The WebSocket connection failed.
+    WebSocket(tungstenite::Error),
+    /// The gateway closed the connection before identifying.
+    Closed,
+    /// A message from the gateway couldn't be decoded.
+    Decode(String),
+    /// The connection task has exited, so no more events can be sent.
+    Disconnected,
+}
+
+/// Options used to connect to the gateway.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// WebSocket URL of the gateway, e.g. `ws://localhost:8080/gateway`.
+    pub url: String,
+    /// Authentication token for the bot or user.
+    pub token: String,
+    /// Encoding requested for the connection.
+    pub encoding: Encoding,
+    /// User-agent like string identifying the client.
+    pub client_agent: String,
+}
+
+impl ClientOptions {
+    /// Creates options for connecting to the gateway at the URL with the token.
+    pub fn new(url: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            token: token.into(),
+            encoding: Encoding::default(),
+            client_agent: format!("bonfire-client/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    /// Sets the encoding requested for the connection.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the string identifying the client to the gateway.
+    pub fn client_agent(mut self, client_agent: impl Into<String>) -> Self {
+        self.client_agent = client_agent.into();
+        self
+    }
+}
+
+/// A connection to the gateway.
+///
+/// Events from the gateway are read from [`GatewayClient::events`],
+/// and heartbeats are sent in the background at the interval the
+/// gateway advertised in its handshake.
+pub struct GatewayClient {
+    handshake: v0::GatewayHandshake,
+    events: GatewayEvents,
+    sender: mpsc::UnboundedSender<v0::GatewayClientEvent>,
+}
+
+impl GatewayClient {
+    /// Connects to the gateway and identifies with the token.
+    pub async fn connect(options: ClientOptions) -> Result<Self, ClientError> {
+        let mut request = options
+            .url
+            .as_str()
+            .into_client_request()
+            .map_err(ClientError::InvalidUrl)?;
+
+        request.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(options.encoding.subprotocol()),
+        );
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(ClientError::WebSocket)?;
+
+        // The gateway sends its handshake before anything else.
+        let handshake: v0::GatewayHandshake = loop {
+            let message = socket
+                .next()
+                .await
+                .ok_or(ClientError::Closed)?
+                .map_err(ClientError::WebSocket)?;
+
+            if let Some(handshake) = decode_message(message)? {
+                break handshake;
+            }
+        };
+
+        let identify = v0::GatewayIdentify {
+            token: options.token,
+            client_type: v0::gateway_identify::ClientType::Native.into(),
+            client_agent: options.client_agent,
+            resume_session_id: 0,
+            resume_seq: 0,
+            intents: vec![],
+        };
+
+        socket
+            .send(encode_message(&identify, options.encoding))
+            .await
+            .map_err(ClientError::WebSocket)?;
+
+        let heartbeat_interval = handshake
+            .capabilities
+            .as_ref()
+            .map(|capabilities| capabilities.heartbeat_interval_ms)
+            .filter(|interval_ms| *interval_ms > 0)
+            .map(Duration::from_millis);
+
+        let (events_sender, events) = mpsc::channel(EVENT_BUFFER);
+        let (sender, outgoing) = mpsc::unbounded_channel();
+
+        tokio::spawn(run_connection(
+            socket,
+            options.encoding,
+            heartbeat_interval,
+            events_sender,
+            outgoing,
+        ));
+
+        Ok(Self {
+            handshake,
+            events: GatewayEvents(events),
+            sender,
+        })
+    }
+
+    /// The handshake the gateway sent when connecting.
+    pub fn handshake(&self) -> &v0::GatewayHandshake {
+        &self.handshake
+    }
+
+    /// Stream of the events sent by the gateway.
+    ///
+    /// Ends when the connection is closed.
+    pub fn events(&mut self) -> &mut GatewayEvents {
+        &mut self.events
+    }
+
+    /// Sends an event to the gateway.
+    pub fn send(&self, event: v0::GatewayClientEvent) -> Result<(), ClientError> {
+        self.sender
+            .send(event)
+            .map_err(|_| ClientError::Disconnected)
+    }
+}
+
+/// Stream of decoded events sent by the gateway.
+pub struct GatewayEvents(mpsc::Receiver<Result<v0::GatewayServerEvent, ClientError>>);
+
+impl Stream for GatewayEvents {
+    type Item = Result<v0::GatewayServerEvent, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+/// Drives the connection, forwarding events in both
+/// directions and sending heartbeats until it closes.
+async fn run_connection(
+    mut socket: Socket,
+    encoding: Encoding,
+    heartbeat_interval: Option<Duration>,
+    events: mpsc::Sender<Result<v0::GatewayServerEvent, ClientError>>,
+    mut outgoing: mpsc::UnboundedReceiver<v0::GatewayClientEvent>,
+) {
+    // Gateways that don't advertise an interval don't expect
+    // heartbeats, so the timer is never polled for them.
+    let mut heartbeat =
+        tokio::time::interval(heartbeat_interval.unwrap_or(Duration::from_secs(60)));
+    heartbeat.tick().await;
+
+    let mut heartbeat_seq = 0;
+
+    loop {
+        let message = tokio::select! {
+            _ = heartbeat.tick(), if heartbeat_interval.is_some() => {
+                heartbeat_seq += 1;
+
+                v0::GatewayClientEvent {
+                    event: Some(v0::gateway_client_event::Event::Heartbeat(v0::Heartbeat {
+                        seq: heartbeat_seq,
+                    })),
+                }
+            }
+            Some(event) = outgoing.recv() => event,
+            received = socket.next() => {
+                let event = match received {
+                    Some(Ok(message)) => match decode_message(message) {
+                        Ok(Some(event)) => Ok(event),
+                        Ok(None) => continue,
+                        Err(err) => Err(err),
+                    },
+                    Some(Err(err)) => Err(ClientError::WebSocket(err)),
+                    None => return,
+                };
+
+                let failed = event.is_err();
+
+                // Stop once the client dropped the event stream.
+                if events.send(event).await.is_err() || failed {
+                    return;
+                }
+
+                continue;
+            }
+        };
+
+        if let Err(err) = socket.send(encode_message(&message, encoding)).await {
+            let _ = events.send(Err(ClientError::WebSocket(err))).await;
+            return;
+        }
+    }
+}
+
+fn encode_message<M: prost::Message + Serialize>(message: &M, encoding: Encoding) -> Message {
+    match encoding {
+        Encoding::Protobuf => Message::Binary(message.encode_to_vec().into()),
+        Encoding::Json => Message::Text(
+            serde_json::to_string(message)
+                .expect("gateway messages always serialize")
+                .into(),
+        ),
+    }
+}
+
+/// Decodes a gateway message, returning `None` for non-data messages.
+fn decode_message<M: prost::Message + Default + DeserializeOwned>(
+    message: Message,
+) -> Result<Option<M>, ClientError> {
+    match message {
+        Message::Text(text) => serde_json::from_str(text.as_str())
+            .map(Some)
+            .map_err(|err| ClientError::Decode(err.to_string())),
+        Message::Binary(bytes) => M::decode(bytes)
+            .map(Some)
+            .map_err(|err| ClientError::Decode(err.to_string())),
+        Message::Close(_) => Err(ClientError::Closed),
+        _ => Ok(None),
+    }
+}
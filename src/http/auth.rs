@@ -0,0 +1,245 @@
+use axum::{
+    Json,
+    extract::{FromRequestParts, OptionalFromRequestParts, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    response::IntoResponse,
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    http::SharedState,
+    server::{
+        auth::{CookieSameSite, TokenCookieConfig},
+        session_token::IssuedSessionToken,
+    },
+    user::UserId,
+};
+
+/// Extracts the authenticated user from a request.
+///
+/// The token is read from a `Bearer` authorization header, falling
+/// back to the `token` cookie set by the web login flow. Requests
+/// without a valid token are rejected as unauthorized.
+pub struct AuthUser(pub UserId);
+
+impl FromRequestParts<SharedState> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = request_token(&parts.headers) else {
+            return Err(StatusCode::UNAUTHORIZED);
+        };
+
+        let user_id = state.auth().read().validate_token(&token);
+
+        user_id.map(AuthUser).ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Extracts the user from requests that may be made anonymously.
+///
+/// Requests without a token are let through without a user, but a
+/// token that's present and invalid is still rejected as unauthorized.
+impl OptionalFromRequestParts<SharedState> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let Some(token) = request_token(&parts.headers) else {
+            return Ok(None);
+        };
+
+        let user_id = state.auth().read().validate_token(&token);
+
+        user_id
+            .map(|user_id| Some(AuthUser(user_id)))
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Reads the token from the authorization header or the `token` cookie.
+fn request_token(headers: &HeaderMap) -> Option<String> {
+    bearer_token(headers).or_else(|| {
+        CookieJar::from_headers(headers)
+            .get("token")
+            .map(|cookie| cookie.value().to_string())
+    })
+}
+
+/// Reads the token from a `Bearer` authorization header.
+///
+/// Unlike cookies, browsers never attach the header on their own,
+/// so it's only present when the client set it deliberately.
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_string())
+}
+
+/// Builds the `token` cookie set by the web login flow.
+///
+/// The cookie expires along with the session token it holds.
+pub fn token_cookie(config: &TokenCookieConfig, issued: &IssuedSessionToken) -> Cookie<'static> {
+    let remaining_ms = issued.expires_at_ms - Utc::now().timestamp_millis();
+
+    let mut cookie = Cookie::build(("token", issued.token.clone()))
+        .path("/")
+        .http_only(true)
+        .secure(config.secure)
+        .same_site(match config.same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        })
+        .max_age(cookie::time::Duration::milliseconds(remaining_ms.max(0)))
+        .build();
+
+    if let Some(domain) = &config.domain {
+        cookie.set_domain(domain.clone());
+    }
+
+    cookie
+}
+
+/// A session token issued by refreshing an existing token.
+#[derive(Serialize, JsonSchema)]
+pub struct RefreshedToken {
+    /// The new token to authenticate with.
+    ///
+    /// The token presented to the refresh is no longer valid.
+    token: String,
+    /// When the new token expires in milliseconds since the Unix epoch.
+    expires_at_ms: i64,
+}
+
+/// Replaces the user's session token with one that expires later.
+///
+/// The `token` cookie is updated when the request authenticated with it,
+/// so web clients stay logged in without handling the token themselves.
+pub async fn handle_refresh(
+    headers: HeaderMap,
+    jar: CookieJar,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Some(token) = request_token(&headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let refreshed = match state.auth().read().refresh_token(&token) {
+        Ok(Some(refreshed)) => refreshed,
+        // Unknown, expired, and bot tokens can't be refreshed.
+        Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to refresh token");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let jar = if bearer_token(&headers).is_none() {
+        jar.add(token_cookie(&state.config().auth.cookie, &refreshed))
+    } else {
+        jar
+    };
+
+    let body = RefreshedToken {
+        token: refreshed.token,
+        expires_at_ms: refreshed.expires_at_ms,
+    };
+
+    (jar, Json(body)).into_response()
+}
+
+/// Logs the user out by clearing the `token` cookie.
+///
+/// Bot and session tokens presented to the endpoint are
+/// revoked, so they can't be used to authenticate again.
+pub async fn handle_logout(
+    headers: HeaderMap,
+    jar: CookieJar,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let revoked = request_token(&headers).map(|token| state.auth().read().revoke_token(&token));
+
+    if let Some(Err(err)) = revoked {
+        tracing::error!(?err, "failed to revoke token");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    // The path and domain have to match the cookie set by the login callback for it to be removed.
+    let mut cookie = Cookie::build(("token", "")).path("/").build();
+    if let Some(domain) = &state.config().auth.cookie.domain {
+        cookie.set_domain(domain.clone());
+    }
+    let jar = jar.remove(cookie);
+
+    (jar, StatusCode::NO_CONTENT).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issued(expires_in_ms: i64) -> IssuedSessionToken {
+        IssuedSessionToken {
+            token: "token-value".to_string(),
+            user: UserId(1),
+            expires_at_ms: Utc::now().timestamp_millis() + expires_in_ms,
+        }
+    }
+
+    #[test]
+    fn token_cookies_follow_the_configured_attributes() {
+        let lax = TokenCookieConfig {
+            secure: false,
+            same_site: CookieSameSite::Lax,
+            domain: None,
+            ..TokenCookieConfig::default()
+        };
+        let header = token_cookie(&lax, &issued(60_000)).to_string();
+        assert!(header.starts_with("token=token-value;"));
+        assert!(header.contains("HttpOnly"));
+        assert!(header.contains("SameSite=Lax"));
+        assert!(!header.contains("Secure"));
+        assert!(!header.contains("Domain"));
+
+        let cross_site = TokenCookieConfig {
+            secure: true,
+            same_site: CookieSameSite::None,
+            domain: Some("example.com".to_string()),
+            ..TokenCookieConfig::default()
+        };
+        let header = token_cookie(&cross_site, &issued(60_000)).to_string();
+        assert!(header.contains("SameSite=None"));
+        assert!(header.contains("Secure"));
+        assert!(header.contains("Domain=example.com"));
+    }
+
+    #[test]
+    fn token_cookies_expire_with_their_token() {
+        let config = TokenCookieConfig::default();
+
+        // Allow for the time passing between issuing and building the cookie.
+        let max_age = token_cookie(&config, &issued(2 * 60 * 60 * 1000))
+            .max_age()
+            .unwrap();
+        assert!(max_age.whole_seconds() > 2 * 60 * 60 - 5);
+        assert!(max_age.whole_seconds() <= 2 * 60 * 60);
+
+        // Already expired tokens don't get a negative age.
+        let max_age = token_cookie(&config, &issued(-1000)).max_age().unwrap();
+        assert_eq!(max_age.whole_seconds(), 0);
+    }
+}
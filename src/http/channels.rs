@@ -0,0 +1,951 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    channel::{CategoryId, ChannelId},
+    http::{
+        SharedState,
+        auth::AuthUser,
+        encoding::{Encoded, Encoding, ProtoEncode},
+    },
+    message::MessageId,
+    proto::v0::api,
+    server::{
+        CreateChannelError,
+        category::{Category, CategoryError},
+        channel::{
+            Channel, ChannelType,
+            text::{
+                Durability, MAX_TOPIC_CHARS, TextChannel, TextChannelError, TextChannelSettings,
+                retention::RetentionPolicy,
+            },
+            voice::{VoiceChannel, VoiceChannelError},
+        },
+        permission::Permissions,
+    },
+    user::UserId,
+};
+
+/// Request body for creating a channel.
+#[derive(Deserialize, JsonSchema)]
+pub struct CreateChannelRequest {
+    /// User-facing label for the channel.
+    label: String,
+    /// Longer description of what the channel is about.
+    #[serde(default)]
+    topic: Option<String>,
+    /// The type of channel to create, defaults to a text channel.
+    ///
+    /// Parsed by the handler, so unknown types are rejected as bad requests.
+    #[serde(rename = "type", default)]
+    #[schemars(with = "Option<ChannelType>")]
+    channel_type: Option<String>,
+    /// Limits how long messages are retained in the channel.
+    #[serde(default)]
+    retention: RetentionPolicy,
+    /// Limits users to posting once every this many seconds.
+    #[serde(default)]
+    slow_mode_secs: Option<u64>,
+    /// Permissions users must hold to read the channel's messages.
+    #[serde(default)]
+    read_permissions: Permissions,
+    /// How durable new messages are when their creation is acknowledged.
+    #[serde(default)]
+    durability: Durability,
+}
+
+/// Request body for updating a channel's settings.
+///
+/// Omitted fields are left unchanged.
+#[derive(Deserialize, JsonSchema)]
+pub struct UpdateChannelRequest {
+    /// Set to an empty string to clear the topic.
+    topic: Option<String>,
+    retention: Option<RetentionPolicy>,
+    /// Set to zero to disable slow mode.
+    slow_mode_secs: Option<u64>,
+    /// Set to zero to let every user read the channel.
+    read_permissions: Option<Permissions>,
+    durability: Option<Durability>,
+}
+
+/// Request body for creating a category.
+#[derive(Deserialize, JsonSchema)]
+pub struct CreateCategoryRequest {
+    name: String,
+    #[serde(default)]
+    position: u32,
+}
+
+/// Request body for ordering channels within a category.
+#[derive(Deserialize, JsonSchema)]
+pub struct OrderChannelsRequest {
+    /// The category to place the channels in, or none for uncategorized.
+    category_id: Option<CategoryId>,
+    /// The channels in the category, in their display order.
+    channels: Vec<ChannelId>,
+}
+
+/// Request body for marking a channel's messages as read.
+#[derive(Deserialize, JsonSchema)]
+pub struct AckRequest {
+    /// The last message the user has read.
+    message_id: MessageId,
+}
+
+/// The user's read state in a channel after acknowledging a message.
+#[derive(Serialize, JsonSchema)]
+pub struct AckResponse {
+    /// The last message the user has read.
+    last_read: MessageId,
+    /// Number of messages the user hasn't read, up to 1000.
+    unread_count: u64,
+}
+
+/// A group of channels in the channel list.
+#[derive(Serialize, JsonSchema)]
+pub struct ChannelGroup {
+    /// The category of the group, or none for the uncategorized group.
+    category: Option<Category>,
+    channels: Vec<ChannelResponse>,
+}
+
+/// A channel as returned by the channel endpoints.
+#[derive(Serialize, JsonSchema)]
+pub struct ChannelResponse {
+    id: ChannelId,
+    label: String,
+    #[serde(rename = "type")]
+    channel_type: ChannelType,
+    created_at_ms: u64,
+    /// Longer description of the channel, unset if it has none.
+    topic: Option<String>,
+    message_count: u64,
+    retention: RetentionPolicy,
+    slow_mode_secs: Option<u64>,
+    /// Permissions users must hold to read the channel's messages.
+    read_permissions: Permissions,
+    durability: Durability,
+    /// Number of messages the authenticated user hasn't read, up to 1000.
+    ///
+    /// Counting stops at 1000, which clients should show as "999+".
+    /// Only included for text channels, and in the
+    /// channel list for authenticated users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unread_count: Option<u64>,
+}
+
+impl From<&TextChannel> for ChannelResponse {
+    fn from(channel: &TextChannel) -> Self {
+        let settings = channel.settings();
+
+        Self {
+            id: channel.channel_id(),
+            label: channel.get_label().to_string(),
+            channel_type: channel.channel_type(),
+            created_at_ms: channel.created_at_ms(),
+            topic: settings.topic,
+            message_count: channel.message_count(),
+            retention: settings.retention,
+            slow_mode_secs: settings.slow_mode_secs,
+            read_permissions: settings.read_permissions,
+            durability: settings.durability,
+            unread_count: None,
+        }
+    }
+}
+
+impl From<&VoiceChannel> for ChannelResponse {
+    fn from(channel: &VoiceChannel) -> Self {
+        Self {
+            id: channel.channel_id(),
+            label: channel.get_label().to_string(),
+            channel_type: channel.channel_type(),
+            created_at_ms: channel.created_at_ms(),
+            topic: None,
+            // Voice channels don't store any messages.
+            message_count: 0,
+            retention: RetentionPolicy::default(),
+            slow_mode_secs: None,
+            read_permissions: Permissions::NONE,
+            durability: Durability::default(),
+            unread_count: None,
+        }
+    }
+}
+
+impl ProtoEncode for ChannelResponse {
+    type Message = api::Channel;
+
+    fn to_proto(&self) -> Self::Message {
+        api::Channel {
+            id: self.id.0,
+            label: self.label.clone(),
+            created_at_ms: self.created_at_ms,
+            topic: self.topic.clone(),
+            message_count: self.message_count,
+            retention: Some(api::RetentionPolicy {
+                max_age_secs: self.retention.max_age_secs,
+                max_messages: self.retention.max_messages,
+            }),
+            slow_mode_secs: self.slow_mode_secs,
+            read_permissions: self.read_permissions.0,
+            unread_count: self.unread_count,
+            channel_type: match self.channel_type {
+                ChannelType::Text => api::ChannelType::Text,
+                ChannelType::Voice => api::ChannelType::Voice,
+            }
+            .into(),
+            durability: match self.durability {
+                Durability::Fast => api::Durability::Fast,
+                Durability::Durable => api::Durability::Durable,
+            }
+            .into(),
+        }
+    }
+}
+
+impl ProtoEncode for Vec<ChannelGroup> {
+    type Message = api::ChannelList;
+
+    fn to_proto(&self) -> Self::Message {
+        api::ChannelList {
+            groups: self
+                .iter()
+                .map(|group| api::ChannelGroup {
+                    category: group.category.as_ref().map(|category| api::Category {
+                        id: category.id.0,
+                        name: category.name.clone(),
+                        position: category.position,
+                    }),
+                    channels: group.channels.iter().map(ProtoEncode::to_proto).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Retrieves a list of all channels available on the server.
+///
+/// Channels are grouped by category, with the uncategorized group first
+/// followed by each category in order of it's position. Channels within
+/// a group are ordered by their position.
+///
+/// Authenticated users also receive the number of unread messages in each channel.
+///
+/// Text channels the user can't read are left out, so their existence
+/// isn't revealed.
+pub async fn handle_list_channels(
+    user: Option<AuthUser>,
+    encoding: Encoding,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let loaded = {
+        let categories = state.categories();
+        let categories = categories.read();
+        (categories.categories(), categories.placements())
+    };
+    let (categories, placements) = match loaded {
+        (Ok(categories), Ok(placements)) => (categories, placements),
+        (Err(err), _) | (_, Err(err)) => {
+            tracing::error!(?err, "failed to load channel categories");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Start with the uncategorized group, followed by each category.
+    let mut groups: Vec<ChannelGroup> = std::iter::once(None)
+        .chain(categories.into_iter().map(Some))
+        .map(|category| ChannelGroup {
+            category,
+            channels: Vec::new(),
+        })
+        .collect();
+
+    let group_index: HashMap<CategoryId, usize> = groups
+        .iter()
+        .enumerate()
+        .filter_map(|(i, g)| g.category.as_ref().map(|c| (c.id, i)))
+        .collect();
+
+    let text_channels: Vec<_> = state
+        .text_channels()
+        .into_iter()
+        .filter(|channel| match user {
+            Some(AuthUser(user_id)) => state.can_read(user_id, channel),
+            // Anonymous users don't hold any permissions.
+            None => Permissions::NONE.contains(channel.settings().read_permissions),
+        })
+        .collect();
+
+    let mut channels: Vec<_> = text_channels
+        .iter()
+        .map(|channel| ChannelResponse::from(channel.as_ref()))
+        .collect();
+
+    if let Some(AuthUser(user_id)) = user {
+        let counts = match unread_counts(&state, user_id, text_channels).await {
+            Ok(counts) => counts,
+            Err(status) => return status.into_response(),
+        };
+
+        for (response, count) in channels.iter_mut().zip(counts) {
+            response.unread_count = Some(count);
+        }
+    }
+    channels.extend(
+        state
+            .voice_channels()
+            .iter()
+            .map(|channel| ChannelResponse::from(channel.as_ref())),
+    );
+
+    let mut channels: Vec<_> = channels
+        .into_iter()
+        .map(|response| {
+            let placement = placements.get(&response.id).copied().unwrap_or_default();
+            (placement, response)
+        })
+        .collect();
+    channels.sort_by_key(|(placement, response)| (placement.position, response.id.0));
+
+    for (placement, response) in channels {
+        // Channels placed in a since-removed category fall back to uncategorized.
+        let index = placement
+            .category_id
+            .and_then(|id| group_index.get(&id).copied())
+            .unwrap_or(0);
+
+        groups[index].channels.push(response);
+    }
+
+    Encoded(encoding, groups).into_response()
+}
+
+/// Retrieves a single channel, along with the authenticated user's unread count.
+pub async fn handle_get_channel(
+    AuthUser(user_id): AuthUser,
+    encoding: Encoding,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let response = match (
+        state.text_channel(channel_id),
+        state.voice_channel(channel_id),
+    ) {
+        (Some(channel), _) => {
+            if !state.can_read(user_id, &channel) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+
+            let mut response = ChannelResponse::from(channel.as_ref());
+            match unread_counts(&state, user_id, vec![channel]).await {
+                Ok(counts) => response.unread_count = counts.first().copied(),
+                Err(status) => return status.into_response(),
+            }
+
+            response
+        }
+        (None, Some(channel)) => ChannelResponse::from(channel.as_ref()),
+        (None, None) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    Encoded(encoding, response).into_response()
+}
+
+/// Creates a new text or voice channel on the server, for users that can manage channels.
+///
+/// The retention and slow mode settings only apply to text channels.
+pub async fn handle_create_channel(
+    AuthUser(user_id): AuthUser,
+    encoding: Encoding,
+    State(state): State<SharedState>,
+    Json(request): Json<CreateChannelRequest>,
+) -> impl IntoResponse {
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MANAGE_CHANNELS)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let channel_type = match request.channel_type.as_deref().map(str::parse) {
+        None => ChannelType::Text,
+        Some(Ok(channel_type)) => channel_type,
+        Some(Err(err)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown channel type {:?}", err.0),
+            )
+                .into_response();
+        }
+    };
+
+    // Labels that are only whitespace would render as blank in clients.
+    if request.label.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "channel label is required").into_response();
+    }
+
+    // Empty topics are treated as no topic.
+    let topic = request.topic.filter(|topic| !topic.trim().is_empty());
+    if let Err(reason) = check_topic(topic.as_deref()) {
+        return (StatusCode::BAD_REQUEST, reason).into_response();
+    }
+
+    let created = match channel_type {
+        ChannelType::Text => {
+            let settings = TextChannelSettings {
+                topic,
+                retention: request.retention,
+                slow_mode_secs: request.slow_mode_secs.filter(|&secs| secs > 0),
+                read_permissions: request.read_permissions,
+                durability: request.durability,
+            };
+
+            state
+                .create_text_channel(request.label, settings)
+                .map(|channel| ChannelResponse::from(channel.as_ref()))
+        }
+        ChannelType::Voice => state
+            .create_voice_channel(request.label)
+            .map(|channel| ChannelResponse::from(channel.as_ref())),
+    };
+
+    match created {
+        Ok(response) => Encoded(encoding, response).into_response(),
+        Err(
+            CreateChannelError::TextChannelError(TextChannelError::LabelRequired)
+            | CreateChannelError::VoiceChannelError(VoiceChannelError::LabelRequired),
+        ) => (StatusCode::BAD_REQUEST, "channel label is required").into_response(),
+        Err(CreateChannelError::LimitReached { max }) => (
+            StatusCode::CONFLICT,
+            format!("the server already has the maximum of {max} channels"),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Rejects topics longer than [`MAX_TOPIC_CHARS`], explaining why in the error.
+fn check_topic(topic: Option<&str>) -> Result<(), String> {
+    let len = topic.map_or(0, |topic| topic.chars().count());
+    if len > MAX_TOPIC_CHARS {
+        return Err(format!(
+            "channel topic is {len} characters, the maximum is {MAX_TOPIC_CHARS}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Updates the settings of an existing channel, for users that can manage channels.
+pub async fn handle_update_channel(
+    AuthUser(user_id): AuthUser,
+    encoding: Encoding,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+    Json(request): Json<UpdateChannelRequest>,
+) -> impl IntoResponse {
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MANAGE_CHANNELS)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut settings = channel.settings();
+    if let Some(topic) = request.topic {
+        settings.topic = Some(topic).filter(|topic| !topic.trim().is_empty());
+    }
+    if let Err(reason) = check_topic(settings.topic.as_deref()) {
+        return (StatusCode::BAD_REQUEST, reason).into_response();
+    }
+    if let Some(retention) = request.retention {
+        settings.retention = retention;
+    }
+    if let Some(secs) = request.slow_mode_secs {
+        settings.slow_mode_secs = Some(secs).filter(|&secs| secs > 0);
+    }
+    if let Some(read_permissions) = request.read_permissions {
+        settings.read_permissions = read_permissions;
+    }
+    if let Some(durability) = request.durability {
+        settings.durability = durability;
+    }
+
+    if let Err(err) = state.set_text_channel_settings(&channel, settings) {
+        tracing::error!(%err, "failed to persist channel settings");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Encoded(encoding, ChannelResponse::from(channel.as_ref())).into_response()
+}
+
+/// Creates a new channel category, for users that can manage channels.
+pub async fn handle_create_category(
+    AuthUser(user_id): AuthUser,
+    State(state): State<SharedState>,
+    Json(request): Json<CreateCategoryRequest>,
+) -> impl IntoResponse {
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MANAGE_CHANNELS)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let categories = state.categories();
+
+    let result = categories
+        .write()
+        .create_category(request.name, request.position);
+
+    match result {
+        Ok(category) => Json(category).into_response(),
+        Err(CategoryError::NameRequired) => StatusCode::BAD_REQUEST.into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to create category");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Places channels in a category in the supplied order, for users that can manage channels.
+pub async fn handle_order_channels(
+    AuthUser(user_id): AuthUser,
+    State(state): State<SharedState>,
+    Json(request): Json<OrderChannelsRequest>,
+) -> impl IntoResponse {
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MANAGE_CHANNELS)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Only allow ordering channels that actually exist.
+    if request
+        .channels
+        .iter()
+        .any(|id| state.text_channel(*id).is_none() && state.voice_channel(*id).is_none())
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let result = state
+        .categories()
+        .read()
+        .order_channels(request.category_id, &request.channels);
+
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(CategoryError::NotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to order channels");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Marks the messages in a channel up to and including a message as read.
+pub async fn handle_ack(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+    Json(request): Json<AckRequest>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !state.can_read(user_id, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Only messages in the channel can be acknowledged.
+    match channel.message(request.message_id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!(%err, "failed to read message");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let last_read = match state
+        .read_states()
+        .read()
+        .ack(user_id, channel_id, request.message_id)
+    {
+        Ok(last_read) => last_read,
+        Err(err) => {
+            tracing::error!(%err, "failed to persist read state");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match unread_counts(&state, user_id, vec![channel]).await {
+        Ok(counts) => Json(AckResponse {
+            last_read,
+            unread_count: counts.first().copied().unwrap_or_default(),
+        })
+        .into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Counts the messages the user hasn't read in each of the channels.
+///
+/// Counting reads the channels' keyspaces with blocking IO,
+/// so it's run on the blocking pool rather than the async workers.
+async fn unread_counts(
+    state: &SharedState,
+    user_id: UserId,
+    channels: Vec<Arc<TextChannel>>,
+) -> Result<Vec<u64>, StatusCode> {
+    let state = Arc::clone(state);
+    let counts = tokio::task::spawn_blocking(move || {
+        channels
+            .iter()
+            .map(|channel| state.unread_count(user_id, channel))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await;
+
+    match counts {
+        Ok(Ok(counts)) => Ok(counts),
+        Ok(Err(err)) => {
+            tracing::error!(%err, "failed to count unread messages");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(err) => {
+            tracing::error!(%err, "unexpected panic counting unread messages");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::{
+        http::tests::{json_body, server},
+        server::{ServerEvent, channel::text::tests::test_message},
+        user::UserId,
+    };
+
+    #[tokio::test]
+    async fn fetching_a_channel_requires_a_user() {
+        let server = server();
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        channel
+            .create_message(test_message(UserId(2), "hello"), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        let uri = format!("/channels/{}", channel.channel_id());
+        assert_eq!(
+            server.get_status(&uri, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let token = server.token(UserId(1));
+        let response = server.request(Method::GET, &uri, Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = json_body(response).await;
+        assert_eq!(body["message_count"], 1);
+        assert_eq!(body["unread_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn channels_the_user_cant_read_are_hidden() {
+        let server = server();
+        let state = &server.state;
+        state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let restricted = state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        let message = restricted
+            .create_message(test_message(UserId(2), "hello"), None, Permissions::NONE)
+            .await
+            .unwrap();
+        state
+            .permissions()
+            .read()
+            .set_permissions(UserId(2), Permissions::MANAGE_MESSAGES)
+            .unwrap();
+
+        let labels = |body: serde_json::Value| -> Vec<String> {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .flat_map(|group| group["channels"].as_array().unwrap().clone())
+                .map(|channel| channel["label"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let member = server.token(UserId(1));
+        let moderator = server.token(UserId(2));
+
+        for (token, expected) in [
+            (None, vec!["general"]),
+            (Some(&member), vec!["general"]),
+            (Some(&moderator), vec!["general", "moderators"]),
+        ] {
+            let response = server
+                .request(Method::GET, "/channels", token.map(String::as_str), None)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let mut labels = labels(json_body(response).await);
+            labels.sort();
+            assert_eq!(labels, expected);
+        }
+
+        let uri = format!("/channels/{}", restricted.channel_id());
+        assert_eq!(
+            server.get_status(&uri, Some(&member)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            server.get_status(&uri, Some(&moderator)).await,
+            StatusCode::OK
+        );
+
+        let ack = serde_json::json!({ "message_id": message.id });
+        let uri = format!("/channels/{}/ack", restricted.channel_id());
+        for (token, status) in [
+            (&member, StatusCode::FORBIDDEN),
+            (&moderator, StatusCode::OK),
+        ] {
+            let response = server
+                .request(Method::POST, &uri, Some(token), Some(ack.clone()))
+                .await;
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn acking_the_latest_message_clears_the_unread_count() {
+        let server = server();
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let send = |content| {
+            channel.create_message(test_message(UserId(2), content), None, Permissions::NONE)
+        };
+
+        send("first").await.unwrap();
+        let latest = send("second").await.unwrap();
+
+        let token = server.token(UserId(1));
+        let uri = format!("/channels/{}", channel.channel_id());
+        let unread_count = async || {
+            let response = server.request(Method::GET, &uri, Some(&token), None).await;
+            json_body(response).await["unread_count"].clone()
+        };
+        assert_eq!(unread_count().await, 2);
+
+        let response = server
+            .request(
+                Method::POST,
+                &format!("{uri}/ack"),
+                Some(&token),
+                Some(serde_json::json!({ "message_id": latest.id })),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["unread_count"], 0);
+        assert_eq!(unread_count().await, 0);
+
+        // Only messages sent after the ack are unread.
+        send("third").await.unwrap();
+        assert_eq!(unread_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn text_and_voice_channels_can_be_ordered() {
+        let server = server();
+        let token = server.token(UserId(1));
+        server
+            .state
+            .permissions()
+            .read()
+            .set_permissions(UserId(1), Permissions::MANAGE_CHANNELS)
+            .unwrap();
+        let general = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap()
+            .channel_id();
+        let lounge = server
+            .state
+            .create_voice_channel("lounge".to_string())
+            .unwrap()
+            .channel_id();
+
+        // Place the voice channel before the text channel.
+        let body = serde_json::json!({
+            "category_id": null,
+            "channels": [lounge.to_string(), general.to_string()],
+        });
+        let response = server
+            .request(Method::PUT, "/channels/order", Some(&token), Some(body))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = server
+            .request(Method::GET, "/channels", Some(&token), None)
+            .await;
+        let groups = json_body(response).await;
+        let labels: Vec<_> = groups[0]["channels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|channel| channel["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["lounge", "general"]);
+
+        // Unknown channels still can't be ordered.
+        let body = serde_json::json!({ "channels": ["12345"] });
+        let response = server
+            .request(Method::PUT, "/channels/order", Some(&token), Some(body))
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn text_and_voice_channels_are_created_by_the_same_endpoint() {
+        let server = server();
+        let token = server.token(UserId(1));
+        server
+            .state
+            .permissions()
+            .read()
+            .set_permissions(UserId(1), Permissions::MANAGE_CHANNELS)
+            .unwrap();
+
+        let create = async |body: serde_json::Value| {
+            server
+                .request(Method::POST, "/channels", Some(&token), Some(body))
+                .await
+        };
+
+        let response = create(serde_json::json!({ "label": "general", "type": "text" })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let text = json_body(response).await;
+        assert_eq!(text["label"], "general");
+        assert_eq!(text["type"], "text");
+
+        let response = create(serde_json::json!({ "label": "lounge", "type": "voice" })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let voice = json_body(response).await;
+        assert_eq!(voice["label"], "lounge");
+        assert_eq!(voice["type"], "voice");
+
+        let id = |body: &serde_json::Value| -> ChannelId {
+            body["id"].as_str().unwrap().parse().unwrap()
+        };
+        assert!(server.state.text_channel(id(&text)).is_some());
+        assert!(server.state.voice_channel(id(&voice)).is_some());
+
+        // Unknown types and blank labels are refused.
+        let response = create(serde_json::json!({ "label": "stage", "type": "stage" })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = create(serde_json::json!({ "label": " ", "type": "voice" })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(server.state.voice_channels().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn topics_can_be_set_and_cleared() {
+        let server = server();
+        let token = server.token(UserId(1));
+        server
+            .state
+            .permissions()
+            .read()
+            .set_permissions(UserId(1), Permissions::MANAGE_CHANNELS)
+            .unwrap();
+
+        let body = serde_json::json!({ "label": "general", "topic": "campfire stories" });
+        let response = server
+            .request(Method::POST, "/channels", Some(&token), Some(body))
+            .await;
+        let created = json_body(response).await;
+        assert_eq!(created["topic"], "campfire stories");
+
+        let uri = format!("/channels/{}", created["id"].as_str().unwrap());
+        let mut events = server.state.subscribe_events();
+        let update = async |topic: String| {
+            let body = serde_json::json!({ "topic": topic });
+            server
+                .request(Method::PATCH, &uri, Some(&token), Some(body))
+                .await
+        };
+
+        let response = update("ghost stories".to_string()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["topic"], "ghost stories");
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            ServerEvent::ChannelUpdated { topic: Some(topic), .. } if topic == "ghost stories"
+        ));
+
+        // An empty topic clears it.
+        let response = update(String::new()).await;
+        assert_eq!(json_body(response).await["topic"], serde_json::Value::Null);
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            ServerEvent::ChannelUpdated { topic: None, .. }
+        ));
+
+        let response = server.request(Method::GET, &uri, Some(&token), None).await;
+        assert_eq!(json_body(response).await["topic"], serde_json::Value::Null);
+
+        // Topics over the limit are refused, leaving the topic as it was.
+        let response = update("a".repeat(MAX_TOPIC_CHARS + 1)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -1,17 +1,62 @@
 use askama::Template;
-use axum::{Router, response::IntoResponse, routing::get};
+use axum::{Router, extract::State, response::IntoResponse, routing::get};
+
+use crate::http::SharedState;
 
 pub mod templates;
 
-pub fn make_client_router() -> Router {
-    Router::new().route("/login", get(handle_login))
+pub fn make_client_router(state: SharedState) -> Router {
+    Router::new()
+        .route("/login", get(handle_login))
+        .with_state(state)
 }
 
 /// Renders the login page
-async fn handle_login() -> impl IntoResponse {
-    let oauth2_providers = Vec::new();
+async fn handle_login(State(state): State<SharedState>) -> impl IntoResponse {
+    let oauth2_providers = state
+        .auth()
+        .read()
+        .oauth2_clients()
+        .iter()
+        .map(|client| templates::OAuth2Provider {
+            id: client.id.clone(),
+            label: client.label.clone(),
+        })
+        .collect();
 
     let page = templates::LoginTemplate { oauth2_providers };
 
     page.render().unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use crate::{
+        http::tests::{TestServer, server, server_with},
+        server::auth::tests::test_provider,
+    };
+
+    async fn login_page(server: &TestServer) -> String {
+        let response = server
+            .request(Method::GET, "/client/login", None, None)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn configured_providers_are_listed_on_the_login_page() {
+        let configured = server_with(|config| config.oauth2_client(test_provider()));
+        let page = login_page(&configured).await;
+        assert!(page.contains(r#"<a href="/oauth/test">Test</a>"#), "{page}");
+
+        let page = login_page(&server()).await;
+        assert!(!page.contains("/oauth/"), "{page}");
+    }
+}
@@ -0,0 +1,106 @@
+//! Cross-origin resource sharing for browser clients hosted on other origins.
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::server::CorsConfig;
+
+/// Builds the CORS layer for the allowed origins.
+///
+/// Returns `None` if no origins are allowed, leaving browsers
+/// to only allow same-origin requests.
+pub fn cors_layer(config: &CorsConfig) -> Option<CorsLayer> {
+    if config.allowed_origins.is_empty() {
+        return None;
+    }
+
+    // The values are checked when the config is built, so invalid ones are skipped.
+    let origins = config
+        .allowed_origins
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect::<Vec<_>>();
+    let methods = config
+        .allowed_methods
+        .iter()
+        .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+        .collect::<Vec<_>>();
+    let headers = config
+        .allowed_headers
+        .iter()
+        .filter_map(|header| HeaderName::from_bytes(header.as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(config.allow_credentials),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{Request, header},
+        response::Response,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::{
+        make_app_router,
+        tests::{TestServer, server_with},
+    };
+
+    async fn preflight(server: &TestServer, origin: &str) -> Response {
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/channels")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap();
+
+        make_app_router(Arc::clone(&server.state))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn preflights_are_only_allowed_for_allowed_origins() {
+        let server = server_with(|config| {
+            config.cors(CorsConfig {
+                allowed_origins: vec!["https://app.example.com".to_string()],
+                ..CorsConfig::default()
+            })
+        });
+
+        let response = preflight(&server, "https://app.example.com").await;
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+                .to_str()
+                .unwrap()
+                .contains("POST")
+        );
+
+        let response = preflight(&server, "https://evil.example.com").await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{
+        SharedState,
+        auth::AuthUser,
+        encoding::Encoding,
+        messages::{HistoryParams, create_message_error_response, list_messages},
+        search::{SearchParams, search_channel},
+    },
+    message::{MessageFlags, MessageId, MessageMentions},
+    server::{
+        CreateChannelError,
+        channel::{
+            Channel,
+            direct::{DirectChannel, DirectChannelError},
+            text::TextChannelMessage,
+        },
+    },
+    user::UserId,
+};
+
+/// Query parameters for creating a direct channel.
+#[derive(Deserialize, JsonSchema)]
+pub struct CreateDirectQuery {
+    /// Comma-separated IDs of additional users to include in a group channel.
+    with: Option<String>,
+}
+
+/// Request body for posting a message to a direct channel.
+#[derive(Deserialize, JsonSchema)]
+pub struct PostMessageRequest {
+    content: String,
+    /// The message in the channel that this message replies to.
+    reply_to: Option<MessageId>,
+    /// Key chosen by the client to deduplicate retries of the message.
+    ///
+    /// Posting with the same nonce again shortly after returns
+    /// the message created the first time.
+    nonce: Option<String>,
+    /// Flags of the message, only text-to-speech and suppress embeds can be set.
+    #[serde(default)]
+    flags: MessageFlags,
+}
+
+/// A direct channel as returned by the direct channel endpoints.
+#[derive(Serialize, JsonSchema)]
+pub struct DirectChannelResponse {
+    id: ChannelId,
+    participants: Vec<UserId>,
+}
+
+impl From<&DirectChannel> for DirectChannelResponse {
+    fn from(direct: &DirectChannel) -> Self {
+        Self {
+            id: direct.channel().channel_id(),
+            participants: direct.participants().iter().copied().collect(),
+        }
+    }
+}
+
+/// Opens a direct channel between the authenticated user and another user.
+///
+/// Returns the existing channel if the users already have one. The other
+/// users have to be known to the server, and new channels count towards
+/// the authenticated user's direct channel limit.
+pub async fn handle_create(
+    AuthUser(user_id): AuthUser,
+    Path(other): Path<String>,
+    Query(query): Query<CreateDirectQuery>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let mut participants = BTreeSet::from([user_id]);
+
+    let others = std::iter::once(other.as_str())
+        .chain(query.with.as_deref().unwrap_or_default().split(','))
+        .filter(|id| !id.is_empty());
+
+    for id in others {
+        let Ok(id) = id.parse::<UserId>() else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+
+        participants.insert(id);
+    }
+
+    let err = match state.create_direct_channel(user_id, participants) {
+        Ok(direct) => return Json(DirectChannelResponse::from(direct.as_ref())).into_response(),
+        Err(CreateChannelError::DirectChannelError(err)) => err,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match err {
+        DirectChannelError::UnknownUser(user) => {
+            (StatusCode::NOT_FOUND, format!("user {user} not found")).into_response()
+        }
+        DirectChannelError::UserLimitReached { max } => (
+            StatusCode::CONFLICT,
+            format!("you already have the maximum of {max} direct channels"),
+        )
+            .into_response(),
+        DirectChannelError::ServerLimitReached { max } => (
+            StatusCode::CONFLICT,
+            format!("the server already has the maximum of {max} direct channels"),
+        )
+            .into_response(),
+        _ => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+/// Lists the direct channels the authenticated user participates in.
+pub async fn handle_list(
+    AuthUser(user_id): AuthUser,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let channels = state.direct_channels(user_id);
+
+    let channels: Vec<DirectChannelResponse> = channels
+        .iter()
+        .map(|direct| DirectChannelResponse::from(direct.as_ref()))
+        .collect();
+
+    Json(channels).into_response()
+}
+
+/// Fetches a page of a direct channel's message history, oldest first.
+pub async fn handle_list_messages(
+    AuthUser(user_id): AuthUser,
+    encoding: Encoding,
+    Path(channel_id): Path<String>,
+    Query(params): Query<HistoryParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    match lookup(&state, user_id, &channel_id) {
+        Ok(direct) => list_messages(encoding, &direct.channel(), params),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Posts a message to a direct channel as the authenticated user.
+pub async fn handle_post_message(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+    Json(request): Json<PostMessageRequest>,
+) -> impl IntoResponse {
+    let direct = match lookup(&state, user_id, &channel_id) {
+        Ok(direct) => direct,
+        Err(status) => return status.into_response(),
+    };
+
+    let message = TextChannelMessage {
+        id: MessageId::default(),
+        author: user_id,
+        author_name: None,
+        timestamp_ms: Utc::now().timestamp_millis() as u64,
+        edited_at: None,
+        content: request.content,
+        reply_to: request.reply_to,
+        mentions: MessageMentions::default(),
+        flags: request.flags,
+    };
+
+    let permissions = state.permissions().read().permissions(user_id);
+
+    match direct
+        .channel()
+        .create_message(message, request.nonce.as_deref(), permissions)
+        .await
+    {
+        Ok(message) => Json(message).into_response(),
+        Err(err) => create_message_error_response(err),
+    }
+}
+
+/// Searches the messages in a direct channel.
+pub async fn handle_search(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    Query(params): Query<SearchParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    match lookup(&state, user_id, &channel_id) {
+        Ok(direct) => search_channel(&direct.channel(), params),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Resolves a direct channel, checking that the user is a participant.
+fn lookup(
+    state: &SharedState,
+    user_id: UserId,
+    channel_id: &str,
+) -> Result<std::sync::Arc<DirectChannel>, StatusCode> {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    state
+        .direct_channel(user_id, channel_id)
+        .map_err(|err| match err {
+            DirectChannelError::Forbidden => StatusCode::FORBIDDEN,
+            _ => StatusCode::NOT_FOUND,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::http::tests::{json_body, server, server_with};
+
+    #[tokio::test]
+    async fn direct_channels_are_only_opened_with_known_users_up_to_the_limit() {
+        let server = server_with(|config| config.direct_channel_limits(1, 10));
+        let token = server.token(UserId(1));
+        for user in [2, 3] {
+            server
+                .state
+                .presence()
+                .read()
+                .record_seen(UserId(user), 0)
+                .unwrap();
+        }
+
+        let response = server
+            .request(Method::POST, "/users/9/dm", Some(&token), None)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = server
+            .request(Method::POST, "/users/2/dm", Some(&token), None)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = server
+            .request(Method::POST, "/users/3/dm", Some(&token), None)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn only_participants_can_use_a_direct_channel() {
+        let server = server();
+        let direct = server.direct_channel(&[UserId(1), UserId(2)]);
+        let messages = format!("/dms/{}/messages", direct.channel().channel_id());
+        let search = format!("/dms/{}/search?q=hello", direct.channel().channel_id());
+
+        let sender = server.token(UserId(1));
+        let recipient = server.token(UserId(2));
+        let outsider = server.token(UserId(3));
+
+        let body = || Some(json!({ "content": "hello" }));
+        let response = server
+            .request(Method::POST, &messages, Some(&sender), body())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = server
+            .request(Method::POST, &messages, Some(&outsider), body())
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = server
+            .request(Method::GET, &messages, Some(&recipient), None)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let history = json_body(response).await;
+        assert_eq!(history.as_array().unwrap().len(), 1);
+        assert_eq!(history[0]["content"], "hello");
+
+        assert_eq!(
+            server.get_status(&messages, Some(&outsider)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            server.get_status(&search, Some(&outsider)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            server.get_status(&messages, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            server.get_status("/dms/1/messages", Some(&sender)).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
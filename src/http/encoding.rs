@@ -0,0 +1,156 @@
+//! Content negotiation for REST responses.
+//!
+//! Responses are encoded as JSON by default. Clients that list a
+//! Protobuf media type in their `Accept` header receive the binary
+//! encoding instead, using the types from [`crate::proto::v0::api`].
+
+use std::convert::Infallible;
+
+use axum::{
+    Json,
+    extract::FromRequestParts,
+    http::{HeaderMap, HeaderValue, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{
+    proto::v0::api,
+    server::{
+        channel::text::TextChannelMessage,
+        mentions::{ResolvedMention, ResolvedMentions},
+    },
+};
+
+/// Media type used for Protobuf encoded responses.
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Media types accepted as requesting a Protobuf encoded response.
+const PROTOBUF_MEDIA_TYPES: [&str; 2] = [PROTOBUF_CONTENT_TYPE, "application/protobuf"];
+
+/// The encoding a client requested for the response.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Protobuf,
+}
+
+impl Encoding {
+    /// Picks the response encoding from the `Accept` header.
+    ///
+    /// Protobuf is only used when the client explicitly accepts it,
+    /// anything else, including a missing header, falls back to JSON.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accepts_protobuf = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+            .any(|media_type| PROTOBUF_MEDIA_TYPES.contains(&media_type));
+
+        if accepts_protobuf {
+            Self::Protobuf
+        } else {
+            Self::Json
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Encoding {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// Implemented by response types that have a Protobuf representation.
+pub trait ProtoEncode: Serialize {
+    /// The Protobuf message the type is encoded as.
+    type Message: prost::Message;
+
+    /// Converts the response to it's Protobuf message.
+    fn to_proto(&self) -> Self::Message;
+}
+
+/// A response body encoded with the encoding the client requested.
+pub struct Encoded<T>(pub Encoding, pub T);
+
+impl<T: ProtoEncode> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        let Encoded(encoding, body) = self;
+
+        match encoding {
+            Encoding::Json => Json(body).into_response(),
+            Encoding::Protobuf => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+                )],
+                prost::Message::encode_to_vec(&body.to_proto()),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Converts a message to it's Protobuf representation.
+impl From<&TextChannelMessage> for api::ChannelMessage {
+    fn from(message: &TextChannelMessage) -> Self {
+        Self {
+            id: message.id.0,
+            author: message.author.0,
+            author_name: message.author_name.clone(),
+            timestamp_ms: message.timestamp_ms,
+            edited_at: message.edited_at,
+            content: message.content.clone(),
+            reply_to: message.reply_to.map(|id| id.0),
+            mentioned_users: message.mentions.users.iter().map(|id| id.0).collect(),
+            mentioned_roles: message.mentions.roles.iter().map(|id| id.0).collect(),
+            mentioned_channels: message.mentions.channels.iter().map(|id| id.0).collect(),
+            flags: message.flags.0,
+        }
+    }
+}
+
+impl From<&ResolvedMentions> for api::ResolvedMentions {
+    fn from(mentions: &ResolvedMentions) -> Self {
+        fn encode<Id>(mention: &ResolvedMention<Id>, id: u64) -> api::ResolvedMention {
+            api::ResolvedMention {
+                id,
+                name: mention.name.clone(),
+                resolved: mention.resolved,
+            }
+        }
+
+        Self {
+            users: mentions.users.iter().map(|m| encode(m, m.id.0)).collect(),
+            roles: mentions.roles.iter().map(|m| encode(m, m.id.0)).collect(),
+            channels: mentions
+                .channels
+                .iter()
+                .map(|m| encode(m, m.id.0))
+                .collect(),
+        }
+    }
+}
+
+impl ProtoEncode for TextChannelMessage {
+    type Message = api::ChannelMessage;
+
+    fn to_proto(&self) -> Self::Message {
+        self.into()
+    }
+}
+
+impl ProtoEncode for Vec<TextChannelMessage> {
+    type Message = api::ChannelMessageList;
+
+    fn to_proto(&self) -> Self::Message {
+        api::ChannelMessageList {
+            messages: self.iter().map(Into::into).collect(),
+        }
+    }
+}
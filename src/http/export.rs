@@ -0,0 +1,131 @@
+use std::io::{self, Write};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use futures::{SinkExt, channel::mpsc};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+};
+
+/// Number of exported chunks buffered before the export task waits on the client.
+const EXPORT_BUFFER_CHUNKS: usize = 16;
+
+/// Streams a channel's message history to the client as NDJSON.
+///
+/// Exports include every message regardless of the channel's
+/// read permissions, so only admins can export a channel.
+pub async fn handle_export(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    if !state.permissions().read().is_admin(user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, io::Error>>(EXPORT_BUFFER_CHUNKS);
+    let mut errors = sender.clone();
+
+    // The export scans the keyspace with blocking IO, so run it on
+    // a blocking task that streams chunks to the response body.
+    tokio::task::spawn_blocking(move || match channel.export(StreamWriter(sender)) {
+        Ok(count) => tracing::info!(channel_id = ?channel_id, count, "exported channel messages"),
+        Err(err) => {
+            tracing::error!(?err, channel_id = ?channel_id, "failed to export channel");
+
+            // The headers were already sent, so abort the body rather than
+            // ending it, or the client would mistake it for a complete export.
+            let _ =
+                futures::executor::block_on(errors.send(Err(io::Error::other("export failed"))));
+        }
+    });
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"channel-{channel_id}.ndjson\""),
+        ),
+    ];
+
+    (headers, Body::from_stream(receiver)).into_response()
+}
+
+/// Adapts the sending half of a body stream to a blocking writer.
+struct StreamWriter(mpsc::Sender<Result<Bytes, io::Error>>);
+
+impl Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        futures::executor::block_on(self.0.send(Ok(Bytes::copy_from_slice(buf))))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export stream closed"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::{
+        http::tests::server_with,
+        server::{
+            channel::{
+                Channel,
+                text::{TextChannelMessage, TextChannelSettings, tests::test_message},
+            },
+            permission::Permissions,
+        },
+        user::UserId,
+    };
+
+    #[tokio::test]
+    async fn only_admins_can_export_a_channel() {
+        let server = server_with(|config| config.admin_user(UserId(1)));
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        channel
+            .create_message(test_message(UserId(2), "hello"), None, Permissions::NONE)
+            .await
+            .unwrap();
+        let uri = format!("/channels/{}/export", channel.channel_id());
+
+        let member = server.token(UserId(2));
+        let response = server.request(Method::GET, &uri, Some(&member), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let admin = server.token(UserId(1));
+        let response = server.request(Method::GET, &uri, Some(&admin), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let exported: Vec<TextChannelMessage> = body
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert!(exported.iter().any(|msg| msg.content == "hello"));
+    }
+}
@@ -3,31 +3,49 @@ use axum::{
         ConnectInfo, Query, State,
         ws::{self, WebSocket, WebSocketUpgrade},
     },
-    http::StatusCode,
+    http::{HeaderMap, StatusCode, header},
     response::IntoResponse,
 };
 use axum_extra::{TypedHeader, headers};
+use chrono::Utc;
 use futures::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use serde::Deserialize;
+use parking_lot::RwLock;
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{self, DeserializeOwned},
+};
 use serde_json::Value;
-use std::{
-    net::SocketAddr,
-    sync::{Arc, RwLock},
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    oneshot,
 };
 use tracing::{Instrument, debug_span, info_span};
 
 use prost::Message;
 
 use crate::{
-    proto::v0::{self, GatewayServerEvent},
-    server::gateway,
+    channel::ChannelId,
+    http::auth::bearer_token,
+    message::{MessageFlags, MessageId, MessageMentions},
+    proto::{GatewayVersion, v0, v1},
+    server::{
+        Config,
+        channel::text::{
+            TextChannelMessage, content::ContentError, create::CreateMessageError,
+            reply::ReplyError,
+        },
+        gateway,
+        metrics::metrics,
+    },
+    user::UserId,
 };
 
 /// Identifies the encoding used by the gateway.
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug)]
 pub enum Encoding {
     /// Encodes and decodes messages using the Protobuf specification.
     Protobuf,
@@ -37,11 +55,98 @@ pub enum Encoding {
     Json,
 }
 
+impl Encoding {
+    /// The encodings supported by the gateway.
+    pub const ALL: &[Encoding] = &[Encoding::Protobuf, Encoding::Json];
+
+    /// Returns the value used to select the encoding in the query parameters.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "Protobuf",
+            Encoding::Json => "Json",
+        }
+    }
+
+    /// Returns the WebSocket subprotocol used to select the encoding.
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "protobuf",
+            Encoding::Json => "json",
+        }
+    }
+
+    /// Returns the encoding selected by a WebSocket subprotocol.
+    pub fn from_subprotocol(protocol: &str) -> Option<Encoding> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|encoding| encoding.subprotocol() == protocol)
+    }
+}
+
+/// Indicates the requested gateway encoding isn't supported.
+#[derive(Debug)]
+pub struct UnsupportedEncoding(pub String);
+
+/// Parses encodings case-insensitively, so `json` and `Json` both select JSON.
+impl FromStr for Encoding {
+    type Err = UnsupportedEncoding;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Encoding::ALL
+            .iter()
+            .copied()
+            .find(|encoding| encoding.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnsupportedEncoding(s.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Encoding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoding = String::deserialize(deserializer)?;
+
+        encoding
+            .parse()
+            .map_err(|_| de::Error::unknown_variant(&encoding, &["protobuf", "json"]))
+    }
+}
+
+/// How long the send task is given to close the connection after the receive task exits.
+const SEND_TASK_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Close code sent when a client doesn't identify within the identify timeout.
+pub const IDENTIFY_TIMEOUT_CLOSE_CODE: u16 = 4009;
+
+/// How long closing a connection that timed out is allowed to take.
+const TIMEOUT_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Close code sent when a connection is refused or closed to stay within the session limits.
+pub const SESSION_LIMIT_CLOSE_CODE: u16 = 4008;
+
+/// Close code sent when a client's identify message fails validation.
+pub const MALFORMED_IDENTIFY_CLOSE_CODE: u16 = 4002;
+
+/// Close code sent when a client's identify token isn't valid.
+pub const UNAUTHORIZED_CLOSE_CODE: u16 = 4004;
+
+/// The maximum length of a client's agent string in bytes.
+const MAX_CLIENT_AGENT_BYTES: usize = 256;
+
+/// Compression options supported for gateway messages.
+///
+/// Messages aren't compressed yet, so this only advertises the identity option.
+const GATEWAY_COMPRESSION: &[&str] = &["none"];
+
+/// Intents clients can use to select the events they receive.
+const GATEWAY_INTENTS: &[&str] = &["messages"];
+
 /// Query parameters supported by the gateway endpoint.
 #[derive(Deserialize)]
 pub struct GatewayQuery {
     version: Option<String>,
-    encoding: Option<Encoding>,
+    /// Parsed separately so an unsupported encoding
+    /// can be reported instead of rejecting the query.
+    encoding: Option<String>,
 }
 
 /// The initial handler for the HTTP request to initiate WebSocket negotiation.
@@ -55,16 +160,26 @@ pub async fn ws_handler(
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     query: Query<GatewayQuery>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<super::SharedState>,
 ) -> impl IntoResponse {
+    // Browsers attach the user's cookies to WebSocket connections opened by
+    // any site, so connections from other sites are refused before upgrading.
+    if state.config().check_gateway_origin && !origin_allowed(&state, &headers) {
+        tracing::warn!(%addr, "rejected gateway connection from a disallowed origin");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     // Short-circuit early if we can't support the requested version.
-    match query.0.version {
-        Some(version) => {
-            if version == "v0" {
-                return StatusCode::BAD_REQUEST.into_response();
-            }
+    //
+    // The selected version determines the codec used for the rest of the session.
+    let version = match query.0.version.as_deref().map(str::parse::<GatewayVersion>) {
+        Some(Ok(version)) => version,
+        Some(Err(err)) => {
+            tracing::debug!(?err, "client requested unsupported gateway version");
+            return StatusCode::BAD_REQUEST.into_response();
         }
-        None => {}
+        None => GatewayVersion::default(),
     };
 
     // Grab the user agent for logging and identification.
@@ -74,18 +189,79 @@ pub async fn ws_handler(
         String::from("Unknown browser")
     };
 
-    println!("`{user_agent}` at {addr} connected to gateway");
+    tracing::info!(%user_agent, %addr, "client connected to gateway");
 
-    // Either extract the encoding from the query
-    // parameters, or use the default JSON encoding.
-    let encoding = match query.0.encoding {
-        Some(encoding) => encoding,
-        None => Encoding::Json,
+    // Select the first encoding subprotocol offered by the client
+    // that's supported, which is echoed back in the upgrade response.
+    let ws = ws.protocols(Encoding::ALL.iter().map(Encoding::subprotocol));
+
+    let query_encoding = match query.0.encoding.as_deref().map(str::parse::<Encoding>) {
+        Some(Ok(encoding)) => Some(encoding),
+        Some(Err(err)) => {
+            tracing::warn!(
+                ?err,
+                "client requested unsupported gateway encoding, using json"
+            );
+            None
+        }
+        None => None,
     };
 
+    // The subprotocol takes precedence, falling back to the query
+    // parameters, or the default JSON encoding if neither is given.
+    let encoding = ws
+        .selected_protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+        .and_then(Encoding::from_subprotocol)
+        .or(query_encoding)
+        .unwrap_or(Encoding::Json);
+
+    let max_message_bytes = state.config().max_gateway_message_bytes;
+
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, state, encoding))
+    //
+    // Messages over the limit are rejected by the WebSocket protocol
+    // layer before they're buffered, so a client can't exhaust memory.
+    ws.max_message_size(max_message_bytes)
+        .max_frame_size(max_message_bytes)
+        .on_upgrade(move |socket| handle_socket(socket, addr, state, version, encoding))
+}
+
+/// Returns true if the upgrade request is allowed to connect from it's origin.
+///
+/// Browsers always send an `Origin` header, and can't set the authorization
+/// header on WebSocket upgrades, so requests without an origin, or with a
+/// valid bot token in the header, are from other clients and allowed.
+fn origin_allowed(state: &super::SharedState, headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+
+    // Connections from the web client served by the server itself.
+    let host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok());
+    let same_origin = origin
+        .split_once("://")
+        .zip(host)
+        .is_some_and(|((_, origin_host), host)| origin_host.eq_ignore_ascii_case(host));
+
+    if same_origin
+        || state
+            .config()
+            .cors
+            .allowed_origins
+            .iter()
+            .any(|o| o == origin)
+    {
+        return true;
+    }
+
+    bearer_token(headers).is_some_and(|token| state.auth().read().validate_token(&token).is_some())
 }
 
 /// The WebSocket state machine spawned per connection.
@@ -93,35 +269,93 @@ async fn handle_socket(
     mut socket: WebSocket,
     who: SocketAddr,
     state: super::SharedState,
+    version: GatewayVersion,
     encoding: Encoding,
 ) {
     tracing::info!(
         encoding_test = ?encoding,
         who = ?who,
+        %version,
         "new gateway socket connection, sending handshake to client"
     );
 
+    let capabilities = gateway_capabilities(state.config());
+
+    // Clients that stall before identifying are disconnected,
+    // so they can't hold on to the task and socket forever.
+    let identify_timeout = Duration::from_millis(state.config().identify_timeout_ms);
+
     // First, send a handshake message to the client to
     // identify the server version and capabilities.
-    send_handshake_message(&mut socket, &encoding)
-        .instrument(info_span!("gateway_handshake_send"))
-        .await;
+    let handshake = send_handshake_message(&mut socket, version, &encoding, capabilities)
+        .instrument(info_span!("gateway_handshake_send"));
+
+    match tokio::time::timeout(identify_timeout, handshake).await {
+        Ok(Ok(())) => {}
+        Ok(Err(HandshakeError::Encode(err))) => {
+            tracing::error!(%err, "failed to encode gateway handshake");
+
+            let frame = ws::CloseFrame {
+                code: ws::close_code::ERROR,
+                reason: "failed to encode handshake".into(),
+            };
+            if let Err(err) = socket.send(ws::Message::Close(Some(frame))).await {
+                tracing::error!(%err, "failed to close gateway websocket");
+            }
+
+            return;
+        }
+        Ok(Err(HandshakeError::Send(err))) => {
+            // The socket is broken, so there's no point closing it.
+            tracing::error!(%err, "failed to send gateway handshake to client");
+            return;
+        }
+        Err(_) => {
+            tracing::warn!(who = ?who, "timed out sending gateway handshake to client");
+            close_timed_out(socket).await;
+            return;
+        }
+    }
 
     tracing::info!(encoding_test = ?encoding, who = ?who, "waiting for client to identify to gateway");
 
     // Decode the identity message sent from the client to the websocket.
     //
     // This retries until a valid identify message is received.
-    let Some(identity) = receive_identity_message(&mut socket)
-        .instrument(info_span!("gateway_ident_recv"))
-        .await
-    else {
-        tracing::error!("failed to get gateway identity message from client");
-        return;
+    let max_message_bytes = state.config().max_gateway_message_bytes;
+
+    let identity = tokio::time::timeout(
+        identify_timeout,
+        receive_identity_message(&mut socket, version, max_message_bytes)
+            .instrument(info_span!("gateway_ident_recv")),
+    )
+    .await;
+
+    let identity = match identity {
+        Ok(Some(identity)) => identity,
+        Ok(None) => {
+            tracing::error!("failed to get gateway identity message from client");
+            return;
+        }
+        Err(_) => {
+            tracing::warn!(who = ?who, "gateway client didn't identify in time");
+            close_timed_out(socket).await;
+            return;
+        }
     };
 
-    // TODO: Support a "Resume" message to allow a client to recall an existing
-    //       session cached on the server instead of creating a new one.
+    // Tell clients exactly what was wrong with their identify, so it
+    // isn't mistaken for a problem with their token.
+    if let Err(err) = validate_identify(&identity) {
+        tracing::warn!(who = ?who, ?err, "gateway client sent an invalid identity");
+
+        let frame = malformed_identify_close_frame(&err);
+        if let Err(err) = socket.send(ws::Message::Close(Some(frame))).await {
+            tracing::error!(%err, "failed to close gateway websocket");
+        }
+
+        return;
+    }
 
     tracing::info!(
         encoding_test = ?encoding,
@@ -129,18 +363,17 @@ async fn handle_socket(
         client_agent = ?identity.client_agent,
         "successfully received client identity");
 
-    let Some(user_id) = state
-        .write()
-        .unwrap()
-        .server
-        .read()
-        .unwrap()
-        .auth()
-        .write()
-        .unwrap()
-        .validate_token(&identity.token)
-    else {
+    let Some(user_id) = state.auth().read().validate_token(&identity.token) else {
         tracing::error!("failed to validate gateway client's identity token");
+
+        let frame = ws::CloseFrame {
+            code: UNAUTHORIZED_CLOSE_CODE,
+            reason: "invalid token".into(),
+        };
+        if let Err(err) = socket.send(ws::Message::Close(Some(frame))).await {
+            tracing::error!(%err, "failed to close gateway websocket");
+        }
+
         return;
     };
 
@@ -150,46 +383,108 @@ async fn handle_socket(
         client_agent = ?identity.client_agent,
         "successfully authenticated gateway client token");
 
-    // Create the client connection session.
-    let session = state
-        .write()
-        .unwrap()
-        .server
-        .read()
-        .unwrap()
-        .gateway()
-        .write()
-        .unwrap()
-        .create_session(user_id, identity.clone());
+    // Resume the client's previous session if it asked to and the session can
+    // still be resumed, otherwise the client gets a new session and has to
+    // refresh it's state as if it had identified for the first time.
+    let resumed = match identity.resume_session_id {
+        0 => None,
+        id => state.gateway().write().resume_session(
+            user_id,
+            gateway::SessionId(id),
+            identity.resume_seq,
+        ),
+    };
+
+    let (session, replay, events) = match resumed {
+        Some(resumed) => (resumed.session, Some(resumed.replay), resumed.events),
+        None => {
+            let created = state
+                .gateway()
+                .write()
+                .create_session(user_id, identity.clone());
+
+            let session = match created {
+                Ok(session) => session,
+                Err(err) => {
+                    tracing::warn!(?err, "refusing gateway client over the session limit");
+
+                    let frame = session_limit_close_frame("too many concurrent sessions");
+                    if let Err(err) = socket.send(ws::Message::Close(Some(frame))).await {
+                        tracing::error!(%err, "failed to close gateway websocket");
+                    }
+
+                    return;
+                }
+            };
+
+            let events = session.read().subscribe();
+
+            (session, None, events)
+        }
+    };
+
+    // Tell the client which session it's attached to, followed by any missed events.
+    let ready = v0::GatewayServerEvent {
+        event: Some(v0::gateway_server_event::Event::SessionReady(
+            v0::SessionReady {
+                session_id: session.read().session_id().0,
+                resumed: replay.is_some(),
+            },
+        )),
+        seq: 0,
+    };
+    let backlog: Vec<_> = std::iter::once(ready)
+        .chain(replay.into_iter().flatten())
+        .collect();
 
     tracing::info!(
         encoding_test = ?encoding,
         who = ?who,
         client_agent = ?identity.client_agent,
-        session_id = ?session.read().unwrap().session_id(),
+        session_id = ?session.read().session_id(),
         "created gateway session for authenticated client");
 
     tracing::info!(
         encoding_test = ?encoding,
         who = ?who,
         client_agent = ?identity.client_agent,
-        session_id = ?session.read().unwrap().session_id(),
+        session_id = ?session.read().session_id(),
         "starting gateway send and receive tasks");
 
     // Split the socket into a sender and receiver so that we
     // can process events in both directions simultaniously.
     let (sender, receiver) = socket.split();
 
+    // Used by the receive task to have the send task close the
+    // connection, since only the sending half can send a close frame.
+    let (close_sender, close_receiver) = oneshot::channel();
+
     // Spawn the task to handle sending messages to the client.
     //
     // This is used to inform the client of events, such as new
     // messages message edits, reactions, etc. and notifications.
-    let mut send_task = tokio::spawn(task_send(sender, Arc::clone(&session), encoding));
+    let mut send_task = tokio::spawn(task_send(
+        sender,
+        Arc::clone(&session),
+        version,
+        encoding,
+        close_receiver,
+        backlog,
+        events,
+    ));
 
     // Spawn the task to handle receiving messages from the client.
     //
     // This is used by the client to send new messages and user events (i.e. status messages).
-    let mut receive_task = tokio::spawn(task_receive(receiver, Arc::clone(&session), encoding));
+    let mut receive_task = tokio::spawn(task_receive(
+        receiver,
+        Arc::clone(&session),
+        Arc::clone(&state),
+        version,
+        encoding,
+        max_message_bytes,
+        close_sender,
+    ));
 
     // If any one of the tasks exit, abort the other.
     tokio::select! {
@@ -205,56 +500,113 @@ async fn handle_socket(
                 tracing::error!(%err, "unexpected panic receiving gateway messages from client")
             };
 
-            send_task.abort();
+            // The send task exits on it's own once the receive task is done, after
+            // sending any close frame it was asked to, so give it a moment to do so.
+            if tokio::time::timeout(SEND_TASK_CLOSE_TIMEOUT, &mut send_task).await.is_err() {
+                send_task.abort();
+            }
         }
     }
 
     // If we hit this point then the WebSocket
     // tasks exited and we need to do cleanup.
 
+    // Keep the session around for a while so the client can resume it.
+    let session_id = session.read().session_id();
+    state.gateway().write().disconnect_session(session_id);
+
+    // Voice needs a live connection, so the session's device leaves its
+    // voice channels now rather than when the session expires.
+    leave_voice_channels(&state, &session);
+
+    // Remember when the user was last online, so it survives restarts.
+    let user_id = session.read().user_id();
+    if let Err(err) = state
+        .presence()
+        .read()
+        .record_seen(user_id, Utc::now().timestamp())
+    {
+        tracing::error!(%err, "failed to record when the user was last seen");
+    }
+
     tracing::info!(who = ?who,
         client_agent = ?identity.client_agent,
-        session_id = ?session.read().unwrap().session_id(),
+        session_id = ?session_id,
         "gateway websocket connection closed");
 }
 
+/// Indicates the gateway handshake couldn't be sent to the client.
+#[derive(Debug)]
+enum HandshakeError {
+    /// Indicates the handshake couldn't be encoded.
+    Encode(prost::EncodeError),
+    /// Indicates the websocket failed while sending the handshake.
+    Send(axum::Error),
+}
+
 /// Sends a handshake message from the gateway server to the connected client.
 ///
 /// This informs the client of the server's version and capabilities.
-async fn send_handshake_message(socket: &mut WebSocket, encoding: &Encoding) {
-    // Build the gateway handshake.
-    let handshake = v0::GatewayHandshake {
-        version: "0.0.0".into(),
-    };
-
-    // Encode the gateway handshake.
-    let handshake_message = match encoding {
-        Encoding::Protobuf => {
-            let mut handshake_buf = Vec::new();
-            handshake_buf.reserve(handshake.encoded_len());
-            handshake.encode(&mut handshake_buf).unwrap();
-
-            ws::Message::Binary(handshake_buf.into())
-        }
-        Encoding::Json => {
-            let j = serde_json::to_string(&handshake).unwrap();
-
-            ws::Message::Text(j.into())
-        }
-    };
+async fn send_handshake_message(
+    socket: &mut WebSocket,
+    version: GatewayVersion,
+    encoding: &Encoding,
+    capabilities: v0::GatewayCapabilities,
+) -> Result<(), HandshakeError> {
+    // Build and encode the gateway handshake for the negotiated version.
+    let handshake_message = match version {
+        GatewayVersion::V0 => encode_message(
+            &v0::GatewayHandshake {
+                version: "0.0.0".into(),
+                capabilities: Some(capabilities),
+            },
+            encoding,
+        ),
+        GatewayVersion::V1 => encode_message(
+            &v1::GatewayHandshake {
+                version: env!("CARGO_PKG_VERSION").into(),
+                supported_versions: GatewayVersion::SUPPORTED
+                    .iter()
+                    .map(|v| v.as_str().to_string())
+                    .collect(),
+                selected_version: version.as_str().into(),
+                capabilities: Some(capabilities.into()),
+            },
+            encoding,
+        ),
+    }
+    .map_err(HandshakeError::Encode)?;
 
     // First, send a handshake to the client.
     socket
         .send(handshake_message)
         .instrument(info_span!("socket_send"))
         .await
-        .unwrap();
+        .map_err(HandshakeError::Send)
+}
+
+/// Builds the server capabilities advertised in the gateway handshake.
+fn gateway_capabilities(config: &Config) -> v0::GatewayCapabilities {
+    v0::GatewayCapabilities {
+        encodings: Encoding::ALL
+            .iter()
+            .map(|e| e.as_str().to_string())
+            .collect(),
+        compression: GATEWAY_COMPRESSION.iter().map(|c| c.to_string()).collect(),
+        heartbeat_interval_ms: config.heartbeat_interval_ms,
+        max_message_bytes: config.max_gateway_message_bytes as u64,
+        intents: GATEWAY_INTENTS.iter().map(|i| i.to_string()).collect(),
+    }
 }
 
 /// Waits until it receives a valid identity message from the connected client.
 ///
 /// This informs the server of the client's capabilities and identity.
-async fn receive_identity_message(socket: &mut WebSocket) -> Option<v0::GatewayIdentify> {
+async fn receive_identity_message(
+    socket: &mut WebSocket,
+    version: GatewayVersion,
+    max_message_bytes: usize,
+) -> Option<v0::GatewayIdentify> {
     // Wait for the client to identify it's self.
     loop {
         // Wait for the next message from the client.
@@ -272,165 +624,635 @@ async fn receive_identity_message(socket: &mut WebSocket) -> Option<v0::GatewayI
             }
         };
 
-        // Decode the identity message sent from the client to the websocket.
-        let ident_message = match message {
-            ws::Message::Text(text) => {
-                // Convert the message to a serde_json::Value.
-                let value: Value = axum::Json::from_bytes(text.as_bytes()).unwrap().0;
-
-                // Now that we know the received json struct is valid, actually decode it.
-                match serde_path_to_error::deserialize(value) {
-                    Ok(v) => v,
-                    Err(error) => {
-                        tracing::error!(%error,
-                            "failed to decode client identity message as JSON"
-                        );
-
-                        continue;
-                    }
-                }
+        // Refuse to decode messages over the size limit.
+        if message_len(&message) > max_message_bytes {
+            tracing::warn!("client identity message exceeded the size limit");
+
+            let close = ws::Message::Close(Some(too_large_close_frame(max_message_bytes)));
+            if let Err(err) = socket.send(close).await {
+                tracing::error!(%err, "failed to close gateway websocket");
             }
-            ws::Message::Binary(bytes) => match v0::GatewayIdentify::decode(bytes) {
-                Ok(msg) => msg,
-                Err(err) => {
-                    tracing::error!(%err, "error reciving client identify message");
-                    continue;
-                }
-            },
-            _ => continue,
+
+            return None;
+        }
+
+        // Decode the identity message sent from the client to the websocket.
+        let ident_message = match version {
+            GatewayVersion::V0 => decode_message::<v0::GatewayIdentify>(message),
+            GatewayVersion::V1 => decode_message::<v1::GatewayIdentify>(message).map(Into::into),
+        };
+
+        let Some(ident_message) = ident_message else {
+            tracing::error!("failed to decode client identity message");
+            continue;
         };
 
         return Some(ident_message);
     }
 }
 
+/// Indicates a client's identify message decoded but isn't valid.
+#[derive(Debug)]
+enum IdentifyError {
+    /// Indicates the token was empty.
+    EmptyToken,
+    /// Indicates the client agent doesn't start with a `product/version` token.
+    InvalidClientAgent,
+    /// Indicates the client asked for an intent the gateway doesn't support.
+    UnsupportedIntent,
+}
+
+/// Validates the fields of a client's identify message.
+///
+/// The token itself is checked afterwards, so that an unauthorized
+/// client can be told apart from one that sent a malformed identify.
+fn validate_identify(identity: &v0::GatewayIdentify) -> Result<(), IdentifyError> {
+    if identity.token.trim().is_empty() {
+        return Err(IdentifyError::EmptyToken);
+    }
+
+    if !is_valid_client_agent(&identity.client_agent) {
+        return Err(IdentifyError::InvalidClientAgent);
+    }
+
+    if let Some(intent) = identity
+        .intents
+        .iter()
+        .find(|intent| !GATEWAY_INTENTS.contains(&intent.as_str()))
+    {
+        tracing::debug!(%intent, "gateway client asked for an unsupported intent");
+        return Err(IdentifyError::UnsupportedIntent);
+    }
+
+    Ok(())
+}
+
+/// Checks that a client agent starts with a `product/version` token,
+/// like `bonfire-client/0.1.0`, optionally followed by comments.
+fn is_valid_client_agent(client_agent: &str) -> bool {
+    if client_agent.len() > MAX_CLIENT_AGENT_BYTES {
+        return false;
+    }
+
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '+'))
+    };
+
+    let product = client_agent.split_whitespace().next().unwrap_or_default();
+    product
+        .split_once('/')
+        .is_some_and(|(name, version)| is_token(name) && is_token(version))
+}
+
+/// Builds the frame used to close connections that sent an invalid identify.
+fn malformed_identify_close_frame(err: &IdentifyError) -> ws::CloseFrame {
+    let reason = match err {
+        IdentifyError::EmptyToken => "token is required",
+        IdentifyError::InvalidClientAgent => "client agent must start with a product/version token",
+        // The intent isn't echoed back, since close reasons are limited to 123 bytes.
+        IdentifyError::UnsupportedIntent => "unsupported intent",
+    };
+
+    ws::CloseFrame {
+        code: MALFORMED_IDENTIFY_CLOSE_CODE,
+        reason: reason.into(),
+    }
+}
+
+/// Returns the size of a WebSocket message's payload in bytes.
+fn message_len(message: &ws::Message) -> usize {
+    match message {
+        ws::Message::Text(text) => text.len(),
+        ws::Message::Binary(bytes) => bytes.len(),
+        _ => 0,
+    }
+}
+
+/// Builds the frame used to close connections that sent an oversized message.
+fn too_large_close_frame(max_message_bytes: usize) -> ws::CloseFrame {
+    ws::CloseFrame {
+        code: ws::close_code::SIZE,
+        reason: format!("message exceeded the {max_message_bytes} byte limit").into(),
+    }
+}
+
+/// Closes a connection that didn't identify in time.
+///
+/// The close is itself bounded, since a client that stopped
+/// reading may never accept the close frame.
+async fn close_timed_out(mut socket: WebSocket) {
+    let frame = ws::CloseFrame {
+        code: IDENTIFY_TIMEOUT_CLOSE_CODE,
+        reason: "timed out waiting for identify".into(),
+    };
+
+    let close = socket.send(ws::Message::Close(Some(frame)));
+    if let Ok(Err(err)) = tokio::time::timeout(TIMEOUT_CLOSE_TIMEOUT, close).await {
+        tracing::error!(%err, "failed to close gateway websocket");
+    }
+}
+
+/// Builds the frame used to close connections over the session limits.
+fn session_limit_close_frame(reason: &'static str) -> ws::CloseFrame {
+    ws::CloseFrame {
+        code: SESSION_LIMIT_CLOSE_CODE,
+        reason: reason.into(),
+    }
+}
+
+/// Encodes a gateway message for sending to the client.
+///
+/// Messages are encoded to binary Protobuf or JSON text as specified by the encoding.
+fn encode_message<M: Message + Serialize>(
+    message: &M,
+    encoding: &Encoding,
+) -> Result<ws::Message, prost::EncodeError> {
+    match encoding {
+        Encoding::Protobuf => {
+            let mut buf = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut buf)?;
+
+            Ok(ws::Message::Binary(buf.into()))
+        }
+        Encoding::Json => {
+            let j = serde_json::to_string(message).unwrap();
+
+            Ok(ws::Message::Text(j.into()))
+        }
+    }
+}
+
+/// Decodes a gateway message received from the client.
+///
+/// If the WebSocket message is binary then the message is decoded
+/// as Protobuf, if it's text then it'll be decoded as JSON. Returns
+/// `None` for undecodable messages and non-data WebSocket messages.
+fn decode_message<M: Message + Default + DeserializeOwned>(message: ws::Message) -> Option<M> {
+    match message {
+        ws::Message::Text(text) => {
+            // Convert the message to a serde_json::Value.
+            let value: Value = match axum::Json::from_bytes(text.as_bytes()) {
+                Ok(value) => value.0,
+                Err(err) => {
+                    tracing::error!(%err, "failed to parse gateway message as JSON");
+                    return None;
+                }
+            };
+
+            // Now that we know the received json struct is valid, actually decode it.
+            match serde_path_to_error::deserialize(value) {
+                Ok(v) => Some(v),
+                Err(error) => {
+                    tracing::error!(%error, "failed to decode gateway message as JSON");
+                    None
+                }
+            }
+        }
+        ws::Message::Binary(bytes) => match M::decode(bytes) {
+            Ok(msg) => Some(msg),
+            Err(err) => {
+                tracing::error!(%err, "failed to decode gateway message as Protobuf");
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
 /// Task used to handle the sending gateway messages from the session to the client.
 async fn task_send(
     mut sender: SplitSink<WebSocket, ws::Message>,
     session: Arc<RwLock<gateway::Session>>,
+    version: GatewayVersion,
     encoding: Encoding,
+    mut close_receiver: oneshot::Receiver<ws::CloseFrame>,
+    backlog: Vec<v0::GatewayServerEvent>,
+    mut sub: broadcast::Receiver<v0::GatewayServerEvent>,
 ) {
-    // Get a receiver for server-generated gateway events for the session.
-    let mut sub = session.read().unwrap().subscribe();
+    // Server-wide and channel events are dispatched to the session's own
+    // events, so `sub` is the only receiver the session needs.
+
+    // Notified if the session is closed to make room for a newer one.
+    let evicted = session.read().evicted();
+
+    // Send the session ready event and any replayed events first.
+    for event in backlog {
+        if !send_server_event(&mut sender, version, &encoding, event).await {
+            return;
+        }
+    }
 
     loop {
         // Wait for the next session event generated by the server
-        // that needs to be forwarded to the client session.
-        let event: GatewayServerEvent = match sub
-            .recv()
-            .instrument(info_span!("gateway_socket_wait_server_event"))
-            .await
-        {
+        // that needs to be forwarded to the client session, or for
+        // the receive task to ask for the connection to be closed.
+        let recv = tokio::select! {
+            close = &mut close_receiver => {
+                // The receive task exited without asking for a close frame.
+                let Ok(frame) = close else {
+                    break;
+                };
+
+                if let Err(err) = sender.send(ws::Message::Close(Some(frame))).await {
+                    tracing::error!(%err, "failed to close gateway websocket");
+                }
+
+                break;
+            }
+            _ = evicted.notified() => {
+                let frame = session_limit_close_frame("session replaced by a newer session");
+                if let Err(err) = sender.send(ws::Message::Close(Some(frame))).await {
+                    tracing::error!(%err, "failed to close gateway websocket");
+                }
+
+                break;
+            }
+            recv = sub
+                .recv()
+                .instrument(info_span!("gateway_socket_wait_server_event")) => recv,
+        };
+
+        let event: v0::GatewayServerEvent = match recv {
             Ok(event) => event,
+            Err(RecvError::Lagged(dropped)) => {
+                // The dropped events were already sequenced, so the client sees
+                // a gap in the sequence numbers and can resume from before it.
+                tracing::warn!(dropped, "gateway client fell behind, events were dropped");
+                continue;
+            }
             Err(err) => {
                 tracing::error!(%err, "failed to receive gateway event from server");
                 continue;
             }
         };
 
-        // Encode the event as specified by the encoding
-        // query parameter and send it to the client.
-        match encoding {
-            Encoding::Protobuf => {
-                // Encode the event to Protobuf.
-                let mut buf = Vec::new();
-                buf.reserve(event.encoded_len());
-                if let Err(err) = event.encode(&mut buf) {
-                    tracing::error!(%err, "failed to encode gateway server event to protobuf");
-                    break;
-                };
+        if !send_server_event(&mut sender, version, &encoding, event).await {
+            break;
+        }
+    }
 
-                // Send the encoded Protobuf bytes as a binary message.
-                sender.send(ws::Message::Binary(buf.into()))
-            }
-            Encoding::Json => {
-                // Encode the event as a JSON text message.
-                let j = serde_json::to_string(&event).unwrap();
-                sender.send(ws::Message::Text(j.into()))
-            }
+    tracing::info!(session_id = ?session.read().session_id(), "gateway to client socket closed");
+}
+
+/// Encodes a server event as specified by the negotiated version
+/// and the encoding query parameter.
+fn encode_server_event(
+    version: GatewayVersion,
+    encoding: &Encoding,
+    event: v0::GatewayServerEvent,
+) -> Result<ws::Message, prost::EncodeError> {
+    match version {
+        GatewayVersion::V0 => encode_message(&event, encoding),
+        GatewayVersion::V1 => encode_message(&v1::GatewayServerEvent::from(event), encoding),
+    }
+}
+
+/// Encodes a server event for the negotiated version and sends it to the client.
+///
+/// Returns false if the event couldn't be sent and the connection should be closed.
+async fn send_server_event(
+    sender: &mut SplitSink<WebSocket, ws::Message>,
+    version: GatewayVersion,
+    encoding: &Encoding,
+    event: v0::GatewayServerEvent,
+) -> bool {
+    let message = match encode_server_event(version, encoding, event) {
+        Ok(message) => message,
+        Err(err) => {
+            tracing::error!(%err, "failed to encode gateway server event to protobuf");
+            return false;
         }
+    };
+
+    if let Err(err) = sender
+        .send(message)
         .instrument(debug_span!("gateway_socket_send"))
         .await
-        .unwrap();
-
-        continue;
+    {
+        tracing::error!(%err, "failed to send gateway server event to client");
+        return false;
     }
 
-    tracing::info!(session_id = ?session.read().unwrap().session_id(), "gateway to client socket closed");
+    true
 }
 
-/// Task used to handle ingesting gateway messages from the client.
-async fn task_receive(
-    mut receiver: SplitStream<WebSocket>,
-    session: Arc<RwLock<gateway::Session>>,
-    _encoding: Encoding,
+/// Marks the session as alive and acknowledges the client's heartbeat.
+fn acknowledge_heartbeat(session: &RwLock<gateway::Session>, heartbeat: &v0::Heartbeat) {
+    let mut session = session.write();
+    session.contacted();
+    session.send_event(v0::GatewayServerEvent {
+        event: Some(v0::gateway_server_event::Event::HeartbeatAck(
+            v0::HeartbeatAck { seq: heartbeat.seq },
+        )),
+        seq: 0,
+    });
+}
+
+/// Joins or leaves a voice channel as one of the session user's devices.
+fn join_voice_channel(
+    state: &super::SharedState,
+    session: &Arc<RwLock<gateway::Session>>,
+    join: &v0::VoiceJoin,
 ) {
-    // Get a channel sender for ingesting received client events to the server.
-    let sender = session.read().unwrap().client_event_sender();
+    let channel_id = ChannelId(join.channel_id);
+    let Some(channel) = state.voice_channel(channel_id) else {
+        tracing::debug!(%channel_id, "ignoring voice join for unknown channel");
+        return;
+    };
 
-    loop {
-        // Wait to receive the next message.
-        //
-        // This returns 'None' in the event the
-        // receiver channel has been closed.
-        let Some(recv) = receiver
-            .next()
-            .instrument(info_span!("gateway_socket_recv"))
-            .await
-        else {
-            break;
-        };
+    let mut session = session.write();
+    let user = session.user_id();
 
-        // Check if the event receive was valid.
-        let Ok(message) = recv else {
-            tracing::error!("failed to receive gateway event from client");
-            continue;
-        };
+    // Each session is counted as one device, however many times it asks to join.
+    if join.join {
+        if session.join_voice(channel_id) {
+            channel.join(user);
+        }
+    } else if session.leave_voice(channel_id) {
+        channel.leave(user);
+    }
+}
+
+/// Removes the session's device from every voice channel it joined.
+fn leave_voice_channels(state: &super::SharedState, session: &Arc<RwLock<gateway::Session>>) {
+    let (user, channel_ids) = {
+        let mut session = session.write();
+        (session.user_id(), session.take_voice_channels())
+    };
+
+    for channel_id in channel_ids {
+        if let Some(channel) = state.voice_channel(channel_id) {
+            channel.leave(user);
+        }
+    }
+}
+
+/// Mutes or deafens the session's user in a voice channel at their request.
+fn update_voice_state(state: &super::SharedState, user: UserId, update: &v0::VoiceStateUpdate) {
+    let Some(channel) = state.voice_channel(ChannelId(update.channel_id)) else {
+        tracing::debug!(
+            channel_id = update.channel_id,
+            "ignoring voice state update for unknown channel"
+        );
+        return;
+    };
+
+    if let Err(err) = channel.set_self_state(user, update.self_mute, update.self_deaf) {
+        tracing::debug!(?err, "ignoring voice state update");
+    }
+}
+
+/// Starts or stops dispatching a channel's events to the session.
+///
+/// Only text channels the user can read, voice channels, and
+/// direct channels the user participates in, can be watched.
+fn watch_channel(
+    state: &super::SharedState,
+    session: &Arc<RwLock<gateway::Session>>,
+    watch: &v0::WatchChannel,
+) {
+    let (id, user) = {
+        let session = session.read();
+        (session.session_id(), session.user_id())
+    };
+    let channel_id = ChannelId(watch.channel_id);
+
+    if !watch.watch {
+        state.gateway().read().unwatch_channel(id, channel_id);
+        return;
+    }
+
+    let readable = match state.text_channel(channel_id) {
+        Some(channel) => state.can_read(user, &channel),
+        None => {
+            state.voice_channel(channel_id).is_some()
+                || state.direct_channel(user, channel_id).is_ok()
+        }
+    };
+    if !readable {
+        tracing::debug!(%channel_id, "ignoring watch for unknown or unreadable channel");
+        return;
+    }
+
+    state.gateway().read().watch_channel(id, channel_id);
+}
+
+/// Creates a message sent by a client, and acknowledges it to the session.
+///
+/// The message is posted to a text channel the user can read, or
+/// to a direct channel if the user is one of it's participants.
+async fn create_message(
+    state: super::SharedState,
+    session: Arc<RwLock<gateway::Session>>,
+    user: UserId,
+    create: v0::CreateMessage,
+) {
+    // The client's send time is only used to measure latency, the
+    // message is stamped with the server's time by the channel worker.
+    //
+    // Clients with clocks ahead of the server's would report
+    // negative latency, so those samples are skipped.
+    if let Some(sent_at_ms) = create.client_sent_at_ms {
+        let received_at_ms = Utc::now().timestamp_millis() as u64;
+        if let Some(latency_ms) = received_at_ms.checked_sub(sent_at_ms) {
+            metrics()
+                .message_client_latency_seconds
+                .observe(latency_ms as f64 / 1000.0);
+        }
+    }
+
+    let channel_id = ChannelId(create.channel_id);
+    // Channels the user can't read are reported as not found,
+    // so their existence isn't revealed.
+    let channel = state
+        .text_channel(channel_id)
+        .or_else(|| {
+            state
+                .direct_channel(user, channel_id)
+                .ok()
+                .map(|direct| direct.channel())
+        })
+        .filter(|channel| state.can_read(user, channel));
+
+    let result = match channel {
+        Some(channel) => {
+            let message = TextChannelMessage {
+                id: MessageId::default(),
+                author: user,
+                author_name: None,
+                timestamp_ms: Utc::now().timestamp_millis() as u64,
+                edited_at: None,
+                content: create.content,
+                reply_to: (create.reply_to != 0).then_some(MessageId(create.reply_to)),
+                mentions: MessageMentions::default(),
+                flags: MessageFlags(create.flags),
+            };
+
+            let nonce = Some(create.nonce.as_str()).filter(|nonce| !nonce.is_empty());
+            let permissions = state.permissions().read().permissions(user);
+
+            channel
+                .create_message(message, nonce, permissions)
+                .await
+                .map_err(|err| {
+                    tracing::debug!(?err, "rejected message from gateway client");
+                    create_message_error_reason(&err)
+                })
+        }
+        None => Err("channel not found"),
+    };
+
+    let (message_id, error) = match result {
+        Ok(message) => (message.id.0, String::new()),
+        Err(reason) => (0, reason.to_string()),
+    };
+
+    session.write().send_event(v0::GatewayServerEvent {
+        event: Some(v0::gateway_server_event::Event::MessageAck(
+            v0::MessageAck {
+                channel_id: create.channel_id,
+                message_id,
+                nonce: create.nonce,
+                error,
+            },
+        )),
+        seq: 0,
+    });
+}
+
+/// Describes why a message couldn't be created, for acknowledging it to the client.
+fn create_message_error_reason(err: &CreateMessageError) -> &'static str {
+    match err {
+        CreateMessageError::InvalidContent(ContentError::Empty) => "message content is empty",
+        CreateMessageError::InvalidContent(ContentError::TooLong { .. }) => {
+            "message content is too long"
+        }
+        CreateMessageError::InvalidContent(ContentError::TooLarge { .. }) => {
+            "message content is too large"
+        }
+        CreateMessageError::InvalidReply(ReplyError::ReferenceNotFound(_)) => {
+            "replied to message does not exist"
+        }
+        CreateMessageError::InvalidNonce => "nonce is empty or too long",
+        CreateMessageError::InvalidFlags(_) => "flags can't be set",
+        CreateMessageError::Rejected(reason) => gateway::rejection_reason(reason),
+        CreateMessageError::ChannelBusy => "channel is busy",
+        CreateMessageError::InvalidReply(ReplyError::DatabaseError(_))
+        | CreateMessageError::ChannelClosed => "internal error",
+    }
+}
+
+/// Task used to handle ingesting gateway messages from the client.
+async fn task_receive(
+    mut receiver: SplitStream<WebSocket>,
+    session: Arc<RwLock<gateway::Session>>,
+    state: super::SharedState,
+    version: GatewayVersion,
+    _encoding: Encoding,
+    max_message_bytes: usize,
+    close_sender: oneshot::Sender<ws::CloseFrame>,
+) {
+    // Get a channel sender for ingesting received client events to the server.
+    let sender = session.read().client_event_sender();
+
+    loop {
+        // Wait to receive the next message.
+        //
+        // This returns 'None' in the event the
+        // receiver channel has been closed.
+        let Some(recv) = receiver
+            .next()
+            .instrument(info_span!("gateway_socket_recv"))
+            .await
+        else {
+            break;
+        };
+
+        // Check if the event receive was valid.
+        let Ok(message) = recv else {
+            tracing::error!("failed to receive gateway event from client");
+            continue;
+        };
 
         // If we get a ping message, update the last-seen for the client session.
         if let ws::Message::Ping(_ping) = message {
             // Update the last-seen timestamp for the client session.
-            session.write().unwrap().contacted();
+            session.write().contacted();
+            continue;
+        }
+
+        // Refuse to decode messages over the size limit, and
+        // close the connection since the client is misbehaving.
+        if message_len(&message) > max_message_bytes {
+            tracing::warn!(
+                session_id = ?session.read().session_id(),
+                "client event exceeded the size limit, closing connection"
+            );
+
+            let _ = close_sender.send(too_large_close_frame(max_message_bytes));
             break;
         }
 
         tracing::trace!("gateway received encoded client event");
 
-        // Attempt to decode the client event.
-        //
-        // If the WebSocket message is binary then the message is decoded
-        // as Protobuf, if it's text then it'll be decoded as JSON.
-        let event: v0::GatewayClientEvent = match message {
-            ws::Message::Text(text) => {
-                // Convert the message to a serde_json::Value.
-                let value: Value = axum::Json::from_bytes(text.as_bytes()).unwrap().0;
-
-                // Now that we know the received json struct is valid, actually decode it.
-                match serde_path_to_error::deserialize(value) {
-                    Ok(v) => v,
-                    Err(error) => {
-                        tracing::error!(%error,
-                            "failed to decode client identity message as JSON"
-                        );
-
-                        continue;
-                    }
-                }
-            }
-
-            ws::Message::Binary(bytes) => match v0::GatewayClientEvent::decode(bytes) {
-                Ok(event) => event,
-                Err(err) => {
-                    tracing::error!(%err,
-                        "failed to decode client identity message as Protobuf"
-                    );
+        // Attempt to decode the client event for the negotiated version.
+        let event = match version {
+            GatewayVersion::V0 => decode_message::<v0::GatewayClientEvent>(message),
+            GatewayVersion::V1 => decode_message::<v1::GatewayClientEvent>(message).map(Into::into),
+        };
 
-                    continue;
-                }
-            },
-            _ => continue,
+        let Some(event) = event else {
+            continue;
         };
 
+        // Heartbeats keep the session alive and are acknowledged
+        // through the send task, rather than going to the session worker.
+        if let Some(v0::gateway_client_event::Event::Heartbeat(heartbeat)) = &event.event {
+            acknowledge_heartbeat(&session, heartbeat);
+
+            continue;
+        }
+
+        // Voice state updates are applied to the voice channel directly,
+        // which broadcasts the new state to the channel's subscribers.
+        if let Some(v0::gateway_client_event::Event::VoiceStateUpdate(update)) = &event.event {
+            let user = session.read().user_id();
+            update_voice_state(&state, user, update);
+
+            continue;
+        }
+
+        if let Some(v0::gateway_client_event::Event::VoiceJoin(join)) = &event.event {
+            join_voice_channel(&state, &session, join);
+
+            continue;
+        }
+
+        if let Some(v0::gateway_client_event::Event::WatchChannel(watch)) = &event.event {
+            watch_channel(&state, &session, watch);
+
+            continue;
+        }
+
+        // Messages are created in the background, so waiting on a busy
+        // channel doesn't hold up the client's heartbeats.
+        //
+        // Messages are always attributed to the session's user, whatever the client sent.
+        if let Some(v0::gateway_client_event::Event::CreateMessage(create)) = &event.event {
+            let user = session.read().user_id();
+            tokio::spawn(create_message(
+                state.clone(),
+                Arc::clone(&session),
+                user,
+                create.clone(),
+            ));
+
+            continue;
+        }
+
         tracing::trace!(
             event = ?event.clone(),
             "gateway decoded client event");
@@ -448,5 +1270,822 @@ async fn task_receive(
             "gateway ingested decoded client event to session");
     }
 
-    tracing::info!(session_id = ?session.read().unwrap().session_id(), "client to gateway socket closed");
+    tracing::info!(session_id = ?session.read().session_id(), "client to gateway socket closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::{
+        http::{
+            make_app_router,
+            tests::{json_body, server, server_with},
+        },
+        server::{
+            CorsConfig,
+            channel::{Channel, text::TextChannelSettings},
+            permission::Permissions,
+        },
+        user::UserId,
+    };
+
+    /// Serves the server's router on a local port, returning it's address.
+    async fn serve(state: super::super::SharedState) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let app = make_app_router(state).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        addr
+    }
+
+    /// Sends a WebSocket upgrade request with the extra headers,
+    /// returning the stream and the head of the response in lowercase.
+    async fn upgrade(addr: SocketAddr, uri: &str, headers: &[(&str, &str)]) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut request = format!(
+            "GET {uri} HTTP/1.1\r\n\
+             Host: {addr}\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        // Read a byte at a time, so none of the frames after the head are consumed.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+
+        (stream, String::from_utf8(head).unwrap().to_lowercase())
+    }
+
+    /// Reads the next frame sent by the server, returning it's opcode and payload.
+    async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let opcode = stream.read_u8().await.unwrap() & 0x0f;
+
+        // Frames sent by the server aren't masked, so only the length follows.
+        let len = match stream.read_u8().await.unwrap() & 0x7f {
+            126 => stream.read_u16().await.unwrap() as usize,
+            127 => stream.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        (opcode, payload)
+    }
+
+    /// Sends a frame to the server, masked with a zero key since client frames have to be masked.
+    async fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+        }
+        frame.extend([0; 4]);
+        frame.extend(payload);
+
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    #[test]
+    fn handshake_capabilities_round_trip_in_each_encoding() {
+        let config = Config::builder()
+            .data_dir(std::env::temp_dir())
+            .build()
+            .unwrap();
+        let capabilities = gateway_capabilities(&config);
+        assert_eq!(
+            capabilities.heartbeat_interval_ms,
+            config.heartbeat_interval_ms
+        );
+        assert_eq!(capabilities.encodings.len(), Encoding::ALL.len());
+
+        let v0_handshake = v0::GatewayHandshake {
+            version: "0.0.0".into(),
+            capabilities: Some(capabilities.clone()),
+        };
+        let v1_handshake = v1::GatewayHandshake {
+            version: env!("CARGO_PKG_VERSION").into(),
+            supported_versions: vec!["v1".into()],
+            selected_version: "v1".into(),
+            capabilities: Some(capabilities.into()),
+        };
+
+        for encoding in [Encoding::Json, Encoding::Protobuf] {
+            let encoded = encode_message(&v0_handshake, &encoding).unwrap();
+            let decoded: v0::GatewayHandshake = decode_message(encoded).unwrap();
+            assert_eq!(decoded, v0_handshake);
+
+            let encoded = encode_message(&v1_handshake, &encoding).unwrap();
+            let decoded: v1::GatewayHandshake = decode_message(encoded).unwrap();
+            assert_eq!(decoded, v1_handshake);
+        }
+    }
+
+    #[test]
+    fn over_limit_messages_are_refused_before_decoding() {
+        let limit = 16;
+
+        // The size check runs on the raw frame, so even a well formed
+        // message is refused once it's over the limit.
+        let text = ws::Message::Text("a".repeat(limit + 1).into());
+        let binary = ws::Message::Binary(vec![0; limit + 1].into());
+        assert!(message_len(&text) > limit);
+        assert!(message_len(&binary) > limit);
+
+        let text = ws::Message::Text("a".repeat(limit).into());
+        let binary = ws::Message::Binary(vec![0; limit].into());
+        assert!(message_len(&text) <= limit);
+        assert!(message_len(&binary) <= limit);
+
+        // Control frames aren't counted against the limit.
+        assert_eq!(
+            message_len(&ws::Message::Ping(vec![0; limit + 1].into())),
+            0
+        );
+
+        let frame = too_large_close_frame(limit);
+        assert_eq!(frame.code, ws::close_code::SIZE);
+        assert!(frame.reason.contains("16"));
+    }
+
+    /// A valid identify for a new session authenticated with the token.
+    fn identify(token: &str) -> v0::GatewayIdentify {
+        v0::GatewayIdentify {
+            token: token.to_string(),
+            client_type: v0::gateway_identify::ClientType::Native.into(),
+            client_agent: "bonfire-test/0.1.0".to_string(),
+            resume_session_id: 0,
+            resume_seq: 0,
+            intents: vec![],
+        }
+    }
+
+    fn join(channel_id: ChannelId, join: bool) -> v0::VoiceJoin {
+        v0::VoiceJoin {
+            channel_id: channel_id.0,
+            join,
+        }
+    }
+
+    /// Returns each participant of the voice channel, and their number of devices.
+    fn roster(state: &super::super::SharedState, channel_id: ChannelId) -> Vec<(UserId, usize)> {
+        state
+            .voice_channel(channel_id)
+            .unwrap()
+            .participants()
+            .into_iter()
+            .map(|participant| (participant.user, participant.devices))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn voice_roster_tracks_joined_sessions() {
+        let server = server();
+        let state = &server.state;
+        let channel_id = state
+            .create_voice_channel("lounge".to_string())
+            .unwrap()
+            .channel_id();
+
+        let (phone, laptop, other) = {
+            let gateway = state.gateway();
+            let mut gateway = gateway.write();
+            (
+                gateway
+                    .create_session(UserId(1), Default::default())
+                    .unwrap(),
+                gateway
+                    .create_session(UserId(1), Default::default())
+                    .unwrap(),
+                gateway
+                    .create_session(UserId(2), Default::default())
+                    .unwrap(),
+            )
+        };
+
+        join_voice_channel(state, &phone, &join(channel_id, true));
+        // Joining again from the same session doesn't add another device.
+        join_voice_channel(state, &phone, &join(channel_id, true));
+        join_voice_channel(state, &laptop, &join(channel_id, true));
+        join_voice_channel(state, &other, &join(channel_id, true));
+        assert_eq!(
+            roster(state, channel_id),
+            vec![(UserId(1), 2), (UserId(2), 1)]
+        );
+
+        // The user stays connected until their last device leaves.
+        join_voice_channel(state, &phone, &join(channel_id, false));
+        assert_eq!(
+            roster(state, channel_id),
+            vec![(UserId(1), 1), (UserId(2), 1)]
+        );
+
+        // Disconnecting leaves the session's voice channels.
+        leave_voice_channels(state, &laptop);
+        leave_voice_channels(state, &other);
+        assert!(roster(state, channel_id).is_empty());
+
+        // Sessions that never joined don't remove anyone.
+        join_voice_channel(state, &other, &join(channel_id, true));
+        join_voice_channel(state, &phone, &join(channel_id, false));
+        leave_voice_channels(state, &laptop);
+        assert_eq!(roster(state, channel_id), vec![(UserId(2), 1)]);
+    }
+
+    #[tokio::test]
+    async fn voice_state_changes_reach_watching_sessions() {
+        use v0::gateway_server_event::Event;
+
+        let server = server();
+        let state = &server.state;
+        let channel_id = state
+            .create_voice_channel("lounge".to_string())
+            .unwrap()
+            .channel_id();
+
+        let (speaker, listener) = {
+            let gateway = state.gateway();
+            let mut gateway = gateway.write();
+            (
+                gateway
+                    .create_session(UserId(1), Default::default())
+                    .unwrap(),
+                gateway
+                    .create_session(UserId(2), Default::default())
+                    .unwrap(),
+            )
+        };
+
+        let watch = v0::WatchChannel {
+            channel_id: channel_id.0,
+            watch: true,
+        };
+        watch_channel(state, &listener, &watch);
+        let mut events = listener.read().subscribe();
+
+        join_voice_channel(state, &speaker, &join(channel_id, true));
+        match next_event(&mut events).await {
+            Event::VoiceParticipantJoined(joined) => {
+                assert_eq!(joined.channel_id, channel_id.0);
+                assert_eq!(joined.user, 1);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let update = v0::VoiceStateUpdate {
+            channel_id: channel_id.0,
+            self_mute: true,
+            self_deaf: false,
+        };
+        update_voice_state(state, UserId(1), &update);
+        match next_event(&mut events).await {
+            Event::VoiceStateUpdated(updated) => {
+                assert_eq!(updated.user, 1);
+                assert!(updated.self_mute);
+                assert!(!updated.self_deaf && !updated.server_mute && !updated.server_deaf);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let participants = state.voice_channel(channel_id).unwrap().participants();
+        assert!(participants[0].state.self_mute);
+
+        leave_voice_channels(state, &speaker);
+        match next_event(&mut events).await {
+            Event::VoiceParticipantLeft(left) => assert_eq!(left.user, 1),
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    /// Waits for the next event sent to the session.
+    async fn next_event(
+        events: &mut tokio::sync::broadcast::Receiver<v0::GatewayServerEvent>,
+    ) -> v0::gateway_server_event::Event {
+        tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("timed out waiting for an event")
+            .unwrap()
+            .event
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn heartbeats_are_acknowledged_with_their_seq() {
+        use v0::gateway_client_event::Event as ClientEvent;
+        use v0::gateway_server_event::Event;
+
+        let server = server();
+        let session = server
+            .state
+            .gateway()
+            .write()
+            .create_session(UserId(1), Default::default())
+            .unwrap();
+        let mut events = session.read().subscribe();
+
+        // The heartbeat round-trips through the wire encoding first.
+        let heartbeat = v0::GatewayClientEvent {
+            event: Some(ClientEvent::Heartbeat(v0::Heartbeat { seq: 7 })),
+        };
+        let encoded = encode_message(&heartbeat, &Encoding::Json).unwrap();
+        let decoded: v0::GatewayClientEvent = decode_message(encoded).unwrap();
+        let Some(ClientEvent::Heartbeat(heartbeat)) = decoded.event else {
+            panic!("expected a heartbeat");
+        };
+
+        acknowledge_heartbeat(&session, &heartbeat);
+
+        match next_event(&mut events).await {
+            Event::HeartbeatAck(ack) => assert_eq!(ack.seq, 7),
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_requested_subprotocol_selects_the_encoding() {
+        const TEXT: u8 = 0x1;
+        const BINARY: u8 = 0x2;
+
+        let server = server();
+        let addr = serve(Arc::clone(&server.state)).await;
+
+        // The subprotocol is echoed back, and the handshake is sent in it's encoding.
+        let (mut stream, head) =
+            upgrade(addr, "/gateway", &[("Sec-WebSocket-Protocol", "protobuf")]).await;
+        assert!(head.starts_with("http/1.1 101"), "{head}");
+        assert!(
+            head.contains("sec-websocket-protocol: protobuf\r\n"),
+            "{head}"
+        );
+        let (opcode, payload) = read_frame(&mut stream).await;
+        assert_eq!(opcode, BINARY);
+        assert!(
+            decode_message::<v0::GatewayHandshake>(ws::Message::Binary(payload.into())).is_some()
+        );
+
+        // The subprotocol takes precedence over the query parameter.
+        let (mut stream, head) = upgrade(
+            addr,
+            "/gateway?encoding=protobuf",
+            &[("Sec-WebSocket-Protocol", "json")],
+        )
+        .await;
+        assert!(head.contains("sec-websocket-protocol: json\r\n"), "{head}");
+        assert_eq!(read_frame(&mut stream).await.0, TEXT);
+
+        // Without a subprotocol, the query parameter is used as a fallback.
+        let (mut stream, head) = upgrade(addr, "/gateway?encoding=protobuf", &[]).await;
+        assert!(!head.contains("sec-websocket-protocol"), "{head}");
+        assert_eq!(read_frame(&mut stream).await.0, BINARY);
+    }
+
+    #[test]
+    fn encodings_are_parsed_case_insensitively() {
+        assert!(matches!("json".parse(), Ok(Encoding::Json)));
+        assert!(matches!("JSON".parse(), Ok(Encoding::Json)));
+        assert!(matches!("protobuf".parse(), Ok(Encoding::Protobuf)));
+        assert!(matches!("ProtoBuf".parse(), Ok(Encoding::Protobuf)));
+        assert!(matches!(
+            "xml".parse::<Encoding>(),
+            Err(UnsupportedEncoding(encoding)) if encoding == "xml"
+        ));
+
+        assert!(matches!(
+            serde_json::from_str(r#""json""#),
+            Ok(Encoding::Json)
+        ));
+        assert!(serde_json::from_str::<Encoding>(r#""xml""#).is_err());
+    }
+
+    #[tokio::test]
+    async fn clients_that_dont_identify_are_disconnected() {
+        const CLOSE: u8 = 0x8;
+
+        let server = server_with(|config| config.identify_timeout_ms(100));
+        let addr = serve(Arc::clone(&server.state)).await;
+
+        let (mut stream, head) = upgrade(addr, "/gateway", &[]).await;
+        assert!(head.starts_with("http/1.1 101"), "{head}");
+
+        // The handshake is sent, then the connection is closed once the client stays silent.
+        read_frame(&mut stream).await;
+        let (opcode, payload) =
+            tokio::time::timeout(Duration::from_secs(1), read_frame(&mut stream))
+                .await
+                .expect("connection wasn't closed after the identify timeout");
+        assert_eq!(opcode, CLOSE);
+        assert_eq!(
+            u16::from_be_bytes([payload[0], payload[1]]),
+            IDENTIFY_TIMEOUT_CLOSE_CODE
+        );
+    }
+
+    #[tokio::test]
+    async fn upgrades_from_disallowed_origins_are_refused() {
+        let server = server_with(|config| {
+            config.cors(CorsConfig {
+                allowed_origins: vec!["https://app.example.com".to_string()],
+                ..CorsConfig::default()
+            })
+        });
+        let addr = serve(Arc::clone(&server.state)).await;
+        let status = async |headers: &[(&str, &str)]| {
+            let (_, head) = upgrade(addr, "/gateway", headers).await;
+            head.split(' ').nth(1).unwrap().to_string()
+        };
+
+        assert_eq!(
+            status(&[("Origin", "https://evil.example.com")]).await,
+            "403"
+        );
+        assert_eq!(
+            status(&[("Origin", "https://app.example.com")]).await,
+            "101"
+        );
+        assert_eq!(
+            status(&[("Origin", &format!("http://{addr}"))]).await,
+            "101"
+        );
+
+        // Clients without an origin, or with a token in the header, aren't browsers.
+        assert_eq!(status(&[]).await, "101");
+        let authorization = format!("Bearer {}", server.token(UserId(1)));
+        assert_eq!(
+            status(&[
+                ("Origin", "https://evil.example.com"),
+                ("Authorization", &authorization),
+            ])
+            .await,
+            "101"
+        );
+
+        // The check can be turned off entirely.
+        let unchecked = server_with(|config| config.check_gateway_origin(false));
+        let addr = serve(Arc::clone(&unchecked.state)).await;
+        let (_, head) = upgrade(addr, "/gateway", &[("Origin", "https://evil.example.com")]).await;
+        assert!(head.starts_with("http/1.1 101"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn closing_a_connection_records_when_the_user_was_last_seen() {
+        const BINARY: u8 = 0x2;
+        const CLOSE: u8 = 0x8;
+
+        let server = server();
+        let addr = serve(Arc::clone(&server.state)).await;
+        let user = UserId(1);
+        let token = server.token(user);
+
+        let (mut stream, head) = upgrade(addr, "/gateway?encoding=protobuf", &[]).await;
+        assert!(head.starts_with("http/1.1 101"), "{head}");
+        read_frame(&mut stream).await;
+
+        write_frame(&mut stream, BINARY, &identify(&token).encode_to_vec()).await;
+
+        // The session is ready once the client is told about it.
+        let (_, payload) = read_frame(&mut stream).await;
+        let ready = v0::GatewayServerEvent::decode(payload.as_slice()).unwrap();
+        assert!(matches!(
+            ready.event,
+            Some(v0::gateway_server_event::Event::SessionReady(_))
+        ));
+        assert_eq!(server.state.presence().read().last_seen(user), None);
+
+        let before_s = Utc::now().timestamp();
+        write_frame(&mut stream, CLOSE, &[]).await;
+
+        // The last seen time is recorded once the connection is cleaned up.
+        let last_seen_s = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                if let Some(seen) = server.state.presence().read().last_seen(user) {
+                    break seen;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("last seen wasn't recorded after the connection closed");
+        assert!(last_seen_s >= before_s && last_seen_s <= Utc::now().timestamp());
+
+        // And shown on the user's profile.
+        let response = server
+            .request(Method::GET, "/users/1", Some(&token), None)
+            .await;
+        let profile = json_body(response).await;
+        assert_eq!(profile["last_seen_s"], last_seen_s);
+        assert_eq!(profile["online"], false);
+    }
+
+    #[tokio::test]
+    async fn invalid_identifies_are_closed_with_the_reason() {
+        const BINARY: u8 = 0x2;
+        const CLOSE: u8 = 0x8;
+
+        let server = server();
+        let addr = serve(Arc::clone(&server.state)).await;
+        let token = server.token(UserId(1));
+
+        let cases = [
+            (
+                v0::GatewayIdentify {
+                    token: " ".to_string(),
+                    ..identify(&token)
+                },
+                MALFORMED_IDENTIFY_CLOSE_CODE,
+                "token is required",
+            ),
+            (
+                v0::GatewayIdentify {
+                    client_agent: "bonfire test".to_string(),
+                    ..identify(&token)
+                },
+                MALFORMED_IDENTIFY_CLOSE_CODE,
+                "client agent must start with a product/version token",
+            ),
+            (
+                v0::GatewayIdentify {
+                    intents: vec!["typing".to_string()],
+                    ..identify(&token)
+                },
+                MALFORMED_IDENTIFY_CLOSE_CODE,
+                "unsupported intent",
+            ),
+            // Well formed, but the token isn't valid.
+            (
+                identify("not-a-token"),
+                UNAUTHORIZED_CLOSE_CODE,
+                "invalid token",
+            ),
+        ];
+
+        for (invalid, code, reason) in cases {
+            let (mut stream, head) = upgrade(addr, "/gateway?encoding=protobuf", &[]).await;
+            assert!(head.starts_with("http/1.1 101"), "{head}");
+            read_frame(&mut stream).await;
+
+            write_frame(&mut stream, BINARY, &invalid.encode_to_vec()).await;
+
+            let (opcode, payload) = read_frame(&mut stream).await;
+            assert_eq!(opcode, CLOSE);
+            assert_eq!(u16::from_be_bytes([payload[0], payload[1]]), code);
+            assert_eq!(std::str::from_utf8(&payload[2..]).unwrap(), reason);
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_attributed_to_the_session_user() {
+        use v0::gateway_client_event::Event as ClientEvent;
+        use v0::gateway_server_event::Event;
+
+        let server = server();
+        let state = &server.state;
+        let channel = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+
+        let session = state
+            .gateway()
+            .write()
+            .create_session(UserId(1), Default::default())
+            .unwrap();
+        let mut events = session.read().subscribe();
+
+        // The client claims the message is from another user.
+        let spoofed = serde_json::json!({
+            "event": {
+                "type": "create_message",
+                "channel_id": channel.channel_id().0,
+                "content": "spoofed",
+                "reply_to": 0,
+                "nonce": "",
+                "author": 2,
+            }
+        });
+        let event: v0::GatewayClientEvent =
+            decode_message(ws::Message::Text(spoofed.to_string().into())).unwrap();
+        let Some(ClientEvent::CreateMessage(create)) = event.event else {
+            panic!("unexpected client event {:?}", event.event);
+        };
+
+        let user = session.read().user_id();
+        create_message(Arc::clone(state), Arc::clone(&session), user, create).await;
+        let Event::MessageAck(ack) = next_event(&mut events).await else {
+            panic!("expected the message to be acknowledged");
+        };
+        assert_eq!(ack.error, "");
+
+        let created = channel.message(MessageId(ack.message_id)).unwrap().unwrap();
+        assert_eq!(created.author, UserId(1));
+    }
+
+    #[tokio::test]
+    async fn channels_the_user_cant_read_cant_be_watched_or_posted_to() {
+        use v0::gateway_server_event::Event;
+
+        let server = server();
+        let state = &server.state;
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let channel_id = state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap()
+            .channel_id();
+        let grant = |user| {
+            state
+                .permissions()
+                .read()
+                .set_permissions(user, Permissions::MANAGE_MESSAGES)
+                .unwrap();
+        };
+        grant(UserId(2));
+
+        let (outsider, reader) = {
+            let gateway = state.gateway();
+            let mut gateway = gateway.write();
+            (
+                gateway
+                    .create_session(UserId(1), Default::default())
+                    .unwrap(),
+                gateway
+                    .create_session(UserId(2), Default::default())
+                    .unwrap(),
+            )
+        };
+        let mut outsider_events = outsider.read().subscribe();
+        let mut reader_events = reader.read().subscribe();
+
+        let watch = v0::WatchChannel {
+            channel_id: channel_id.0,
+            watch: true,
+        };
+        watch_channel(state, &outsider, &watch);
+        watch_channel(state, &reader, &watch);
+
+        let create = v0::CreateMessage {
+            channel_id: channel_id.0,
+            content: "hello".to_string(),
+            ..Default::default()
+        };
+        create_message(
+            Arc::clone(state),
+            Arc::clone(&outsider),
+            UserId(1),
+            create.clone(),
+        )
+        .await;
+        match next_event(&mut outsider_events).await {
+            Event::MessageAck(ack) => {
+                assert_eq!(ack.message_id, 0);
+                assert_eq!(ack.error, "channel not found");
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        // Granting the permissions later doesn't start a watch that was refused.
+        grant(UserId(1));
+
+        create_message(Arc::clone(state), Arc::clone(&reader), UserId(2), create).await;
+        loop {
+            match next_event(&mut reader_events).await {
+                Event::MessageCreated(created) if created.content == "hello" => {
+                    assert_eq!(created.author, 2);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        assert!(outsider_events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn created_channels_reach_the_sessions_that_can_see_them() {
+        use v0::gateway_server_event::Event;
+
+        let server = server();
+        let state = &server.state;
+        state
+            .permissions()
+            .read()
+            .set_permissions(UserId(2), Permissions::MANAGE_MESSAGES)
+            .unwrap();
+
+        let (member, moderator) = {
+            let gateway = state.gateway();
+            let mut gateway = gateway.write();
+            (
+                gateway
+                    .create_session(UserId(1), Default::default())
+                    .unwrap(),
+                gateway
+                    .create_session(UserId(2), Default::default())
+                    .unwrap(),
+            )
+        };
+        let mut member_events = member.read().subscribe();
+        let mut moderator_events = moderator.read().subscribe();
+
+        let channel_id = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap()
+            .channel_id();
+        for events in [&mut member_events, &mut moderator_events] {
+            match next_event(events).await {
+                Event::ChannelCreated(created) => {
+                    assert_eq!(created.id, channel_id.0);
+                    assert_eq!(created.label, "general");
+                }
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+
+        // Channels the user can't read aren't announced to them.
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        match next_event(&mut moderator_events).await {
+            Event::ChannelCreated(created) => assert_eq!(created.label, "moderators"),
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        assert!(member_events.try_recv().is_err());
+    }
+
+    #[test]
+    fn attachments_round_trip_in_both_encodings() {
+        let event = gateway::attachment_added(
+            ChannelId(1),
+            MessageId(2),
+            v0::Attachment {
+                id: 3,
+                filename: "ember.png".to_string(),
+                content_type: "image/png".to_string(),
+                size: 0,
+                data: vec![0x00, 0x9f, 0xff, 0x10],
+            },
+        );
+
+        // Encode the event the same way the send task does.
+        let encoded =
+            encode_server_event(GatewayVersion::V0, &Encoding::Protobuf, event.clone()).unwrap();
+        assert!(matches!(&encoded, ws::Message::Binary(_)));
+        assert_eq!(
+            decode_message::<v0::GatewayServerEvent>(encoded),
+            Some(event.clone())
+        );
+
+        // JSON carries the bytes as base64, rather than an array of numbers.
+        let encoded =
+            encode_server_event(GatewayVersion::V0, &Encoding::Json, event.clone()).unwrap();
+        let ws::Message::Text(text) = &encoded else {
+            panic!("JSON events are sent as text");
+        };
+        assert!(
+            text.as_str().contains(r#""data":"AJ//EA==""#),
+            "{}",
+            text.as_str()
+        );
+        assert_eq!(
+            decode_message::<v0::GatewayServerEvent>(encoded),
+            Some(event.clone())
+        );
+
+        // The bytes survive the conversion to the newer protocol version too.
+        let expected = v1::GatewayServerEvent::from(event.clone());
+        for encoding in [Encoding::Protobuf, Encoding::Json] {
+            let encoded =
+                encode_server_event(GatewayVersion::V1, &encoding, event.clone()).unwrap();
+            assert_eq!(
+                decode_message::<v1::GatewayServerEvent>(encoded),
+                Some(expected.clone())
+            );
+        }
+    }
 }
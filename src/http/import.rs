@@ -0,0 +1,93 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::channel::text::{TextChannelMessage, import::ImportError},
+};
+
+/// Response returned after importing messages.
+#[derive(Serialize, JsonSchema)]
+pub struct ImportResponse {
+    /// The number of messages imported.
+    imported: usize,
+    /// Why the import stopped, if it stopped partway through.
+    ///
+    /// The messages after the imported ones weren't imported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Imports a batch of messages into a channel.
+///
+/// The body is NDJSON in the same format produced by the export
+/// endpoint, so a channel export can be imported as-is.
+///
+/// Imported messages keep their authors, IDs, and timestamps,
+/// so only admins can import them.
+pub async fn handle_import(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+    body: String,
+) -> impl IntoResponse {
+    if !state.permissions().read().is_admin(user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Decode each non-blank line as a message.
+    let messages = match body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str::<TextChannelMessage>)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(messages) => messages,
+        Err(err) => {
+            return (StatusCode::BAD_REQUEST, err.to_string()).into_response();
+        }
+    };
+
+    match channel.import(messages).await {
+        Ok(imported) => Json(ImportResponse {
+            imported,
+            error: None,
+        })
+        .into_response(),
+        Err(ImportError::Interrupted { imported, error }) => {
+            tracing::error!(imported, ?error, "import stopped partway through");
+
+            let body = ImportResponse {
+                imported,
+                error: Some(format!("{error:?}")),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+        }
+        Err(
+            err @ (ImportError::EmptyBatch
+            | ImportError::BatchTooLarge(_)
+            | ImportError::TimestampOutOfOrder(_)
+            | ImportError::DuplicateMessageId(_)
+            | ImportError::ContentTooLarge(_)),
+        ) => (StatusCode::BAD_REQUEST, format!("{err:?}")).into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to import messages");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
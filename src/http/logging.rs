@@ -0,0 +1,61 @@
+//! Structured access logging for HTTP requests.
+
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::CookieJar;
+use tracing::{Instrument, Level, info_span};
+
+use crate::http::SharedState;
+
+/// Middleware that emits a structured log event for every HTTP request.
+///
+/// Each request is wrapped in a span carrying the method, path, and
+/// authenticated user (if any), and once the response is produced
+/// an event is logged with the response status and latency.
+pub async fn access_log(
+    State(state): State<SharedState>,
+    jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    // Resolve the log level and the authenticated user before
+    // running the request so no locks are held across awaits.
+    let (level, user_id) = {
+        let user_id = jar
+            .get("token")
+            .and_then(|cookie| state.auth().read().validate_token(cookie.value()));
+
+        (state.config().access_log_level, user_id)
+    };
+
+    let span = info_span!(
+        "http_request",
+        %method,
+        %path,
+        user_id = user_id.map(|id| id.0),
+    );
+
+    let start = Instant::now();
+    let response = next.run(request).instrument(span.clone()).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let status = response.status().as_u16();
+
+    let _entered = span.enter();
+    match level {
+        Level::ERROR => tracing::error!(status, latency_ms, "handled http request"),
+        Level::WARN => tracing::warn!(status, latency_ms, "handled http request"),
+        Level::INFO => tracing::info!(status, latency_ms, "handled http request"),
+        Level::DEBUG => tracing::debug!(status, latency_ms, "handled http request"),
+        _ => tracing::trace!(status, latency_ms, "handled http request"),
+    }
+
+    response
+}
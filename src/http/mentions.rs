@@ -0,0 +1,172 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::channel::Channel,
+};
+
+/// The default number of candidates returned for each kind of mention.
+pub const DEFAULT_MENTION_LIMIT: usize = 10;
+
+/// The maximum number of candidates returned for each kind of mention.
+pub const MAX_MENTION_LIMIT: usize = 25;
+
+/// Query parameters supported by the mention autocomplete endpoint.
+#[derive(Deserialize, JsonSchema)]
+pub struct MentionParams {
+    /// The text typed so far after the mention sigil.
+    #[serde(default)]
+    prefix: String,
+    /// Maximum number of candidates to return for each kind of mention.
+    limit: Option<usize>,
+}
+
+/// A candidate for a mention.
+#[derive(Serialize, JsonSchema)]
+pub struct MentionCandidate {
+    id: String,
+    label: String,
+}
+
+/// Candidates for completing a mention, grouped by what they mention.
+///
+/// Every group is always included, even if it's empty.
+#[derive(Serialize, JsonSchema)]
+pub struct MentionCandidates {
+    /// Always empty for now, as the server doesn't keep a directory of
+    /// users with names to match the prefix against.
+    users: Vec<MentionCandidate>,
+    /// Always empty for now, as the server doesn't keep a directory of roles.
+    roles: Vec<MentionCandidate>,
+    channels: Vec<MentionCandidate>,
+}
+
+/// Suggests mentions starting with a prefix for the mention picker in a channel.
+///
+/// Only the channels the user can read are suggested. Channels the user
+/// can't read are reported as not found, so their existence isn't revealed.
+pub async fn handle_members(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    Query(params): Query<MentionParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    if !state
+        .text_channel(channel_id)
+        .is_some_and(|channel| state.can_read(user_id, &channel))
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_MENTION_LIMIT)
+        .clamp(1, MAX_MENTION_LIMIT);
+
+    let channels = state
+        .text_channels()
+        .iter()
+        .filter(|channel| state.can_read(user_id, channel))
+        .map(|channel| MentionCandidate {
+            id: channel.channel_id().to_string(),
+            label: channel.get_label().to_string(),
+        })
+        .collect();
+
+    Json(MentionCandidates {
+        users: Vec::new(),
+        roles: Vec::new(),
+        channels: rank_by_prefix(channels, &params.prefix, limit),
+    })
+    .into_response()
+}
+
+/// Keeps the candidates whose label starts with the prefix, most relevant first.
+///
+/// Matching ignores case, but candidates matching the prefix exactly or
+/// with the same case are ranked first, followed by shorter labels.
+fn rank_by_prefix(
+    candidates: Vec<MentionCandidate>,
+    prefix: &str,
+    limit: usize,
+) -> Vec<MentionCandidate> {
+    let prefix_lower = prefix.to_lowercase();
+
+    let mut matches: Vec<_> = candidates
+        .into_iter()
+        .filter(|c| c.label.to_lowercase().starts_with(&prefix_lower))
+        .collect();
+
+    matches.sort_by_cached_key(|c| {
+        (
+            c.label != prefix,
+            !c.label.starts_with(prefix),
+            c.label.len(),
+            c.label.to_lowercase(),
+        )
+    });
+    matches.truncate(limit);
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::{
+        http::tests::{json_body, server},
+        server::{channel::text::TextChannelSettings, permission::Permissions},
+        user::UserId,
+    };
+
+    #[tokio::test]
+    async fn only_readable_channels_are_suggested() {
+        let server = server();
+        let general = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let moderators = server
+            .state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        let member = server.token(UserId(1));
+
+        let uri = format!("/channels/{}/members", general.channel_id());
+        let response = server.request(Method::GET, &uri, Some(&member), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        // Users and roles aren't kept by the server yet, but the groups are still returned.
+        assert_eq!(body["users"], serde_json::json!([]));
+        assert_eq!(body["roles"], serde_json::json!([]));
+        let labels: Vec<_> = body["channels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|candidate| candidate["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["general"]);
+
+        let uri = format!("/channels/{}/members", moderators.channel_id());
+        let response = server.request(Method::GET, &uri, Some(&member), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
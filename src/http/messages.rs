@@ -0,0 +1,576 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{
+        SharedState,
+        auth::AuthUser,
+        encoding::{Encoded, Encoding, ProtoEncode},
+    },
+    message::{MessageContent, MessageFlags, MessageId, MessageMentions, decode_message},
+    proto::v0::api,
+    server::{
+        channel::text::{
+            MessageRejection, TextChannel, TextChannelMessage,
+            automod::AutoModReason,
+            content::{ContentError, validate_content},
+            create::CreateMessageError,
+            edit::EditMessageError,
+            reply::ReplyError,
+        },
+        mentions::ResolvedMentions,
+    },
+};
+
+/// The default number of messages returned by a page of history.
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+
+/// The maximum number of messages returned by a page of history.
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+/// Query parameters supported by the message history endpoint.
+#[derive(Deserialize, JsonSchema)]
+pub struct HistoryParams {
+    /// Only return messages sent before this message.
+    before: Option<MessageId>,
+    /// Only return messages sent after this message.
+    after: Option<MessageId>,
+    /// Maximum number of messages to return.
+    limit: Option<usize>,
+}
+
+/// Query parameters supported by the single message endpoint.
+#[derive(Deserialize, JsonSchema)]
+pub struct GetMessageParams {
+    /// Attach the display names of the message's mentions.
+    #[serde(default)]
+    resolve_mentions: bool,
+}
+
+/// Request to replace the content of a message.
+#[derive(Deserialize, JsonSchema)]
+pub struct EditMessageRequest {
+    /// The new text body of the message.
+    content: String,
+    /// Replaces the message's text-to-speech and suppress embeds flags, if set.
+    #[serde(default)]
+    flags: Option<MessageFlags>,
+}
+
+/// Request to preview how the server parses a message's content.
+#[derive(Deserialize, JsonSchema)]
+pub struct PreviewMessageRequest {
+    /// The text body of the message being composed.
+    content: String,
+}
+
+/// How the server would parse a message's content, without sending it.
+#[derive(Serialize, JsonSchema)]
+pub struct MessagePreview {
+    /// The content as it would be stored, after normalization.
+    content: String,
+    /// The content decoded into text and mention blocks.
+    blocks: MessageContent,
+    /// The users, roles, and channels mentioned in the content.
+    mentions: MessageMentions,
+}
+
+/// A message along with the context needed to display it.
+#[derive(Serialize, JsonSchema)]
+pub struct MessageResponse {
+    #[serde(flatten)]
+    message: TextChannelMessage,
+    /// The message this message replies to, if it still exists.
+    referenced_message: Option<TextChannelMessage>,
+    /// Display names of the message's mentions, if they were requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved_mentions: Option<ResolvedMentions>,
+}
+
+impl ProtoEncode for MessageResponse {
+    type Message = api::ChannelMessageWithReference;
+
+    fn to_proto(&self) -> Self::Message {
+        api::ChannelMessageWithReference {
+            message: Some((&self.message).into()),
+            referenced_message: self.referenced_message.as_ref().map(Into::into),
+            resolved_mentions: self.resolved_mentions.as_ref().map(Into::into),
+        }
+    }
+}
+
+/// Fetches a page of a channel's message history, oldest first.
+///
+/// Pages are selected with a `before` or `after` message ID cursor,
+/// and default to the most recent messages in the channel.
+pub async fn handle_list_messages(
+    AuthUser(user_id): AuthUser,
+    encoding: Encoding,
+    Path(channel_id): Path<String>,
+    Query(params): Query<HistoryParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !state.can_read(user_id, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    list_messages(encoding, &channel, params)
+}
+
+/// Fetches a page of a text channel's message history and encodes it as a response.
+///
+/// Shared by the endpoints for the different kinds of text channels.
+pub(crate) fn list_messages(
+    encoding: Encoding,
+    channel: &TextChannel,
+    params: HistoryParams,
+) -> Response {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let messages = match (params.before, params.after) {
+        (Some(_), Some(_)) => return StatusCode::BAD_REQUEST.into_response(),
+        (None, Some(after)) => channel.messages_after(after, limit),
+        (Some(before), None) => channel.messages_before(before, limit),
+        (None, None) => channel.messages_before(MessageId(u64::MAX), limit),
+    };
+
+    match messages {
+        Ok(messages) => Encoded(encoding, messages).into_response(),
+        Err(err) => {
+            tracing::error!(%err, "failed to read message history");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Fetches a single message from a channel.
+///
+/// Replies include the message they reference so clients can show the quoted context.
+///
+/// Clients that don't keep their own directory of users and channels can
+/// ask for the display names of the message's mentions to be attached.
+pub async fn handle_get_message(
+    AuthUser(user_id): AuthUser,
+    encoding: Encoding,
+    Path((channel_id, message_id)): Path<(String, String)>,
+    Query(params): Query<GetMessageParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let (Ok(channel_id), Ok(message_id)) = (
+        channel_id.parse::<ChannelId>(),
+        message_id.parse::<MessageId>(),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !state.can_read(user_id, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let message = match channel.message(message_id) {
+        Ok(Some(message)) => message,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!(%err, "failed to read message");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let referenced_message = match channel.referenced_message(&message) {
+        Ok(referenced) => referenced,
+        Err(err) => {
+            tracing::error!(%err, "failed to read referenced message");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let resolved_mentions = params
+        .resolve_mentions
+        .then(|| state.resolve_mentions(&message.mentions));
+
+    Encoded(
+        encoding,
+        MessageResponse {
+            message,
+            referenced_message,
+            resolved_mentions,
+        },
+    )
+    .into_response()
+}
+
+/// Previews how the server will parse a message, without storing it.
+///
+/// The content is validated and normalized with the server's limits,
+/// so content that would be rejected when sent is rejected here too.
+pub async fn handle_preview_message(
+    AuthUser(_): AuthUser,
+    State(state): State<SharedState>,
+    Json(request): Json<PreviewMessageRequest>,
+) -> Response {
+    let config = state.config();
+    let content = match validate_content(
+        &request.content,
+        config.max_message_graphemes,
+        config.max_message_bytes,
+    ) {
+        Ok(content) => content,
+        Err(err) => return content_error_response(err),
+    };
+
+    let blocks = decode_message(&content);
+    let mentions = blocks.mentions();
+
+    Json(MessagePreview {
+        content,
+        blocks,
+        mentions,
+    })
+    .into_response()
+}
+
+/// Edits the content of a message posted by the authenticated user.
+pub async fn handle_edit_message(
+    AuthUser(user_id): AuthUser,
+    Path((channel_id, message_id)): Path<(String, String)>,
+    State(state): State<SharedState>,
+    Json(request): Json<EditMessageRequest>,
+) -> Response {
+    let (Ok(channel_id), Ok(message_id)) = (
+        channel_id.parse::<ChannelId>(),
+        message_id.parse::<MessageId>(),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match channel
+        .edit_message(message_id, user_id, &request.content, request.flags)
+        .await
+    {
+        Ok(message) => Json(message).into_response(),
+        Err(EditMessageError::InvalidContent(err)) => content_error_response(err),
+        Err(EditMessageError::InvalidFlags(flags)) => invalid_flags_response(flags),
+        Err(EditMessageError::MessageNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(EditMessageError::NotAuthor) => StatusCode::FORBIDDEN.into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to edit message");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Converts an error validating a message's content to a response.
+fn content_error_response(err: ContentError) -> Response {
+    match err {
+        ContentError::Empty => {
+            (StatusCode::BAD_REQUEST, "message content is empty").into_response()
+        }
+        ContentError::TooLong { len, max } => (
+            StatusCode::BAD_REQUEST,
+            format!("message content is {len} characters, the maximum is {max}"),
+        )
+            .into_response(),
+        ContentError::TooLarge { bytes, max } => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("message content is {bytes} bytes, the maximum is {max}"),
+        )
+            .into_response(),
+    }
+}
+
+/// Converts message flags users can't set to a response.
+fn invalid_flags_response(flags: MessageFlags) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        format!(
+            "message flags {} can't be set, only {} are allowed",
+            flags.0,
+            MessageFlags::USER_SETTABLE.0
+        ),
+    )
+        .into_response()
+}
+
+/// Converts an error creating a message to a response.
+pub(crate) fn create_message_error_response(err: CreateMessageError) -> Response {
+    match err {
+        CreateMessageError::InvalidContent(err) => content_error_response(err),
+        CreateMessageError::Rejected(MessageRejection::TooLarge {
+            bytes,
+            max_bytes: max,
+        }) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("message content is {bytes} bytes, the maximum is {max}"),
+        )
+            .into_response(),
+        CreateMessageError::InvalidNonce => {
+            (StatusCode::BAD_REQUEST, "nonce is empty or too long").into_response()
+        }
+        CreateMessageError::InvalidFlags(flags) => invalid_flags_response(flags),
+        CreateMessageError::InvalidReply(ReplyError::ReferenceNotFound(id)) => (
+            StatusCode::BAD_REQUEST,
+            format!("replied to message {id} does not exist in the channel"),
+        )
+            .into_response(),
+        CreateMessageError::InvalidReply(ReplyError::DatabaseError(err)) => {
+            tracing::error!(%err, "failed to validate reply reference");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        CreateMessageError::Rejected(
+            MessageRejection::RateLimited { retry_after_ms }
+            | MessageRejection::SlowMode { retry_after_ms },
+        ) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            // Retry-After is in whole seconds, so round up.
+            [(
+                header::RETRY_AFTER,
+                retry_after_ms.div_ceil(1000).to_string(),
+            )],
+        )
+            .into_response(),
+        CreateMessageError::Rejected(MessageRejection::AutoModerated(reason)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            match reason {
+                AutoModReason::Repetition { repeats, max } => {
+                    format!("message was sent {repeats} times in a row, the maximum is {max}")
+                }
+                AutoModReason::Mentions { mentions, max } => {
+                    format!("message has {mentions} mentions, the maximum is {max}")
+                }
+                AutoModReason::Caps { .. } => "message has too many capital letters".to_string(),
+            },
+        )
+            .into_response(),
+        CreateMessageError::ChannelBusy => {
+            tracing::warn!("rejected message for busy channel");
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+        CreateMessageError::ChannelClosed => {
+            tracing::error!("failed to forward message to closed channel");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{Method, Request},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        http::{
+            encoding::PROTOBUF_CONTENT_TYPE,
+            make_app_router,
+            tests::{json_body, server, server_with},
+        },
+        server::{
+            channel::{
+                Channel,
+                text::{TextChannelSettings, tests::test_message},
+            },
+            permission::Permissions,
+        },
+        user::UserId,
+    };
+
+    #[tokio::test]
+    async fn a_full_channel_queue_is_reported_as_unavailable() {
+        let server = server_with(|config| config.channel_queue_capacity(1));
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let send = |content| {
+            channel.create_message(test_message(UserId(1), content), None, Permissions::NONE)
+        };
+
+        // The worker doesn't run until the test yields, so the first message fills the queue.
+        let mut first = pin!(send("first"));
+        assert!(futures::poll!(first.as_mut()).is_pending());
+
+        // The overflow is reported right away instead of waiting for room.
+        let Err(err) = tokio::time::timeout(Duration::from_secs(1), send("second"))
+            .await
+            .expect("sending to a full queue waited for room")
+        else {
+            panic!("expected the full queue to turn the message away");
+        };
+        assert_eq!(
+            create_message_error_response(err).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        first.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn history_requires_a_user_that_can_read_the_channel() {
+        let server = server();
+        let state = &server.state;
+
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let channel = state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        let message = channel
+            .create_message(test_message(UserId(2), "hello"), None, Permissions::ALL)
+            .await
+            .unwrap();
+        state
+            .permissions()
+            .read()
+            .set_permissions(UserId(2), Permissions::MANAGE_MESSAGES)
+            .unwrap();
+
+        let reader = server.token(UserId(2));
+        let outsider = server.token(UserId(1));
+
+        for uri in [
+            format!("/channels/{}/messages", channel.channel_id()),
+            format!("/channels/{}/messages/{}", channel.channel_id(), message.id),
+        ] {
+            assert_eq!(
+                server.get_status(&uri, None).await,
+                StatusCode::UNAUTHORIZED,
+                "{uri}"
+            );
+            assert_eq!(
+                server.get_status(&uri, Some(&outsider)).await,
+                StatusCode::FORBIDDEN,
+                "{uri}"
+            );
+            assert_eq!(
+                server.get_status(&uri, Some(&reader)).await,
+                StatusCode::OK,
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_fetched_in_the_requested_encoding() {
+        let server = server();
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let message = channel
+            .create_message(test_message(UserId(1), "hello"), None, Permissions::NONE)
+            .await
+            .unwrap();
+        let token = server.token(UserId(1));
+
+        let fetch = |accept: Option<&'static str>| {
+            let mut request = Request::get(format!(
+                "/channels/{}/messages/{}",
+                channel.channel_id(),
+                message.id
+            ))
+            .header(header::AUTHORIZATION, format!("Bearer {token}"));
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+
+            make_app_router(Arc::clone(&server.state)).oneshot(request.body(Body::empty()).unwrap())
+        };
+        let body = |response: Response| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        // JSON is the default.
+        let response = fetch(None).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let json: serde_json::Value = serde_json::from_slice(&body(response).await).unwrap();
+        assert_eq!(json["content"], "hello");
+
+        let response = fetch(Some("application/x-protobuf")).await.unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            PROTOBUF_CONTENT_TYPE
+        );
+        let decoded =
+            <api::ChannelMessageWithReference as prost::Message>::decode(body(response).await)
+                .unwrap();
+        let decoded = decoded.message.unwrap();
+        assert_eq!(decoded.id, message.id.0);
+        assert_eq!(decoded.content, "hello");
+    }
+
+    #[tokio::test]
+    async fn previews_show_the_parsed_blocks_and_mentions() {
+        let server = server();
+        let token = server.token(UserId(1));
+        let content = "hi <@12> and <@12> in <#34> at <t:1700000000> <@&56>";
+        let body = serde_json::json!({ "content": content });
+
+        let response = server
+            .request(Method::POST, "/messages/preview", None, Some(body.clone()))
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = server
+            .request(Method::POST, "/messages/preview", Some(&token), Some(body))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!({
+                "content": content,
+                "blocks": [
+                    { "type": "text", "value": "hi" },
+                    { "type": "user", "value": "12" },
+                    { "type": "text", "value": "and" },
+                    { "type": "user", "value": "12" },
+                    { "type": "text", "value": "in" },
+                    { "type": "channel", "value": "34" },
+                    { "type": "text", "value": "at" },
+                    { "type": "timestamp", "value": 1700000000 },
+                    { "type": "role", "value": "56" },
+                ],
+                // Each target is only listed once.
+                "mentions": {
+                    "users": ["12"],
+                    "roles": ["56"],
+                    "channels": ["34"],
+                },
+            })
+        );
+    }
+}
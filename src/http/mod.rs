@@ -1,44 +1,160 @@
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use axum::{
-    Json, Router,
+    Router,
     extract::State,
-    http::StatusCode,
+    http::header,
+    middleware,
     response::{IntoResponse, Redirect},
-    routing::{any, get, post},
+    routing::{any, delete, get, patch, post, put},
 };
 
-use crate::server::{Server, channel::Channel};
+use crate::server::{Server, metrics::metrics};
 
+pub mod auth;
+pub mod channels;
 pub mod client;
+pub mod cors;
+pub mod direct;
+pub mod encoding;
+pub mod export;
 pub mod gateway;
+pub mod import;
+pub mod logging;
+pub mod mentions;
+pub mod messages;
+pub mod notifications;
 pub mod oauth2;
+pub mod openapi;
+pub mod pins;
+pub mod purge;
+pub mod reindex;
+pub mod search;
+pub mod stats;
+pub mod users;
+pub mod voice;
+pub mod webhook;
 
 /// Provides the shared state for the app router.
-pub struct AppState {
-    /// The application server
-    server: Arc<RwLock<Server>>,
-}
-
-pub type SharedState = Arc<RwLock<AppState>>;
+///
+/// The server synchronizes access to it's own state internally,
+/// so handlers share it without an outer lock.
+pub type SharedState = Arc<Server>;
 
 /// Create the HTTP app router for the server.
-pub fn make_app_router(server: Arc<RwLock<Server>>) -> Router {
-    let state: SharedState = Arc::new(RwLock::new(AppState { server }));
+pub fn make_app_router(server: Arc<Server>) -> Router {
+    let state: SharedState = server;
 
-    Router::new()
+    let cors = cors::cors_layer(&state.config().cors);
+
+    let router = Router::new()
         .route("/", get(handle_web_interface))
-        .route("/channels", get(handle_list_channels))
-        .route("/channels", post(handle_create_channel))
+        .route("/channels", get(channels::handle_list_channels))
+        .route("/channels", post(channels::handle_create_channel))
+        .route("/channels/{id}", get(channels::handle_get_channel))
+        .route("/channels/{id}", patch(channels::handle_update_channel))
+        .route("/channels/order", put(channels::handle_order_channels))
+        .route("/categories", post(channels::handle_create_category))
+        // Download a channel's message history as NDJSON.
+        .route("/channels/{id}/export", get(export::handle_export))
+        // Bulk import messages into a channel.
+        .route("/channels/{id}/import", post(import::handle_import))
+        // Rebuild a channel's search index, for admins.
+        .route("/channels/{id}/reindex", post(reindex::handle_reindex))
+        // Delete the messages sent in a time range, for admins.
+        .route("/channels/{id}/purge", post(purge::handle_delete_range))
+        // The users connected to a voice channel.
+        .route(
+            "/channels/{id}/voice/participants",
+            get(voice::handle_list_participants),
+        )
+        // Mute or deafen a voice channel participant, for moderators.
+        .route(
+            "/channels/{id}/voice/participants/{user_id}",
+            put(voice::handle_set_server_state),
+        )
+        // Suggestions for the mention picker in a channel.
+        .route("/channels/{id}/members", get(mentions::handle_members))
+        // Page through a channel's message history.
+        .route(
+            "/channels/{id}/messages",
+            get(messages::handle_list_messages).delete(purge::handle_delete_by_author),
+        )
+        // Fetch a single message, along with the message it replies to,
+        // or edit a message posted by the user.
+        .route(
+            "/channels/{id}/messages/{message_id}",
+            get(messages::handle_get_message).patch(messages::handle_edit_message),
+        )
+        // Preview how the server parses a message being composed.
+        .route("/messages/preview", post(messages::handle_preview_message))
+        // Mark a channel's messages as read.
+        .route("/channels/{id}/ack", post(channels::handle_ack))
+        // Notification preferences of the authenticated user.
+        .route(
+            "/channels/{id}/settings",
+            put(notifications::handle_set_channel_settings),
+        )
+        .route(
+            "/notifications/settings",
+            put(notifications::handle_set_server_settings),
+        )
+        // Pinned messages in a channel.
+        .route("/channels/{id}/pins", get(pins::handle_list_pins))
+        .route("/channels/{id}/pins/{message_id}", post(pins::handle_pin))
+        .route(
+            "/channels/{id}/pins/{message_id}",
+            delete(pins::handle_unpin),
+        )
+        // Full-text search across every channel the user can read.
+        .route("/search", get(search::handle_search_all))
+        // Full-text search of a channel's messages.
+        .route("/channels/{id}/search", get(search::handle_search))
+        // Create a webhook for posting to a channel.
+        .route("/channels/{id}/webhooks", post(webhook::handle_create))
+        // Post a message to a channel through a webhook.
+        .route("/webhooks/{id}/{token}", post(webhook::handle_execute))
+        // Public profile of a user, including when they were last online.
+        .route("/users/{id}", get(users::handle_get_user))
+        // Private direct channels between users.
+        .route("/users/{id}/dm", post(direct::handle_create))
+        .route("/dms", get(direct::handle_list))
+        .route(
+            "/dms/{id}/messages",
+            get(direct::handle_list_messages).post(direct::handle_post_message),
+        )
+        .route("/dms/{id}/search", get(direct::handle_search))
+        // Prometheus metrics for operators.
+        .route("/metrics", get(handle_metrics))
+        // Snapshot of the server's activity for admins.
+        .route("/stats", get(stats::handle_stats))
         // Inject the web client router at the `/client` path.
-        .nest_service("/client", client::make_client_router())
+        .nest_service("/client", client::make_client_router(state.clone()))
+        // Login methods for clients that don't use the login page.
+        .route("/auth/providers", get(oauth2::handle_providers))
+        // Replace the session token with one that expires later.
+        .route("/auth/refresh", post(auth::handle_refresh))
+        // Clear the session cookie and revoke the token.
+        .route("/auth/logout", post(auth::handle_logout))
         // Redirect URL to a provider's authorization endpoint.
         .route("/oauth/{provider}", any(oauth2::handle_redirect))
         // Callback from a user successfully authenticating with a provider.
         .route("/oauth/{provider}/callback", any(oauth2::handle_callback))
         // Gateway websocket used for server to client communications.
-        .route("/gateway", post(gateway::ws_handler))
-        .with_state(state)
+        .route("/gateway", any(gateway::ws_handler))
+        // Emit a structured access log entry for each request.
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            logging::access_log,
+        ))
+        .with_state(state);
+
+    // Without any allowed origins, no CORS headers are sent,
+    // so browsers only allow same-origin requests.
+    match cors {
+        Some(cors) => router.layer(cors),
+        None => router,
+    }
 }
 
 /// Redirect users that hit the root in a browser to the client endpoint.
@@ -46,32 +162,147 @@ pub(crate) async fn handle_web_interface() -> impl IntoResponse {
     Redirect::temporary("/client")
 }
 
-/// Retrieves a list of all channels available on the server.
-async fn handle_list_channels(State(state): State<SharedState>) -> impl IntoResponse {
-    let state = state.read().unwrap();
+/// Exports the server metrics in the Prometheus text format.
+async fn handle_metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    metrics().record_stats(&state.stats());
 
-    let text_channels = state.server.read().unwrap().text_channels();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().encode(),
+    )
+}
 
-    let names: Vec<&str> = text_channels.iter().map(|c| c.get_label()).collect();
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
 
-    Json(names).into_response()
-}
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode, header},
+        response::Response,
+    };
+    use tower::ServiceExt;
 
-/// Creates a new channel on the server.
-async fn handle_create_channel(State(state): State<SharedState>) -> impl IntoResponse {
-    let state = state.read().unwrap();
-
-    let channel = match state
-        .server
-        .write()
-        .unwrap()
-        .create_text_channel("todo-change-to-variable".to_string())
-    {
-        Ok(channel) => channel,
-        Err(_) => {
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-        }
+    use crate::{
+        server::{ConfigBuilder, Server, channel::direct::DirectChannel},
+        user::UserId,
     };
 
-    StatusCode::OK.into_response()
+    use super::{SharedState, make_app_router};
+
+    /// A server storing it's data in a temporary directory.
+    pub(crate) struct TestServer {
+        pub state: SharedState,
+        _dir: tempfile::TempDir,
+    }
+
+    impl TestServer {
+        /// Issues a session token that authenticates as the user.
+        pub(crate) fn token(&self, user: UserId) -> String {
+            self.state
+                .auth()
+                .read()
+                .issue_session_token(user)
+                .unwrap()
+                .token
+        }
+
+        /// Opens a direct channel created by the first user, after
+        /// making the other users known to the server.
+        pub(crate) fn direct_channel(&self, users: &[UserId]) -> Arc<DirectChannel> {
+            for &user in &users[1..] {
+                self.state.presence().read().record_seen(user, 0).unwrap();
+            }
+
+            self.state
+                .create_direct_channel(users[0], users.iter().copied().collect())
+                .unwrap()
+        }
+
+        /// Returns the status of a GET request, authenticated with the token if one is given.
+        pub(crate) async fn get_status(&self, uri: &str, token: Option<&str>) -> StatusCode {
+            self.request(Method::GET, uri, token, None).await.status()
+        }
+
+        /// Sends a request through the server's router, authenticated
+        /// with the token if one is given.
+        pub(crate) async fn request(
+            &self,
+            method: Method,
+            uri: &str,
+            token: Option<&str>,
+            body: Option<serde_json::Value>,
+        ) -> Response {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+
+            let body = match body {
+                Some(body) => {
+                    request = request.header(header::CONTENT_TYPE, "application/json");
+                    Body::from(body.to_string())
+                }
+                None => Body::empty(),
+            };
+
+            make_app_router(Arc::clone(&self.state))
+                .oneshot(request.body(body).unwrap())
+                .await
+                .unwrap()
+        }
+    }
+
+    /// Reads the body of a response as JSON.
+    pub(crate) async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Starts a server with the default config.
+    pub(crate) fn server() -> TestServer {
+        server_with(|config| config)
+    }
+
+    /// Starts a server with the config adjusted by `configure`.
+    pub(crate) fn server_with(
+        configure: impl FnOnce(ConfigBuilder) -> ConfigBuilder,
+    ) -> TestServer {
+        let dir = tempfile::tempdir().unwrap();
+        let config = configure(ConfigBuilder::default().data_dir(dir.path()))
+            .build()
+            .unwrap();
+
+        TestServer {
+            state: Arc::new(Server::new(config).unwrap()),
+            _dir: dir,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicked_lock_holder_doesnt_break_later_requests() {
+        let server = server();
+        let token = server.token(UserId(1));
+
+        // Panic while holding the locks the request needs. Standard library
+        // locks would be poisoned, failing every later request with them.
+        let state = Arc::clone(&server.state);
+        let panicked = std::thread::spawn(move || {
+            let auth = state.auth();
+            let _auth = auth.write();
+            let categories = state.categories();
+            let _categories = categories.write();
+            panic!("handler panicked while holding locks");
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        assert_eq!(
+            server.get_status("/channels", Some(&token)).await,
+            StatusCode::OK
+        );
+    }
 }
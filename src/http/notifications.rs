@@ -0,0 +1,69 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::notification::NotificationSettings,
+};
+
+/// Replaces the authenticated user's notification settings for a channel.
+///
+/// Muting a channel only suppresses notifications, messages are still
+/// delivered to clients watching the channel.
+pub async fn handle_set_channel_settings(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+    Json(settings): Json<NotificationSettings>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    // Settings can only be set for channels the user can read.
+    if state.text_channel(channel_id).is_none()
+        && state.direct_channel(user_id, channel_id).is_err()
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let result = state
+        .notification_settings()
+        .read()
+        .set_channel_settings(user_id, channel_id, settings);
+
+    match result {
+        Ok(()) => Json(settings).into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to persist notification settings");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Replaces the authenticated user's server-wide notification settings.
+///
+/// Applies to every channel the user hasn't set their own settings for.
+pub async fn handle_set_server_settings(
+    AuthUser(user_id): AuthUser,
+    State(state): State<SharedState>,
+    Json(settings): Json<NotificationSettings>,
+) -> impl IntoResponse {
+    let result = state
+        .notification_settings()
+        .read()
+        .set_server_settings(user_id, settings);
+
+    match result {
+        Ok(()) => Json(settings).into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to persist notification settings");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
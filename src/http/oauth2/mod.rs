@@ -1,15 +1,63 @@
 use axum::{
+    Json,
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Redirect},
 };
 
-use axum_extra::extract::{CookieJar, cookie::Cookie};
-use cookie::time::Duration;
+use axum_extra::extract::CookieJar;
 use oauth2::{AuthorizationCode, CsrfToken};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use crate::http::SharedState;
+use crate::{
+    http::{SharedState, auth::token_cookie},
+    server::{Config, auth::OAuth2Error, metrics::metrics},
+};
+
+/// The login methods supported by the server.
+#[derive(Serialize, JsonSchema)]
+pub struct AuthProviders {
+    /// OAuth2 providers users can log in with.
+    providers: Vec<AuthProvider>,
+    /// Whether users can log in with a password.
+    ///
+    /// Always false, since only OAuth2 logins are supported.
+    password_login: bool,
+}
+
+/// An OAuth2 provider users can log in with.
+#[derive(Serialize, JsonSchema)]
+pub struct AuthProvider {
+    /// Provider ID used in the authorization URL.
+    id: String,
+    /// Name of the provider to show to the user.
+    label: String,
+    /// Local URL that redirects the user to the provider to log in.
+    authorize_url: String,
+}
+
+/// Lists the login methods for clients that don't use the server-rendered login page.
+///
+/// Only the public details of each provider are returned, never their secrets.
+pub async fn handle_providers(State(state): State<SharedState>) -> impl IntoResponse {
+    let providers = state
+        .auth()
+        .read()
+        .oauth2_clients()
+        .iter()
+        .map(|client| AuthProvider {
+            id: client.id.clone(),
+            label: client.label.clone(),
+            authorize_url: format!("/oauth/{}", client.id),
+        })
+        .collect();
+
+    Json(AuthProviders {
+        providers,
+        password_login: false,
+    })
+}
 
 /// Handles redirecting a user to the specified OAuth2 provider's authorization endpoint.
 ///
@@ -17,14 +65,20 @@ use crate::http::SharedState;
 /// check exchange the authorization code for a token, and to issue the user a local token.
 pub async fn handle_redirect(
     Path(provider): Path<String>,
+    headers: HeaderMap,
     State(state): State<SharedState>,
 ) -> impl IntoResponse {
-    let auth = state.read().unwrap().server.read().unwrap().auth();
+    let Some(base_url) = public_base_url(state.config(), &headers) else {
+        tracing::warn!("rejected oauth2 login from an untrusted forwarded host");
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let auth = state.auth();
 
     // Generate an authorization URL for the request.
-    let Some(authorize_url) = auth.read().unwrap().oauth2_authorize_web(
+    let Some(authorize_url) = auth.read().oauth2_authorize_web(
         provider.clone(),
-        &format!("http://localhost:3000/oauth2/{provider}/callback"),
+        &format!("{base_url}/oauth/{provider}/callback"),
     ) else {
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     };
@@ -32,7 +86,84 @@ pub async fn handle_redirect(
     Redirect::temporary(authorize_url.as_str()).into_response()
 }
 
-#[derive(Deserialize)]
+/// Returns the URL users reach the server at, to build the OAuth2 callback URL from.
+///
+/// The configured public base URL is preferred. Otherwise the host forwarded by
+/// a reverse proxy is used if it's trusted, falling back to the local port.
+///
+/// Returns `None` if the request was forwarded for a host that isn't trusted,
+/// so a client can't have the provider redirect to a host it controls.
+fn public_base_url(config: &Config, headers: &HeaderMap) -> Option<String> {
+    if let Some(url) = &config.public_base_url {
+        return Some(url.clone());
+    }
+
+    let Some((host, proto)) = forwarded_host(headers) else {
+        return Some(format!("http://localhost:{}", config.bind_addr.port()));
+    };
+
+    // Hosts are matched exactly, including any port.
+    let trusted = config
+        .trusted_forwarded_hosts
+        .iter()
+        .any(|trusted| trusted.eq_ignore_ascii_case(&host));
+    if !trusted {
+        return None;
+    }
+
+    let scheme = match proto.as_deref() {
+        Some("https") => "https",
+        _ => "http",
+    };
+
+    Some(format!("{scheme}://{host}"))
+}
+
+/// Returns the host and protocol forwarded by a reverse proxy, if any.
+///
+/// The standard `Forwarded` header is preferred over `X-Forwarded-Host` and
+/// `X-Forwarded-Proto`. When several proxies forwarded the request, the values
+/// set by the first proxy, which the user connected to, are used.
+fn forwarded_host(headers: &HeaderMap) -> Option<(String, Option<String>)> {
+    let first_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+
+    // Forwarded: for=192.0.2.60;proto=https;host=chat.example.com
+    if let Some(forwarded) = first_value(header::FORWARDED.as_str()) {
+        let mut host = None;
+        let mut proto = None;
+
+        for pair in forwarded.split(';') {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "host" => host = Some(value),
+                "proto" => proto = Some(value.to_ascii_lowercase()),
+                _ => {}
+            }
+        }
+
+        if let Some(host) = host {
+            return Some((host, proto));
+        }
+    }
+
+    let host = first_value("x-forwarded-host")?;
+    let proto = first_value("x-forwarded-proto").map(str::to_ascii_lowercase);
+
+    Some((host.to_string(), proto))
+}
+
+#[derive(Deserialize, JsonSchema)]
 pub struct CallbackQuery {
     code: String,
     state: String,
@@ -45,39 +176,124 @@ pub async fn handle_callback(
     jar: CookieJar,
     State(state): State<SharedState>,
 ) -> impl IntoResponse {
-    let auth = state.read().unwrap().server.read().unwrap().auth();
+    let auth = state.auth();
 
     // Extract the OAuth2 callback code and state supplied by the OAuth2 provider.
     let code = AuthorizationCode::new(query.0.code);
-    let state = CsrfToken::new(query.0.state);
+    let csrf_state = CsrfToken::new(query.0.state);
 
     // Attempt to exchange the code and state for a local auth token.
-    let Some(token) = auth
+    //
+    // The lock on the auth service is released before waiting on the exchange.
+    let exchange = auth
         .read()
-        .unwrap()
-        .oauth2_code_exchange_web(provider, code, state)
-    else {
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        .oauth2_code_exchange_web(provider.clone(), code, csrf_state);
+
+    let issued = match exchange.await {
+        Ok(issued) => issued,
+        Err(err) => {
+            metrics()
+                .oauth_logins
+                .with_label_values(&[provider.as_str(), "failure"])
+                .inc();
+
+            return match err {
+                OAuth2Error::UnknownProvider => StatusCode::NOT_FOUND,
+                OAuth2Error::InvalidState => StatusCode::BAD_REQUEST,
+                OAuth2Error::StateAlreadyUsed => StatusCode::CONFLICT,
+                OAuth2Error::ExchangeFailed
+                | OAuth2Error::AccountLookupFailed
+                | OAuth2Error::LoginFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            }
+            .into_response();
+        }
     };
 
-    // Build the cookie for the token.
+    metrics()
+        .oauth_logins
+        .with_label_values(&[provider.as_str(), "success"])
+        .inc();
+
+    // Build the cookie for the token, expiring when the token does.
     // ref: https://mattrighetti.com/2025/05/03/authentication-with-axum
-    let cookie = Cookie::build(("token", token))
-        .path("/")
-        .http_only(true)
-        .max_age(Duration::hours(6))
-        .secure(if cfg!(debug_assertions) {
-            // Safari won't allow secure cookies
-            // coming from localhost in debug mode
-            false
-        } else {
-            // Secure cookies in release mode
-            true
-        })
-        .build();
+    let cookie = token_cookie(&state.config().auth.cookie, &issued);
 
     // Add the cookie to the response.
     //
     // Redirect use back to the web client.
     (jar.add(cookie), Redirect::temporary("/client")).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Method};
+
+    use super::*;
+    use crate::{
+        http::tests::{json_body, server_with},
+        server::{ConfigBuilder, auth::tests::test_provider},
+    };
+
+    #[test]
+    fn callback_urls_are_built_from_the_public_base_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |configure: fn(ConfigBuilder) -> ConfigBuilder| {
+            configure(Config::builder().data_dir(dir.path()))
+                .build()
+                .unwrap()
+        };
+        let forwarded = |host: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-host", HeaderValue::from_static(host));
+            headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+            headers
+        };
+
+        // The configured URL wins over whatever host the request claims.
+        let configured = config(|config| config.public_base_url("https://chat.example.com"));
+        assert_eq!(
+            public_base_url(&configured, &forwarded("evil.example.com")).as_deref(),
+            Some("https://chat.example.com")
+        );
+
+        // Forwarded hosts are only used if they're trusted.
+        let proxied = config(|config| config.trusted_forwarded_host("chat.example.com"));
+        assert_eq!(
+            public_base_url(&proxied, &forwarded("chat.example.com")).as_deref(),
+            Some("https://chat.example.com")
+        );
+        assert_eq!(
+            public_base_url(&proxied, &forwarded("evil.example.com")),
+            None
+        );
+
+        // Without a proxy, the local port is used.
+        assert_eq!(
+            public_base_url(&proxied, &HeaderMap::new()).as_deref(),
+            Some("http://localhost:3000")
+        );
+    }
+
+    #[tokio::test]
+    async fn providers_are_listed_without_their_secrets() {
+        let server = server_with(|config| config.oauth2_client(test_provider()));
+
+        let response = server
+            .request(Method::GET, "/auth/providers", None, None)
+            .await;
+        let body = json_body(response).await;
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "providers": [{
+                    "id": "test",
+                    "label": "Test",
+                    "authorize_url": "/oauth/test",
+                }],
+                "password_login": false,
+            })
+        );
+        assert!(!body.to_string().contains(&test_provider().client_secret));
+    }
+}
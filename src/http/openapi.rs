@@ -0,0 +1,1061 @@
+//! Generates an OpenAPI document describing the REST API.
+//!
+//! Request and response schemas are generated from the `JsonSchema`
+//! derives on the HTTP types, so they can't drift from the handlers.
+//! The paths are listed by hand and should be kept in step with the
+//! routes in [`make_app_router`](crate::http::make_app_router), which
+//! the tests check.
+
+use schemars::{JsonSchema, SchemaGenerator, generate::SchemaSettings};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    http::{
+        auth::RefreshedToken,
+        channels::{
+            AckRequest, AckResponse, ChannelGroup, ChannelResponse, CreateCategoryRequest,
+            CreateChannelRequest, OrderChannelsRequest, UpdateChannelRequest,
+        },
+        direct::{CreateDirectQuery, DirectChannelResponse, PostMessageRequest},
+        import::ImportResponse,
+        mentions::{MentionCandidates, MentionParams},
+        messages::{
+            EditMessageRequest, GetMessageParams, HistoryParams, MessagePreview, MessageResponse,
+            PreviewMessageRequest,
+        },
+        oauth2::{AuthProviders, CallbackQuery},
+        purge::{DeleteByAuthorParams, PurgeRangeRequest, PurgeResponse},
+        reindex::ReindexResponse,
+        search::{ChannelSearchResult, SearchParams, SearchResults},
+        stats::StatsResponse,
+        users::UserProfileResponse,
+        voice::{ServerVoiceStateRequest, VoiceParticipantResponse, VoiceStateResponse},
+        webhook::{CreatedWebhook, WebhookMessage},
+    },
+    server::{
+        category::Category, channel::text::TextChannelMessage, notification::NotificationSettings,
+    },
+};
+
+/// Name of the security scheme used by endpoints that require a user.
+const BEARER_AUTH: &str = "bearerAuth";
+
+/// Builds the OpenAPI document for the REST API.
+pub fn openapi_document() -> Value {
+    let mut paths = Paths {
+        generator: SchemaSettings::openapi3().into_generator(),
+        paths: Map::new(),
+    };
+
+    paths.channels();
+    paths.messages();
+    paths.search();
+    paths.voice();
+    paths.users();
+    paths.direct();
+    paths.webhooks();
+    paths.oauth();
+    paths.stats();
+
+    let Paths {
+        mut generator,
+        paths,
+    } = paths;
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Bonfire",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(true),
+            "securitySchemes": {
+                BEARER_AUTH: {
+                    "type": "http",
+                    "scheme": "bearer",
+                },
+            },
+        },
+    })
+}
+
+/// Collects the path items of the document.
+struct Paths {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Paths {
+    /// Adds an operation to the document.
+    fn add(&mut self, path: &str, method: &str, operation: Value) {
+        let item = self
+            .paths
+            .entry(path)
+            .or_insert_with(|| Value::Object(Map::new()));
+
+        item[method] = operation;
+    }
+
+    /// Describes a JSON request body.
+    fn body<T: JsonSchema>(&mut self) -> Value {
+        json!({
+            "required": true,
+            "content": {
+                "application/json": { "schema": self.generator.subschema_for::<T>() },
+            },
+        })
+    }
+
+    /// Describes a JSON response.
+    fn response<T: JsonSchema>(&mut self, description: &str) -> Value {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": { "schema": self.generator.subschema_for::<T>() },
+            },
+        })
+    }
+
+    /// Describes an NDJSON request body, with each line matching the schema.
+    fn ndjson_body<T: JsonSchema>(&mut self) -> Value {
+        json!({
+            "required": true,
+            "content": {
+                "application/x-ndjson": { "schema": self.generator.subschema_for::<T>() },
+            },
+        })
+    }
+
+    /// Describes an NDJSON response, with each line matching the schema.
+    fn ndjson_response<T: JsonSchema>(&mut self, description: &str) -> Value {
+        json!({
+            "description": description,
+            "content": {
+                "application/x-ndjson": { "schema": self.generator.subschema_for::<T>() },
+            },
+        })
+    }
+
+    /// Describes each field of a query parameter struct as a parameter.
+    fn query<T: JsonSchema>(&mut self) -> Vec<Value> {
+        let schema = self.generator.root_schema_for::<T>();
+
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map_or_else(Vec::new, |required| {
+                required.iter().filter_map(Value::as_str).collect()
+            });
+
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            return Vec::new();
+        };
+
+        properties
+            .iter()
+            .map(|(name, property)| {
+                let mut parameter = json!({
+                    "name": name,
+                    "in": "query",
+                    "required": required.contains(&name.as_str()),
+                    "schema": property,
+                });
+
+                if let Some(description) = property.get("description") {
+                    parameter["description"] = description.clone();
+                }
+
+                parameter
+            })
+            .collect()
+    }
+
+    fn channels(&mut self) {
+        let list = self.response::<Vec<ChannelGroup>>("The channels, grouped by category.");
+        self.add(
+            "/channels",
+            "get",
+            json!({
+                "summary": "List the channels on the server.",
+                "responses": { "200": list },
+            }),
+        );
+
+        let body = self.body::<CreateChannelRequest>();
+        let created = self.response::<ChannelResponse>("The created channel.");
+        self.add(
+            "/channels",
+            "post",
+            json!({
+                "summary": "Create a text or voice channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": body,
+                "responses": {
+                    "200": created,
+                    "400": empty("The label is blank, the topic is too long, or the type is unsupported."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage channels."),
+                    "409": empty("The server already has the maximum number of channels."),
+                },
+            }),
+        );
+
+        let channel = self.response::<ChannelResponse>("The channel.");
+        self.add(
+            "/channels/{id}",
+            "get",
+            json!({
+                "summary": "Fetch a channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "responses": {
+                    "200": channel,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't read the channel."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<UpdateChannelRequest>();
+        let updated = self.response::<ChannelResponse>("The updated channel.");
+        self.add(
+            "/channels/{id}",
+            "patch",
+            json!({
+                "summary": "Update a channel's settings.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "requestBody": body,
+                "responses": {
+                    "200": updated,
+                    "400": empty("The topic is too long."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage channels."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<OrderChannelsRequest>();
+        self.add(
+            "/channels/order",
+            "put",
+            json!({
+                "summary": "Order the channels within a category.",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": body,
+                "responses": {
+                    "204": empty("The channels were ordered."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage channels."),
+                    "404": empty("The category or a channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<AckRequest>();
+        let read_state = self.response::<AckResponse>("The user's read state in the channel.");
+        self.add(
+            "/channels/{id}/ack",
+            "post",
+            json!({
+                "summary": "Mark a channel's messages as read.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "requestBody": body,
+                "responses": {
+                    "200": read_state,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't read the channel."),
+                    "404": empty("The channel or message doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<NotificationSettings>();
+        let settings = self.response::<NotificationSettings>("The updated settings.");
+        self.add(
+            "/channels/{id}/settings",
+            "put",
+            json!({
+                "summary": "Set the user's notification settings for a channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "requestBody": body,
+                "responses": {
+                    "200": settings,
+                    "401": empty("The user isn't authenticated."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<NotificationSettings>();
+        let settings = self.response::<NotificationSettings>("The updated settings.");
+        self.add(
+            "/notifications/settings",
+            "put",
+            json!({
+                "summary": "Set the user's server-wide notification settings.",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": body,
+                "responses": {
+                    "200": settings,
+                    "401": empty("The user isn't authenticated."),
+                },
+            }),
+        );
+
+        let body = self.body::<CreateCategoryRequest>();
+        let created = self.response::<Category>("The created category.");
+        self.add(
+            "/categories",
+            "post",
+            json!({
+                "summary": "Create a channel category.",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": body,
+                "responses": {
+                    "200": created,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage channels."),
+                },
+            }),
+        );
+    }
+
+    fn messages(&mut self) {
+        let mut parameters = vec![path_param("id", "ID of the channel.")];
+        parameters.extend(self.query::<HistoryParams>());
+        let page = self.response::<Vec<TextChannelMessage>>("The messages, oldest first.");
+        self.add(
+            "/channels/{id}/messages",
+            "get",
+            json!({
+                "summary": "Page through a channel's message history.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": page,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't read the channel."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let mut parameters = vec![path_param("id", "ID of the channel.")];
+        parameters.extend(self.query::<DeleteByAuthorParams>());
+        let deleted = self.response::<PurgeResponse>("The messages were deleted.");
+        self.add(
+            "/channels/{id}/messages",
+            "delete",
+            json!({
+                "summary": "Delete every message a user posted in a channel, for moderators.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": deleted,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage messages."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let mut parameters = vec![
+            path_param("id", "ID of the channel."),
+            path_param("message_id", "ID of the message."),
+        ];
+        parameters.extend(self.query::<GetMessageParams>());
+        let message = self.response::<MessageResponse>("The message.");
+        self.add(
+            "/channels/{id}/messages/{message_id}",
+            "get",
+            json!({
+                "summary": "Fetch a message, along with the message it replies to.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": message,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't read the channel."),
+                    "404": empty("The channel or message doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<EditMessageRequest>();
+        let edited = self.response::<TextChannelMessage>("The edited message.");
+        self.add(
+            "/channels/{id}/messages/{message_id}",
+            "patch",
+            json!({
+                "summary": "Edit the content of a message posted by the user.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [
+                    path_param("id", "ID of the channel."),
+                    path_param("message_id", "ID of the message."),
+                ],
+                "requestBody": body,
+                "responses": {
+                    "200": edited,
+                    "400": empty("The content is empty or too long, or the flags can't be set."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't the author of the message."),
+                    "404": empty("The channel or message doesn't exist."),
+                    "413": empty("The content is over the size limit."),
+                },
+            }),
+        );
+
+        let body = self.body::<PreviewMessageRequest>();
+        let preview = self.response::<MessagePreview>("The parsed content.");
+        self.add(
+            "/messages/preview",
+            "post",
+            json!({
+                "summary": "Preview how a message's content is parsed, without sending it.",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": body,
+                "responses": {
+                    "200": preview,
+                    "400": empty("The content is empty or too long."),
+                    "401": empty("The user isn't authenticated."),
+                    "413": empty("The content is over the size limit."),
+                },
+            }),
+        );
+
+        let pins = self.response::<Vec<TextChannelMessage>>("The pinned messages.");
+        self.add(
+            "/channels/{id}/pins",
+            "get",
+            json!({
+                "summary": "List the pinned messages in a channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "responses": {
+                    "200": pins,
+                    "401": empty("The user isn't authenticated."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        self.add(
+            "/channels/{id}/pins/{message_id}",
+            "post",
+            json!({
+                "summary": "Pin a message in a channel, for moderators.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [
+                    path_param("id", "ID of the channel."),
+                    path_param("message_id", "ID of the message."),
+                ],
+                "responses": {
+                    "204": empty("The message was pinned."),
+                    "400": empty("The channel or message ID is invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage messages."),
+                    "404": empty("The channel or message doesn't exist."),
+                    "409": empty("The channel has the maximum number of pinned messages."),
+                },
+            }),
+        );
+
+        self.add(
+            "/channels/{id}/pins/{message_id}",
+            "delete",
+            json!({
+                "summary": "Unpin a message in a channel, for moderators.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [
+                    path_param("id", "ID of the channel."),
+                    path_param("message_id", "ID of the message."),
+                ],
+                "responses": {
+                    "204": empty("The message was unpinned."),
+                    "400": empty("The channel or message ID is invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage messages."),
+                    "404": empty("The channel or message doesn't exist."),
+                },
+            }),
+        );
+
+        let mut parameters = vec![path_param("id", "ID of the channel.")];
+        parameters.extend(self.query::<MentionParams>());
+        let candidates = self.response::<MentionCandidates>("The suggested mentions.");
+        self.add(
+            "/channels/{id}/members",
+            "get",
+            json!({
+                "summary": "Suggest mentions starting with a prefix, for the mention picker.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": candidates,
+                    "400": empty("The channel ID is invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "404": empty("The channel doesn't exist, or the user can't read it."),
+                },
+            }),
+        );
+
+        let messages = self.ndjson_response::<TextChannelMessage>("The messages, oldest first.");
+        self.add(
+            "/channels/{id}/export",
+            "get",
+            json!({
+                "summary": "Download a channel's message history as NDJSON, for admins.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "responses": {
+                    "200": messages,
+                    "400": empty("The channel ID is invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't an admin."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.ndjson_body::<TextChannelMessage>();
+        let imported = self.response::<ImportResponse>("The messages were imported.");
+        let interrupted = self.response::<ImportResponse>(
+            "The import stopped partway through, after importing some of the messages.",
+        );
+        self.add(
+            "/channels/{id}/import",
+            "post",
+            json!({
+                "summary": "Import messages in the export format into a channel, for admins.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "requestBody": body,
+                "responses": {
+                    "200": imported,
+                    "400": empty("A message couldn't be decoded, or the batch is invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't an admin."),
+                    "404": empty("The channel doesn't exist."),
+                    "500": interrupted,
+                },
+            }),
+        );
+    }
+
+    fn search(&mut self) {
+        let parameters = self.query::<SearchParams>();
+        let results = self.response::<Vec<ChannelSearchResult>>("The matched messages.");
+        self.add(
+            "/search",
+            "get",
+            json!({
+                "summary": "Search every channel the user can read.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": { "200": results, "401": empty("The user isn't authenticated.") },
+            }),
+        );
+
+        let mut parameters = vec![path_param("id", "ID of the channel.")];
+        parameters.extend(self.query::<SearchParams>());
+        let results = self.response::<SearchResults>("A page of the matched messages.");
+        self.add(
+            "/channels/{id}/search",
+            "get",
+            json!({
+                "summary": "Search a channel's messages.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": results,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't read the channel."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+    }
+
+    fn voice(&mut self) {
+        let participants =
+            self.response::<Vec<VoiceParticipantResponse>>("The connected users, oldest first.");
+        self.add(
+            "/channels/{id}/voice/participants",
+            "get",
+            json!({
+                "summary": "List the users connected to a voice channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the voice channel.")],
+                "responses": {
+                    "200": participants,
+                    "401": empty("The user isn't authenticated."),
+                    "404": empty("The voice channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<ServerVoiceStateRequest>();
+        let voice_state = self.response::<VoiceStateResponse>("The participant's new state.");
+        self.add(
+            "/channels/{id}/voice/participants/{user_id}",
+            "put",
+            json!({
+                "summary": "Mute or deafen a voice channel participant, for moderators.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [
+                    path_param("id", "ID of the voice channel."),
+                    path_param("user_id", "ID of the participant."),
+                ],
+                "requestBody": body,
+                "responses": {
+                    "200": voice_state,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't mute members."),
+                    "404": empty("The channel doesn't exist, or the user isn't connected to it."),
+                },
+            }),
+        );
+    }
+
+    fn users(&mut self) {
+        let profile = self.response::<UserProfileResponse>("The user's profile.");
+        self.add(
+            "/users/{id}",
+            "get",
+            json!({
+                "summary": "Get a user's profile, including when they were last online.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the user.")],
+                "responses": { "200": profile, "401": empty("The user isn't authenticated.") },
+            }),
+        );
+    }
+
+    fn direct(&mut self) {
+        let mut parameters = vec![path_param("id", "ID of the other user.")];
+        parameters.extend(self.query::<CreateDirectQuery>());
+        let channel = self.response::<DirectChannelResponse>("The direct channel.");
+        self.add(
+            "/users/{id}/dm",
+            "post",
+            json!({
+                "summary": "Open a direct channel with another user.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": channel,
+                    "400": empty("The participants are invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "404": empty("One of the other users isn't known to the server."),
+                    "409": empty("The user or server has the maximum number of direct channels."),
+                },
+            }),
+        );
+
+        let channels = self.response::<Vec<DirectChannelResponse>>("The direct channels.");
+        self.add(
+            "/dms",
+            "get",
+            json!({
+                "summary": "List the user's direct channels.",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": { "200": channels, "401": empty("The user isn't authenticated.") },
+            }),
+        );
+
+        let mut parameters = vec![path_param("id", "ID of the direct channel.")];
+        parameters.extend(self.query::<HistoryParams>());
+        let page = self.response::<Vec<TextChannelMessage>>("The messages, oldest first.");
+        self.add(
+            "/dms/{id}/messages",
+            "get",
+            json!({
+                "summary": "Page through a direct channel's message history.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": page,
+                    "400": empty("Both the before and after cursors were given."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't a participant of the channel."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<PostMessageRequest>();
+        let message = self.response::<TextChannelMessage>("The created message.");
+        self.add(
+            "/dms/{id}/messages",
+            "post",
+            json!({
+                "summary": "Post a message to a direct channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the direct channel.")],
+                "requestBody": body,
+                "responses": {
+                    "200": message,
+                    "400": empty("The message content, reply, nonce, or flags are invalid."),
+                    "403": empty("The user isn't a participant of the channel."),
+                    "413": empty("The message content is over the size limit."),
+                    "422": empty("The message was rejected by auto-moderation."),
+                    "429": empty("The user is sending messages too quickly."),
+                },
+            }),
+        );
+
+        let mut parameters = vec![path_param("id", "ID of the direct channel.")];
+        parameters.extend(self.query::<SearchParams>());
+        let results = self.response::<SearchResults>("A page of the matched messages.");
+        self.add(
+            "/dms/{id}/search",
+            "get",
+            json!({
+                "summary": "Search a direct channel's messages.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": parameters,
+                "responses": {
+                    "200": results,
+                    "400": empty("The query or cursor is invalid."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't a participant of the channel."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+    }
+
+    fn webhooks(&mut self) {
+        let created = self.response::<CreatedWebhook>("The created webhook.");
+        self.add(
+            "/channels/{id}/webhooks",
+            "post",
+            json!({
+                "summary": "Create a webhook for posting to a channel.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "responses": {
+                    "200": created,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user can't manage channels."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+
+        let body = self.body::<WebhookMessage>();
+        let message = self.response::<TextChannelMessage>("The created message.");
+        self.add(
+            "/webhooks/{id}/{token}",
+            "post",
+            json!({
+                "summary": "Post a message through a webhook.",
+                "parameters": [
+                    path_param("id", "ID of the webhook."),
+                    path_param("token", "Secret token of the webhook."),
+                ],
+                "requestBody": body,
+                "responses": {
+                    "200": message,
+                    "401": empty("The token is invalid."),
+                    "404": empty("The webhook doesn't exist."),
+                    "413": empty("The message content is over the size limit."),
+                    "422": empty("The message was rejected by auto-moderation."),
+                    "429": empty("The webhook is sending messages too quickly."),
+                },
+            }),
+        );
+    }
+
+    fn stats(&mut self) {
+        let stats = self.response::<StatsResponse>("A snapshot of the server's activity.");
+        self.add(
+            "/stats",
+            "get",
+            json!({
+                "summary": "Report the server's activity, for admins.",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": {
+                    "200": stats,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't an admin."),
+                },
+            }),
+        );
+
+        let reindexed = self.response::<ReindexResponse>("The index was rebuilt.");
+        self.add(
+            "/channels/{id}/reindex",
+            "post",
+            json!({
+                "summary": "Rebuild a channel's search index from it's messages, for admins.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "responses": {
+                    "200": reindexed,
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't an admin."),
+                    "404": empty("The channel doesn't exist."),
+                    "409": empty("The index is already being rebuilt."),
+                    "500": empty("The channel failed to load, and still can't be loaded."),
+                },
+            }),
+        );
+
+        let body = self.body::<PurgeRangeRequest>();
+        let deleted = self.response::<PurgeResponse>("The messages were deleted.");
+        self.add(
+            "/channels/{id}/purge",
+            "post",
+            json!({
+                "summary": "Delete the messages sent in a time range from a channel, for admins.",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [path_param("id", "ID of the channel.")],
+                "requestBody": body,
+                "responses": {
+                    "200": deleted,
+                    "400": empty("The start of the range isn't before it's end."),
+                    "401": empty("The user isn't authenticated."),
+                    "403": empty("The user isn't an admin."),
+                    "404": empty("The channel doesn't exist."),
+                },
+            }),
+        );
+    }
+
+    fn oauth(&mut self) {
+        let providers = self.response::<AuthProviders>("The supported login methods.");
+        self.add(
+            "/auth/providers",
+            "get",
+            json!({
+                "summary": "List the login methods supported by the server.",
+                "responses": { "200": providers },
+            }),
+        );
+
+        let refreshed = self.response::<RefreshedToken>("The replacement session token.");
+        self.add(
+            "/auth/refresh",
+            "post",
+            json!({
+                "summary": "Replace the session token with one that expires later.",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": {
+                    "200": refreshed,
+                    "401": empty("The token is missing, expired, or isn't a session token."),
+                },
+            }),
+        );
+
+        self.add(
+            "/auth/logout",
+            "post",
+            json!({
+                "summary": "Log out, revoking the token.",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": { "204": empty("The user was logged out.") },
+            }),
+        );
+
+        self.add(
+            "/oauth/{provider}",
+            "get",
+            json!({
+                "summary": "Redirect to a provider's authorization endpoint.",
+                "parameters": [path_param("provider", "ID of the OAuth provider.")],
+                "responses": { "307": empty("Redirect to the provider.") },
+            }),
+        );
+
+        let mut parameters = vec![path_param("provider", "ID of the OAuth provider.")];
+        parameters.extend(self.query::<CallbackQuery>());
+        self.add(
+            "/oauth/{provider}/callback",
+            "get",
+            json!({
+                "summary": "Complete authentication with a provider.",
+                "parameters": parameters,
+                "responses": { "307": empty("Redirect to the client with a session token.") },
+            }),
+        );
+    }
+}
+
+/// Describes a required string path parameter.
+fn path_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+/// Describes a response without a body.
+fn empty(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::{make_app_router, tests::server};
+
+    /// Routes that aren't part of the REST API: the web interface
+    /// redirect, the gateway websocket, and the Prometheus metrics.
+    const UNDOCUMENTED_ROUTES: [&str; 3] = ["/", "/gateway", "/metrics"];
+
+    /// Methods a route can be registered with.
+    const ROUTE_METHODS: [&str; 6] = ["get", "post", "put", "patch", "delete", "any"];
+
+    /// Lists the paths and methods of the routes in the app router.
+    ///
+    /// Axum can't list a router's routes, so they're read from the source
+    /// of [`make_app_router`]. Routes accepting any method are listed with
+    /// the method `any`.
+    fn router_routes() -> BTreeSet<(String, String)> {
+        let source = include_str!("mod.rs");
+        let start = source.find("pub fn make_app_router").unwrap();
+        let source = &source[start..];
+        let source = &source[..source.find("\n}\n").unwrap()];
+
+        let mut routes = BTreeSet::new();
+        for (i, _) in source.match_indices(".route(") {
+            // Find the end of the call by matching up it's parentheses.
+            let call = &source[i + ".route".len()..];
+            let mut depth = 0;
+            let end = call
+                .char_indices()
+                .find_map(|(i, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    (depth == 0).then_some(i)
+                })
+                .unwrap();
+            let call = &call[..end];
+
+            let path = call.split('"').nth(1).unwrap();
+            for method in ROUTE_METHODS {
+                let registered = call.match_indices(&format!("{method}(")).any(|(i, _)| {
+                    !call[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == ':')
+                });
+                if registered {
+                    routes.insert((path.to_string(), method.to_string()));
+                }
+            }
+        }
+
+        routes
+    }
+
+    #[test]
+    fn every_api_route_is_documented() {
+        let document = openapi_document();
+        let documented: BTreeSet<(String, String)> = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| (path.clone(), method.clone()))
+            })
+            .collect();
+
+        // Routes accepting any method are documented with the methods clients use.
+        let routed: BTreeSet<(String, String)> = router_routes()
+            .into_iter()
+            .filter(|(path, _)| !UNDOCUMENTED_ROUTES.contains(&path.as_str()))
+            .flat_map(|(path, method)| {
+                let methods: Vec<String> = match method.as_str() {
+                    "any" => documented
+                        .iter()
+                        .filter(|(documented, _)| *documented == path)
+                        .map(|(_, method)| method.clone())
+                        .collect(),
+                    _ => vec![method],
+                };
+                methods
+                    .into_iter()
+                    .map(move |method| (path.clone(), method))
+            })
+            .collect();
+
+        let undocumented: Vec<_> = routed.difference(&documented).collect();
+        assert!(
+            undocumented.is_empty(),
+            "undocumented routes: {undocumented:?}"
+        );
+        let unrouted: Vec<_> = documented.difference(&routed).collect();
+        assert!(
+            unrouted.is_empty(),
+            "documented paths without a route: {unrouted:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn documented_paths_are_routed() {
+        let server = server();
+
+        for (path, item) in openapi_document()["paths"].as_object().unwrap() {
+            // Fill in the path parameters.
+            let uri = path
+                .split('/')
+                .map(|segment| match segment.starts_with('{') {
+                    true => "1",
+                    false => segment,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+
+            for method in item.as_object().unwrap().keys() {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                let request = Request::builder()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap();
+
+                // Requests without a matching route fall back to a status
+                // the handlers never respond with, so they can be told apart.
+                let response = make_app_router(server.state.clone())
+                    .fallback(async || StatusCode::IM_A_TEAPOT)
+                    .oneshot(request)
+                    .await
+                    .unwrap();
+
+                assert_ne!(
+                    response.status(),
+                    StatusCode::IM_A_TEAPOT,
+                    "{method} {path}"
+                );
+                assert_ne!(
+                    response.status(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{method} {path}"
+                );
+            }
+        }
+    }
+}
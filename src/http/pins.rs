@@ -0,0 +1,165 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    message::MessageId,
+    server::{channel::text::pins::PinError, permission::Permissions},
+    user::UserId,
+};
+
+/// Lists the pinned messages in a channel.
+pub async fn handle_list_pins(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !state.can_read(user_id, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match channel.pinned_messages() {
+        Ok(messages) => Json(messages).into_response(),
+        Err(err) => {
+            tracing::error!(%err, "failed to read pinned messages");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Pins a message in a channel.
+///
+/// Requires the manage messages permission.
+pub async fn handle_pin(
+    AuthUser(user_id): AuthUser,
+    Path((channel_id, message_id)): Path<(String, String)>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    update_pin(user_id, channel_id, message_id, state, true)
+}
+
+/// Unpins a message in a channel.
+///
+/// Requires the manage messages permission.
+pub async fn handle_unpin(
+    AuthUser(user_id): AuthUser,
+    Path((channel_id, message_id)): Path<(String, String)>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    update_pin(user_id, channel_id, message_id, state, false)
+}
+
+/// Pins or unpins a message after checking the user's permissions.
+fn update_pin(
+    user_id: UserId,
+    channel_id: String,
+    message_id: String,
+    state: SharedState,
+    pinned: bool,
+) -> axum::response::Response {
+    let (Ok(channel_id), Ok(message_id)) = (
+        channel_id.parse::<ChannelId>(),
+        message_id.parse::<MessageId>(),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let channel = {
+        if !state
+            .permissions()
+            .read()
+            .has(user_id, Permissions::MANAGE_MESSAGES)
+        {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+
+        let Some(channel) = state.text_channel(channel_id) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+
+        channel
+    };
+
+    let result = if pinned {
+        channel.pin(message_id)
+    } else {
+        channel.unpin(message_id)
+    };
+
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(PinError::MessageNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(PinError::TooManyPins(max)) => (
+            StatusCode::CONFLICT,
+            format!("channel already has the maximum of {max} pinned messages"),
+        )
+            .into_response(),
+        Err(PinError::DatabaseError(err)) => {
+            tracing::error!(%err, "failed to update pinned messages");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::{
+        http::tests::{json_body, server},
+        server::channel::{
+            Channel,
+            text::{TextChannelSettings, tests::test_message},
+        },
+    };
+
+    #[tokio::test]
+    async fn pins_are_only_listed_to_users_that_can_read_the_channel() {
+        let server = server();
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let channel = server
+            .state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        let message = channel
+            .create_message(test_message(UserId(2), "secret"), None, Permissions::NONE)
+            .await
+            .unwrap();
+        channel.pin(message.id).unwrap();
+        server
+            .state
+            .permissions()
+            .read()
+            .set_permissions(UserId(2), Permissions::MANAGE_MESSAGES)
+            .unwrap();
+        let uri = format!("/channels/{}/pins", channel.channel_id());
+
+        let member = server.token(UserId(1));
+        let response = server.request(Method::GET, &uri, Some(&member), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let moderator = server.token(UserId(2));
+        let response = server
+            .request(Method::GET, &uri, Some(&moderator), None)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await[0]["content"], "secret");
+    }
+}
@@ -0,0 +1,106 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::{channel::text::purge::PurgeError, permission::Permissions},
+    user::UserId,
+};
+
+/// Query parameters selecting the messages to delete by author.
+#[derive(Deserialize, JsonSchema)]
+pub struct DeleteByAuthorParams {
+    /// Delete every message posted by this user.
+    author: UserId,
+}
+
+/// Request to delete the messages sent in a time range.
+#[derive(Deserialize, JsonSchema)]
+pub struct PurgeRangeRequest {
+    /// Start of the range in milliseconds since the Unix epoch.
+    start_ms: u64,
+    /// End of the range in milliseconds since the Unix epoch, exclusive.
+    end_ms: u64,
+}
+
+/// Response returned after deleting messages in bulk.
+#[derive(Serialize, JsonSchema)]
+pub struct PurgeResponse {
+    /// The number of messages deleted.
+    deleted: usize,
+}
+
+/// Deletes every message a user posted in a channel, for moderators.
+pub async fn handle_delete_by_author(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    Query(params): Query<DeleteByAuthorParams>,
+    State(state): State<SharedState>,
+) -> Response {
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MANAGE_MESSAGES)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match channel.delete_by_author(params.author).await {
+        Ok(deleted) => Json(PurgeResponse { deleted }).into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to delete messages by author");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Deletes every message sent in a time range from a channel, for admins.
+pub async fn handle_delete_range(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+    Json(request): Json<PurgeRangeRequest>,
+) -> Response {
+    if !state.permissions().read().is_admin(user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match channel
+        .delete_in_range(request.start_ms, request.end_ms)
+        .await
+    {
+        Ok(deleted) => Json(PurgeResponse { deleted }).into_response(),
+        Err(PurgeError::InvalidRange) => (
+            StatusCode::BAD_REQUEST,
+            "the start of the range must be before it's end",
+        )
+            .into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to delete messages in range");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
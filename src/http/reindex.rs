@@ -0,0 +1,63 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::channel::text::reindex::ReindexError,
+};
+
+/// Response returned after rebuilding a channel's search index.
+#[derive(Serialize, JsonSchema)]
+pub struct ReindexResponse {
+    /// The number of messages re-added to the index.
+    reindexed: usize,
+}
+
+/// Rebuilds a channel's search index from it's stored messages, for admins.
+///
+/// Channels that failed to load when the server started are loaded again
+/// first, so a channel with a corrupted search index can be recovered.
+///
+/// Responds once the rebuild is committed, which can take a while for
+/// channels with a long history.
+pub async fn handle_reindex(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+) -> Response {
+    if !state.permissions().read().is_admin(user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let channel = match state.text_channel(channel_id) {
+        Some(channel) => channel,
+        None => match state.reload_failed_channel(channel_id) {
+            Ok(Some(channel)) => channel,
+            Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+            Err(err) => {
+                tracing::error!(?err, "failed to reload channel");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+    };
+
+    match channel.reindex().await {
+        Ok(reindexed) => Json(ReindexResponse { reindexed }).into_response(),
+        Err(ReindexError::InProgress) => StatusCode::CONFLICT.into_response(),
+        Err(err) => {
+            tracing::error!(?err, "failed to reindex channel");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    message::MessageId,
+    server::channel::text::{
+        TextChannel,
+        search::{
+            DEFAULT_FUZZY_DISTANCE, DEFAULT_SEARCH_LIMIT, DEFAULT_SNIPPET_CHARS, SearchCursor,
+            SearchError, SearchHit, SearchMode, SearchQuery, SearchSort,
+        },
+    },
+    user::UserId,
+};
+
+/// Query parameters supported by the search endpoint.
+#[derive(Deserialize, JsonSchema)]
+pub struct SearchParams {
+    /// The full-text query.
+    q: String,
+    /// How the query is matched against messages.
+    #[serde(default)]
+    mode: SearchModeParam,
+    /// Levenshtein distance used by fuzzy searches.
+    distance: Option<u8>,
+    /// How the results are ordered.
+    #[serde(default)]
+    sort: SearchSortParam,
+    /// Only match messages from this author.
+    author: Option<u64>,
+    /// Only match messages sent at or after this timestamp in milliseconds.
+    from: Option<u64>,
+    /// Only match messages sent at or before this timestamp in milliseconds.
+    to: Option<u64>,
+    /// Maximum number of results to return.
+    limit: Option<usize>,
+    /// Maximum length of the highlighted snippets in characters.
+    snippet_len: Option<usize>,
+    /// The `next_cursor` of the previous page, to fetch the next page.
+    ///
+    /// Only supported when searching a single channel.
+    cursor: Option<String>,
+}
+
+/// Search modes accepted by the `mode` query parameter.
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchModeParam {
+    #[default]
+    Exact,
+    Fuzzy,
+    Prefix,
+    /// The full query syntax, with terms scoped to the
+    /// `content`, `author`, or `timestamp` fields.
+    Advanced,
+}
+
+/// Orders accepted by the `sort` query parameter.
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSortParam {
+    /// By relevance to the query alone.
+    Relevance,
+    /// By when the messages were sent, newest first.
+    Recency,
+    /// By relevance, boosted for recent messages.
+    #[default]
+    Hybrid,
+}
+
+/// A message matched by a search.
+#[derive(Serialize, JsonSchema)]
+pub struct SearchResult {
+    /// ID of the matched message, to open or reply to it.
+    message_id: MessageId,
+    score: f32,
+    author: String,
+    timestamp_ms: u64,
+    content: String,
+    reply_to: Option<MessageId>,
+    edited_at: Option<u64>,
+    highlight: String,
+}
+
+/// A page of the messages matched by a search of a channel.
+#[derive(Serialize, JsonSchema)]
+pub struct SearchResults {
+    results: Vec<SearchResult>,
+    /// Passed as the `cursor` to fetch the next page, unset on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+/// A message matched by a search across multiple channels.
+#[derive(Serialize, JsonSchema)]
+pub struct ChannelSearchResult {
+    channel_id: String,
+    #[serde(flatten)]
+    result: SearchResult,
+}
+
+impl From<SearchHit> for SearchResult {
+    fn from(hit: SearchHit) -> Self {
+        Self {
+            message_id: hit.message_id,
+            score: hit.score,
+            author: hit.author.to_string(),
+            timestamp_ms: hit.timestamp_ms,
+            content: hit.content,
+            reply_to: hit.reply_to,
+            edited_at: hit.edited_at,
+            highlight: hit.highlight,
+        }
+    }
+}
+
+impl SearchParams {
+    /// Converts the parameters to a query for a channel's search index.
+    fn into_query(self) -> Result<SearchQuery, SearchError> {
+        let cursor = self
+            .cursor
+            .map(|cursor| cursor.parse::<SearchCursor>())
+            .transpose()
+            .map_err(|_| SearchError::InvalidCursor)?;
+
+        Ok(SearchQuery {
+            text: self.q,
+            mode: match self.mode {
+                SearchModeParam::Exact => SearchMode::Exact,
+                SearchModeParam::Fuzzy => SearchMode::Fuzzy {
+                    distance: self.distance.unwrap_or(DEFAULT_FUZZY_DISTANCE),
+                },
+                SearchModeParam::Prefix => SearchMode::Prefix,
+                SearchModeParam::Advanced => SearchMode::Advanced,
+            },
+            sort: match self.sort {
+                SearchSortParam::Relevance => SearchSort::Relevance,
+                SearchSortParam::Recency => SearchSort::Recency,
+                SearchSortParam::Hybrid => SearchSort::Hybrid,
+            },
+            author: self.author.map(UserId),
+            from_ms: self.from,
+            to_ms: self.to,
+            limit: self.limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+            cursor,
+            snippet_chars: self.snippet_len.unwrap_or(DEFAULT_SNIPPET_CHARS),
+        })
+    }
+}
+
+/// Searches the messages in every channel the authenticated user can read.
+pub async fn handle_search_all(
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<SearchParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let query = match params.into_query() {
+        Ok(query) => query,
+        Err(err) => return search_error_response(err),
+    };
+    let limit = query.limit;
+
+    let hits = match state.search_all(user_id, query, limit).await {
+        Ok(hits) => hits,
+        Err(err) => return search_error_response(err),
+    };
+
+    let results: Vec<ChannelSearchResult> = hits
+        .into_iter()
+        .map(|hit| ChannelSearchResult {
+            channel_id: hit.channel_id.to_string(),
+            result: SearchResult::from(hit.hit),
+        })
+        .collect();
+
+    Json(results).into_response()
+}
+
+/// Searches the messages in a text channel the authenticated user can read.
+pub async fn handle_search(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    Query(params): Query<SearchParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.text_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !state.can_read(user_id, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    search_channel(&channel, params)
+}
+
+/// Searches a text channel and encodes the results as a response.
+///
+/// Shared by the endpoints for the different kinds of text channels.
+pub(crate) fn search_channel(channel: &TextChannel, params: SearchParams) -> Response {
+    let page = match params.into_query().and_then(|query| channel.search(query)) {
+        Ok(page) => page,
+        Err(err) => return search_error_response(err),
+    };
+
+    Json(SearchResults {
+        results: page.hits.into_iter().map(SearchResult::from).collect(),
+        next_cursor: page.next_cursor.map(|cursor| cursor.to_string()),
+    })
+    .into_response()
+}
+
+/// Converts an error searching a channel to a response.
+fn search_error_response(err: SearchError) -> Response {
+    match err {
+        SearchError::InvalidQuery(err) => {
+            tracing::debug!(?err, "rejected unparsable search query");
+            StatusCode::BAD_REQUEST.into_response()
+        }
+        SearchError::OffsetTooLarge(offset) => {
+            tracing::debug!(offset, "rejected search past the offset limit");
+            StatusCode::BAD_REQUEST.into_response()
+        }
+        SearchError::InvalidCursor => {
+            (StatusCode::BAD_REQUEST, "invalid search cursor").into_response()
+        }
+        SearchError::FieldNotSearchable(field) => (
+            StatusCode::BAD_REQUEST,
+            format!("the {field} field can't be searched in this mode"),
+        )
+            .into_response(),
+        SearchError::WildcardOnly => (
+            StatusCode::BAD_REQUEST,
+            "queries of only wildcards aren't supported",
+        )
+            .into_response(),
+        SearchError::UnsupportedSyntax => {
+            (StatusCode::BAD_REQUEST, "unsupported query syntax").into_response()
+        }
+        err => {
+            tracing::error!(?err, "failed to search channel");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::{
+        http::tests::{json_body, server},
+        server::{
+            channel::{
+                Channel,
+                text::{
+                    TextChannelSettings,
+                    search::{MAX_SEARCH_OFFSET, SearchKey},
+                    tests::{test_message, wait_for_commit},
+                },
+            },
+            permission::Permissions,
+        },
+    };
+
+    #[tokio::test]
+    async fn channel_search_requires_read_permissions() {
+        let server = server();
+        let state = &server.state;
+
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let channel = state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        state
+            .permissions()
+            .read()
+            .set_permissions(UserId(2), Permissions::MANAGE_MESSAGES)
+            .unwrap();
+
+        let uri = format!("/channels/{}/search?q=hello", channel.channel_id());
+        assert_eq!(
+            server.get_status(&uri, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            server
+                .get_status(&uri, Some(&server.token(UserId(1))))
+                .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            server
+                .get_status(&uri, Some(&server.token(UserId(2))))
+                .await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn cursors_page_through_every_result() {
+        let server = server();
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let mut created_ids = Vec::new();
+        for i in 0..5 {
+            let created = channel
+                .create_message(
+                    test_message(UserId(1), &format!("bonfire {i}")),
+                    None,
+                    Permissions::NONE,
+                )
+                .await
+                .unwrap();
+            created_ids.push(created.id.to_string());
+        }
+        wait_for_commit().await;
+
+        let token = server.token(UserId(1));
+        let base = format!(
+            "/channels/{}/search?q=bonfire&limit=2",
+            channel.channel_id()
+        );
+
+        let mut contents = Vec::new();
+        let mut message_ids = Vec::new();
+        let mut pages = 0;
+        let mut uri = base.clone();
+        loop {
+            let response = server.request(Method::GET, &uri, Some(&token), None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = json_body(response).await;
+            pages += 1;
+
+            for result in body["results"].as_array().unwrap() {
+                contents.push(result["content"].as_str().unwrap().to_string());
+                message_ids.push(result["message_id"].as_str().unwrap().to_string());
+            }
+
+            match body["next_cursor"].as_str() {
+                Some(cursor) => uri = format!("{base}&cursor={cursor}"),
+                None => break,
+            }
+        }
+
+        // Each result is returned exactly once.
+        contents.sort();
+        assert_eq!(
+            contents,
+            [
+                "bonfire 0",
+                "bonfire 1",
+                "bonfire 2",
+                "bonfire 3",
+                "bonfire 4"
+            ]
+        );
+        assert_eq!(pages, 3);
+
+        // Results carry the ID of their message, as a string like other IDs.
+        message_ids.sort();
+        created_ids.sort();
+        assert_eq!(message_ids, created_ids);
+
+        // Deep pages are refused rather than paying to run the query that deep.
+        let last = SearchKey::new(SearchSort::Hybrid, 1.0, 0, MessageId(1));
+        let deep = SearchCursor::new(SearchSort::Hybrid, last, 0, MAX_SEARCH_OFFSET + 1);
+        let uri = format!("{base}&cursor={deep}");
+        assert_eq!(
+            server.get_status(&uri, Some(&token)).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        // Cursors are only accepted from searches with the same sort.
+        let other_sort = SearchCursor::new(SearchSort::Recency, last, 0, 2);
+        for cursor in [other_sort.to_string(), "2".to_string()] {
+            let uri = format!("{base}&cursor={cursor}");
+            assert_eq!(
+                server.get_status(&uri, Some(&token)).await,
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::stats::{ChannelStats, ServerStats},
+};
+
+/// A snapshot of the server's activity.
+#[derive(Serialize, JsonSchema)]
+pub struct StatsResponse {
+    text_channels: usize,
+    direct_channels: usize,
+    messages: u64,
+    active_sessions: usize,
+    channels: Vec<ChannelStatsResponse>,
+    /// Persisted channels that couldn't be loaded, and are unavailable.
+    failed_channels: Vec<FailedChannelResponse>,
+}
+
+/// A snapshot of a channel's activity.
+#[derive(Serialize, JsonSchema)]
+pub struct ChannelStatsResponse {
+    id: ChannelId,
+    message_count: u64,
+    subscribers: usize,
+}
+
+/// A persisted channel that couldn't be loaded.
+#[derive(Serialize, JsonSchema)]
+pub struct FailedChannelResponse {
+    id: ChannelId,
+    reason: String,
+}
+
+impl From<ServerStats> for StatsResponse {
+    fn from(stats: ServerStats) -> Self {
+        Self {
+            text_channels: stats.text_channels,
+            direct_channels: stats.direct_channels,
+            messages: stats.messages,
+            active_sessions: stats.active_sessions,
+            channels: stats.channels.into_iter().map(Into::into).collect(),
+            failed_channels: stats
+                .failed_channels
+                .into_iter()
+                .map(|failed| FailedChannelResponse {
+                    id: failed.channel_id,
+                    reason: failed.reason,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<ChannelStats> for ChannelStatsResponse {
+    fn from(stats: ChannelStats) -> Self {
+        Self {
+            id: stats.channel_id,
+            message_count: stats.message_count,
+            subscribers: stats.subscribers,
+        }
+    }
+}
+
+/// Reports the server's activity to admins.
+pub async fn handle_stats(
+    AuthUser(user_id): AuthUser,
+    State(state): State<SharedState>,
+) -> Response {
+    if !state.permissions().read().is_admin(user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    Json(StatsResponse::from(state.stats())).into_response()
+}
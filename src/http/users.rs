@@ -0,0 +1,42 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    http::{SharedState, auth::AuthUser},
+    user::UserId,
+};
+
+/// A user's public profile.
+#[derive(Serialize, JsonSchema)]
+pub struct UserProfileResponse {
+    id: UserId,
+    /// Whether the user has a client connected to the gateway.
+    online: bool,
+    /// When the user's last gateway connection closed, in seconds since
+    /// the Unix epoch. Unset if the user has never connected.
+    last_seen_s: Option<i64>,
+}
+
+/// Returns a user's profile, including when they were last online.
+pub async fn handle_get_user(
+    AuthUser(_): AuthUser,
+    Path(user_id): Path<String>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(user_id) = user_id.parse::<UserId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    Json(UserProfileResponse {
+        id: user_id,
+        online: state.gateway().read().is_online(user_id),
+        last_seen_s: state.presence().read().last_seen(user_id),
+    })
+    .into_response()
+}
@@ -0,0 +1,126 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser},
+    server::{
+        channel::voice::{VoiceParticipant, VoiceState, VoiceStateError},
+        permission::Permissions,
+    },
+    user::UserId,
+};
+
+/// A user connected to a voice channel.
+#[derive(Serialize, JsonSchema)]
+pub struct VoiceParticipantResponse {
+    user: UserId,
+    /// When the user joined the channel, in milliseconds since the Unix epoch.
+    joined_at_ms: u64,
+    /// The number of the user's devices connected to the channel.
+    devices: usize,
+    #[serde(flatten)]
+    state: VoiceStateResponse,
+}
+
+/// Whether a participant is muted or deafened.
+#[derive(Serialize, JsonSchema)]
+pub struct VoiceStateResponse {
+    /// The participant muted their microphone.
+    self_mute: bool,
+    /// The participant muted the channel's audio.
+    self_deaf: bool,
+    /// A moderator muted the participant.
+    server_mute: bool,
+    /// A moderator deafened the participant.
+    server_deaf: bool,
+}
+
+impl From<VoiceState> for VoiceStateResponse {
+    fn from(state: VoiceState) -> Self {
+        Self {
+            self_mute: state.self_mute,
+            self_deaf: state.self_deaf,
+            server_mute: state.server_mute,
+            server_deaf: state.server_deaf,
+        }
+    }
+}
+
+/// Request to mute or deafen a participant on the server.
+#[derive(Deserialize, JsonSchema)]
+pub struct ServerVoiceStateRequest {
+    /// Mutes the participant for everyone in the channel.
+    mute: bool,
+    /// Mutes the channel's audio for the participant.
+    deaf: bool,
+}
+
+impl From<VoiceParticipant> for VoiceParticipantResponse {
+    fn from(participant: VoiceParticipant) -> Self {
+        Self {
+            user: participant.user,
+            joined_at_ms: participant.joined_at_ms,
+            devices: participant.devices,
+            state: participant.state.into(),
+        }
+    }
+}
+
+/// Lists the users connected to a voice channel, ordered by when they joined.
+pub async fn handle_list_participants(
+    AuthUser(_): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.voice_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let participants: Vec<VoiceParticipantResponse> =
+        channel.participants().into_iter().map(Into::into).collect();
+
+    Json(participants).into_response()
+}
+
+/// Mutes or deafens a participant of a voice channel, for moderators.
+pub async fn handle_set_server_state(
+    AuthUser(user_id): AuthUser,
+    Path((channel_id, participant)): Path<(String, String)>,
+    State(state): State<SharedState>,
+    Json(request): Json<ServerVoiceStateRequest>,
+) -> impl IntoResponse {
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MUTE_MEMBERS)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let (Ok(channel_id), Ok(participant)) = (
+        channel_id.parse::<ChannelId>(),
+        participant.parse::<UserId>(),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Some(channel) = state.voice_channel(channel_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match channel.set_server_state(participant, request.mute, request.deaf) {
+        Ok(voice_state) => Json(VoiceStateResponse::from(voice_state)).into_response(),
+        Err(VoiceStateError::NotConnected) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
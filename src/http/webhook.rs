@@ -0,0 +1,133 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelId,
+    http::{SharedState, auth::AuthUser, messages::create_message_error_response},
+    message::{MessageFlags, MessageId, MessageMentions},
+    server::{
+        channel::text::TextChannelMessage,
+        permission::Permissions,
+        webhook::{WebhookError, WebhookId},
+    },
+    user::SYSTEM_USER_ID,
+};
+
+/// Response returned when a webhook is created.
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedWebhook {
+    /// ID of the created webhook.
+    id: String,
+    /// Secret token used to execute the webhook.
+    token: String,
+}
+
+/// Request body for posting a message through a webhook.
+#[derive(Deserialize, JsonSchema)]
+pub struct WebhookMessage {
+    /// Text content of the message.
+    content: String,
+    /// Optional name to display as the message author.
+    username: Option<String>,
+    /// The message in the channel that this message replies to.
+    reply_to: Option<MessageId>,
+}
+
+/// Creates a new webhook for a channel, for users that can manage channels.
+pub async fn handle_create(
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<String>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let Ok(channel_id) = channel_id.parse::<ChannelId>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    if !state
+        .permissions()
+        .read()
+        .has(user_id, Permissions::MANAGE_CHANNELS)
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if state.text_channel(channel_id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let webhook = match state.webhooks().write().create_webhook(channel_id) {
+        Ok(webhook) => webhook,
+        Err(err) => {
+            tracing::error!(?err, "failed to create webhook");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    Json(CreatedWebhook {
+        id: webhook.id.to_string(),
+        token: webhook.token,
+    })
+    .into_response()
+}
+
+/// Posts a message to a channel through a webhook.
+pub async fn handle_execute(
+    Path((webhook_id, token)): Path<(String, String)>,
+    State(state): State<SharedState>,
+    Json(body): Json<WebhookMessage>,
+) -> impl IntoResponse {
+    let Ok(webhook_id) = webhook_id.parse::<WebhookId>() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Verify the webhook and resolve the channel it posts to.
+    let channel = {
+        let webhook = match state
+            .webhooks()
+            .write()
+            .authorize_execute(webhook_id, &token)
+        {
+            Ok(webhook) => webhook,
+            Err(WebhookError::NotFound) => return StatusCode::NOT_FOUND.into_response(),
+            Err(WebhookError::InvalidToken) => return StatusCode::UNAUTHORIZED.into_response(),
+            Err(WebhookError::RateLimited) => {
+                return StatusCode::TOO_MANY_REQUESTS.into_response();
+            }
+            Err(err) => {
+                tracing::error!(?err, "failed to load webhook");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+        let Some(channel) = state.text_channel(webhook.channel_id) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+
+        channel
+    };
+
+    // The channel attributes the message to the system author and flags it as a webhook message.
+    let message = TextChannelMessage {
+        id: MessageId::default(),
+        author: SYSTEM_USER_ID,
+        author_name: body.username,
+        timestamp_ms: Utc::now().timestamp_millis() as u64,
+        edited_at: None,
+        content: body.content,
+        reply_to: body.reply_to,
+        mentions: MessageMentions::default(),
+        flags: MessageFlags::default(),
+    };
+
+    match channel.post_webhook_message(message).await {
+        Ok(message) => Json(message).into_response(),
+        Err(err) => create_message_error_response(err),
+    }
+}
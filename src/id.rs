@@ -0,0 +1,78 @@
+//! Serialization helpers shared by the snowflake ID types.
+//!
+//! IDs are serialized as strings since JSON consumers (notably
+//! JavaScript) can't represent the full range of a `u64` as a number.
+//! Deserialization accepts either a string or an integer.
+
+use std::fmt;
+#[cfg(feature = "server")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{
+    Deserializer, Serializer,
+    de::{self, Visitor},
+};
+
+/// Builds a snowflake ID generator for the instance.
+///
+/// The timestamps of the generated IDs count milliseconds from the epoch,
+/// which is given in milliseconds since the Unix epoch. Decoding the time
+/// an ID was created at has to add the same epoch back on.
+#[cfg(feature = "server")]
+pub(crate) fn id_generator<G: From<snowflaked::Builder>>(instance_id: u16, epoch_ms: u64) -> G {
+    snowflaked::Builder::new()
+        .instance(instance_id)
+        .epoch(UNIX_EPOCH + Duration::from_millis(epoch_ms))
+        .build()
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+#[cfg(feature = "server")]
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Describes a snowflake ID in generated JSON schemas.
+///
+/// Schemas only describe the string form produced by [`serialize_id`].
+pub(crate) fn id_schema() -> schemars::Schema {
+    schemars::json_schema!({
+        "type": "string",
+        "pattern": "^[0-9]+$",
+    })
+}
+
+/// Serializes a snowflake ID as a string.
+pub(crate) fn serialize_id<S: Serializer>(id: u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&id)
+}
+
+/// Deserializes a snowflake ID from either a string or an integer.
+pub(crate) fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    deserializer.deserialize_any(IdVisitor)
+}
+
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a snowflake ID as a string or an integer")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::custom("snowflake IDs can't be negative"))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+        v.parse().map_err(E::custom)
+    }
+}
@@ -3,12 +3,18 @@ pub mod message;
 pub mod role;
 pub mod user;
 
+mod id;
+
 pub mod proto;
 
 /// Implements the server-side logic.
 #[cfg(feature = "server")]
 pub mod server;
 
+/// Typed client for the gateway.
+#[cfg(feature = "client")]
+pub mod client;
+
 /// HTTP server interface.
 #[cfg(feature = "server")]
 pub mod http;
@@ -1,7 +1,143 @@
 //! The common types for messages, including message encoding and decoding.
 
+use std::{
+    fmt::Display,
+    hash::{self, Hasher},
+    num::ParseIntError,
+    ops::{BitOr, BitOrAssign},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use snowflaked::Snowflake;
+
 use crate::{channel::ChannelId, role::RoleId, user::UserId};
 
+/// Concrete type for message ID's.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug, Default)]
+pub struct MessageId(pub u64);
+
+/// Enables for using the ID's for keys in HashMaps.
+impl hash::Hash for MessageId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0);
+    }
+}
+
+/// Enables extracting the snowflake ID components from IDs.
+impl Snowflake for MessageId {
+    fn from_parts(timestamp: u64, instance: u64, sequence: u64) -> Self {
+        Self(u64::from_parts(timestamp, instance, sequence))
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.0.timestamp()
+    }
+
+    fn instance(&self) -> u64 {
+        self.0.instance()
+    }
+
+    fn sequence(&self) -> u64 {
+        self.0.sequence()
+    }
+}
+
+/// Display IDs in logs and formatting strings.
+impl Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+/// Allows for calling parse() on strings to convert them to a message ID.
+impl FromStr for MessageId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MessageId(u64::from_str(s)?))
+    }
+}
+
+/// Serializes IDs as strings for API payloads.
+impl Serialize for MessageId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::id::serialize_id(self.0, serializer)
+    }
+}
+
+/// Deserializes IDs from either strings or integers.
+impl<'de> Deserialize<'de> for MessageId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::id::deserialize_id(deserializer).map(MessageId)
+    }
+}
+
+/// Describes IDs as strings in generated schemas.
+impl schemars::JsonSchema for MessageId {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "MessageId".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        crate::id::id_schema()
+    }
+}
+
+/// A set of attributes of a message.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(transparent)]
+pub struct MessageFlags(pub u32);
+
+impl MessageFlags {
+    /// No flags.
+    pub const NONE: MessageFlags = MessageFlags(0);
+    /// The message is pinned in it's channel.
+    ///
+    /// Maintained by the channel as the message is pinned and unpinned.
+    pub const PINNED: MessageFlags = MessageFlags(1 << 0);
+    /// Clients should read the message aloud with text-to-speech.
+    pub const TTS: MessageFlags = MessageFlags(1 << 1);
+    /// Clients shouldn't show embeds for links in the message.
+    pub const SUPPRESS_EMBEDS: MessageFlags = MessageFlags(1 << 2);
+    /// The message was posted by the server rather than a user.
+    pub const SYSTEM: MessageFlags = MessageFlags(1 << 3);
+    /// The message was posted through a webhook rather than by a user.
+    pub const WEBHOOK: MessageFlags = MessageFlags(1 << 4);
+    /// The flags users can set when sending or editing their messages.
+    pub const USER_SETTABLE: MessageFlags = MessageFlags(Self::TTS.0 | Self::SUPPRESS_EMBEDS.0);
+
+    /// Returns true if every flag in `other` is set.
+    pub fn contains(self, other: MessageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears every flag in `flags`.
+    pub fn set(&mut self, flags: MessageFlags, enabled: bool) {
+        if enabled {
+            self.0 |= flags.0;
+        } else {
+            self.0 &= !flags.0;
+        }
+    }
+}
+
+impl BitOr for MessageFlags {
+    type Output = MessageFlags;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        MessageFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MessageFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
 pub const ROLE_BLOCK_PREFIX: &str = "@&";
 pub const USER_BLOCK_PREFIX: &str = "@";
 pub const CHANNEL_BLOCK_PREFIX: &str = "#";
@@ -28,7 +164,11 @@ pub fn format_timestamp(unix_timestamp: i64) -> String {
 }
 
 /// Represents a block of message content.
-#[derive(Clone, PartialEq, Eq)]
+///
+/// Serialized with a "type" field identifying the
+/// kind of block, and the block's value in "value".
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, schemars::JsonSchema)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
 pub enum MessageBlock {
     User(UserId),
     Channel(ChannelId),
@@ -38,8 +178,46 @@ pub enum MessageBlock {
 }
 
 /// Represents the contents of a message.
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+#[serde(transparent)]
 pub struct MessageContent(pub Vec<MessageBlock>);
 
+impl MessageContent {
+    /// Collects the users, roles, and channels mentioned in the message.
+    ///
+    /// Each mention is only listed once, in the order it first appears.
+    pub fn mentions(&self) -> MessageMentions {
+        let mut mentions = MessageMentions::default();
+
+        for block in &self.0 {
+            match *block {
+                MessageBlock::User(id) if !mentions.users.contains(&id) => mentions.users.push(id),
+                MessageBlock::Role(id) if !mentions.roles.contains(&id) => mentions.roles.push(id),
+                MessageBlock::Channel(id) if !mentions.channels.contains(&id) => {
+                    mentions.channels.push(id)
+                }
+                _ => {}
+            }
+        }
+
+        mentions
+    }
+}
+
+/// The users, roles, and channels mentioned in a message's content.
+///
+/// Sent alongside the raw content so clients don't have to
+/// decode the message to find out who was mentioned.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct MessageMentions {
+    #[serde(default)]
+    pub users: Vec<UserId>,
+    #[serde(default)]
+    pub roles: Vec<RoleId>,
+    #[serde(default)]
+    pub channels: Vec<ChannelId>,
+}
+
 /// Decode a message contents block from a string.
 impl From<&str> for MessageContent {
     fn from(value: &str) -> Self {
@@ -82,30 +260,30 @@ pub fn decode_message_part(original_part: &str) -> MessageBlock {
             return MessageBlock::Text(original_part.to_string());
         };
 
-        return MessageBlock::Role(role_id);
+        MessageBlock::Role(role_id)
     } else if let Some(user) = stripped_part.strip_prefix(USER_BLOCK_PREFIX) {
         // Attempt to parse the string to an integer ID.
         let Ok(user_id) = user.parse() else {
             return MessageBlock::Text(original_part.to_string());
         };
 
-        return MessageBlock::User(user_id);
+        MessageBlock::User(user_id)
     } else if let Some(channel) = stripped_part.strip_prefix(CHANNEL_BLOCK_PREFIX) {
         // Attempt to parse the string to an integer ID.
         let Ok(channel_id) = channel.parse() else {
             return MessageBlock::Text(original_part.to_string());
         };
 
-        return MessageBlock::User(channel_id);
+        MessageBlock::Channel(channel_id)
     } else if let Some(timestamp) = stripped_part.strip_prefix(TIMESTAMP_BLOCK_PREFIX) {
         // Attempt to parse the string to an integer ID.
         let Ok(timestamp) = timestamp.parse() else {
             return MessageBlock::Text(original_part.to_string());
         };
 
-        return MessageBlock::Timestamp(timestamp);
+        MessageBlock::Timestamp(timestamp)
     } else {
-        return MessageBlock::Text(original_part.to_string());
+        MessageBlock::Text(original_part.to_string())
     }
 }
 
@@ -114,7 +292,7 @@ pub fn decode_message(contents: &str) -> MessageContent {
     MessageContent(
         contents
             .split_whitespace()
-            .map(|w| decode_message_part(w))
+            .map(decode_message_part)
             .collect(),
     )
 }
@@ -3,7 +3,332 @@
 //! The gateway types are also used via `serde_json` to
 //! encode and decode the event messages for sockets
 //! that request JSON encoding.
+//!
+//! The REST API responds with JSON by default, and
+//! with the `v0::api` types to clients that accept
+//! Protobuf.
+
+use std::{fmt::Display, str::FromStr};
 
 pub mod v0 {
     include!(concat!(env!("OUT_DIR"), "/v0.gateway.rs"));
+
+    /// Types returned by the REST API to clients that request Protobuf.
+    pub mod api {
+        include!(concat!(env!("OUT_DIR"), "/v0.api.rs"));
+    }
+}
+
+pub mod v1 {
+    include!(concat!(env!("OUT_DIR"), "/v1.gateway.rs"));
+
+    use super::v0;
+
+    // The server works with the v0 types internally, so v1 messages
+    // are converted at the edge of the gateway connection.
+
+    impl From<GatewayIdentify> for v0::GatewayIdentify {
+        fn from(identify: GatewayIdentify) -> Self {
+            Self {
+                token: identify.token,
+                client_type: identify.client_type,
+                client_agent: identify.client_agent,
+                resume_session_id: identify.resume_session_id,
+                resume_seq: identify.resume_seq,
+                intents: identify.intents,
+            }
+        }
+    }
+
+    impl From<v0::GatewayCapabilities> for GatewayCapabilities {
+        fn from(capabilities: v0::GatewayCapabilities) -> Self {
+            Self {
+                encodings: capabilities.encodings,
+                compression: capabilities.compression,
+                heartbeat_interval_ms: capabilities.heartbeat_interval_ms,
+                max_message_bytes: capabilities.max_message_bytes,
+                intents: capabilities.intents,
+            }
+        }
+    }
+
+    impl From<v0::GatewayServerEvent> for GatewayServerEvent {
+        fn from(event: v0::GatewayServerEvent) -> Self {
+            Self {
+                event: event.event.map(|event| match event {
+                    v0::gateway_server_event::Event::Message(message) => {
+                        gateway_server_event::Event::Message(message)
+                    }
+                    v0::gateway_server_event::Event::ChannelCreated(created) => {
+                        gateway_server_event::Event::ChannelCreated(ChannelCreated {
+                            id: created.id,
+                            label: created.label,
+                            channel_type: created.channel_type,
+                        })
+                    }
+                    v0::gateway_server_event::Event::ChannelUpdated(updated) => {
+                        gateway_server_event::Event::ChannelUpdated(ChannelUpdated {
+                            id: updated.id,
+                            label: updated.label,
+                            channel_type: updated.channel_type,
+                            topic: updated.topic,
+                        })
+                    }
+                    v0::gateway_server_event::Event::ChannelDeleted(deleted) => {
+                        gateway_server_event::Event::ChannelDeleted(ChannelDeleted {
+                            id: deleted.id,
+                        })
+                    }
+                    v0::gateway_server_event::Event::HeartbeatAck(ack) => {
+                        gateway_server_event::Event::HeartbeatAck(HeartbeatAck { seq: ack.seq })
+                    }
+                    v0::gateway_server_event::Event::SessionReady(ready) => {
+                        gateway_server_event::Event::SessionReady(SessionReady {
+                            session_id: ready.session_id,
+                            resumed: ready.resumed,
+                        })
+                    }
+                    v0::gateway_server_event::Event::Mentioned(mentioned) => {
+                        gateway_server_event::Event::Mentioned(Mentioned {
+                            channel_id: mentioned.channel_id,
+                            message_id: mentioned.message_id,
+                        })
+                    }
+                    v0::gateway_server_event::Event::MessageAck(ack) => {
+                        gateway_server_event::Event::MessageAck(MessageAck {
+                            channel_id: ack.channel_id,
+                            message_id: ack.message_id,
+                            nonce: ack.nonce,
+                            error: ack.error,
+                        })
+                    }
+                    v0::gateway_server_event::Event::MessageCreated(created) => {
+                        gateway_server_event::Event::MessageCreated(MessageCreated {
+                            channel_id: created.channel_id,
+                            message_id: created.message_id,
+                            author: created.author,
+                            author_name: created.author_name,
+                            content: created.content,
+                            timestamp_ms: created.timestamp_ms,
+                            reply_to: created.reply_to,
+                            mentions: created.mentions.map(MessageMentions::from),
+                            flags: created.flags,
+                        })
+                    }
+                    v0::gateway_server_event::Event::MessageEdited(edited) => {
+                        gateway_server_event::Event::MessageEdited(MessageEdited {
+                            channel_id: edited.channel_id,
+                            message_id: edited.message_id,
+                            content: edited.content,
+                            mentions: edited.mentions.map(MessageMentions::from),
+                            timestamp_ms: edited.timestamp_ms,
+                            edited_at_ms: edited.edited_at_ms,
+                            flags: edited.flags,
+                        })
+                    }
+                    v0::gateway_server_event::Event::PinsUpdated(updated) => {
+                        gateway_server_event::Event::PinsUpdated(PinsUpdated {
+                            channel_id: updated.channel_id,
+                            pinned: updated.pinned,
+                        })
+                    }
+                    v0::gateway_server_event::Event::MessagesDeleted(deleted) => {
+                        gateway_server_event::Event::MessagesDeleted(MessagesDeleted {
+                            channel_id: deleted.channel_id,
+                            message_ids: deleted.message_ids,
+                        })
+                    }
+                    v0::gateway_server_event::Event::MessagesDeletedInRange(deleted) => {
+                        gateway_server_event::Event::MessagesDeletedInRange(
+                            MessagesDeletedInRange {
+                                channel_id: deleted.channel_id,
+                                start_ms: deleted.start_ms,
+                                end_ms: deleted.end_ms,
+                                count: deleted.count,
+                            },
+                        )
+                    }
+                    v0::gateway_server_event::Event::MessageRejected(rejected) => {
+                        gateway_server_event::Event::MessageRejected(MessageRejected {
+                            channel_id: rejected.channel_id,
+                            reason: rejected.reason,
+                            retry_after_ms: rejected.retry_after_ms,
+                        })
+                    }
+                    v0::gateway_server_event::Event::MessageFlagged(flagged) => {
+                        gateway_server_event::Event::MessageFlagged(MessageFlagged {
+                            channel_id: flagged.channel_id,
+                            message_id: flagged.message_id,
+                            author: flagged.author,
+                            reason: flagged.reason,
+                        })
+                    }
+                    v0::gateway_server_event::Event::VoiceParticipantJoined(joined) => {
+                        gateway_server_event::Event::VoiceParticipantJoined(
+                            VoiceParticipantJoined {
+                                channel_id: joined.channel_id,
+                                user: joined.user,
+                                joined_at_ms: joined.joined_at_ms,
+                            },
+                        )
+                    }
+                    v0::gateway_server_event::Event::VoiceParticipantLeft(left) => {
+                        gateway_server_event::Event::VoiceParticipantLeft(VoiceParticipantLeft {
+                            channel_id: left.channel_id,
+                            user: left.user,
+                        })
+                    }
+                    v0::gateway_server_event::Event::VoiceStateUpdated(updated) => {
+                        gateway_server_event::Event::VoiceStateUpdated(VoiceStateUpdated {
+                            channel_id: updated.channel_id,
+                            user: updated.user,
+                            self_mute: updated.self_mute,
+                            self_deaf: updated.self_deaf,
+                            server_mute: updated.server_mute,
+                            server_deaf: updated.server_deaf,
+                        })
+                    }
+                    v0::gateway_server_event::Event::AttachmentAdded(added) => {
+                        gateway_server_event::Event::AttachmentAdded(AttachmentAdded {
+                            channel_id: added.channel_id,
+                            message_id: added.message_id,
+                            attachment: added.attachment.map(Attachment::from),
+                        })
+                    }
+                }),
+                seq: event.seq,
+            }
+        }
+    }
+
+    impl From<v0::MessageMentions> for MessageMentions {
+        fn from(mentions: v0::MessageMentions) -> Self {
+            Self {
+                users: mentions.users,
+                roles: mentions.roles,
+                channels: mentions.channels,
+            }
+        }
+    }
+
+    impl From<v0::Attachment> for Attachment {
+        fn from(attachment: v0::Attachment) -> Self {
+            Self {
+                id: attachment.id,
+                filename: attachment.filename,
+                content_type: attachment.content_type,
+                size: attachment.size,
+                data: attachment.data,
+            }
+        }
+    }
+
+    impl From<GatewayClientEvent> for v0::GatewayClientEvent {
+        fn from(event: GatewayClientEvent) -> Self {
+            Self {
+                event: event.event.map(|event| match event {
+                    gateway_client_event::Event::Message(message) => {
+                        v0::gateway_client_event::Event::Message(message)
+                    }
+                    gateway_client_event::Event::Heartbeat(heartbeat) => {
+                        v0::gateway_client_event::Event::Heartbeat(v0::Heartbeat {
+                            seq: heartbeat.seq,
+                        })
+                    }
+                    gateway_client_event::Event::VoiceStateUpdate(update) => {
+                        v0::gateway_client_event::Event::VoiceStateUpdate(v0::VoiceStateUpdate {
+                            channel_id: update.channel_id,
+                            self_mute: update.self_mute,
+                            self_deaf: update.self_deaf,
+                        })
+                    }
+                    gateway_client_event::Event::VoiceJoin(join) => {
+                        v0::gateway_client_event::Event::VoiceJoin(v0::VoiceJoin {
+                            channel_id: join.channel_id,
+                            join: join.join,
+                        })
+                    }
+                    gateway_client_event::Event::CreateMessage(create) => {
+                        v0::gateway_client_event::Event::CreateMessage(v0::CreateMessage {
+                            channel_id: create.channel_id,
+                            content: create.content,
+                            reply_to: create.reply_to,
+                            nonce: create.nonce,
+                            client_sent_at_ms: create.client_sent_at_ms,
+                            flags: create.flags,
+                        })
+                    }
+                    gateway_client_event::Event::WatchChannel(watch) => {
+                        v0::gateway_client_event::Event::WatchChannel(v0::WatchChannel {
+                            channel_id: watch.channel_id,
+                            watch: watch.watch,
+                        })
+                    }
+                }),
+            }
+        }
+    }
+}
+
+/// Serializes Protobuf `bytes` fields as base64 strings in JSON.
+///
+/// Serde would otherwise encode the bytes as an array of numbers,
+/// which is several times larger than the bytes themselves.
+pub(crate) mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
+    }
+}
+
+/// Identifies a version of the gateway protocol.
+///
+/// Clients that don't request a version use the original protocol.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GatewayVersion {
+    #[default]
+    V0,
+    V1,
+}
+
+impl GatewayVersion {
+    /// The gateway protocol versions supported by the server.
+    pub const SUPPORTED: &[GatewayVersion] = &[GatewayVersion::V0, GatewayVersion::V1];
+
+    /// Returns the identifier used to select the version.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GatewayVersion::V0 => "v0",
+            GatewayVersion::V1 => "v1",
+        }
+    }
+}
+
+impl Display for GatewayVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Indicates the requested gateway version isn't supported.
+#[derive(Debug)]
+pub struct UnsupportedVersion(pub String);
+
+impl FromStr for GatewayVersion {
+    type Err = UnsupportedVersion;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GatewayVersion::SUPPORTED
+            .iter()
+            .copied()
+            .find(|version| version.as_str() == s)
+            .ok_or_else(|| UnsupportedVersion(s.to_string()))
+    }
 }
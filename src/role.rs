@@ -7,6 +7,7 @@ use std::{
     str::FromStr,
 };
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use snowflaked::Snowflake;
 
 /// Concrete type for role ID's.
@@ -54,3 +55,28 @@ impl FromStr for RoleId {
         Ok(RoleId(u64::from_str(s)?))
     }
 }
+
+/// Serializes IDs as strings for API payloads.
+impl Serialize for RoleId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::id::serialize_id(self.0, serializer)
+    }
+}
+
+/// Deserializes IDs from either strings or integers.
+impl<'de> Deserialize<'de> for RoleId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::id::deserialize_id(deserializer).map(RoleId)
+    }
+}
+
+/// Describes IDs as strings in generated schemas.
+impl schemars::JsonSchema for RoleId {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "RoleId".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        crate::id::id_schema()
+    }
+}
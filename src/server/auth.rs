@@ -1,3 +1,9 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EmptyExtraTokenFields,
     EndpointNotSet, EndpointSet, RedirectUrl, RevocationErrorResponseType, Scope,
@@ -6,14 +12,28 @@ use oauth2::{
     basic::{BasicClient, BasicErrorResponseType, BasicTokenType},
     reqwest,
 };
+use parking_lot::Mutex;
+use serde::Deserialize;
 
-use crate::{server::auth, user::UserId};
+use crate::{
+    server::{
+        bot_token::{BotTokenError, BotTokenStore, MintedBotToken},
+        oauth2_account::OAuth2AccountStore,
+        session_token::{IssuedSessionToken, SessionTokenError, SessionTokenStore},
+    },
+    user::UserId,
+};
 
 /// Configures an OAuth2 client that can be used for configuration.
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(from = "OauthClientConfig")]
 pub struct OauthClient {
     /// Provider ID used in lcoal application URLs.
     pub id: String,
+    /// Name of the provider shown to users on the login page.
+    ///
+    /// Defaults to the provider ID when deserialized without a label.
+    pub label: String,
     /// OAuth2 application client ID.
     pub client_id: String,
     /// OAuth2 application client secret.
@@ -22,6 +42,11 @@ pub struct OauthClient {
     pub auth_url: String,
     /// OAuth2 application token URI.
     pub token_url: String,
+    /// URI of the provider's API that returns the logged in account.
+    ///
+    /// The account is identified by the `id` field of the returned
+    /// JSON object, or the `sub` field for OpenID Connect providers.
+    pub user_url: String,
     /// Additional OAuth2 scopes that the application
     /// should request from the OAuth2 server.
     ///
@@ -31,6 +56,36 @@ pub struct OauthClient {
     pub scopes: Vec<String>,
 }
 
+/// The serialized form of [`OauthClient`], where the label is optional.
+#[derive(Deserialize)]
+struct OauthClientConfig {
+    id: String,
+    label: Option<String>,
+    client_id: String,
+    client_secret: String,
+    auth_url: String,
+    token_url: String,
+    user_url: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// Defaults the label to the provider ID when it isn't set.
+impl From<OauthClientConfig> for OauthClient {
+    fn from(config: OauthClientConfig) -> Self {
+        Self {
+            label: config.label.unwrap_or_else(|| config.id.clone()),
+            id: config.id,
+            client_id: config.client_id,
+            client_secret: config.client_secret,
+            auth_url: config.auth_url,
+            token_url: config.token_url,
+            user_url: config.user_url,
+            scopes: config.scopes,
+        }
+    }
+}
+
 pub type OAuth2Client = oauth2::Client<
     StandardErrorResponse<BasicErrorResponseType>,
     StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>,
@@ -66,26 +121,284 @@ impl OauthClient {
 pub struct AuthConfig {
     /// OAuth2 clients that can be used by users to authenticate with SSO.
     pub oauth2_clients: Vec<OauthClient>,
+    /// Attributes of the cookie the token is stored in after a web login.
+    pub cookie: TokenCookieConfig,
+}
+
+/// How long the session tokens issued by a web login are valid for by default.
+pub const DEFAULT_SESSION_LIFETIME: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Restricts when browsers send the token cookie with cross-site requests.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CookieSameSite {
+    /// Only sent with requests from the server's own site.
+    Strict,
+    /// Also sent when the user navigates to the server from another site.
+    #[default]
+    Lax,
+    /// Sent with every request, such as from a web client hosted on
+    /// another site. Requires the cookie to be secure.
+    None,
+}
+
+/// Attributes of the cookie the token is stored in after a web login.
+#[derive(Clone, Debug)]
+pub struct TokenCookieConfig {
+    /// Only send the cookie over HTTPS.
+    ///
+    /// Defaults to false in debug builds, since Safari won't store
+    /// secure cookies set by `localhost`.
+    pub secure: bool,
+    /// Restricts when the cookie is sent with cross-site requests.
+    pub same_site: CookieSameSite,
+    /// Domain the cookie is sent to, including it's subdomains.
+    ///
+    /// Unset to only send the cookie to the host that set it.
+    pub domain: Option<String>,
+    /// How long the session tokens issued by a web login are valid for.
+    ///
+    /// This is the lifetime of the session token, not a fixed cookie
+    /// `Max-Age`. The cookie's `Max-Age` is derived from the expiry of the
+    /// token it holds, so a refreshed token is kept for a full lifetime again.
+    pub session_lifetime: Duration,
+}
+
+impl Default for TokenCookieConfig {
+    fn default() -> Self {
+        Self {
+            secure: !cfg!(debug_assertions),
+            same_site: CookieSameSite::default(),
+            domain: None,
+            session_lifetime: DEFAULT_SESSION_LIFETIME,
+        }
+    }
+}
+
+/// How long a user has to complete an OAuth2 login before the state expires.
+pub const OAUTH2_STATE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Indicates why an OAuth2 code exchange was rejected.
+#[derive(Debug)]
+pub enum OAuth2Error {
+    /// Indicates the requested provider isn't configured.
+    UnknownProvider,
+    /// Indicates the CSRF state wasn't issued for the provider, or has expired.
+    InvalidState,
+    /// Indicates the CSRF state was already used by another callback.
+    StateAlreadyUsed,
+    /// Indicates the provider rejected the code exchange.
+    ExchangeFailed,
+    /// Indicates the logged in account couldn't be fetched from the provider.
+    AccountLookupFailed,
+    /// Indicates the account couldn't be linked to a user, or their token couldn't be issued.
+    LoginFailed,
+}
+
+/// A CSRF state issued for an OAuth2 login.
+struct OAuth2State {
+    /// The provider the state was issued for.
+    provider: String,
+    /// The callback URL the provider was told to redirect to.
+    ///
+    /// The code exchange has to send the same URL.
+    redirect_url: RedirectUrl,
+    /// When the state stops being accepted.
+    expires_at: Instant,
+    /// Set once a callback has consumed the state.
+    ///
+    /// Consumed states are kept until they expire so
+    /// replayed callbacks can be reported as such.
+    consumed: bool,
 }
 
 pub struct AuthService {
     config: AuthConfig,
+
+    /// CSRF states issued for in-progress OAuth2 logins, keyed by the state secret.
+    oauth2_states: Mutex<HashMap<String, OAuth2State>>,
+
+    /// Storage for the tokens minted for bots.
+    bot_tokens: BotTokenStore,
+
+    /// Storage for the expiring tokens issued to users when they log in.
+    ///
+    /// Shared with the OAuth2 code exchanges, which outlive the service lock.
+    session_tokens: Arc<SessionTokenStore>,
+
+    /// Storage for the provider accounts users log in with.
+    oauth2_accounts: Arc<OAuth2AccountStore>,
+
+    /// HTTP client shared by the OAuth2 requests.
+    ///
+    /// The client pools connections internally, so it's cheap to clone.
+    http_client: reqwest::Client,
+}
+
+/// Indicates there was an error constructing the auth service.
+#[derive(Debug)]
+pub enum AuthServiceError {
+    /// Indicates the HTTP client used for OAuth2 couldn't be built,
+    /// typically because the TLS backend failed to initialize.
+    HttpClientError(reqwest::Error),
+    /// Indicates the bot token, session token, or account keyspace couldn't be opened.
+    DatabaseError(fjall::Error),
+}
+
+/// Indicates there was an error issuing, refreshing, or revoking an authentication token.
+#[derive(Debug)]
+pub enum TokenError {
+    BotToken(BotTokenError),
+    SessionToken(SessionTokenError),
 }
 
 impl AuthService {
-    pub fn new(config: AuthConfig) -> Self {
-        Self { config }
+    pub fn new(
+        config: AuthConfig,
+        db: &fjall::Database,
+        instance_id: u16,
+        snowflake_epoch_ms: u64,
+    ) -> Result<Self, AuthServiceError> {
+        let bot_tokens = BotTokenStore::open(db, instance_id, snowflake_epoch_ms)
+            .map_err(AuthServiceError::DatabaseError)?;
+
+        let session_tokens = SessionTokenStore::open(
+            db,
+            instance_id,
+            snowflake_epoch_ms,
+            config.cookie.session_lifetime,
+        )
+        .map_err(AuthServiceError::DatabaseError)?;
+
+        let oauth2_accounts = OAuth2AccountStore::open(db, instance_id, snowflake_epoch_ms)
+            .map_err(AuthServiceError::DatabaseError)?;
+
+        // Construct the HTTP client shared by all OAuth2 requests.
+        let http_client = reqwest::ClientBuilder::new()
+            // Following redirects opens the client up to SSRF vulnerabilities.
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(AuthServiceError::HttpClientError)?;
+
+        Ok(Self {
+            config,
+            oauth2_states: Mutex::new(HashMap::new()),
+            bot_tokens,
+            session_tokens: Arc::new(session_tokens),
+            oauth2_accounts: Arc::new(oauth2_accounts),
+            http_client,
+        })
+    }
+
+    /// Records a CSRF state issued for a login with the provider.
+    fn store_oauth2_state(&self, provider: &str, state: &CsrfToken, redirect_url: RedirectUrl) {
+        let now = Instant::now();
+        let mut states = self.oauth2_states.lock();
+
+        // Drop any states for logins that were never completed.
+        states.retain(|_, s| s.expires_at > now);
+
+        states.insert(
+            state.secret().clone(),
+            OAuth2State {
+                provider: provider.to_string(),
+                redirect_url,
+                expires_at: now + OAUTH2_STATE_TTL,
+                consumed: false,
+            },
+        );
+    }
+
+    /// Atomically consumes a CSRF state returned to the callback.
+    ///
+    /// Only the first callback with a state succeeds, so a replayed
+    /// callback can't exchange the same code a second time.
+    ///
+    /// Returns the callback URL the login was started with.
+    fn consume_oauth2_state(
+        &self,
+        provider: &str,
+        state: &CsrfToken,
+    ) -> Result<RedirectUrl, OAuth2Error> {
+        let mut states = self.oauth2_states.lock();
+
+        let Some(entry) = states.get_mut(state.secret()) else {
+            return Err(OAuth2Error::InvalidState);
+        };
+
+        if entry.expires_at <= Instant::now() || entry.provider != provider {
+            return Err(OAuth2Error::InvalidState);
+        }
+
+        if entry.consumed {
+            return Err(OAuth2Error::StateAlreadyUsed);
+        }
+
+        entry.consumed = true;
+
+        Ok(entry.redirect_url.clone())
+    }
+
+    /// Returns the OAuth2 providers users can log in with.
+    pub fn oauth2_clients(&self) -> &[OauthClient] {
+        &self.config.oauth2_clients
     }
 
     /// Validates the supplied authentication token.
-    pub fn validate_token(&self, token: &String) -> Option<UserId> {
-        None
+    pub fn validate_token(&self, token: &str) -> Option<UserId> {
+        self.bot_tokens
+            .validate(token)
+            .or_else(|| self.session_tokens.validate(token))
+    }
+
+    /// Revokes the supplied authentication token.
+    ///
+    /// Returns false if the token isn't one that can be revoked.
+    pub fn revoke_token(&self, token: &str) -> Result<bool, TokenError> {
+        if self
+            .bot_tokens
+            .revoke(token)
+            .map_err(TokenError::BotToken)?
+        {
+            return Ok(true);
+        }
+
+        self.session_tokens
+            .revoke(token)
+            .map_err(TokenError::SessionToken)
+    }
+
+    /// Issues a session token that authenticates as the user.
+    pub fn issue_session_token(&self, user: UserId) -> Result<IssuedSessionToken, TokenError> {
+        self.session_tokens
+            .issue(user)
+            .map_err(TokenError::SessionToken)
+    }
+
+    /// Replaces an unexpired session token with one that expires later.
+    ///
+    /// Returns `None` if the token isn't a valid session token. Bot
+    /// tokens don't expire, so they can't be refreshed.
+    pub fn refresh_token(&self, token: &str) -> Result<Option<IssuedSessionToken>, TokenError> {
+        self.session_tokens
+            .refresh(token)
+            .map_err(TokenError::SessionToken)
+    }
+
+    /// Mints a bot token that authenticates as the user.
+    pub fn mint_bot_token(
+        &self,
+        user: UserId,
+        label: Option<String>,
+    ) -> Result<MintedBotToken, BotTokenError> {
+        self.bot_tokens.mint(user, label)
     }
 
     /// Generate an oauth2 authorization URL for the specified provider.
+    ///
+    /// The provider redirects the user back to the redirect URL after they log in.
     pub fn oauth2_authorize_web(&self, provider: String, redirect_url: &String) -> Option<String> {
         // Parse and validate the supplied redirect URL.
-        let Ok(redirect_url) = RedirectUrl::new("http://localhost:8080".to_string()) else {
+        let Ok(redirect_url) = RedirectUrl::new(redirect_url.clone()) else {
             tracing::error!("invalid redirect url: {}", redirect_url);
             return None;
         };
@@ -97,75 +410,285 @@ impl AuthService {
         };
 
         // Build an `oauth2` client from the provider config.
-        let client = provider.outh2_client().set_redirect_uri(
-            RedirectUrl::new("http://localhost:8080".to_string()).expect("Invalid redirect URL"),
-        );
+        let client = provider
+            .outh2_client()
+            .set_redirect_uri(redirect_url.clone());
 
         // Generate the authorization URL to redirect the user to;
         let (authorize_url, csrf_state) = client
             .authorize_url(CsrfToken::new_random)
             .add_scope(Scope::new("user:email".to_string()))
             // Add any custom scopes defined in the provider config by the admin.
-            .add_scopes(provider.scopes.clone().into_iter().map(|s| Scope::new(s)))
+            .add_scopes(provider.scopes.clone().into_iter().map(Scope::new))
             .url();
 
-        // TODO: store the state token
+        // Store the state so the callback can verify it.
+        self.store_oauth2_state(&provider.id, &csrf_state, redirect_url);
 
         Some(authorize_url.to_string())
     }
 
     /// Exchange an oauth2 code and state for a token, and issue the user a local authentication token.
+    ///
+    /// The provider and state are verified before the exchange starts, so the
+    /// returned future doesn't borrow the service and the caller doesn't need
+    /// to hold the service lock while waiting on the provider.
     pub fn oauth2_code_exchange_web(
         &self,
         provider: String,
         code: AuthorizationCode,
         state: CsrfToken,
-    ) -> Option<String> {
+    ) -> impl Future<Output = Result<IssuedSessionToken, OAuth2Error>> + Send + 'static {
+        let client = self.prepare_code_exchange(&provider, state);
+        let http_client = self.http_client.clone();
+        let session_tokens = Arc::clone(&self.session_tokens);
+        let oauth2_accounts = Arc::clone(&self.oauth2_accounts);
+
+        async move {
+            let (client, user_url) = client?;
+
+            // Exchange the code for an authorization token.
+            let token = match client.exchange_code(code).request_async(&http_client).await {
+                Ok(token) => token,
+                Err(err) => {
+                    tracing::error!(%err, "failed to exchange oauth2 code for token");
+                    return Err(OAuth2Error::ExchangeFailed);
+                }
+            };
+
+            tracing::info!("OAuth2 code successfully exchanged for a token");
+
+            // NB: Github returns a single comma-separated "scope" parameter instead of multiple
+            // space-separated scopes. Github-specific clients can parse this scope into
+            // multiple scopes by splitting at the commas. Note that it's not safe for the
+            // library to do this by default because RFC 6749 allows scopes to contain commas.
+            let scopes = if let Some(scopes_vec) = token.scopes() {
+                scopes_vec
+                    .iter()
+                    .flat_map(|comma_separated| comma_separated.split(','))
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            };
+            tracing::debug!("OAuth2 provider returned the following scopes: {scopes:?}");
+
+            // Find out which account logged in, to know which user to issue the token to.
+            let account =
+                fetch_oauth2_account(&http_client, &user_url, token.access_token().secret())
+                    .await
+                    .ok_or(OAuth2Error::AccountLookupFailed)?;
+
+            let user = oauth2_accounts.user(&provider, &account).map_err(|err| {
+                tracing::error!(?err, "failed to link oauth2 account to a user");
+                OAuth2Error::LoginFailed
+            })?;
+
+            let issued = session_tokens.issue(user).map_err(|err| {
+                tracing::error!(?err, "failed to issue session token");
+                OAuth2Error::LoginFailed
+            })?;
+
+            tracing::info!(%provider, user_id = ?user, "user logged in with oauth2");
+
+            Ok(issued)
+        }
+    }
+
+    /// Verifies the provider and state of a callback, and builds the client for the code exchange.
+    ///
+    /// Returns the client along with the provider's URL for fetching the logged in account.
+    fn prepare_code_exchange(
+        &self,
+        provider: &str,
+        state: CsrfToken,
+    ) -> Result<(OAuth2Client, String), OAuth2Error> {
         // Check that the specified provider is configured, and retrieve it's config.
         let Some(provider) = self.config.oauth2_clients.iter().find(|c| c.id == provider) else {
             tracing::error!("requested oauth provider {} not found", provider);
-            return None;
+            return Err(OAuth2Error::UnknownProvider);
         };
 
+        // Verify the state was issued by us, consuming it before the exchange
+        // so concurrent callbacks with the same state can't both proceed.
+        let redirect_url = self.consume_oauth2_state(&provider.id, &state)?;
+
         // Build an `oauth2` client from the provider config.
-        let client = provider.outh2_client().set_redirect_uri(
-            RedirectUrl::new("http://localhost:8080".to_string()).expect("Invalid redirect URL"),
-        );
+        Ok((
+            provider.outh2_client().set_redirect_uri(redirect_url),
+            provider.user_url.clone(),
+        ))
+    }
+}
 
-        // Construct the HTTP client to use to exchange the code for a token.
-        let http_client = reqwest::blocking::ClientBuilder::new()
-            // Following redirects opens the client up to SSRF vulnerabilities.
-            .redirect(reqwest::redirect::Policy::none())
-            .build()
-            .expect("Client should build");
+/// Fetches the ID of the account an OAuth2 access token belongs to from the provider.
+///
+/// Returns `None` if the provider doesn't return an account ID.
+async fn fetch_oauth2_account(
+    http_client: &reqwest::Client,
+    user_url: &str,
+    access_token: &str,
+) -> Option<String> {
+    let response = http_client
+        .get(user_url)
+        .bearer_auth(access_token)
+        .header(reqwest::header::ACCEPT, "application/json")
+        // Some providers, such as GitHub, reject requests without a user agent.
+        .header(reqwest::header::USER_AGENT, "bonfire")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
 
-        // Exchange the code for an authorization token.
-        let token = match client.exchange_code(code).request(&http_client) {
-            Ok(token) => token,
+    let body = match response {
+        Ok(response) => response.bytes().await,
+        Err(err) => Err(err),
+    };
+
+    let account: serde_json::Value = match body {
+        Ok(body) => match serde_json::from_slice(&body) {
+            Ok(account) => account,
             Err(err) => {
-                tracing::error!(%err, "failed to exchange oauth2 code for token");
+                tracing::error!(%err, "oauth2 provider returned an invalid account");
                 return None;
             }
-        };
+        },
+        Err(err) => {
+            tracing::error!(%err, "failed to fetch oauth2 account");
+            return None;
+        }
+    };
 
-        tracing::info!("OAuth2 code successfully exchanged for a token");
-
-        // NB: Github returns a single comma-separated "scope" parameter instead of multiple
-        // space-separated scopes. Github-specific clients can parse this scope into
-        // multiple scopes by splitting at the commas. Note that it's not safe for the
-        // library to do this by default because RFC 6749 allows scopes to contain commas.
-        let scopes = if let Some(scopes_vec) = token.scopes() {
-            scopes_vec
-                .iter()
-                .flat_map(|comma_separated| comma_separated.split(','))
-                .collect::<Vec<_>>()
-        } else {
-            Vec::new()
+    // Numeric IDs are kept as their decimal string, so both forms are keyed alike.
+    match account.get("id").or_else(|| account.get("sub")) {
+        Some(serde_json::Value::String(id)) => Some(id.clone()),
+        Some(serde_json::Value::Number(id)) => Some(id.to_string()),
+        _ => {
+            tracing::error!("oauth2 provider returned an account without an ID");
+            None
+        }
+    }
+}
+
+/// Compares two byte strings without short-circuiting on
+/// the first mismatch to avoid leaking timing information.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::user::SYSTEM_USER_ID;
+
+    /// An OAuth2 provider that's never contacted by the tests.
+    pub(crate) fn test_provider() -> OauthClient {
+        OauthClient {
+            id: "test".to_string(),
+            label: "Test".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            auth_url: "https://provider.invalid/authorize".to_string(),
+            token_url: "https://provider.invalid/token".to_string(),
+            user_url: "https://provider.invalid/user".to_string(),
+            scopes: Vec::new(),
+        }
+    }
+
+    fn service(dir: &tempfile::TempDir) -> AuthService {
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        let config = AuthConfig {
+            oauth2_clients: vec![test_provider()],
+            cookie: TokenCookieConfig::default(),
         };
-        tracing::debug!("OAuth2 provider returned the following scopes: {scopes:?}");
 
-        todo!("generate token");
+        AuthService::new(config, &db, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn a_callback_state_can_only_be_used_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = service(&dir);
+
+        let authorize_url = auth
+            .oauth2_authorize_web(
+                "test".to_string(),
+                &"https://bonfire.example/oauth/test/callback".to_string(),
+            )
+            .unwrap();
+        let state = oauth2::url::Url::parse(&authorize_url)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "state")
+            .map(|(_, value)| value.into_owned())
+            .unwrap();
+
+        assert!(
+            auth.consume_oauth2_state("test", &CsrfToken::new(state.clone()))
+                .is_ok()
+        );
+        assert!(matches!(
+            auth.consume_oauth2_state("test", &CsrfToken::new(state)),
+            Err(OAuth2Error::StateAlreadyUsed)
+        ));
+
+        // States that were never issued are rejected outright.
+        assert!(matches!(
+            auth.consume_oauth2_state("test", &CsrfToken::new("forged".to_string())),
+            Err(OAuth2Error::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn logout_invalidates_bot_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = service(&dir);
+
+        let minted = auth.mint_bot_token(UserId(1), None).unwrap();
+        assert_eq!(auth.validate_token(&minted.token), Some(UserId(1)));
+
+        assert!(auth.revoke_token(&minted.token).unwrap());
+        assert_eq!(auth.validate_token(&minted.token), None);
+        assert!(!auth.revoke_token(&minted.token).unwrap());
+    }
+
+    #[test]
+    fn logout_invalidates_session_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = service(&dir);
+
+        let issued = auth.issue_session_token(UserId(1)).unwrap();
+        assert_eq!(auth.validate_token(&issued.token), Some(UserId(1)));
+
+        assert!(auth.revoke_token(&issued.token).unwrap());
+        assert_eq!(auth.validate_token(&issued.token), None);
+    }
+
+    #[test]
+    fn tokens_cant_authenticate_as_the_system_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = service(&dir);
+
+        assert!(matches!(
+            auth.mint_bot_token(SYSTEM_USER_ID, None),
+            Err(BotTokenError::ReservedUser)
+        ));
+        assert!(matches!(
+            auth.issue_session_token(SYSTEM_USER_ID),
+            Err(TokenError::SessionToken(SessionTokenError::ReservedUser))
+        ));
+    }
+
+    #[test]
+    fn bot_tokens_cant_be_refreshed() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = service(&dir);
+
+        let minted = auth.mint_bot_token(UserId(1), None).unwrap();
 
-        Some("".to_string())
+        assert!(auth.refresh_token(&minted.token).unwrap().is_none());
+        assert_eq!(auth.validate_token(&minted.token), Some(UserId(1)));
     }
 }
@@ -0,0 +1,168 @@
+//! Long-lived tokens used by bots and integrations to authenticate.
+//!
+//! Bot tokens are minted by operators rather than issued through a web
+//! login. A token has the form `<token id>.<secret>`, the ID locates the
+//! token record and the secret is compared against the stored secret.
+
+use chrono::Utc;
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    id::id_generator,
+    server::auth::constant_time_eq,
+    user::{SYSTEM_USER_ID, UserId},
+};
+
+/// Length of the generated bot token secrets.
+const BOT_TOKEN_SECRET_LEN: usize = 48;
+
+/// A newly minted bot token.
+pub struct MintedBotToken {
+    /// The full token to present when authenticating.
+    ///
+    /// The token can't be recovered after it's minted.
+    pub token: String,
+    /// The user the token authenticates as.
+    pub user: UserId,
+    /// Operator-supplied label identifying the token.
+    pub label: Option<String>,
+}
+
+/// The bot token as stored in the bot token keyspace.
+#[derive(Serialize, Deserialize)]
+struct BotTokenRecord {
+    user: UserId,
+    label: Option<String>,
+    secret: String,
+    created_at_ms: i64,
+}
+
+/// Indicates there was an error minting or revoking a bot token.
+#[derive(Debug)]
+pub enum BotTokenError {
+    /// Indicates the token would authenticate as the reserved [`SYSTEM_USER_ID`].
+    ReservedUser,
+    /// Indicates the stored token record couldn't be encoded.
+    CorruptRecord(serde_json::Error),
+    /// Indicates there was an error accessing the bot token keyspace.
+    DatabaseError(fjall::Error),
+}
+
+/// Storage for minted bot tokens.
+pub struct BotTokenStore {
+    id_generator: snowflaked::sync::Generator,
+
+    /// Keyspace storing the token records keyed by token ID.
+    keyspace: fjall::Keyspace,
+}
+
+impl BotTokenStore {
+    /// Opens or creates the bot token keyspace.
+    pub fn open(
+        db: &fjall::Database,
+        instance_id: u16,
+        snowflake_epoch_ms: u64,
+    ) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("bot_tokens", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self {
+            id_generator: id_generator(instance_id, snowflake_epoch_ms),
+            keyspace,
+        })
+    }
+
+    /// Mints a new token that authenticates as the user.
+    ///
+    /// Tokens can't be minted for [`SYSTEM_USER_ID`], so
+    /// nobody can post as the server's system author.
+    pub fn mint(
+        &self,
+        user: UserId,
+        label: Option<String>,
+    ) -> Result<MintedBotToken, BotTokenError> {
+        if user == SYSTEM_USER_ID {
+            return Err(BotTokenError::ReservedUser);
+        }
+
+        let id: u64 = self.id_generator.generate();
+
+        let secret: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(BOT_TOKEN_SECRET_LEN)
+            .map(char::from)
+            .collect();
+
+        let record = BotTokenRecord {
+            user,
+            label: label.clone(),
+            secret: secret.clone(),
+            created_at_ms: Utc::now().timestamp_millis(),
+        };
+
+        self.keyspace
+            .insert(
+                id.to_be_bytes(),
+                serde_json::to_vec(&record).map_err(BotTokenError::CorruptRecord)?,
+            )
+            .map_err(BotTokenError::DatabaseError)?;
+
+        tracing::info!(token_id = id, user_id = ?user, "minted bot token");
+
+        Ok(MintedBotToken {
+            token: format!("{id}.{secret}"),
+            user,
+            label,
+        })
+    }
+
+    /// Returns the user a bot token authenticates as, if it's valid.
+    pub fn validate(&self, token: &str) -> Option<UserId> {
+        self.lookup(token).map(|(_, user)| user)
+    }
+
+    /// Revokes a bot token so it can no longer be used to authenticate.
+    ///
+    /// Returns false if the token wasn't a valid bot token.
+    pub fn revoke(&self, token: &str) -> Result<bool, BotTokenError> {
+        let Some((id, user)) = self.lookup(token) else {
+            return Ok(false);
+        };
+
+        self.keyspace
+            .remove(id.to_be_bytes())
+            .map_err(BotTokenError::DatabaseError)?;
+
+        tracing::info!(token_id = id, user_id = ?user, "revoked bot token");
+
+        Ok(true)
+    }
+
+    /// Returns the ID of a bot token and the user it authenticates as, if it's valid.
+    fn lookup(&self, token: &str) -> Option<(u64, UserId)> {
+        let (id, secret) = token.split_once('.')?;
+        let id: u64 = id.parse().ok()?;
+
+        let bytes = match self.keyspace.get(id.to_be_bytes()) {
+            Ok(bytes) => bytes?,
+            Err(err) => {
+                tracing::error!(%err, token_id = id, "failed to read bot token");
+                return None;
+            }
+        };
+
+        let record: BotTokenRecord = match serde_json::from_slice(&bytes) {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::error!(%err, token_id = id, "corrupt bot token record");
+                return None;
+            }
+        };
+
+        if !constant_time_eq(record.secret.as_bytes(), secret.as_bytes()) {
+            return None;
+        }
+
+        Some((id, record.user))
+    }
+}
@@ -0,0 +1,180 @@
+//! Channel categories used to organize the channel list.
+//!
+//! Categories are purely organizational metadata; assigning a channel to
+//! a category doesn't affect how it's messages are stored. Channels that
+//! haven't been placed in a category belong to the uncategorized group.
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::{CategoryId, ChannelId},
+    id::id_generator,
+};
+
+/// Key prefix for category records in the metadata keyspace.
+const CATEGORY_KEY_PREFIX: &[u8] = b"category/";
+
+/// Key prefix for channel placement records in the metadata keyspace.
+const PLACEMENT_KEY_PREFIX: &[u8] = b"placement/";
+
+/// A named group of channels.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Category {
+    /// Unique ID of the category.
+    pub id: CategoryId,
+    /// User-facing name of the category.
+    pub name: String,
+    /// Ordering of the category in the channel list, lowest first.
+    pub position: u32,
+}
+
+/// Where a channel is placed in the channel list.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ChannelPlacement {
+    /// The category the channel belongs to, if any.
+    pub category_id: Option<CategoryId>,
+    /// Ordering of the channel within it's category, lowest first.
+    pub position: u32,
+}
+
+/// Indicates there was an error managing categories.
+#[derive(Debug)]
+pub enum CategoryError {
+    /// Indicates that a blank name was supplied.
+    NameRequired,
+    /// Indicates the category doesn't exist.
+    NotFound,
+    /// Indicates a stored record couldn't be decoded.
+    CorruptRecord(serde_json::Error),
+    /// Indicates there was an error accessing the metadata keyspace.
+    DatabaseError(fjall::Error),
+}
+
+/// Service for managing channel categories and channel ordering.
+pub struct CategoryService {
+    id_generator: snowflaked::Generator,
+
+    /// Keyspace storing the category and channel placement records.
+    keyspace: fjall::Keyspace,
+}
+
+impl CategoryService {
+    /// Constructs the category service, opening or creating the metadata keyspace.
+    pub fn new(
+        db: &fjall::Database,
+        instance_id: u16,
+        snowflake_epoch_ms: u64,
+    ) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("channel_metadata", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self {
+            id_generator: id_generator(instance_id, snowflake_epoch_ms),
+            keyspace,
+        })
+    }
+
+    /// Creates a new category.
+    pub fn create_category(
+        &mut self,
+        name: String,
+        position: u32,
+    ) -> Result<Category, CategoryError> {
+        if name.trim().is_empty() {
+            return Err(CategoryError::NameRequired);
+        }
+
+        let category = Category {
+            id: self.id_generator.generate(),
+            name,
+            position,
+        };
+
+        self.put(&category_key(category.id), &category)?;
+
+        Ok(category)
+    }
+
+    /// Returns all categories ordered by their position.
+    pub fn categories(&self) -> Result<Vec<Category>, CategoryError> {
+        let mut categories: Vec<Category> = self.scan(CATEGORY_KEY_PREFIX)?;
+        categories.sort_by_key(|c| (c.position, c.id.0));
+
+        Ok(categories)
+    }
+
+    /// Returns the placements of all channels that have been placed.
+    pub fn placements(&self) -> Result<HashMap<ChannelId, ChannelPlacement>, CategoryError> {
+        let mut placements = HashMap::new();
+
+        for guard in self.keyspace.prefix(PLACEMENT_KEY_PREFIX) {
+            let (key, value) = guard.into_inner().map_err(CategoryError::DatabaseError)?;
+
+            let id = u64::from_be_bytes(key[PLACEMENT_KEY_PREFIX.len()..].try_into().unwrap());
+            let placement = serde_json::from_slice(&value).map_err(CategoryError::CorruptRecord)?;
+
+            placements.insert(ChannelId(id), placement);
+        }
+
+        Ok(placements)
+    }
+
+    /// Places the channels in a category in the supplied order.
+    ///
+    /// Supplying no category moves the channels to the uncategorized group.
+    pub fn order_channels(
+        &self,
+        category_id: Option<CategoryId>,
+        channels: &[ChannelId],
+    ) -> Result<(), CategoryError> {
+        if let Some(category_id) = category_id {
+            let exists = self
+                .keyspace
+                .contains_key(category_key(category_id))
+                .map_err(CategoryError::DatabaseError)?;
+
+            if !exists {
+                return Err(CategoryError::NotFound);
+            }
+        }
+
+        for (position, channel_id) in channels.iter().enumerate() {
+            let placement = ChannelPlacement {
+                category_id,
+                position: position as u32,
+            };
+
+            self.put(&placement_key(*channel_id), &placement)?;
+        }
+
+        Ok(())
+    }
+
+    fn put<T: Serialize>(&self, key: &[u8], value: &T) -> Result<(), CategoryError> {
+        let value = serde_json::to_vec(value).map_err(CategoryError::CorruptRecord)?;
+
+        self.keyspace
+            .insert(key, value)
+            .map_err(CategoryError::DatabaseError)
+    }
+
+    fn scan<T: for<'de> Deserialize<'de>>(&self, prefix: &[u8]) -> Result<Vec<T>, CategoryError> {
+        self.keyspace
+            .prefix(prefix)
+            .map(|guard| {
+                let value = guard.value().map_err(CategoryError::DatabaseError)?;
+                serde_json::from_slice(&value).map_err(CategoryError::CorruptRecord)
+            })
+            .collect()
+    }
+}
+
+fn category_key(id: CategoryId) -> Vec<u8> {
+    [CATEGORY_KEY_PREFIX, &id.0.to_be_bytes()].concat()
+}
+
+fn placement_key(id: ChannelId) -> Vec<u8> {
+    [PLACEMENT_KEY_PREFIX, &id.0.to_be_bytes()].concat()
+}
@@ -0,0 +1,81 @@
+//! Provides direct message channels between a set of users.
+//!
+//! Direct channels reuse the text channel storage and search
+//! machinery, but are scoped to their participants and aren't
+//! part of the server's public channel list.
+
+use std::{collections::BTreeSet, sync::Arc};
+
+use crate::{server::channel::text::TextChannel, user::UserId};
+
+/// The maximum number of participants in a group direct channel.
+pub const MAX_DIRECT_PARTICIPANTS: usize = 10;
+
+/// A private text channel between a fixed set of users.
+pub struct DirectChannel {
+    /// The users that can access the channel.
+    participants: BTreeSet<UserId>,
+
+    /// The participant that opened the channel, if known.
+    ///
+    /// Channels opened before this was recorded don't have a creator.
+    creator: Option<UserId>,
+
+    /// The underlying text channel storing the messages.
+    channel: Arc<TextChannel>,
+}
+
+/// Indicates there was an error accessing a direct channel.
+#[derive(Debug)]
+pub enum DirectChannelError {
+    /// Indicates the direct channel doesn't exist.
+    NotFound,
+    /// Indicates the user isn't a participant in the direct channel.
+    Forbidden,
+    /// Indicates too few or too many participants were supplied.
+    InvalidParticipants,
+    /// Indicates a participant isn't a user known to the server.
+    UnknownUser(UserId),
+    /// Indicates the user already opened the maximum number of direct channels.
+    UserLimitReached { max: usize },
+    /// Indicates the server already has the maximum number of direct channels.
+    ServerLimitReached { max: usize },
+}
+
+impl DirectChannel {
+    /// Constructs a direct channel for the participants.
+    pub fn new(
+        participants: BTreeSet<UserId>,
+        creator: Option<UserId>,
+        channel: Arc<TextChannel>,
+    ) -> Self {
+        Self {
+            participants,
+            creator,
+            channel,
+        }
+    }
+
+    /// Returns the users that can access the channel.
+    pub fn participants(&self) -> &BTreeSet<UserId> {
+        &self.participants
+    }
+
+    /// Returns the participant that opened the channel, if known.
+    pub fn creator(&self) -> Option<UserId> {
+        self.creator
+    }
+
+    /// Returns true if the user can access the channel.
+    pub fn is_participant(&self, user: UserId) -> bool {
+        self.participants.contains(&user)
+    }
+
+    /// Returns the underlying text channel.
+    ///
+    /// Callers are responsible for checking membership
+    /// with [`DirectChannel::is_participant`] first.
+    pub fn channel(&self) -> Arc<TextChannel> {
+        Arc::clone(&self.channel)
+    }
+}
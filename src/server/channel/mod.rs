@@ -3,20 +3,73 @@
 //! The channel types (voice and text) get their own
 //! submodules that encapsulate their functionality.
 
+use std::{fmt::Display, future::Future, str::FromStr};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
 
 use crate::channel::ChannelId;
 
 /// Indicates the type of a channel.
+///
+/// Serialized as it's lowercase name, e.g. `"text"`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
 pub enum ChannelType {
     Text,
     Voice,
 }
 
+impl ChannelType {
+    /// Every type of channel.
+    pub const ALL: &[ChannelType] = &[ChannelType::Text, ChannelType::Voice];
+
+    /// Returns the name used to identify the type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelType::Text => "text",
+            ChannelType::Voice => "voice",
+        }
+    }
+}
+
+impl Display for ChannelType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Indicates the name doesn't identify a type of channel.
+#[derive(Debug)]
+pub struct UnknownChannelType(pub String);
+
+impl FromStr for ChannelType {
+    type Err = UnknownChannelType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ChannelType::ALL
+            .iter()
+            .copied()
+            .find(|channel_type| channel_type.as_str() == s)
+            .ok_or_else(|| UnknownChannelType(s.to_string()))
+    }
+}
+
+/// Indicates the channel's worker isn't running to receive actions.
+#[derive(Debug)]
+pub struct ChannelClosed;
+
 /// Generic trait for channel types.
+///
+/// Transports read from a channel by subscribing to it's events,
+/// and write to it by sending it actions.
 pub trait Channel {
     type Event;
 
+    /// Actions that can be sent to the channel.
+    type Action;
+
     /// Returns the ID of the channel.
     fn channel_id(&self) -> ChannelId;
 
@@ -28,7 +81,40 @@ pub trait Channel {
 
     /// Returns a subscriber for receiving channel events.
     fn subscribe(&self) -> broadcast::Receiver<Self::Event>;
+
+    /// Sends an action to the channel, waiting if the channel's queue is full.
+    fn send_action(
+        &self,
+        action: Self::Action,
+    ) -> impl Future<Output = Result<(), ChannelClosed>> + Send;
 }
 
+pub mod direct;
 pub mod text;
 pub mod voice;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_types_round_trip() {
+        for &channel_type in ChannelType::ALL {
+            let name = channel_type.to_string();
+            assert_eq!(name.parse::<ChannelType>().unwrap(), channel_type);
+
+            let json = serde_json::to_string(&channel_type).unwrap();
+            assert_eq!(json, format!("\"{name}\""));
+            assert_eq!(
+                serde_json::from_str::<ChannelType>(&json).unwrap(),
+                channel_type
+            );
+        }
+
+        assert!(matches!(
+            "stage".parse::<ChannelType>(),
+            Err(UnknownChannelType(name)) if name == "stage"
+        ));
+        assert!(serde_json::from_str::<ChannelType>("\"Text\"").is_err());
+    }
+}
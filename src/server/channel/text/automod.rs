@@ -0,0 +1,238 @@
+//! Automatic moderation of new messages with spam heuristics.
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{message::decode_message, user::UserId};
+
+/// How long after an author first sends some content that sending
+/// it again is counted as a repeat.
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Messages with fewer letters than this aren't checked for capitals,
+/// so short messages like "OK" or "LOL" aren't moderated.
+pub const MIN_CAPS_LETTERS: usize = 12;
+
+/// What's done with a message that breaks an auto-moderation rule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AutoModAction {
+    /// The message is stored, and a [`super::TextChannelEvent::MessageFlagged`]
+    /// event is emitted so moderators can review it.
+    #[default]
+    Flag,
+    /// The message is rejected with [`super::MessageRejection::AutoModerated`].
+    Reject,
+}
+
+/// Thresholds of the heuristics new messages are checked against.
+///
+/// Each heuristic is disabled while it's threshold is unset.
+#[derive(Clone, Debug, Default)]
+pub struct AutoModConfig {
+    /// The number of times an author can send the same content in a
+    /// row within [`REPEAT_WINDOW`] before it's moderated.
+    pub max_repeats: Option<usize>,
+    /// The maximum number of users and roles mentioned in a message.
+    pub max_mentions: Option<usize>,
+    /// The maximum fraction of a message's letters that can be capitals, from 0 to 1.
+    ///
+    /// Only checked for messages with at least [`MIN_CAPS_LETTERS`] letters.
+    pub max_caps_ratio: Option<f32>,
+    /// What's done with messages that break a rule.
+    pub action: AutoModAction,
+}
+
+/// The auto-moderation rule a message broke.
+#[derive(Clone, Debug)]
+pub enum AutoModReason {
+    /// The author sent the same content too many times in a row.
+    Repetition { repeats: usize, max: usize },
+    /// The message mentioned too many users and roles.
+    Mentions { mentions: usize, max: usize },
+    /// Too much of the message was written in capitals.
+    Caps { ratio: f32, max: f32 },
+}
+
+/// The content an author last sent.
+struct LastContent {
+    /// Hash of the normalized content.
+    hash: u64,
+    /// When the content was first sent in the current run of repeats.
+    first_sent: Instant,
+    /// The number of times the content was sent in a row.
+    count: usize,
+}
+
+/// Checks new messages in a channel against the auto-moderation rules.
+pub struct AutoMod {
+    config: AutoModConfig,
+    /// The content each author last sent in the channel.
+    last_content: Mutex<HashMap<UserId, LastContent>>,
+}
+
+impl AutoMod {
+    pub fn new(config: AutoModConfig) -> Self {
+        Self {
+            config,
+            last_content: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// What's done with messages that break a rule.
+    pub fn action(&self) -> AutoModAction {
+        self.config.action
+    }
+
+    /// Records a new message from the author and checks it against the rules.
+    ///
+    /// Returns the first rule the message broke, if any.
+    pub fn check(&self, author: UserId, content: &str) -> Option<AutoModReason> {
+        // Repeats are recorded even when another rule is broken,
+        // so the count reflects everything the author sent.
+        let repetition = self.check_repetition(author, content);
+
+        if let Some(max) = self.config.max_mentions {
+            let mentions = decode_message(content).mentions();
+            let count = mentions.users.len() + mentions.roles.len();
+            if count > max {
+                return Some(AutoModReason::Mentions {
+                    mentions: count,
+                    max,
+                });
+            }
+        }
+
+        if let Some(max) = self.config.max_caps_ratio {
+            let (letters, capitals) = content
+                .chars()
+                .filter(|c| c.is_alphabetic())
+                .fold((0, 0), |(letters, capitals), c| {
+                    (letters + 1, capitals + usize::from(c.is_uppercase()))
+                });
+
+            let ratio = capitals as f32 / letters as f32;
+            if letters >= MIN_CAPS_LETTERS && ratio > max {
+                return Some(AutoModReason::Caps { ratio, max });
+            }
+        }
+
+        repetition
+    }
+
+    /// Records the content as the author's last, checking how many times it was repeated.
+    fn check_repetition(&self, author: UserId, content: &str) -> Option<AutoModReason> {
+        let max = self.config.max_repeats?;
+
+        // Differences in case and surrounding whitespace
+        // are ignored, so they don't get around the limit.
+        let mut hasher = DefaultHasher::new();
+        content.trim().to_lowercase().hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let now = Instant::now();
+        let mut last_content = self.last_content.lock();
+
+        // Forget authors that haven't repeated themselves recently so the table stays bounded.
+        last_content.retain(|_, last| now.duration_since(last.first_sent) < REPEAT_WINDOW);
+
+        let last = last_content.entry(author).or_insert(LastContent {
+            hash,
+            first_sent: now,
+            count: 0,
+        });
+
+        if last.hash != hash {
+            *last = LastContent {
+                hash,
+                first_sent: now,
+                count: 0,
+            };
+        }
+
+        last.count += 1;
+
+        (last.count > max).then_some(AutoModReason::Repetition {
+            repeats: last.count,
+            max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel::ChannelId,
+        server::{
+            channel::{
+                Channel,
+                text::{
+                    MessageRejection, TextChannel, TextChannelEvent, TextChannelOptions,
+                    TextChannelSettings,
+                    create::CreateMessageError,
+                    tests::{test_message, test_options},
+                },
+            },
+            permission::Permissions,
+        },
+    };
+
+    #[tokio::test]
+    async fn messages_over_the_mention_cap_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path().join("db"))
+            .open()
+            .unwrap();
+        let options = TextChannelOptions {
+            auto_moderation: Some(AutoModConfig {
+                max_mentions: Some(2),
+                action: AutoModAction::Reject,
+                ..AutoModConfig::default()
+            }),
+            ..test_options()
+        };
+        let channel = TextChannel::new(
+            ChannelId(1),
+            &dir.path().join("channel"),
+            db,
+            "general".to_string(),
+            TextChannelSettings::default(),
+            &options,
+        )
+        .unwrap();
+        let mut events = channel.subscribe();
+        let send = |content| {
+            channel.create_message(test_message(UserId(1), content), None, Permissions::NONE)
+        };
+
+        // Users and roles both count, but each target only once.
+        send("<@5> <@&6> <@5> <@5>").await.unwrap();
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            TextChannelEvent::NewMessage(_)
+        ));
+
+        assert!(matches!(
+            send("<@5> <@6> <@&7>").await,
+            Err(CreateMessageError::Rejected(
+                MessageRejection::AutoModerated(AutoModReason::Mentions {
+                    mentions: 3,
+                    max: 2
+                })
+            ))
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            TextChannelEvent::MessageRejected {
+                author: UserId(1),
+                reason: MessageRejection::AutoModerated(AutoModReason::Mentions { .. }),
+            }
+        ));
+        assert_eq!(channel.message_count(), 1);
+    }
+}
@@ -0,0 +1,106 @@
+//! Validation and normalization of message content.
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Indicates the content of a message was rejected.
+#[derive(Debug)]
+pub enum ContentError {
+    /// Indicates the content was empty or only whitespace.
+    Empty,
+    /// Indicates the content is longer than the maximum
+    /// number of characters, counted as graphemes.
+    TooLong { len: usize, max: usize },
+    /// Indicates the content is larger than the maximum number of bytes.
+    TooLarge { bytes: usize, max: usize },
+}
+
+/// Validates and normalizes the content of a new message.
+///
+/// The content is normalized to NFC, and control characters other than
+/// newlines and tabs are stripped. The normalized content is returned,
+/// or an error if it's empty or over the length limit. Content is never
+/// truncated, since that would silently change what the author wrote.
+///
+/// The size limit is checked against the content as it was received,
+/// before doing any work on it, so oversized content is cheap to reject.
+pub fn validate_content(
+    content: &str,
+    max_graphemes: usize,
+    max_bytes: usize,
+) -> Result<String, ContentError> {
+    if content.len() > max_bytes {
+        return Err(ContentError::TooLarge {
+            bytes: content.len(),
+            max: max_bytes,
+        });
+    }
+
+    let normalized: String = content
+        .nfc()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect();
+
+    if normalized.trim().is_empty() {
+        return Err(ContentError::Empty);
+    }
+
+    // Count what users perceive as characters, so combined emoji and
+    // accented letters count once no matter how many bytes they take.
+    let len = normalized.graphemes(true).count();
+    if len > max_graphemes {
+        return Err(ContentError::TooLong {
+            len,
+            max: max_graphemes,
+        });
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_at_the_size_limit_is_accepted() {
+        let content = "a".repeat(64);
+
+        assert_eq!(validate_content(&content, 1000, 64).unwrap(), content);
+        assert!(matches!(
+            validate_content(&format!("{content}a"), 1000, 64),
+            Err(ContentError::TooLarge { bytes: 65, max: 64 })
+        ));
+    }
+
+    #[test]
+    fn multibyte_content_is_limited_by_graphemes() {
+        // Each flag is two code points and eight bytes, but one grapheme.
+        let flags = "\u{1F1E8}\u{1F1E6}".repeat(5);
+        assert_eq!(validate_content(&flags, 5, 1000).unwrap(), flags);
+        assert!(matches!(
+            validate_content(&format!("{flags}\u{1F1E8}\u{1F1E6}"), 5, 1000),
+            Err(ContentError::TooLong { len: 6, max: 5 })
+        ));
+
+        // A decomposed accent is normalized to a single character.
+        assert_eq!(
+            validate_content("cafe\u{301}", 4, 1000).unwrap(),
+            "caf\u{e9}"
+        );
+    }
+
+    #[test]
+    fn control_characters_are_stripped() {
+        assert_eq!(
+            validate_content("hello\u{0}\u{7}\u{1b}[2J\nworld\t!", 1000, 1000).unwrap(),
+            "hello[2J\nworld\t!"
+        );
+
+        // Content that's only control characters and whitespace is empty.
+        assert!(matches!(
+            validate_content("\u{0}\u{8} \u{7f}", 1000, 1000),
+            Err(ContentError::Empty)
+        ));
+    }
+}
@@ -0,0 +1,521 @@
+//! Creating new messages in a text channel.
+
+use std::time::Duration;
+
+use tachyonix::TrySendError;
+use tokio::sync::oneshot;
+
+use crate::{
+    message::{MessageFlags, MessageId, MessageMentions},
+    server::{
+        channel::{
+            Channel,
+            text::{
+                MessageRejection, TextChannel, TextChannelAction, TextChannelEvent,
+                TextChannelMessage,
+                automod::AutoModAction,
+                content::{ContentError, validate_content},
+                nonce::MAX_NONCE_BYTES,
+                reply::ReplyError,
+            },
+        },
+        permission::Permissions,
+    },
+    user::{SYSTEM_USER_ID, UserId},
+};
+
+/// Indicates a message couldn't be created in the channel.
+#[derive(Debug)]
+pub enum CreateMessageError {
+    /// Indicates the message's content was rejected.
+    InvalidContent(ContentError),
+    /// Indicates the message's reply reference couldn't be accepted.
+    InvalidReply(ReplyError),
+    /// Indicates the nonce was empty or longer than [`MAX_NONCE_BYTES`].
+    InvalidNonce,
+    /// Indicates flags outside of [`MessageFlags::USER_SETTABLE`] were set.
+    InvalidFlags(MessageFlags),
+    /// Indicates the message was rejected because the author posted too soon.
+    Rejected(MessageRejection),
+    /// Indicates the channel's queue of new messages is full.
+    ///
+    /// The worker has fallen behind, so the message is rejected
+    /// rather than waiting for room in the queue.
+    ChannelBusy,
+    /// Indicates the channel worker isn't running.
+    ChannelClosed,
+}
+
+impl TextChannel {
+    /// Validates a new message and forwards it to the channel worker.
+    ///
+    /// Messages are rejected with [`CreateMessageError::ChannelBusy`]
+    /// instead of waiting when the channel's queue is full, so clients
+    /// get immediate feedback when the channel is overloaded. Messages
+    /// the queue doesn't accept don't count towards the posting limits.
+    ///
+    /// The author's `permissions` are used to exempt moderators from the
+    /// channel's slow mode.
+    ///
+    /// This is the ingest point for messages from every transport, so
+    /// rejected messages are also reported to the author's other clients
+    /// with a [`TextChannelEvent::MessageRejected`] event.
+    ///
+    /// Clients that retry sends can supply a `nonce`. If the author used the
+    /// same nonce in the channel within [`super::nonce::NONCE_TTL`], the message created
+    /// the first time is returned instead of creating a duplicate.
+    ///
+    /// Returns the message as it was stored, including it's assigned ID.
+    pub async fn create_message(
+        &self,
+        mut msg: TextChannelMessage,
+        nonce: Option<&str>,
+        permissions: Permissions,
+    ) -> Result<TextChannelMessage, CreateMessageError> {
+        if nonce.is_some_and(|nonce| nonce.is_empty() || nonce.len() > MAX_NONCE_BYTES) {
+            return Err(CreateMessageError::InvalidNonce);
+        }
+
+        // Only the server can post system messages, and messages are pinned separately.
+        if !MessageFlags::USER_SETTABLE.contains(msg.flags) {
+            return Err(CreateMessageError::InvalidFlags(msg.flags));
+        }
+
+        msg.content = validate_content(
+            &msg.content,
+            self.max_content_graphemes,
+            self.max_content_bytes,
+        )
+        .map_err(CreateMessageError::InvalidContent)?;
+
+        self.validate_reply(&msg)
+            .map_err(CreateMessageError::InvalidReply)?;
+
+        let Some(nonce) = nonce else {
+            return self.submit_message(msg, permissions).await;
+        };
+
+        // Retries wait for the first send with the nonce, and get its message.
+        // If the first send failed the slot is still empty, so a retry sends again.
+        let slot = self.nonces.slot(msg.author, nonce);
+        slot.get_or_try_init(|| self.submit_message(msg, permissions))
+            .await
+            .cloned()
+    }
+
+    /// Posts a message from the server itself, such as an announcement.
+    ///
+    /// The message is attributed to [`SYSTEM_USER_ID`] and flagged with
+    /// [`MessageFlags::SYSTEM`]. It isn't subject to the posting limits
+    /// or auto-moderation that apply to users, and waits for room in the
+    /// channel's queue instead of being rejected when it's full.
+    ///
+    /// Returns the message as it was stored, including it's assigned ID.
+    pub async fn post_system_message(
+        &self,
+        content: &str,
+    ) -> Result<TextChannelMessage, CreateMessageError> {
+        let content = validate_content(content, self.max_content_graphemes, self.max_content_bytes)
+            .map_err(CreateMessageError::InvalidContent)?;
+
+        let message = TextChannelMessage {
+            id: MessageId::default(),
+            author: SYSTEM_USER_ID,
+            author_name: None,
+            timestamp_ms: 0,
+            edited_at: None,
+            content,
+            reply_to: None,
+            mentions: MessageMentions::default(),
+            flags: MessageFlags::SYSTEM,
+        };
+
+        let (reply, response) = oneshot::channel();
+
+        self.send_action(TextChannelAction::MessageCreated {
+            message,
+            reply: Some(reply),
+        })
+        .await
+        .map_err(|_| CreateMessageError::ChannelClosed)?;
+
+        // The reply is dropped without a response if the worker exits first.
+        response
+            .await
+            .map_err(|_| CreateMessageError::ChannelClosed)
+    }
+
+    /// Posts a message to the channel through a webhook.
+    ///
+    /// The message is attributed to [`SYSTEM_USER_ID`] and flagged with
+    /// [`MessageFlags::WEBHOOK`], so it can't be mistaken for a user's message.
+    /// Webhooks post under the message's `author_name` instead. Every webhook
+    /// in the channel shares the posting limits of the system author, on top
+    /// of each webhook's own rate limit.
+    ///
+    /// Returns the message as it was stored, including it's assigned ID.
+    pub async fn post_webhook_message(
+        &self,
+        mut msg: TextChannelMessage,
+    ) -> Result<TextChannelMessage, CreateMessageError> {
+        msg.author = SYSTEM_USER_ID;
+        msg.flags = MessageFlags::WEBHOOK;
+        msg.content = validate_content(
+            &msg.content,
+            self.max_content_graphemes,
+            self.max_content_bytes,
+        )
+        .map_err(CreateMessageError::InvalidContent)?;
+
+        self.validate_reply(&msg)
+            .map_err(CreateMessageError::InvalidReply)?;
+
+        // Webhooks don't hold any permissions, so they're subject to slow mode.
+        self.submit_message(msg, Permissions::NONE).await
+    }
+
+    /// Checks the author's posting limits and forwards the message to the channel worker.
+    async fn submit_message(
+        &self,
+        msg: TextChannelMessage,
+        permissions: Permissions,
+    ) -> Result<TextChannelMessage, CreateMessageError> {
+        if let Err(retry_after) = self.rate_limiter.check(msg.author) {
+            return Err(self.reject(
+                msg.author,
+                MessageRejection::RateLimited {
+                    retry_after_ms: retry_after.as_millis() as u64,
+                },
+            ));
+        }
+
+        // Moderators are exempt from the channel's slow mode.
+        let cooldown = self
+            .settings
+            .read()
+            .slow_mode_secs
+            .filter(|_| !permissions.contains(Permissions::BYPASS_SLOW_MODE))
+            .map(Duration::from_secs);
+        let slow_mode = cooldown.map_or(Ok(()), |cooldown| {
+            self.slow_mode.check(msg.author, cooldown)
+        });
+
+        if let Err(retry_after) = slow_mode {
+            return Err(self.reject(
+                msg.author,
+                MessageRejection::SlowMode {
+                    retry_after_ms: retry_after.as_millis() as u64,
+                },
+            ));
+        }
+
+        // Messages that break a rule are either rejected, or stored and flagged for review.
+        let flagged = match &self.automod {
+            Some(automod) => match automod.check(msg.author, &msg.content) {
+                Some(reason) if automod.action() == AutoModAction::Reject => {
+                    return Err(self.reject(msg.author, MessageRejection::AutoModerated(reason)));
+                }
+                flagged => flagged,
+            },
+            None => None,
+        };
+
+        let (reply, response) = oneshot::channel();
+
+        let author = msg.author;
+        let sent = self
+            .message_sender
+            .try_send(TextChannelAction::MessageCreated {
+                message: msg,
+                reply: Some(reply),
+            });
+
+        if let Err(err) = sent {
+            // The message was never posted, so the author can send it again right away.
+            self.rate_limiter.refund(author);
+            if cooldown.is_some() {
+                self.slow_mode.refund(author);
+            }
+
+            return Err(match err {
+                TrySendError::Full(_) => CreateMessageError::ChannelBusy,
+                TrySendError::Closed(_) => CreateMessageError::ChannelClosed,
+            });
+        }
+
+        // The reply is dropped without a response if the worker exits first.
+        let msg = response
+            .await
+            .map_err(|_| CreateMessageError::ChannelClosed)?;
+
+        if let Some(reason) = flagged {
+            // No subscribers is not an error.
+            let _ = self.event_sender.send(TextChannelEvent::MessageFlagged {
+                message_id: msg.id,
+                author: msg.author,
+                reason,
+            });
+        }
+
+        Ok(msg)
+    }
+
+    /// Informs subscribers that a message from the user was rejected.
+    fn reject(&self, author: UserId, reason: MessageRejection) -> CreateMessageError {
+        // No subscribers is not an error.
+        let _ = self.event_sender.send(TextChannelEvent::MessageRejected {
+            author,
+            reason: reason.clone(),
+        });
+
+        CreateMessageError::Rejected(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::pin, time::Duration};
+
+    use super::*;
+    use crate::{
+        channel::ChannelId,
+        server::channel::text::{
+            TextChannelSettings,
+            tests::{test_channel, test_message, test_options, test_query, wait_for_commit},
+        },
+    };
+
+    #[tokio::test]
+    async fn messages_over_the_rate_limit_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path().join("db"))
+            .open()
+            .unwrap();
+        let mut options = test_options();
+        options.message_rate_limit = 1;
+        options.message_rate_interval = Duration::from_secs(60);
+        let channel = TextChannel::new(
+            ChannelId(1),
+            &dir.path().join("channel"),
+            db,
+            "general".to_string(),
+            TextChannelSettings::default(),
+            &options,
+        )
+        .unwrap();
+
+        channel
+            .create_message(test_message(UserId(1), "first"), None, Permissions::NONE)
+            .await
+            .unwrap();
+        assert!(matches!(
+            channel
+                .create_message(test_message(UserId(1), "second"), None, Permissions::NONE)
+                .await,
+            Err(CreateMessageError::Rejected(
+                MessageRejection::RateLimited { .. }
+            ))
+        ));
+
+        // Each user has their own limit.
+        channel
+            .create_message(test_message(UserId(2), "third"), None, Permissions::NONE)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_mode_holds_users_to_the_cooldown() {
+        let (_dir, channel) = test_channel();
+        channel.set_settings(TextChannelSettings {
+            slow_mode_secs: Some(60),
+            ..Default::default()
+        });
+        let send = |author, permissions| {
+            channel.create_message(test_message(author, "hello"), None, permissions)
+        };
+
+        send(UserId(1), Permissions::NONE).await.unwrap();
+        match send(UserId(1), Permissions::NONE).await {
+            Err(CreateMessageError::Rejected(MessageRejection::SlowMode { retry_after_ms })) => {
+                assert!(retry_after_ms > 0 && retry_after_ms <= 60_000);
+            }
+            result => panic!(
+                "expected a slow mode rejection, got {:?}",
+                result.map(|m| m.id)
+            ),
+        }
+
+        // The cooldown is per user.
+        send(UserId(2), Permissions::NONE).await.unwrap();
+
+        // Moderators can keep posting.
+        send(UserId(3), Permissions::BYPASS_SLOW_MODE)
+            .await
+            .unwrap();
+        send(UserId(3), Permissions::BYPASS_SLOW_MODE)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn busy_channels_dont_use_up_the_rate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path().join("db"))
+            .open()
+            .unwrap();
+        let mut options = test_options();
+        options.message_rate_limit = 2;
+        options.message_rate_interval = Duration::from_secs(60);
+        options.queue_capacity = 1;
+        let channel = TextChannel::new(
+            ChannelId(1),
+            &dir.path().join("channel"),
+            db,
+            "general".to_string(),
+            TextChannelSettings::default(),
+            &options,
+        )
+        .unwrap();
+        let send = |content| {
+            channel.create_message(test_message(UserId(1), content), None, Permissions::NONE)
+        };
+
+        // The worker doesn't run until the test yields, so
+        // the first message fills the queue and the second is turned away.
+        let mut first = pin!(send("first"));
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        assert!(matches!(
+            send("second").await,
+            Err(CreateMessageError::ChannelBusy)
+        ));
+        first.await.unwrap();
+
+        // Only the first message counted, so one more fits in the limit.
+        send("third").await.unwrap();
+        assert!(matches!(
+            send("fourth").await,
+            Err(CreateMessageError::Rejected(
+                MessageRejection::RateLimited { .. }
+            ))
+        ));
+        assert_eq!(channel.message_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_with_the_same_nonce_create_one_message() {
+        let (_dir, channel) = test_channel();
+        let too_long = "n".repeat(MAX_NONCE_BYTES + 1);
+        let send = |author, nonce| {
+            channel.create_message(
+                test_message(UserId(author), "hello"),
+                Some(nonce),
+                Permissions::NONE,
+            )
+        };
+
+        let first = send(1, "retry-1").await.unwrap();
+        let retried = send(1, "retry-1").await.unwrap();
+        assert_eq!(retried.id, first.id);
+        assert_eq!(channel.message_count(), 1);
+
+        // Retries sent before the first completes still share its message.
+        let (a, b) = tokio::join!(send(1, "retry-2"), send(1, "retry-2"));
+        assert_eq!(a.unwrap().id, b.unwrap().id);
+        assert_eq!(channel.message_count(), 2);
+
+        // Nonces are scoped to the author.
+        let other = send(2, "retry-1").await.unwrap();
+        assert_ne!(other.id, first.id);
+        assert_eq!(channel.message_count(), 3);
+
+        assert!(matches!(
+            send(1, "").await,
+            Err(CreateMessageError::InvalidNonce)
+        ));
+        assert!(matches!(
+            send(1, &too_long).await,
+            Err(CreateMessageError::InvalidNonce)
+        ));
+    }
+
+    #[tokio::test]
+    async fn flags_round_trip_through_storage() {
+        let (_dir, channel) = test_channel();
+
+        let mut msg = test_message(UserId(1), "read this aloud");
+        msg.flags = MessageFlags::TTS | MessageFlags::SUPPRESS_EMBEDS;
+        let created = channel
+            .create_message(msg, None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        let stored = channel.message(created.id).unwrap().unwrap();
+        assert_eq!(
+            stored.flags,
+            MessageFlags::TTS | MessageFlags::SUPPRESS_EMBEDS
+        );
+
+        channel.pin(created.id).unwrap();
+        let stored = channel.message(created.id).unwrap().unwrap();
+        assert!(
+            stored
+                .flags
+                .contains(MessageFlags::PINNED | MessageFlags::TTS)
+        );
+
+        channel.unpin(created.id).unwrap();
+        let stored = channel.message(created.id).unwrap().unwrap();
+        assert!(!stored.flags.contains(MessageFlags::PINNED));
+    }
+
+    #[tokio::test]
+    async fn users_cant_set_the_system_flag() {
+        let (_dir, channel) = test_channel();
+
+        let mut msg = test_message(UserId(1), "totally from the server");
+        msg.flags = MessageFlags::SYSTEM;
+        let result = channel.create_message(msg, None, Permissions::ALL).await;
+
+        assert!(matches!(
+            result,
+            Err(CreateMessageError::InvalidFlags(MessageFlags::SYSTEM))
+        ));
+        assert_eq!(channel.message_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn system_messages_are_flagged_and_have_no_user_author() {
+        let (_dir, channel) = test_channel();
+
+        let posted = channel
+            .post_system_message("the channel was created")
+            .await
+            .unwrap();
+
+        let stored = channel.message(posted.id).unwrap().unwrap();
+        assert_eq!(stored.author, SYSTEM_USER_ID);
+        assert!(stored.flags.contains(MessageFlags::SYSTEM));
+        assert_eq!(stored.content, "the channel was created");
+
+        // System messages are indexed like any other message.
+        wait_for_commit().await;
+        let page = channel.search(test_query("created")).unwrap();
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].author, SYSTEM_USER_ID);
+    }
+
+    #[tokio::test]
+    async fn webhook_messages_are_flagged_and_have_no_user_author() {
+        let (_dir, channel) = test_channel();
+
+        let mut msg = test_message(UserId(42), "build passed");
+        msg.author_name = Some("CI".to_string());
+        let posted = channel.post_webhook_message(msg).await.unwrap();
+
+        let stored = channel.message(posted.id).unwrap().unwrap();
+        assert_eq!(stored.author, SYSTEM_USER_ID);
+        assert_eq!(stored.flags, MessageFlags::WEBHOOK);
+        assert_eq!(stored.author_name.as_deref(), Some("CI"));
+    }
+}
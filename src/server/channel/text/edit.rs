@@ -0,0 +1,115 @@
+//! Editing messages in a text channel.
+
+use tokio::sync::oneshot;
+
+use crate::{
+    id::now_ms,
+    message::{MessageFlags, MessageId, decode_message},
+    server::channel::{
+        Channel,
+        text::{
+            TextChannel, TextChannelAction, TextChannelMessage,
+            content::{ContentError, validate_content},
+            store::MessageStore,
+        },
+    },
+    user::UserId,
+};
+
+/// Indicates a message couldn't be edited.
+#[derive(Debug)]
+pub enum EditMessageError {
+    /// Indicates the new content was rejected.
+    InvalidContent(ContentError),
+    /// Indicates flags outside of [`MessageFlags::USER_SETTABLE`] were set.
+    InvalidFlags(MessageFlags),
+    /// Indicates the message doesn't exist in the channel.
+    MessageNotFound,
+    /// Indicates the user isn't the author of the message.
+    NotAuthor,
+    /// Indicates there was an error accessing the keyspace.
+    DatabaseError(fjall::Error),
+    /// Indicates the channel worker isn't running.
+    ChannelClosed,
+}
+
+impl TextChannel {
+    /// Replaces the content of a message posted by the author.
+    ///
+    /// The message keeps it's original timestamp, and the time of the
+    /// edit is recorded in it's `edited_at` timestamp.
+    ///
+    /// If `flags` are set they replace the message's user-settable flags,
+    /// the rest of it's flags are kept.
+    ///
+    /// Returns the message as it was stored after the edit.
+    pub async fn edit_message(
+        &self,
+        id: MessageId,
+        author: UserId,
+        content: &str,
+        flags: Option<MessageFlags>,
+    ) -> Result<TextChannelMessage, EditMessageError> {
+        if let Some(flags) = flags.filter(|&flags| !MessageFlags::USER_SETTABLE.contains(flags)) {
+            return Err(EditMessageError::InvalidFlags(flags));
+        }
+
+        let content = validate_content(content, self.max_content_graphemes, self.max_content_bytes)
+            .map_err(EditMessageError::InvalidContent)?;
+
+        let (reply, response) = oneshot::channel();
+
+        self.send_action(TextChannelAction::MessageEdited {
+            id,
+            author,
+            content,
+            flags,
+            reply,
+        })
+        .await
+        .map_err(|_| EditMessageError::ChannelClosed)?;
+
+        response
+            .await
+            .map_err(|_| EditMessageError::ChannelClosed)?
+    }
+}
+
+/// Stores the edited content of a message.
+///
+/// Returns the message from before and after the edit.
+pub(super) fn apply_edit(
+    store: &dyn MessageStore,
+    id: MessageId,
+    author: UserId,
+    content: String,
+    flags: Option<MessageFlags>,
+) -> Result<(TextChannelMessage, TextChannelMessage), EditMessageError> {
+    // Authors never change, so they can be checked before the update.
+    let message = store
+        .get(id)
+        .map_err(EditMessageError::DatabaseError)?
+        .ok_or(EditMessageError::MessageNotFound)?;
+
+    if message.author != author {
+        return Err(EditMessageError::NotAuthor);
+    }
+
+    // The pinned flag may change while the message is being edited,
+    // so the edit is applied as an update rather than re-inserting it.
+    let edited_at = now_ms();
+    let mentions = decode_message(&content).mentions();
+    store
+        .update(id, &mut |edited| {
+            edited.content = content.clone();
+            edited.mentions = mentions.clone();
+            edited.edited_at = Some(edited_at);
+
+            if let Some(flags) = flags {
+                edited.flags.set(MessageFlags::USER_SETTABLE, false);
+                edited.flags |= flags;
+            }
+        })
+        .map_err(EditMessageError::DatabaseError)?
+        .ok_or(EditMessageError::MessageNotFound)
+}
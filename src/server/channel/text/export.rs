@@ -0,0 +1,58 @@
+//! Bulk export of a text channel's message history.
+
+use std::io::{self, BufWriter, Write};
+
+use crate::{message::MessageId, server::channel::text::TextChannel};
+
+/// The number of messages read from the store at a time.
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+/// Indicates there was an error exporting a channel's messages.
+#[derive(Debug)]
+pub enum ExportError {
+    /// Indicates there was an error reading messages from the keyspace.
+    DatabaseError(fjall::Error),
+    /// Indicates there was an error writing to the export writer.
+    WriteError(io::Error),
+}
+
+impl TextChannel {
+    /// Streams every stored message in the channel to the writer as NDJSON.
+    ///
+    /// Messages are written in the order they were sent, one JSON object per line.
+    /// Messages are read from the store a page at a time so the
+    /// channel's history is never buffered in memory.
+    ///
+    /// This performs blocking IO, and should be called from a blocking task.
+    ///
+    /// Returns the number of messages exported.
+    pub fn export<W: Write>(&self, writer: W) -> Result<usize, ExportError> {
+        let mut writer = BufWriter::new(writer);
+        let mut count = 0;
+
+        let mut after = MessageId(0);
+        loop {
+            let page = self
+                .store
+                .messages_after(after, EXPORT_PAGE_SIZE)
+                .map_err(ExportError::DatabaseError)?;
+
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = last.id;
+
+            for message in &page {
+                serde_json::to_writer(&mut writer, message)
+                    .map_err(|e| ExportError::WriteError(e.into()))?;
+                writer.write_all(b"\n").map_err(ExportError::WriteError)?;
+
+                count += 1;
+            }
+        }
+
+        writer.flush().map_err(ExportError::WriteError)?;
+
+        Ok(count)
+    }
+}
@@ -0,0 +1,119 @@
+//! Bulk import of messages into a text channel.
+
+use std::collections::HashSet;
+
+use tantivy::TantivyError;
+use tokio::sync::oneshot;
+
+use crate::{
+    message::MessageId,
+    server::channel::{
+        Channel,
+        text::{TextChannel, TextChannelAction, TextChannelMessage, store::MessageStore},
+    },
+};
+
+/// The maximum number of messages that can be imported in one batch.
+pub const MAX_IMPORT_BATCH: usize = 10_000;
+
+/// How far an imported message's timestamp may go backwards
+/// compared to the previous message before it's rejected.
+///
+/// Allows for small amounts of clock skew in the source system.
+pub const IMPORT_TIMESTAMP_TOLERANCE_MS: u64 = 1000;
+
+/// Indicates there was an error importing messages into a channel.
+#[derive(Debug)]
+pub enum ImportError {
+    /// Indicates the batch was empty.
+    EmptyBatch,
+    /// Indicates the batch exceeded [`MAX_IMPORT_BATCH`] messages.
+    BatchTooLarge(usize),
+    /// Indicates a message's timestamp went too far backwards.
+    TimestampOutOfOrder(MessageId),
+    /// Indicates a message ID was supplied more than once, or already exists.
+    DuplicateMessageId(MessageId),
+    /// Indicates a message's content is over the channel's size limit.
+    ContentTooLarge(MessageId),
+    /// Indicates there was an error writing to the keyspace.
+    DatabaseError(fjall::Error),
+    /// Indicates there was an error writing to the search index.
+    SearchError(TantivyError),
+    /// Indicates the channel worker isn't running.
+    ChannelClosed,
+    /// Indicates the import stopped partway through the batch.
+    ///
+    /// The `imported` messages before the failed one were imported.
+    Interrupted {
+        imported: usize,
+        error: Box<ImportError>,
+    },
+}
+
+impl TextChannel {
+    /// Imports a batch of messages with explicit timestamps and authors.
+    ///
+    /// The messages are written directly to the keyspace and search index
+    /// with a single commit, and aren't broadcast to subscribers. Useful
+    /// for migrating from another system or restoring an export.
+    ///
+    /// Returns the number of messages imported. An import that fails
+    /// partway through returns [`ImportError::Interrupted`] instead,
+    /// with the number of messages imported before the failure.
+    pub async fn import(&self, messages: Vec<TextChannelMessage>) -> Result<usize, ImportError> {
+        if messages.is_empty() {
+            return Err(ImportError::EmptyBatch);
+        }
+
+        if messages.len() > MAX_IMPORT_BATCH {
+            return Err(ImportError::BatchTooLarge(messages.len()));
+        }
+
+        if let Some(msg) = messages
+            .iter()
+            .find(|msg| msg.content.len() > self.max_content_bytes)
+        {
+            return Err(ImportError::ContentTooLarge(msg.id));
+        }
+
+        let (reply, response) = oneshot::channel();
+
+        self.send_action(TextChannelAction::Import { messages, reply })
+            .await
+            .map_err(|_| ImportError::ChannelClosed)?;
+
+        response.await.map_err(|_| ImportError::ChannelClosed)?
+    }
+}
+
+/// Validates that a batch of messages can be imported into the keyspace.
+pub(super) fn validate_import(
+    store: &dyn MessageStore,
+    messages: &[TextChannelMessage],
+) -> Result<(), ImportError> {
+    let mut seen_ids = HashSet::new();
+    let mut previous_timestamp_ms = 0;
+
+    for msg in messages {
+        if msg.timestamp_ms + IMPORT_TIMESTAMP_TOLERANCE_MS < previous_timestamp_ms {
+            return Err(ImportError::TimestampOutOfOrder(msg.id));
+        }
+        previous_timestamp_ms = previous_timestamp_ms.max(msg.timestamp_ms);
+
+        // Messages without an ID are assigned one during import.
+        if msg.id.0 == 0 {
+            continue;
+        }
+
+        if !seen_ids.insert(msg.id) {
+            return Err(ImportError::DuplicateMessageId(msg.id));
+        }
+
+        // Reject messages that were already imported into the channel.
+        if store.contains(msg.id).map_err(ImportError::DatabaseError)? {
+            return Err(ImportError::DuplicateMessageId(msg.id));
+        }
+    }
+
+    Ok(())
+}
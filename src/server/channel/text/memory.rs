@@ -0,0 +1,696 @@
+//! In-memory storage backends for text channels.
+//!
+//! Nothing is persisted, so these are useful for tests and for
+//! embedders that don't need a channel's history to outlive it.
+//! [`TextChannel::new_in_memory`] constructs a channel using them.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    ops::{Bound, Range},
+    sync::Arc,
+};
+
+use parking_lot::{Mutex, RwLock};
+use tantivy::TantivyError;
+
+use crate::{
+    channel::ChannelId,
+    message::MessageId,
+    server::channel::text::{
+        TextChannel, TextChannelError, TextChannelMessage, TextChannelOptions, TextChannelSettings,
+        search::{
+            MAX_FUZZY_DISTANCE, MAX_FUZZY_TERMS, MAX_SEARCH_LIMIT, MAX_SNIPPET_CHARS, SearchError,
+            SearchHit, SearchIndex, SearchKey, SearchMode, SearchQuery, SearchSort, escape_html,
+            recency_boosted,
+        },
+        store::MessageStore,
+    },
+    user::UserId,
+};
+
+impl TextChannel {
+    /// Constructs a new channel that stores and indexes it's messages in memory.
+    ///
+    /// The channel's messages are lost when it's dropped.
+    pub fn new_in_memory(
+        id: ChannelId,
+        label: String,
+        settings: TextChannelSettings,
+        options: &TextChannelOptions,
+    ) -> Result<Self, TextChannelError> {
+        Self::with_backends(
+            id,
+            label,
+            settings,
+            options,
+            Arc::new(InMemoryMessageStore::new()),
+            Arc::new(InMemorySearchIndex::new()),
+        )
+    }
+}
+
+/// Stores a channel's messages and pins in memory.
+#[derive(Default)]
+pub struct InMemoryMessageStore {
+    /// Messages keyed by their ID.
+    messages: RwLock<BTreeMap<u64, TextChannelMessage>>,
+    /// IDs of the pinned messages.
+    pins: RwLock<BTreeSet<u64>>,
+}
+
+impl InMemoryMessageStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MessageStore for InMemoryMessageStore {
+    fn message_count(&self) -> u64 {
+        self.messages.read().len() as u64
+    }
+
+    fn insert(&self, msg: &TextChannelMessage) -> Result<(), fjall::Error> {
+        self.messages.write().insert(msg.id.0, msg.clone());
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        id: MessageId,
+        change: &mut dyn FnMut(&mut TextChannelMessage),
+    ) -> Result<Option<(TextChannelMessage, TextChannelMessage)>, fjall::Error> {
+        let mut messages = self.messages.write();
+        let Some(msg) = messages.get_mut(&id.0) else {
+            return Ok(None);
+        };
+
+        let original = msg.clone();
+        change(msg);
+        msg.id = id;
+
+        Ok(Some((original, msg.clone())))
+    }
+
+    fn remove(&self, id: MessageId) -> Result<(), fjall::Error> {
+        self.messages.write().remove(&id.0);
+        Ok(())
+    }
+
+    fn remove_before(&self, id: MessageId, limit: usize) -> Result<usize, fjall::Error> {
+        let mut messages = self.messages.write();
+
+        let keys = messages
+            .range(..id.0)
+            .take(limit)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        for key in &keys {
+            messages.remove(key);
+        }
+
+        Ok(keys.len())
+    }
+
+    fn contains(&self, id: MessageId) -> Result<bool, fjall::Error> {
+        Ok(self.messages.read().contains_key(&id.0))
+    }
+
+    fn get(&self, id: MessageId) -> Result<Option<TextChannelMessage>, fjall::Error> {
+        Ok(self.messages.read().get(&id.0).cloned())
+    }
+
+    fn nth_id(&self, n: usize) -> Result<Option<MessageId>, fjall::Error> {
+        Ok(self.messages.read().keys().nth(n).copied().map(MessageId))
+    }
+
+    fn messages_before(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        let mut messages = self
+            .messages
+            .read()
+            .range(..id.0)
+            .rev()
+            .take(limit)
+            .map(|(_, msg)| msg.clone())
+            .collect::<Vec<_>>();
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    fn messages_after(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        Ok(self
+            .messages
+            .read()
+            .range((Bound::Excluded(id.0), Bound::Unbounded))
+            .take(limit)
+            .map(|(_, msg)| msg.clone())
+            .collect())
+    }
+
+    fn count_after(&self, id: MessageId, max: u64) -> Result<u64, fjall::Error> {
+        Ok(self
+            .messages
+            .read()
+            .range((Bound::Excluded(id.0), Bound::Unbounded))
+            .take(max.try_into().unwrap_or(usize::MAX))
+            .count() as u64)
+    }
+
+    fn is_pinned(&self, id: MessageId) -> Result<bool, fjall::Error> {
+        Ok(self.pins.read().contains(&id.0))
+    }
+
+    fn pin_count(&self) -> Result<usize, fjall::Error> {
+        Ok(self.pins.read().len())
+    }
+
+    fn pin(&self, id: MessageId) -> Result<(), fjall::Error> {
+        self.pins.write().insert(id.0);
+        Ok(())
+    }
+
+    fn unpin(&self, id: MessageId) -> Result<(), fjall::Error> {
+        self.pins.write().remove(&id.0);
+        Ok(())
+    }
+
+    fn pinned_ids(&self) -> Result<Vec<MessageId>, fjall::Error> {
+        Ok(self.pins.read().iter().copied().map(MessageId).collect())
+    }
+
+    fn sync(&self) -> Result<(), fjall::Error> {
+        // Nothing is persisted, so there's nothing to sync.
+        Ok(())
+    }
+}
+
+/// Indexes a channel's messages in memory.
+///
+/// Terms are matched case-insensitively against the words in the
+/// message content, which is close enough to the Tantivy index for
+/// exercising search without creating an index directory. The query
+/// syntax of exact searches isn't supported, the query is only split
+/// into words that must all match.
+#[derive(Default)]
+pub struct InMemorySearchIndex {
+    /// Messages visible to searches.
+    committed: RwLock<Vec<TextChannelMessage>>,
+    /// Changes waiting for the next commit.
+    pending: Mutex<PendingChanges>,
+}
+
+/// Changes to the in-memory index waiting for the next commit.
+#[derive(Default)]
+struct PendingChanges {
+    /// Messages added since the last commit.
+    added: Vec<TextChannelMessage>,
+    /// Messages with IDs before this one are deleted on commit.
+    delete_before: Option<MessageId>,
+    /// Messages with these IDs are deleted on commit.
+    deleted_messages: Vec<MessageId>,
+    /// Messages posted by these authors are deleted on commit.
+    deleted_authors: Vec<UserId>,
+    /// Messages sent within these time ranges are deleted on commit.
+    deleted_ranges: Vec<Range<u64>>,
+    /// Every committed message is deleted on commit.
+    clear: bool,
+}
+
+impl InMemorySearchIndex {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SearchIndex for InMemorySearchIndex {
+    fn add(&self, msg: &TextChannelMessage) -> Result<(), TantivyError> {
+        self.pending.lock().added.push(msg.clone());
+        Ok(())
+    }
+
+    fn delete_before(&self, before: MessageId) -> Result<(), TantivyError> {
+        let mut pending = self.pending.lock();
+
+        // Like Tantivy, the delete applies to messages added before it.
+        pending.added.retain(|msg| msg.id >= before);
+        pending.delete_before = pending.delete_before.max(Some(before));
+
+        Ok(())
+    }
+
+    fn delete_message(&self, msg: &TextChannelMessage) -> Result<(), TantivyError> {
+        let mut pending = self.pending.lock();
+
+        pending.added.retain(|added| added.id != msg.id);
+        pending.deleted_messages.push(msg.id);
+
+        Ok(())
+    }
+
+    fn delete_by_author(&self, author: UserId) -> Result<(), TantivyError> {
+        let mut pending = self.pending.lock();
+
+        pending.added.retain(|msg| msg.author != author);
+        pending.deleted_authors.push(author);
+
+        Ok(())
+    }
+
+    fn delete_in_range(&self, start_ms: u64, end_ms: u64) -> Result<(), TantivyError> {
+        let mut pending = self.pending.lock();
+
+        let range = start_ms..end_ms;
+        pending
+            .added
+            .retain(|msg| !range.contains(&msg.timestamp_ms));
+        pending.deleted_ranges.push(range);
+
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), TantivyError> {
+        // Anything added before the clear is deleted along with it.
+        *self.pending.lock() = PendingChanges {
+            clear: true,
+            ..Default::default()
+        };
+
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<(), TantivyError> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        let mut committed = self.committed.write();
+
+        if pending.clear {
+            committed.clear();
+        }
+
+        if let Some(before) = pending.delete_before {
+            committed.retain(|msg| msg.id >= before);
+        }
+        if !pending.deleted_messages.is_empty() {
+            committed.retain(|msg| !pending.deleted_messages.contains(&msg.id));
+        }
+        if !pending.deleted_authors.is_empty() {
+            committed.retain(|msg| !pending.deleted_authors.contains(&msg.author));
+        }
+        for range in &pending.deleted_ranges {
+            committed.retain(|msg| !range.contains(&msg.timestamp_ms));
+        }
+        committed.extend(pending.added);
+
+        Ok(())
+    }
+
+    fn search(&self, query: SearchQuery, now_ms: u64) -> Result<Vec<SearchHit>, SearchError> {
+        let terms = words(&query.text)
+            .map(str::to_lowercase)
+            .take(MAX_FUZZY_TERMS)
+            .collect::<Vec<_>>();
+
+        // Nothing to match, such as a query of only punctuation.
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut hits = self
+            .committed
+            .read()
+            .iter()
+            .filter(|msg| query.author.is_none_or(|author| msg.author == author))
+            .filter(|msg| {
+                query
+                    .from_ms
+                    .is_none_or(|from_ms| msg.timestamp_ms >= from_ms)
+            })
+            .filter(|msg| query.to_ms.is_none_or(|to_ms| msg.timestamp_ms <= to_ms))
+            .filter_map(|msg| {
+                let score = match_score(&msg.content, &terms, query.mode)?;
+
+                Some(SearchHit {
+                    score,
+                    message_id: msg.id,
+                    author: msg.author,
+                    timestamp_ms: msg.timestamp_ms,
+                    content: msg.content.clone(),
+                    reply_to: msg.reply_to,
+                    edited_at: msg.edited_at,
+                    highlight: highlight(
+                        &msg.content,
+                        &terms,
+                        query.mode,
+                        query.snippet_chars.clamp(1, MAX_SNIPPET_CHARS),
+                    ),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if query.sort == SearchSort::Hybrid {
+            for hit in &mut hits {
+                hit.score = recency_boosted(hit.score, hit.timestamp_ms, now_ms);
+            }
+        }
+
+        // Match scores only depend on the message, so the cursor's
+        // key still places it's message among the other hits.
+        if let Some(cursor) = query.cursor {
+            hits.retain(|hit| SearchKey::of(query.sort, hit) < cursor.last());
+        }
+
+        hits.sort_by_key(|hit| Reverse(SearchKey::of(query.sort, hit)));
+        hits.truncate(query.limit.clamp(1, MAX_SEARCH_LIMIT));
+
+        Ok(hits)
+    }
+}
+
+/// Splits text into it's words, dropping punctuation and whitespace.
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+/// Returns true if a lowercased word matches the query term at the position.
+fn term_matches(word: &str, terms: &[String], i: usize, mode: SearchMode) -> bool {
+    let term = terms[i].as_str();
+
+    match mode {
+        // Query syntax isn't parsed, so advanced queries only match content words.
+        SearchMode::Exact | SearchMode::Advanced => word == term,
+        SearchMode::Fuzzy { distance } => {
+            levenshtein(word, term) <= distance.min(MAX_FUZZY_DISTANCE) as usize
+        }
+        // Only the last term is still being typed.
+        SearchMode::Prefix if i == terms.len() - 1 => word.starts_with(term),
+        SearchMode::Prefix => word == term,
+    }
+}
+
+/// Scores the content against the query terms.
+///
+/// Every term has to match a word in the content, and the score is the
+/// number of matching words. Returns `None` if a term didn't match.
+fn match_score(content: &str, terms: &[String], mode: SearchMode) -> Option<f32> {
+    let words = words(content).map(str::to_lowercase).collect::<Vec<_>>();
+
+    let mut score = 0;
+    for i in 0..terms.len() {
+        let matches = words
+            .iter()
+            .filter(|word| term_matches(word, terms, i, mode))
+            .count();
+
+        if matches == 0 {
+            return None;
+        }
+
+        score += matches;
+    }
+
+    Some(score as f32)
+}
+
+/// Excerpts the start of the content with the matching words wrapped in
+/// `<b>` tags, escaped as HTML to match the Tantivy snippets.
+fn highlight(content: &str, terms: &[String], mode: SearchMode, max_chars: usize) -> String {
+    let excerpt = match content.char_indices().nth(max_chars) {
+        Some((end, _)) => &content[..end],
+        None => content,
+    };
+
+    let mut highlighted = String::with_capacity(excerpt.len());
+    let mut word_start = None;
+
+    // Walk the excerpt, flushing each word once it's end is reached.
+    for (i, c) in excerpt.char_indices().chain([(excerpt.len(), ' ')]) {
+        if c.is_alphanumeric() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+
+        if let Some(start) = word_start.take() {
+            let word = &excerpt[start..i];
+            let lowercase = word.to_lowercase();
+
+            if (0..terms.len()).any(|t| term_matches(&lowercase, terms, t, mode)) {
+                highlighted.push_str("<b>");
+                highlighted.push_str(&escape_html(word));
+                highlighted.push_str("</b>");
+            } else {
+                highlighted.push_str(&escape_html(word));
+            }
+        }
+
+        if i < excerpt.len() {
+            highlighted.push_str(&escape_html(&c.to_string()));
+        }
+    }
+
+    highlighted
+}
+
+/// Computes the Levenshtein edit distance between two words.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server::{
+            channel::text::{
+                edit::EditMessageError,
+                tests::{test_channel, test_message, test_options, test_query, wait_for_commit},
+            },
+            permission::Permissions,
+        },
+        user::UserId,
+    };
+
+    fn channel() -> TextChannel {
+        TextChannel::new_in_memory(
+            ChannelId(1),
+            "general".to_string(),
+            TextChannelSettings::default(),
+            &test_options(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn created_messages_are_stored() {
+        let channel = channel();
+
+        let created = channel
+            .create_message(test_message(UserId(1), "hello"), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        assert_ne!(created.id, MessageId::default());
+        assert_eq!(channel.message_count(), 1);
+
+        let stored = channel.message(created.id).unwrap().unwrap();
+        assert_eq!(stored.author, UserId(1));
+        assert_eq!(stored.content, "hello");
+    }
+
+    #[tokio::test]
+    async fn edits_replace_the_content() {
+        let channel = channel();
+        let created = channel
+            .create_message(test_message(UserId(1), "original"), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        let edited = channel
+            .edit_message(created.id, UserId(1), "replacement", None)
+            .await
+            .unwrap();
+        assert_eq!(edited.content, "replacement");
+        assert!(edited.edited_at.is_some());
+
+        let stored = channel.message(created.id).unwrap().unwrap();
+        assert_eq!(stored.content, "replacement");
+
+        wait_for_commit().await;
+
+        assert!(
+            channel
+                .search(test_query("original"))
+                .unwrap()
+                .hits
+                .is_empty()
+        );
+        assert_eq!(
+            channel
+                .search(test_query("replacement"))
+                .unwrap()
+                .hits
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_author_can_edit() {
+        let channel = channel();
+        let created = channel
+            .create_message(test_message(UserId(1), "mine"), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        let result = channel
+            .edit_message(created.id, UserId(2), "theirs", None)
+            .await;
+        assert!(matches!(result, Err(EditMessageError::NotAuthor)));
+    }
+
+    #[tokio::test]
+    async fn deleted_messages_are_removed_from_the_store_and_index() {
+        let channel = channel();
+        let kept = channel
+            .create_message(
+                test_message(UserId(1), "keep this"),
+                None,
+                Permissions::NONE,
+            )
+            .await
+            .unwrap();
+        let deleted = channel
+            .create_message(
+                test_message(UserId(2), "delete this"),
+                None,
+                Permissions::NONE,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(channel.delete_by_author(UserId(2)).await.unwrap(), 1);
+
+        assert_eq!(channel.message_count(), 1);
+        assert!(channel.message(kept.id).unwrap().is_some());
+        assert!(channel.message(deleted.id).unwrap().is_none());
+
+        let page = channel.search(test_query("this")).unwrap();
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].author, UserId(1));
+    }
+
+    #[tokio::test]
+    async fn search_matches_message_content() {
+        let channel = channel();
+        for content in [
+            "the campfire is lit",
+            "bring marshmallows",
+            "campfire songs",
+        ] {
+            channel
+                .create_message(test_message(UserId(1), content), None, Permissions::NONE)
+                .await
+                .unwrap();
+        }
+
+        wait_for_commit().await;
+
+        let hits = channel.search(test_query("campfire")).unwrap().hits;
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.content.contains("campfire")));
+
+        assert!(channel.search(test_query("tent")).unwrap().hits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn channels_behave_the_same_with_either_backend() {
+        /// Creates, edits, and deletes messages, returning what each search finds.
+        async fn searches(channel: &TextChannel) -> Vec<Vec<String>> {
+            let mut ids = Vec::new();
+            for (author, content) in [
+                (1, "the campfire is lit"),
+                (2, "bring marshmallows"),
+                (1, "campfire songs tonight"),
+                (3, "who has the tent"),
+            ] {
+                let created = channel
+                    .create_message(
+                        test_message(UserId(author), content),
+                        None,
+                        Permissions::NONE,
+                    )
+                    .await
+                    .unwrap();
+                ids.push(created.id);
+            }
+
+            channel
+                .edit_message(ids[1], UserId(2), "bring campfire snacks", None)
+                .await
+                .unwrap();
+            channel.delete_by_author(UserId(3)).await.unwrap();
+            assert_eq!(channel.message_count(), 3);
+
+            wait_for_commit().await;
+
+            ["campfire", "marshmallows", "tent", "songs"]
+                .into_iter()
+                .map(|text| {
+                    let mut contents: Vec<_> = channel
+                        .search(test_query(text))
+                        .unwrap()
+                        .hits
+                        .into_iter()
+                        .map(|hit| hit.content)
+                        .collect();
+                    contents.sort();
+                    contents
+                })
+                .collect()
+        }
+
+        let (_dir, on_disk) = test_channel();
+        let in_memory = searches(&channel()).await;
+
+        assert_eq!(in_memory, searches(&on_disk).await);
+        assert_eq!(
+            in_memory,
+            [
+                vec![
+                    "bring campfire snacks",
+                    "campfire songs tonight",
+                    "the campfire is lit"
+                ],
+                vec![],
+                vec![],
+                vec!["campfire songs tonight"],
+            ]
+        );
+    }
+}
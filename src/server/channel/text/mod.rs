@@ -1,33 +1,108 @@
 //! Provides text channel functionality.
 
-use std::{io, path::PathBuf};
+use std::{io, path::Path, sync::Arc, time::Duration};
 
-use fjall::KeyspaceCreateOptions;
+use parking_lot::{Mutex, RwLock};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use snowflaked::Snowflake;
 use tantivy::{TantivyError, directory::error::OpenDirectoryError};
-use tokio::sync::broadcast;
+use tokio::{
+    sync::{broadcast, oneshot},
+    task::JoinHandle,
+};
 
 use crate::{
-    server::channel::{
-        ChannelId,
-        text::search::{
-            SCHEMA_KEY_AUTHOR, SCHEMA_KEY_CONTENT, SCHEMA_KEY_TIMESTAMP, text_search_schema,
+    id::now_ms,
+    message::{MessageFlags, MessageId, MessageMentions},
+    server::{
+        channel::{
+            Channel, ChannelId,
+            text::{
+                automod::{AutoMod, AutoModConfig, AutoModReason},
+                edit::EditMessageError,
+                import::ImportError,
+                nonce::NonceCache,
+                purge::PurgeError,
+                ratelimit::RateLimiter,
+                reindex::ReindexError,
+                retention::RetentionPolicy,
+                search::{
+                    MAX_SEARCH_LIMIT, MAX_SEARCH_OFFSET, SearchCursor, SearchError, SearchIndex,
+                    SearchKey, SearchPage, SearchQuery, SearchTokenizer, TantivySearchIndex,
+                },
+                slowmode::SlowMode,
+                store::{FjallMessageStore, MessageStore},
+            },
         },
+        metrics::metrics,
+        permission::Permissions,
     },
     user::UserId,
 };
 
+pub mod automod;
+pub mod content;
+pub mod create;
+pub mod edit;
+pub mod export;
+pub mod import;
+#[cfg(any(test, feature = "memory-backend"))]
+pub mod memory;
+pub mod nonce;
+pub mod pins;
+pub mod purge;
+pub mod ratelimit;
+pub mod reindex;
+pub mod reply;
+pub mod retention;
 pub mod search;
+pub mod slowmode;
+pub mod store;
 pub mod worker;
 
 /// A text message received on a channel.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TextChannelMessage {
+    /// Unique ID of the message.
+    ///
+    /// Assigned by the channel worker when the message is created,
+    /// any ID supplied with a new message is overwritten.
+    pub id: MessageId,
     /// The author of the message.
     pub author: UserId,
+    /// Overrides the display name of the author.
+    ///
+    /// Used by webhooks to post under a custom name.
+    pub author_name: Option<String>,
     /// Timestamp in milliseconds.
+    ///
+    /// Stamped by the channel worker from the time in the message's ID when
+    /// the message is created, any timestamp supplied with a new message is overwritten.
     pub timestamp_ms: u64,
+    /// Timestamp in milliseconds of the last edit, unset if the message was never edited.
+    ///
+    /// Set by the channel worker when the message is edited,
+    /// any timestamp supplied with a new message is overwritten.
+    #[serde(default)]
+    pub edited_at: Option<u64>,
     /// Text body of the message.
     pub content: String,
+    /// The message in the same channel that this message replies to.
+    #[serde(default)]
+    pub reply_to: Option<MessageId>,
+    /// The users, roles, and channels mentioned in the content.
+    ///
+    /// Decoded from the content by the channel worker when the message
+    /// is stored, any mentions supplied with a new message are overwritten.
+    #[serde(default)]
+    pub mentions: MessageMentions,
+    /// Attributes of the message.
+    ///
+    /// Users can only set [`MessageFlags::USER_SETTABLE`] flags, the pinned
+    /// flag is maintained by the channel as the message is pinned and unpinned.
+    #[serde(default)]
+    pub flags: MessageFlags,
 }
 
 /// These are sent to a channel to tell it to do something.
@@ -37,14 +112,74 @@ pub enum TextChannelAction {
     ///
     /// This adds the message to the time-series database for the
     /// channel and adds it as an indexed document for search.
-    MessageCreated(TextChannelMessage),
+    ///
+    /// The stored message, including it's assigned ID, is sent to the reply.
+    MessageCreated {
+        message: TextChannelMessage,
+        reply: Option<oneshot::Sender<TextChannelMessage>>,
+    },
 
     /// Informs the channel that an existing message should be edited
     /// with new contents, and the edit distributed to clients.
     ///
     /// This update's the message's contents stored in the time-series
     /// database and indexed for full-text search.
-    MessageEdited(),
+    ///
+    /// The edited message is sent to the reply.
+    MessageEdited {
+        id: MessageId,
+        author: UserId,
+        content: String,
+        /// Replaces the message's user-settable flags, if set.
+        flags: Option<MessageFlags>,
+        reply: oneshot::Sender<Result<TextChannelMessage, EditMessageError>>,
+    },
+
+    /// Informs the channel that messages with IDs before the specified
+    /// ID have been pruned from the time-series database, and should
+    /// be removed from the full-text search index.
+    PruneIndex { before: MessageId },
+
+    /// Informs the channel that a batch of messages should be stored and
+    /// indexed with a single commit, without broadcasting them to clients.
+    Import {
+        messages: Vec<TextChannelMessage>,
+        reply: oneshot::Sender<Result<usize, ImportError>>,
+    },
+
+    /// Informs the channel that it's search index should be rebuilt
+    /// from the messages in the time-series database.
+    ///
+    /// The number of messages re-added to the index is sent to the reply.
+    Reindex {
+        reply: oneshot::Sender<Result<usize, ReindexError>>,
+    },
+
+    /// Informs the channel that every message posted by the author should
+    /// be deleted from the time-series database and the search index.
+    ///
+    /// The number of deleted messages is sent to the reply.
+    DeleteByAuthor {
+        author: UserId,
+        reply: oneshot::Sender<Result<usize, PurgeError>>,
+    },
+
+    /// Informs the channel that every message sent from `start_ms` up to
+    /// `end_ms` should be deleted from the time-series database and the
+    /// search index.
+    ///
+    /// The number of deleted messages is sent to the reply.
+    DeleteInRange {
+        start_ms: u64,
+        end_ms: u64,
+        reply: oneshot::Sender<Result<usize, PurgeError>>,
+    },
+
+    /// Informs the channel that it's worker should exit.
+    ///
+    /// The worker commits any pending messages to the search index and
+    /// syncs the time-series database to disk before replying.
+    Shutdown { reply: oneshot::Sender<()> },
 }
 
 /// Events that can occur in a text channel.
@@ -55,12 +190,61 @@ pub enum TextChannelAction {
 pub enum TextChannelEvent {
     NewMessage(TextChannelMessage),
     MessageEdited(TextChannelMessage),
+    /// The set of pinned messages changed.
+    PinsUpdated {
+        pinned: Vec<MessageId>,
+    },
+    /// Messages were deleted from the channel by a moderator.
+    MessagesDeleted {
+        ids: Vec<MessageId>,
+    },
+    /// Every message sent from `start_ms` up to `end_ms` was deleted from the channel.
+    ///
+    /// Sent instead of listing the deleted messages, which could be many.
+    MessagesDeletedInRange {
+        start_ms: u64,
+        end_ms: u64,
+        count: usize,
+    },
+    /// A message from the user was rejected instead of being stored.
+    ///
+    /// Transports should only forward this to the author's clients.
+    MessageRejected {
+        author: UserId,
+        reason: MessageRejection,
+    },
+    /// A stored message broke an auto-moderation rule, and should be reviewed.
+    MessageFlagged {
+        message_id: MessageId,
+        author: UserId,
+        reason: AutoModReason,
+    },
+}
+
+/// Why a message was rejected by the channel.
+#[derive(Clone, Debug)]
+pub enum MessageRejection {
+    /// The author posted too many messages recently.
+    RateLimited { retry_after_ms: u64 },
+    /// The author posted within the channel's slow mode cooldown.
+    SlowMode { retry_after_ms: u64 },
+    /// The message's content is over the channel's size limit.
+    ///
+    /// Only sent for messages that reached the worker without going
+    /// through [`TextChannel::create_message`], which rejects them
+    /// with [`content::ContentError::TooLarge`] instead.
+    TooLarge { bytes: usize, max_bytes: usize },
+    /// The message broke an auto-moderation rule.
+    AutoModerated(AutoModReason),
 }
 
 /// Indiciates there's was an error creating or loading a channel.
+#[derive(Debug)]
 pub enum TextChannelError {
     /// Indicates that a blank label was supplied.
     LabelRequired,
+    /// Indicates the topic is longer than [`MAX_TOPIC_CHARS`].
+    TopicTooLong { len: usize, max: usize },
     /// Indicates there was an error creating the channel
     /// keyspace for storing the time-series message data.
     KeyspaceError(fjall::Error),
@@ -77,6 +261,87 @@ pub enum TextChannelError {
 
 pub type TextChannelSender = tachyonix::Sender<TextChannelAction>;
 
+/// Server-level options used to construct text channels.
+#[derive(Clone, Debug)]
+pub struct TextChannelOptions {
+    /// Memory budget in bytes for the channel's search index writer.
+    pub index_writer_heap_bytes: usize,
+    /// Tokenizer used for the channel's search index.
+    pub search_tokenizer: SearchTokenizer,
+    /// Instance ID embedded in the generated message IDs.
+    pub instance_id: u16,
+    /// Epoch that message ID timestamps count from, in milliseconds since the Unix epoch.
+    pub snowflake_epoch_ms: u64,
+    /// The maximum number of pinned messages in the channel.
+    pub max_pins: usize,
+    /// Allows messages to reply to messages that don't exist in the channel.
+    pub allow_dangling_replies: bool,
+    /// Maximum number of messages a user can post per rate limit interval.
+    pub message_rate_limit: u32,
+    /// Duration of the per-user message rate limit interval.
+    pub message_rate_interval: Duration,
+    /// The number of actions that can be queued for the channel worker.
+    pub queue_capacity: usize,
+    /// The maximum length of a message's content in graphemes.
+    pub max_content_graphemes: usize,
+    /// The maximum size of a message's content in bytes.
+    pub max_content_bytes: usize,
+    /// The number of events buffered for the channel's subscribers.
+    pub event_capacity: usize,
+    /// Rules new messages are checked against, unset to disable auto-moderation.
+    pub auto_moderation: Option<AutoModConfig>,
+}
+
+/// The maximum length of a channel's topic in characters.
+pub const MAX_TOPIC_CHARS: usize = 1024;
+
+/// Directory in a channel's data directory containing it's search index.
+pub const SEARCH_INDEX_DIR: &str = "search";
+
+/// User-configurable settings for a text channel.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TextChannelSettings {
+    /// Longer description of what the channel is about, shown alongside it's label.
+    ///
+    /// At most [`MAX_TOPIC_CHARS`] characters.
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// Limits how long messages are retained in the channel.
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Limits users to posting once every this many seconds.
+    #[serde(default)]
+    pub slow_mode_secs: Option<u64>,
+    /// Permissions users must hold to read the channel's messages.
+    ///
+    /// Every user can read the channel if none are required.
+    #[serde(default)]
+    pub read_permissions: Permissions,
+    /// How durable new messages are when their creation is acknowledged.
+    #[serde(default)]
+    pub durability: Durability,
+}
+
+/// How durable a new message is when it's creation is acknowledged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// Messages are acknowledged as soon as they're written.
+    ///
+    /// A crash shortly after can lose the message, and it isn't
+    /// searchable until the worker's next batched index commit.
+    #[default]
+    Fast,
+    /// Messages are acknowledged once they're synced to disk and
+    /// committed to the search index.
+    ///
+    /// Each message waits for an fsync and an index commit, which adds
+    /// milliseconds of latency to every message and limits the channel's
+    /// throughput. If the message can't be made durable it's removed
+    /// again without being broadcast, and the sender gets an error.
+    Durable,
+}
+
 /// A channel on a server.
 pub struct TextChannel {
     /// The unique ID used to identify the channel.
@@ -85,80 +350,169 @@ pub struct TextChannel {
     /// User-facing label for the channel.
     label: String,
 
-    /// Keyspace for storing the time-series data for channel messages.
-    keyspace: fjall::Keyspace,
+    /// Epoch that the channel's snowflake ID timestamps count from.
+    snowflake_epoch_ms: u64,
+
+    /// User-configurable settings for the channel.
+    ///
+    /// Shared with the channel's background tasks.
+    settings: Arc<RwLock<TextChannelSettings>>,
+
+    /// Storage for the time-series data for channel messages.
+    store: Arc<dyn MessageStore>,
+
+    /// Full-text search index of the channel's messages.
+    index: Arc<dyn SearchIndex>,
+
+    /// The maximum number of pinned messages in the channel.
+    max_pins: usize,
+    /// Allows messages to reply to messages that don't exist in the channel.
+    allow_dangling_replies: bool,
+
+    /// The maximum length of a message's content in graphemes.
+    max_content_graphemes: usize,
+    /// The maximum size of a message's content in bytes.
+    max_content_bytes: usize,
+
+    /// Limits how many messages each user can post.
+    rate_limiter: RateLimiter,
+    /// Tracks recent posts for the channel's slow mode.
+    slow_mode: SlowMode,
+    /// Remembers recent nonces to deduplicate retried messages.
+    nonces: NonceCache,
+    /// Checks new messages against the auto-moderation rules, if enabled.
+    automod: Option<AutoMod>,
 
     /// Sender for sending messages to the channel.
     message_sender: TextChannelSender,
 
+    /// Sender for events emitted outside of the channel worker.
+    event_sender: broadcast::Sender<TextChannelEvent>,
+
     /// Receiver for events emitted by the channel.
     ///
     /// This is typically cloned by a transport (i.e. an HTTP WebSocket
     /// handler) to receive and forward the events to the client.
     event_receiver: broadcast::Receiver<TextChannelEvent>,
+
+    /// Handle of the channel's worker task, taken when the channel shuts down.
+    worker: Mutex<Option<JoinHandle<()>>>,
+    /// Handle of the channel's retention task, taken when the channel shuts down.
+    retention: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl TextChannel {
     /// Constructs a new channel instance.
+    ///
+    /// Messages are stored in the fjall database, and
+    /// indexed in a search index in the data directory.
     pub fn new(
         id: ChannelId,
-        data_dir: &PathBuf,
+        data_dir: &Path,
         db: fjall::Database,
         label: String,
+        settings: TextChannelSettings,
+        options: &TextChannelOptions,
     ) -> Result<Self, TextChannelError> {
         if label.is_empty() {
             return Err(TextChannelError::LabelRequired);
         }
 
-        // Construct the database keyspace for storing the channel messages.
+        // Construct the database keyspaces for storing the channel messages.
         //
-        // This will create a new keyspace if none exists, or open an existing one.
-        let keyspace = db
-            .keyspace(&id.0.to_string(), keyspace_create_options)
-            .map_err(|e| TextChannelError::KeyspaceError(e))?;
-
-        // Create the text search schema used for querying logs.
-        let schema = text_search_schema();
-
-        // Create the directory for the search index if required.
-        let index_dir_path: PathBuf = data_dir.join("search");
-        std::fs::create_dir_all(&index_dir_path)
-            .map_err(|e| TextChannelError::SearchIndexPathError(e))?;
-
-        // Open or create the search index.
-        let index_directory = tantivy::directory::MmapDirectory::open(index_dir_path)
-            .map_err(|e| TextChannelError::SearchIndexDirectoryError(e))?;
-        let index = tantivy::Index::open_or_create(index_directory, schema.clone())
-            .map_err(|e| TextChannelError::SearchError(e))?;
-
-        // Create the index writing for the channel's message worker task.
-        let index_writer: tantivy::IndexWriter = index
-            .writer(50_000_000) // 50MB
-            .map_err(|e| TextChannelError::SearchError(e))?;
+        // This will create new keyspaces if none exist, or open the existing ones.
+        let store = FjallMessageStore::open(&db, id).map_err(TextChannelError::KeyspaceError)?;
+
+        let index = TantivySearchIndex::open(
+            &data_dir.join(SEARCH_INDEX_DIR),
+            options.index_writer_heap_bytes,
+            options.search_tokenizer,
+        )?;
+
+        Self::with_backends(
+            id,
+            label,
+            settings,
+            options,
+            Arc::new(store),
+            Arc::new(index),
+        )
+    }
+
+    /// Constructs a new channel instance using the storage backends.
+    pub fn with_backends(
+        id: ChannelId,
+        label: String,
+        settings: TextChannelSettings,
+        options: &TextChannelOptions,
+        store: Arc<dyn MessageStore>,
+        index: Arc<dyn SearchIndex>,
+    ) -> Result<Self, TextChannelError> {
+        if label.is_empty() {
+            return Err(TextChannelError::LabelRequired);
+        }
+
+        if let Some(topic) = &settings.topic {
+            let len = topic.chars().count();
+            if len > MAX_TOPIC_CHARS {
+                return Err(TextChannelError::TopicTooLong {
+                    len,
+                    max: MAX_TOPIC_CHARS,
+                });
+            }
+        }
 
         // Create the channel used to forward messages to the text channel's worker task.
-        let (message_sender, message_receiver) = tachyonix::channel(25);
+        let (message_sender, message_receiver) = tachyonix::channel(options.queue_capacity);
 
-        let (event_sender, event_receiver) = broadcast::channel(25);
+        let (event_sender, event_receiver) = broadcast::channel(options.event_capacity);
+
+        let settings = Arc::new(RwLock::new(settings));
 
         // Spawn the text channel's worker.
         // TODO: restart worker if task crashes.
-        let _handle = tokio::spawn(worker::channel_worker(
+        let worker = tokio::spawn(worker::channel_worker(
+            id,
             message_receiver,
-            keyspace.clone(),
-            index_writer,
-            schema.get_field(SCHEMA_KEY_TIMESTAMP).unwrap(),
-            schema.get_field(SCHEMA_KEY_CONTENT).unwrap(),
-            schema.get_field(SCHEMA_KEY_AUTHOR).unwrap(),
-            event_sender,
+            options.clone(),
+            Arc::clone(&settings),
+            Arc::clone(&store),
+            Arc::clone(&index),
+            event_sender.clone(),
+        ));
+
+        // Spawn the task that prunes messages outside of the retention policy.
+        let retention = tokio::spawn(retention::retention_worker(
+            id,
+            Arc::clone(&store),
+            options.snowflake_epoch_ms,
+            Arc::clone(&settings),
+            message_sender.clone(),
         ));
 
         Ok(Self {
             id,
             label,
-            keyspace,
+            snowflake_epoch_ms: options.snowflake_epoch_ms,
+            settings,
+            store,
+            index,
+            max_pins: options.max_pins,
+            allow_dangling_replies: options.allow_dangling_replies,
+            max_content_graphemes: options.max_content_graphemes,
+            max_content_bytes: options.max_content_bytes,
+            rate_limiter: RateLimiter::new(
+                options.message_rate_limit,
+                options.message_rate_interval,
+            ),
+            slow_mode: SlowMode::default(),
+            nonces: NonceCache::default(),
+            automod: options.auto_moderation.clone().map(AutoMod::new),
             message_sender,
+            event_sender,
             event_receiver,
+            worker: Mutex::new(Some(worker)),
+            retention: Mutex::new(Some(retention)),
         })
     }
 
@@ -167,10 +521,141 @@ impl TextChannel {
     pub fn message_sender(&self) -> TextChannelSender {
         self.message_sender.clone()
     }
+
+    /// Stops the channel's worker after it handles the actions already sent.
+    ///
+    /// Returns once every stored message is committed to the search index
+    /// and synced to disk. Actions sent afterwards fail as the channel is closed.
+    ///
+    /// The retention task is stopped too, so once this returns the channel's
+    /// tasks no longer hold the channel's storage open.
+    pub async fn shutdown(&self) {
+        let (reply, done) = oneshot::channel();
+
+        // The worker already exited if it can't be sent to.
+        if self
+            .send_action(TextChannelAction::Shutdown { reply })
+            .await
+            .is_ok()
+        {
+            let _ = done.await;
+        }
+
+        // The worker exits on it's own after replying.
+        let worker = self.worker.lock().take();
+        if let Some(worker) = worker {
+            let _ = worker.await;
+        }
+
+        // The retention task runs until it's stopped.
+        let retention = self.retention.lock().take();
+        if let Some(retention) = retention {
+            retention.abort();
+            let _ = retention.await;
+        }
+    }
+
+    /// Returns a snapshot of the channel's settings.
+    pub fn settings(&self) -> TextChannelSettings {
+        self.settings.read().clone()
+    }
+
+    /// Replaces the channel's settings.
+    ///
+    /// Background tasks pick up the new settings on their next pass.
+    pub fn set_settings(&self, settings: TextChannelSettings) {
+        *self.settings.write() = settings;
+    }
+
+    /// Returns the settings shared with the channel's background tasks,
+    /// for tasks outside the channel that need to follow changes to them.
+    pub(crate) fn shared_settings(&self) -> Arc<RwLock<TextChannelSettings>> {
+        Arc::clone(&self.settings)
+    }
+
+    /// Returns when the channel was created in milliseconds.
+    ///
+    /// Decoded from the timestamp embedded in the channel's ID,
+    /// which counts from the configured snowflake epoch.
+    pub fn created_at_ms(&self) -> u64 {
+        self.id.timestamp() + self.snowflake_epoch_ms
+    }
+
+    /// Returns the number of messages stored in the channel.
+    pub fn message_count(&self) -> u64 {
+        self.store.message_count()
+    }
+
+    /// Returns the number of transports subscribed to the channel's events.
+    pub fn subscriber_count(&self) -> usize {
+        // The channel holds a receiver of it's own to resubscribe from.
+        self.event_sender.receiver_count().saturating_sub(1)
+    }
+
+    /// Looks up a message in the channel by it's ID.
+    pub fn message(&self, id: MessageId) -> Result<Option<TextChannelMessage>, fjall::Error> {
+        self.store.get(id)
+    }
+
+    /// Returns up to `limit` of the messages sent before the message, oldest first.
+    pub fn messages_before(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        self.store.messages_before(id, limit)
+    }
+
+    /// Returns up to `limit` of the messages sent after the message, oldest first.
+    pub fn messages_after(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        self.store.messages_after(id, limit)
+    }
+
+    /// Counts the messages sent after the message, stopping at `max`.
+    pub fn count_after(&self, id: MessageId, max: u64) -> Result<u64, fjall::Error> {
+        self.store.count_after(id, max)
+    }
+
+    /// Searches the messages in the channel.
+    ///
+    /// Results are ordered by the query's sort, and paged through
+    /// by searching again with the cursor of the next page.
+    pub fn search(&self, query: SearchQuery) -> Result<SearchPage, SearchError> {
+        let (depth, now_ms) = match query.cursor {
+            Some(cursor) if cursor.sort() != query.sort => return Err(SearchError::InvalidCursor),
+            Some(cursor) if cursor.depth() > MAX_SEARCH_OFFSET => {
+                return Err(SearchError::OffsetTooLarge(cursor.depth()));
+            }
+            Some(cursor) => (cursor.depth(), cursor.now_ms()),
+            None => (0, now_ms()),
+        };
+
+        let _timer = metrics().search_seconds.start_timer();
+        metrics().search_queries.inc();
+
+        let sort = query.sort;
+        let limit = query.limit.clamp(1, MAX_SEARCH_LIMIT);
+        let hits = self.index.search(query, now_ms)?;
+
+        // A full page may be followed by more results, unless
+        // the next page would be past the offset limit.
+        let depth = depth + hits.len();
+        let next_cursor = hits
+            .last()
+            .filter(|_| hits.len() == limit && depth <= MAX_SEARCH_OFFSET)
+            .map(|last| SearchCursor::new(sort, SearchKey::of(sort, last), now_ms, depth));
+
+        Ok(SearchPage { hits, next_cursor })
+    }
 }
 
 impl super::Channel for TextChannel {
     type Event = TextChannelEvent;
+    type Action = TextChannelAction;
 
     fn channel_id(&self) -> ChannelId {
         self.id
@@ -187,9 +672,189 @@ impl super::Channel for TextChannel {
     fn subscribe(&self) -> broadcast::Receiver<Self::Event> {
         self.event_receiver.resubscribe()
     }
+
+    async fn send_action(&self, action: Self::Action) -> Result<(), super::ChannelClosed> {
+        self.message_sender
+            .send(action)
+            .await
+            .map_err(|_| super::ChannelClosed)
+    }
 }
 
-/// Options for creating fjall keyspaces for channels.
-fn keyspace_create_options() -> KeyspaceCreateOptions {
-    KeyspaceCreateOptions::default()
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::server::channel::text::search::{
+        DEFAULT_SNIPPET_CHARS, MAX_SEARCH_LIMIT, SearchMode, SearchSort,
+    };
+
+    /// Options for channels created by tests, without limits that get in their way.
+    pub(crate) fn test_options() -> TextChannelOptions {
+        TextChannelOptions {
+            index_writer_heap_bytes: 15_000_000,
+            search_tokenizer: SearchTokenizer::default(),
+            instance_id: 0,
+            snowflake_epoch_ms: 0,
+            max_pins: 50,
+            allow_dangling_replies: false,
+            message_rate_limit: 1000,
+            message_rate_interval: Duration::from_secs(1),
+            queue_capacity: 100,
+            max_content_graphemes: 4000,
+            max_content_bytes: 16 << 10,
+            event_capacity: 100,
+            auto_moderation: None,
+        }
+    }
+
+    /// A new message from the author, as a client would send it.
+    pub(crate) fn test_message(author: UserId, content: &str) -> TextChannelMessage {
+        TextChannelMessage {
+            id: MessageId::default(),
+            author,
+            author_name: None,
+            timestamp_ms: 0,
+            edited_at: None,
+            content: content.to_string(),
+            reply_to: None,
+            mentions: MessageMentions::default(),
+            flags: MessageFlags::default(),
+        }
+    }
+
+    /// A search for the text, with the default limits.
+    pub(crate) fn test_query(text: &str) -> SearchQuery {
+        SearchQuery {
+            text: text.to_string(),
+            mode: SearchMode::Exact,
+            author: None,
+            from_ms: None,
+            to_ms: None,
+            limit: MAX_SEARCH_LIMIT,
+            cursor: None,
+            snippet_chars: DEFAULT_SNIPPET_CHARS,
+            sort: SearchSort::default(),
+        }
+    }
+
+    /// Opens a channel stored in a temporary directory, which is removed when it's dropped.
+    pub(crate) fn test_channel() -> (tempfile::TempDir, TextChannel) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path().join("db"))
+            .open()
+            .unwrap();
+        let channel = TextChannel::new(
+            ChannelId(1),
+            &dir.path().join("channel"),
+            db,
+            "general".to_string(),
+            TextChannelSettings::default(),
+            &test_options(),
+        )
+        .unwrap();
+
+        (dir, channel)
+    }
+
+    /// Waits for the worker to commit the messages indexed so far, and for
+    /// the index reader to pick up the commit, making them searchable.
+    pub(crate) async fn wait_for_commit() {
+        tokio::time::sleep(worker::COMMIT_MAX_LATENCY + Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn a_larger_event_capacity_keeps_slow_subscribers_from_lagging() {
+        // Counts the new messages a subscriber that only reads after
+        // ten messages were sent receives, or `None` if it lagged.
+        async fn received_by_slow_subscriber(event_capacity: usize) -> Option<usize> {
+            let dir = tempfile::tempdir().unwrap();
+            let db = fjall::Database::builder(dir.path().join("db"))
+                .open()
+                .unwrap();
+            let options = TextChannelOptions {
+                event_capacity,
+                ..test_options()
+            };
+            let channel = TextChannel::new(
+                ChannelId(1),
+                &dir.path().join("channel"),
+                db,
+                "general".to_string(),
+                TextChannelSettings::default(),
+                &options,
+            )
+            .unwrap();
+            let mut events = channel.subscribe();
+
+            for i in 0..10 {
+                channel
+                    .create_message(
+                        test_message(UserId(1), &format!("message {i}")),
+                        None,
+                        Permissions::NONE,
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let mut received = 0;
+            loop {
+                match events.try_recv() {
+                    Ok(TextChannelEvent::NewMessage(_)) => received += 1,
+                    Ok(_) => {}
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => return None,
+                    Err(_) => return Some(received),
+                }
+            }
+        }
+
+        assert_eq!(received_by_slow_subscriber(2).await, None);
+        assert_eq!(received_by_slow_subscriber(64).await, Some(10));
+    }
+
+    #[tokio::test]
+    async fn messages_are_searchable_after_shutdown_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path().join("db"))
+            .open()
+            .unwrap();
+        let data_dir = dir.path().join("channel");
+        let open = || {
+            TextChannel::new(
+                ChannelId(1),
+                &data_dir,
+                db.clone(),
+                "general".to_string(),
+                TextChannelSettings::default(),
+                &test_options(),
+            )
+            .unwrap()
+        };
+
+        let contents = ["first bonfire message", "second bonfire message", "third"];
+
+        let channel = open();
+        for content in contents {
+            channel
+                .create_message(test_message(UserId(1), content), None, Permissions::NONE)
+                .await
+                .unwrap();
+        }
+
+        // The worker releases the index writer once it's shut down.
+        channel.shutdown().await;
+        drop(channel);
+
+        let channel = open();
+        assert_eq!(channel.message_count(), contents.len() as u64);
+
+        let page = channel.search(test_query("bonfire")).unwrap();
+        assert_eq!(page.hits.len(), 2);
+
+        let page = channel.search(test_query("third")).unwrap();
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].content, "third");
+    }
 }
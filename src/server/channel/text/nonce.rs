@@ -0,0 +1,102 @@
+//! Idempotency nonces for deduplicating retried messages.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tokio::sync::OnceCell;
+
+use crate::{server::channel::text::TextChannelMessage, user::UserId};
+
+/// How long a nonce is remembered after it was first used.
+///
+/// Long enough to cover a client retrying over a flaky connection,
+/// but short enough that the table of nonces stays small.
+pub const NONCE_TTL: Duration = Duration::from_secs(120);
+
+/// The maximum length of a nonce in bytes.
+pub const MAX_NONCE_BYTES: usize = 64;
+
+/// The message created for a nonce, set once the message is stored.
+pub type NonceSlot = Arc<OnceCell<TextChannelMessage>>;
+
+/// Identifies a nonce used by a user.
+type NonceKey = (UserId, String);
+
+/// Remembers the messages recently created with each nonce.
+///
+/// Nonces are tracked per user and channel, so different
+/// users can't collide with or observe each other's nonces.
+#[derive(Default)]
+pub struct NonceCache {
+    entries: Mutex<NonceEntries>,
+}
+
+/// The nonces used recently, along with the order they expire in.
+#[derive(Default)]
+struct NonceEntries {
+    /// The message created with each nonce.
+    slots: HashMap<NonceKey, NonceSlot>,
+    /// When each nonce expires, in the order they were first used.
+    expiry: VecDeque<(Instant, NonceKey)>,
+}
+
+impl NonceCache {
+    /// Returns the slot for the message created with the user's nonce.
+    ///
+    /// The slot is empty if the nonce wasn't used recently, or if the
+    /// message created with it hasn't been stored yet. Sends that share
+    /// a slot wait for the first to fill it, so only one message is created.
+    pub fn slot(&self, user: UserId, nonce: &str) -> NonceSlot {
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+
+        // Forget expired nonces so the table stays bounded. Only the
+        // oldest nonces are visited, so sends don't scan the whole table.
+        while let Some((expires, _)) = entries.expiry.front() {
+            if *expires > now {
+                break;
+            }
+
+            let (_, key) = entries.expiry.pop_front().unwrap();
+            entries.slots.remove(&key);
+        }
+
+        let key = (user, nonce.to_string());
+        if let Some(slot) = entries.slots.get(&key) {
+            return Arc::clone(slot);
+        }
+
+        let slot = NonceSlot::default();
+        entries.expiry.push_back((now + NONCE_TTL, key.clone()));
+        entries.slots.insert(key, Arc::clone(&slot));
+
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_nonces_are_forgotten() {
+        let cache = NonceCache::default();
+
+        let first = cache.slot(UserId(1), "retry");
+        assert!(Arc::ptr_eq(&first, &cache.slot(UserId(1), "retry")));
+        assert!(!Arc::ptr_eq(&first, &cache.slot(UserId(2), "retry")));
+
+        // Age the first nonce past the TTL.
+        cache.entries.lock().expiry[0].0 = Instant::now();
+
+        assert!(!Arc::ptr_eq(&first, &cache.slot(UserId(1), "retry")));
+
+        let entries = cache.entries.lock();
+        assert_eq!(entries.slots.len(), 2);
+        assert_eq!(entries.expiry.len(), 2);
+    }
+}
@@ -0,0 +1,110 @@
+//! Pinned messages in a text channel.
+
+use crate::{
+    message::{MessageFlags, MessageId},
+    server::channel::text::{TextChannel, TextChannelEvent, TextChannelMessage},
+};
+
+/// Indicates there was an error pinning or unpinning a message.
+#[derive(Debug)]
+pub enum PinError {
+    /// Indicates the message doesn't exist in the channel.
+    MessageNotFound,
+    /// Indicates the channel already has the maximum number of pins.
+    TooManyPins(usize),
+    /// Indicates there was an error accessing the keyspace.
+    DatabaseError(fjall::Error),
+}
+
+impl TextChannel {
+    /// Pins a message in the channel.
+    ///
+    /// Pinning an already pinned message is a no-op.
+    pub fn pin(&self, id: MessageId) -> Result<(), PinError> {
+        if self
+            .store
+            .get(id)
+            .map_err(PinError::DatabaseError)?
+            .is_none()
+        {
+            return Err(PinError::MessageNotFound);
+        }
+
+        if self.store.is_pinned(id).map_err(PinError::DatabaseError)? {
+            return Ok(());
+        }
+
+        let count = self.store.pin_count().map_err(PinError::DatabaseError)?;
+        if count >= self.max_pins {
+            return Err(PinError::TooManyPins(self.max_pins));
+        }
+
+        self.store.pin(id).map_err(PinError::DatabaseError)?;
+        self.set_pinned_flag(id, true)?;
+
+        self.notify_pins_updated();
+
+        Ok(())
+    }
+
+    /// Unpins a message in the channel.
+    pub fn unpin(&self, id: MessageId) -> Result<(), PinError> {
+        if !self.store.is_pinned(id).map_err(PinError::DatabaseError)? {
+            return Err(PinError::MessageNotFound);
+        }
+
+        self.store.unpin(id).map_err(PinError::DatabaseError)?;
+        self.set_pinned_flag(id, false)?;
+
+        self.notify_pins_updated();
+
+        Ok(())
+    }
+
+    /// Returns the IDs of the pinned messages, oldest first.
+    pub fn pinned_ids(&self) -> Result<Vec<MessageId>, fjall::Error> {
+        self.store.pinned_ids()
+    }
+
+    /// Returns the pinned messages, oldest first.
+    ///
+    /// Pins referencing messages that have since been removed are skipped.
+    pub fn pinned_messages(&self) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        let mut messages = Vec::new();
+        for id in self.pinned_ids()? {
+            if let Some(msg) = self.store.get(id)? {
+                messages.push(msg);
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Sets or clears the pinned flag of the stored message.
+    ///
+    /// The pins keyspace lists the pinned messages, the flag
+    /// tells clients a message is pinned when it's fetched.
+    fn set_pinned_flag(&self, id: MessageId, pinned: bool) -> Result<(), PinError> {
+        self.store
+            .update(id, &mut |msg| msg.flags.set(MessageFlags::PINNED, pinned))
+            .map_err(PinError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Informs subscribers that the channel's pins changed.
+    fn notify_pins_updated(&self) {
+        let pinned = match self.pinned_ids() {
+            Ok(pinned) => pinned,
+            Err(err) => {
+                tracing::error!(%err, "failed to read pins for pin update event");
+                return;
+            }
+        };
+
+        // No subscribers is not an error.
+        let _ = self
+            .event_sender
+            .send(TextChannelEvent::PinsUpdated { pinned });
+    }
+}
@@ -0,0 +1,250 @@
+//! Bulk deletion of a text channel's messages, for moderation.
+
+use tantivy::TantivyError;
+use tokio::sync::oneshot;
+
+use crate::{
+    message::MessageId,
+    server::channel::{
+        Channel,
+        text::{
+            TextChannel, TextChannelAction, TextChannelMessage, search::SearchIndex,
+            store::MessageStore,
+        },
+    },
+    user::UserId,
+};
+
+/// The number of stored messages scanned at a time when purging a channel.
+pub const PURGE_SCAN_BATCH_SIZE: usize = 1000;
+
+/// Indicates there was an error deleting a channel's messages.
+#[derive(Debug)]
+pub enum PurgeError {
+    /// Indicates the start of the time range isn't before it's end.
+    InvalidRange,
+    /// Indicates there was an error accessing the keyspace.
+    DatabaseError(fjall::Error),
+    /// Indicates there was an error deleting from the search index.
+    SearchError(TantivyError),
+    /// Indicates the channel worker isn't running.
+    ChannelClosed,
+}
+
+impl TextChannel {
+    /// Deletes every message the user posted in the channel.
+    ///
+    /// The messages are removed from the time-series database and the
+    /// search index with a single commit by the channel's worker, and
+    /// subscribers are sent one event listing the deleted messages.
+    ///
+    /// Returns the number of messages deleted.
+    pub async fn delete_by_author(&self, author: UserId) -> Result<usize, PurgeError> {
+        let (reply, response) = oneshot::channel();
+
+        self.send_action(TextChannelAction::DeleteByAuthor { author, reply })
+            .await
+            .map_err(|_| PurgeError::ChannelClosed)?;
+
+        response.await.map_err(|_| PurgeError::ChannelClosed)?
+    }
+
+    /// Deletes every message sent in the time range, from `start_ms`
+    /// up to but not including `end_ms`, in milliseconds.
+    ///
+    /// Like [`TextChannel::delete_by_author`] the messages are deleted with
+    /// a single commit, but subscribers are sent one event describing the
+    /// range rather than listing every deleted message.
+    ///
+    /// Returns the number of messages deleted.
+    pub async fn delete_in_range(&self, start_ms: u64, end_ms: u64) -> Result<usize, PurgeError> {
+        if start_ms >= end_ms {
+            return Err(PurgeError::InvalidRange);
+        }
+
+        let (reply, response) = oneshot::channel();
+
+        self.send_action(TextChannelAction::DeleteInRange {
+            start_ms,
+            end_ms,
+            reply,
+        })
+        .await
+        .map_err(|_| PurgeError::ChannelClosed)?;
+
+        response.await.map_err(|_| PurgeError::ChannelClosed)?
+    }
+}
+
+/// The messages removed from a channel by a purge.
+pub(super) struct Purged {
+    /// IDs of the deleted messages, oldest first.
+    pub(super) ids: Vec<MessageId>,
+    /// True if any of the deleted messages were pinned.
+    pub(super) unpinned: bool,
+}
+
+/// Removes the author's messages from the store and the search index.
+///
+/// The deletion isn't visible to searches until the index is committed.
+pub(super) fn purge_author(
+    store: &dyn MessageStore,
+    index: &dyn SearchIndex,
+    author: UserId,
+) -> Result<Purged, PurgeError> {
+    let purged = remove_matching(store, |msg| msg.author == author)?;
+
+    index
+        .delete_by_author(author)
+        .map_err(PurgeError::SearchError)?;
+
+    Ok(purged)
+}
+
+/// Removes the messages sent in the time range from the store and the search index.
+///
+/// The deletion isn't visible to searches until the index is committed.
+pub(super) fn purge_range(
+    store: &dyn MessageStore,
+    index: &dyn SearchIndex,
+    start_ms: u64,
+    end_ms: u64,
+) -> Result<Purged, PurgeError> {
+    let purged = remove_matching(store, |msg| (start_ms..end_ms).contains(&msg.timestamp_ms))?;
+
+    index
+        .delete_in_range(start_ms, end_ms)
+        .map_err(PurgeError::SearchError)?;
+
+    Ok(purged)
+}
+
+/// Removes the stored messages that match, along with their pins.
+fn remove_matching(
+    store: &dyn MessageStore,
+    matches: impl Fn(&TextChannelMessage) -> bool,
+) -> Result<Purged, PurgeError> {
+    let mut purged = Purged {
+        ids: Vec::new(),
+        unpinned: false,
+    };
+    let mut after = MessageId(0);
+
+    loop {
+        let page = store
+            .messages_after(after, PURGE_SCAN_BATCH_SIZE)
+            .map_err(PurgeError::DatabaseError)?;
+
+        for msg in page.iter().filter(|msg| matches(msg)) {
+            if store.is_pinned(msg.id).map_err(PurgeError::DatabaseError)? {
+                store.unpin(msg.id).map_err(PurgeError::DatabaseError)?;
+                purged.unpinned = true;
+            }
+
+            store.remove(msg.id).map_err(PurgeError::DatabaseError)?;
+            purged.ids.push(msg.id);
+        }
+
+        match page.last() {
+            Some(last) if page.len() == PURGE_SCAN_BATCH_SIZE => after = last.id,
+            _ => break,
+        }
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::server::{
+        channel::text::{
+            TextChannelEvent,
+            tests::{test_channel, test_message, test_query, wait_for_commit},
+        },
+        permission::Permissions,
+    };
+
+    #[tokio::test]
+    async fn purging_an_author_only_deletes_their_messages() {
+        let (_dir, channel) = test_channel();
+
+        let mut sent = Vec::new();
+        for (author, content) in [
+            (UserId(1), "buy cheap logs"),
+            (UserId(2), "anyone bringing logs"),
+            (UserId(1), "cheap logs again"),
+        ] {
+            let msg = channel
+                .create_message(test_message(author, content), None, Permissions::NONE)
+                .await
+                .unwrap();
+            sent.push(msg);
+        }
+        wait_for_commit().await;
+
+        let mut events = channel.subscribe();
+        assert_eq!(channel.delete_by_author(UserId(1)).await.unwrap(), 2);
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            TextChannelEvent::MessagesDeleted { ids } if ids == [sent[0].id, sent[2].id]
+        ));
+        assert_eq!(channel.message_count(), 1);
+        assert!(channel.message(sent[0].id).unwrap().is_none());
+        assert!(channel.message(sent[1].id).unwrap().is_some());
+        assert!(channel.message(sent[2].id).unwrap().is_none());
+
+        let hits = channel.search(test_query("logs")).unwrap().hits;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].author, UserId(2));
+
+        // Nothing is left to delete, so no event is sent.
+        assert_eq!(channel.delete_by_author(UserId(1)).await.unwrap(), 0);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn purging_a_range_keeps_the_messages_outside_it() {
+        let (_dir, channel) = test_channel();
+
+        // Each message is sent in a later millisecond than the last.
+        let mut sent = Vec::new();
+        for content in ["early embers", "middle embers", "late embers"] {
+            let msg = channel
+                .create_message(test_message(UserId(1), content), None, Permissions::NONE)
+                .await
+                .unwrap();
+            sent.push(msg);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        wait_for_commit().await;
+
+        assert!(matches!(
+            channel.delete_in_range(10, 10).await,
+            Err(PurgeError::InvalidRange)
+        ));
+
+        // The end of the range is excluded, so only the middle message is deleted.
+        let start_ms = sent[1].timestamp_ms;
+        let end_ms = sent[2].timestamp_ms;
+        let mut events = channel.subscribe();
+        assert_eq!(channel.delete_in_range(start_ms, end_ms).await.unwrap(), 1);
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            TextChannelEvent::MessagesDeletedInRange { start_ms: start, end_ms: end, count: 1 }
+                if start == start_ms && end == end_ms
+        ));
+        assert!(channel.message(sent[0].id).unwrap().is_some());
+        assert!(channel.message(sent[1].id).unwrap().is_none());
+        assert!(channel.message(sent[2].id).unwrap().is_some());
+
+        let hits = channel.search(test_query("embers")).unwrap().hits;
+        let mut contents: Vec<_> = hits.iter().map(|hit| hit.content.as_str()).collect();
+        contents.sort();
+        assert_eq!(contents, ["early embers", "late embers"]);
+    }
+}
@@ -0,0 +1,72 @@
+//! Per-user rate limiting of the messages posted to a text channel.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::user::UserId;
+
+/// Tracks the number of messages posted in the current rate limit window.
+struct RateWindow {
+    started: Instant,
+    count: u32,
+}
+
+/// Limits how many messages each user can post within an interval.
+///
+/// Users are limited across all of their sessions, so opening more
+/// connections doesn't allow a user to post more messages.
+pub struct RateLimiter {
+    /// Maximum number of messages a user can post per interval.
+    limit: u32,
+    /// Duration of the rate limiting window.
+    interval: Duration,
+
+    /// Rate limiting windows for users that recently posted.
+    windows: Mutex<HashMap<UserId, RateWindow>>,
+}
+
+impl RateLimiter {
+    /// Constructs a rate limiter allowing `limit` messages per `interval`.
+    pub fn new(limit: u32, interval: Duration) -> Self {
+        Self {
+            limit,
+            interval,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a message from the user if it's within the limit.
+    ///
+    /// Returns how long the user must wait if they're over the limit.
+    pub fn check(&self, user: UserId) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock();
+
+        // Drop windows that have expired so the table stays bounded.
+        windows.retain(|_, window| now.duration_since(window.started) < self.interval);
+
+        let window = windows.entry(user).or_insert(RateWindow {
+            started: now,
+            count: 0,
+        });
+
+        if window.count >= self.limit {
+            return Err(self.interval - now.duration_since(window.started));
+        }
+
+        window.count += 1;
+
+        Ok(())
+    }
+
+    /// Gives back a message recorded by [`Self::check`] that wasn't posted after all.
+    pub fn refund(&self, user: UserId) {
+        if let Some(window) = self.windows.lock().get_mut(&user) {
+            window.count = window.count.saturating_sub(1);
+        }
+    }
+}
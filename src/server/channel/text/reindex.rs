@@ -0,0 +1,223 @@
+//! Rebuilding a text channel's search index from its stored messages.
+
+use tantivy::TantivyError;
+use tokio::sync::oneshot;
+
+use crate::{
+    message::MessageId,
+    server::channel::{
+        Channel,
+        text::{TextChannel, TextChannelAction, search::SearchIndex, store::MessageStore},
+    },
+};
+
+/// The number of stored messages re-added to the index at a time.
+///
+/// The worker handles queued actions between batches, so new messages
+/// aren't held up for the whole rebuild.
+pub const REINDEX_BATCH_SIZE: usize = 1000;
+
+/// Indicates there was an error rebuilding a channel's search index.
+#[derive(Debug)]
+pub enum ReindexError {
+    /// Indicates the channel's search index is already being rebuilt.
+    InProgress,
+    /// Indicates there was an error reading messages from the keyspace.
+    DatabaseError(fjall::Error),
+    /// Indicates there was an error writing to the search index.
+    SearchError(TantivyError),
+    /// Indicates the channel worker isn't running.
+    ChannelClosed,
+}
+
+impl TextChannel {
+    /// Rebuilds the channel's search index from the stored messages.
+    ///
+    /// Every document is deleted from the index, and each stored message
+    /// is re-added in batches by the channel's worker, which keeps handling
+    /// new messages between batches. Searches see the old index until the
+    /// rebuild is committed once every message has been re-added.
+    ///
+    /// Useful after the index was lost or corrupted, or the search
+    /// tokenizer was changed.
+    ///
+    /// Returns the number of messages re-added to the index.
+    pub async fn reindex(&self) -> Result<usize, ReindexError> {
+        let (reply, response) = oneshot::channel();
+
+        self.send_action(TextChannelAction::Reindex { reply })
+            .await
+            .map_err(|_| ReindexError::ChannelClosed)?;
+
+        response.await.map_err(|_| ReindexError::ChannelClosed)?
+    }
+}
+
+/// A rebuild of the search index in progress in the channel's worker.
+pub(super) struct Reindex {
+    /// The last message re-added to the index.
+    after: MessageId,
+    /// The last message stored when the rebuild started.
+    ///
+    /// Messages created after this are added to the index
+    /// by the worker as usual, so they're skipped.
+    through: MessageId,
+    /// The number of messages re-added to the index.
+    count: usize,
+    reply: oneshot::Sender<Result<usize, ReindexError>>,
+}
+
+impl Reindex {
+    /// Clears the index to start rebuilding it.
+    ///
+    /// If the rebuild can't be started the error is sent to the reply.
+    pub(super) fn start(
+        store: &dyn MessageStore,
+        index: &dyn SearchIndex,
+        reply: oneshot::Sender<Result<usize, ReindexError>>,
+    ) -> Option<Self> {
+        let result = store
+            .messages_before(MessageId(u64::MAX), 1)
+            .map_err(ReindexError::DatabaseError)
+            .and_then(|last| {
+                index.clear().map_err(ReindexError::SearchError)?;
+                Ok(last)
+            });
+
+        match result {
+            Ok(last) => Some(Self {
+                after: MessageId(0),
+                through: last.first().map_or(MessageId(0), |msg| msg.id),
+                count: 0,
+                reply,
+            }),
+            Err(err) => {
+                tracing::error!(?err, "failed to start reindexing channel");
+
+                // The caller may have given up waiting, which is fine.
+                let _ = reply.send(Err(err));
+                None
+            }
+        }
+    }
+
+    /// Returns true if the message is yet to be re-added to the index.
+    pub(super) fn pending(&self, id: MessageId) -> bool {
+        id.0 > self.after.0 && id.0 <= self.through.0
+    }
+
+    /// Re-adds the next batch of stored messages to the index.
+    ///
+    /// Returns true once every message has been re-added.
+    pub(super) fn step(
+        &mut self,
+        store: &dyn MessageStore,
+        index: &dyn SearchIndex,
+    ) -> Result<bool, ReindexError> {
+        if self.after.0 >= self.through.0 {
+            return Ok(true);
+        }
+
+        let page = store
+            .messages_after(self.after, REINDEX_BATCH_SIZE)
+            .map_err(ReindexError::DatabaseError)?;
+
+        for msg in page.iter().take_while(|msg| msg.id.0 <= self.through.0) {
+            index.add(msg).map_err(ReindexError::SearchError)?;
+
+            self.after = msg.id;
+            self.count += 1;
+        }
+
+        // Done once the page reaches the messages created during the rebuild,
+        // or there are no more stored messages.
+        Ok(page.len() < REINDEX_BATCH_SIZE
+            || page.last().is_some_and(|msg| msg.id.0 >= self.through.0))
+    }
+
+    /// Commits the rebuilt index and sends the result to the reply.
+    ///
+    /// If the rebuild failed the index is left uncommitted, but the next
+    /// commit will include the partially rebuilt index, so the channel
+    /// should be reindexed again.
+    pub(super) fn finish(self, index: &dyn SearchIndex, result: Result<(), ReindexError>) {
+        // The commit includes the messages created during the rebuild.
+        let result = result
+            .and_then(|()| index.commit().map_err(ReindexError::SearchError))
+            .map(|()| self.count);
+
+        if let Err(err) = &result {
+            tracing::error!(?err, "failed to reindex channel");
+        }
+
+        // The caller may have given up waiting, which is fine.
+        let _ = self.reply.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel::ChannelId,
+        server::{
+            channel::text::{
+                TextChannelSettings,
+                tests::{test_message, test_options, test_query, wait_for_commit},
+            },
+            permission::Permissions,
+        },
+        user::UserId,
+    };
+
+    #[tokio::test]
+    async fn a_deleted_index_is_rebuilt_from_the_stored_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path().join("db"))
+            .open()
+            .unwrap();
+        let data_dir = dir.path().join("channel");
+        let open = || {
+            TextChannel::new(
+                ChannelId(1),
+                &data_dir,
+                db.clone(),
+                "general".to_string(),
+                TextChannelSettings::default(),
+                &test_options(),
+            )
+            .unwrap()
+        };
+
+        let contents = ["first bonfire message", "second bonfire message", "third"];
+
+        let channel = open();
+        for content in contents {
+            channel
+                .create_message(test_message(UserId(1), content), None, Permissions::NONE)
+                .await
+                .unwrap();
+        }
+        channel.shutdown().await;
+        drop(channel);
+
+        std::fs::remove_dir_all(data_dir.join("search")).unwrap();
+
+        // The messages are still stored, but the new index is empty.
+        let channel = open();
+        assert_eq!(channel.message_count(), contents.len() as u64);
+        assert!(
+            channel
+                .search(test_query("bonfire"))
+                .unwrap()
+                .hits
+                .is_empty()
+        );
+
+        assert_eq!(channel.reindex().await.unwrap(), contents.len());
+        wait_for_commit().await;
+
+        assert_eq!(channel.search(test_query("bonfire")).unwrap().hits.len(), 2);
+        assert_eq!(channel.search(test_query("third")).unwrap().hits.len(), 1);
+    }
+}
@@ -0,0 +1,51 @@
+//! References from messages to the messages they reply to.
+
+use crate::{
+    message::MessageId,
+    server::channel::text::{TextChannel, TextChannelMessage},
+};
+
+/// Indicates a message's reply reference couldn't be accepted.
+#[derive(Debug)]
+pub enum ReplyError {
+    /// Indicates the referenced message doesn't exist in the channel.
+    ReferenceNotFound(MessageId),
+    /// Indicates there was an error reading the keyspace.
+    DatabaseError(fjall::Error),
+}
+
+impl TextChannel {
+    /// Checks that a message's reply reference can be accepted.
+    ///
+    /// The referenced message must exist in this channel, unless the
+    /// server is configured to allow dangling reply references.
+    pub fn validate_reply(&self, msg: &TextChannelMessage) -> Result<(), ReplyError> {
+        let Some(reply_to) = msg.reply_to else {
+            return Ok(());
+        };
+
+        if self.allow_dangling_replies {
+            return Ok(());
+        }
+
+        match self.store.get(reply_to) {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(ReplyError::ReferenceNotFound(reply_to)),
+            Err(err) => Err(ReplyError::DatabaseError(err)),
+        }
+    }
+
+    /// Resolves the message that a message replies to.
+    ///
+    /// Returns `None` if the message isn't a reply, or if the
+    /// referenced message doesn't exist or has been pruned.
+    pub fn referenced_message(
+        &self,
+        msg: &TextChannelMessage,
+    ) -> Result<Option<TextChannelMessage>, fjall::Error> {
+        match msg.reply_to {
+            Some(reply_to) => self.store.get(reply_to),
+            None => Ok(None),
+        }
+    }
+}
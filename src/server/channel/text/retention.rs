@@ -0,0 +1,236 @@
+//! Retention policies for pruning old messages from text channels.
+//!
+//! Each text channel runs a retention task alongside it's message
+//! worker that periodically removes messages falling outside of the
+//! channel's retention policy from the time-series keyspace, and
+//! then asks the message worker to drop them from the search index.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use parking_lot::RwLock;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use snowflaked::Snowflake;
+
+use crate::{
+    channel::ChannelId,
+    message::MessageId,
+    server::{
+        channel::text::{
+            TextChannelAction, TextChannelSender, TextChannelSettings, store::MessageStore,
+        },
+        metrics::metrics,
+    },
+};
+
+/// How often the retention task checks for messages to prune.
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The maximum number of messages removed from the keyspace in one pass.
+///
+/// Pruning is done in batches so the keyspace isn't monopolized
+/// while a large backlog of expired messages is removed.
+pub const PRUNE_BATCH_SIZE: usize = 1000;
+
+/// Limits how long messages are retained in a text channel.
+///
+/// Channels without any limits set retain messages forever.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RetentionPolicy {
+    /// Messages older than this many seconds are pruned.
+    pub max_age_secs: Option<u64>,
+    /// Only this many of the most recent messages are retained.
+    pub max_messages: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Returns true if the policy retains messages forever.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age_secs.is_none() && self.max_messages.is_none()
+    }
+}
+
+/// Retention task that runs for each text channel to prune old messages.
+#[tracing::instrument(skip(store, settings, action_sender))]
+pub async fn retention_worker(
+    channel_id: ChannelId,
+    store: Arc<dyn MessageStore>,
+    snowflake_epoch_ms: u64,
+    settings: Arc<RwLock<TextChannelSettings>>,
+    action_sender: TextChannelSender,
+) {
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let policy = settings.read().retention;
+        if policy.is_unlimited() {
+            continue;
+        }
+
+        // Keyspace scans are blocking IO, so run them off the async workers.
+        let store = Arc::clone(&store);
+        let pruned = match tokio::task::spawn_blocking(move || {
+            prune(store.as_ref(), policy, snowflake_epoch_ms)
+        })
+        .await
+        {
+            Ok(Ok(pruned)) => pruned,
+            Ok(Err(err)) => {
+                tracing::error!(%err, "failed to prune messages from keyspace");
+                continue;
+            }
+            Err(err) => {
+                tracing::error!(%err, "unexpected panic pruning messages");
+                continue;
+            }
+        };
+
+        let Some((count, before)) = pruned else {
+            continue;
+        };
+
+        tracing::info!(
+            count,
+            before = before.0,
+            "pruned messages outside of retention policy"
+        );
+
+        metrics()
+            .messages_deleted
+            .with_label_values(&[channel_id.to_string().as_str()])
+            .inc_by(count as u64);
+
+        // Ask the message worker to drop the pruned messages from
+        // the search index, as it owns the only index writer.
+        if action_sender
+            .send(TextChannelAction::PruneIndex { before })
+            .await
+            .is_err()
+        {
+            tracing::info!("channel message receiver was closed, stopping retention worker");
+            break;
+        }
+    }
+}
+
+/// Removes the messages outside of the retention policy from the store.
+///
+/// Returns the number of messages removed and the exclusive upper
+/// ID bound of the removed messages, if any were removed.
+///
+/// Pruning is resumable; messages are always removed oldest first so
+/// an interrupted pass is simply continued by the next one.
+fn prune(
+    store: &dyn MessageStore,
+    policy: RetentionPolicy,
+    snowflake_epoch_ms: u64,
+) -> Result<Option<(usize, MessageId)>, fjall::Error> {
+    // Messages are keyed by their ID, which starts with the
+    // time they were sent, so the cutoff is expressed as an ID.
+    let mut cutoff_id: u64 = 0;
+
+    // Messages older than the maximum age are outside of the policy.
+    if let Some(max_age_secs) = policy.max_age_secs {
+        let now_ms = Utc::now().timestamp_millis() as u64;
+        let cutoff_ms = now_ms.saturating_sub(max_age_secs.saturating_mul(1000));
+        cutoff_id = MessageId::from_parts(cutoff_ms.saturating_sub(snowflake_epoch_ms), 0, 0).0;
+    }
+
+    // Any messages beyond the most recent `max_messages` are outside of the policy.
+    if let Some(max_messages) = policy.max_messages {
+        let count = store.message_count();
+        if count > max_messages {
+            let excess = (count - max_messages) as usize;
+            if let Some(id) = store.nth_id(excess)? {
+                cutoff_id = cutoff_id.max(id.0);
+            }
+        }
+    }
+
+    if cutoff_id == 0 {
+        return Ok(None);
+    }
+
+    let mut removed = 0;
+
+    loop {
+        let count = store.remove_before(MessageId(cutoff_id), PRUNE_BATCH_SIZE)?;
+        if count == 0 {
+            break;
+        }
+
+        removed += count;
+    }
+
+    if removed == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((removed, MessageId(cutoff_id))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server::channel::text::{memory::InMemoryMessageStore, tests::test_message},
+        user::UserId,
+    };
+
+    /// Stores a message sent the number of seconds ago.
+    fn insert_sent_ago(store: &InMemoryMessageStore, secs_ago: u64, content: &str) -> MessageId {
+        let sent_ms = Utc::now().timestamp_millis() as u64 - secs_ago * 1000;
+        let mut msg = test_message(UserId(1), content);
+        msg.id = MessageId::from_parts(sent_ms, 0, 0);
+        msg.timestamp_ms = sent_ms;
+        store.insert(&msg).unwrap();
+
+        msg.id
+    }
+
+    #[test]
+    fn messages_past_the_max_age_are_pruned() {
+        let store = InMemoryMessageStore::new();
+        let old = insert_sent_ago(&store, 2 * 60 * 60, "old");
+        let recent = insert_sent_ago(&store, 10, "recent");
+
+        let policy = RetentionPolicy {
+            max_age_secs: Some(60 * 60),
+            max_messages: None,
+        };
+        let (count, before) = prune(&store, policy, 0).unwrap().unwrap();
+
+        assert_eq!(count, 1);
+        assert!(before > old && before <= recent);
+        assert!(store.get(old).unwrap().is_none());
+        assert!(store.get(recent).unwrap().is_some());
+
+        // Nothing else is outside of the policy.
+        assert!(prune(&store, policy, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn messages_beyond_the_max_count_are_pruned_oldest_first() {
+        let store = InMemoryMessageStore::new();
+        let ids = [
+            insert_sent_ago(&store, 30, "first"),
+            insert_sent_ago(&store, 20, "second"),
+            insert_sent_ago(&store, 10, "third"),
+        ];
+
+        let policy = RetentionPolicy {
+            max_age_secs: None,
+            max_messages: Some(2),
+        };
+        let (count, _) = prune(&store, policy, 0).unwrap().unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(store.message_count(), 2);
+        assert!(store.get(ids[0]).unwrap().is_none());
+        assert!(store.get(ids[1]).unwrap().is_some());
+        assert!(store.get(ids[2]).unwrap().is_some());
+    }
+}
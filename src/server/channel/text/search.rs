@@ -1,17 +1,148 @@
 //! Full-text search functionality of text channel messages.
+//!
+//! Channels index and search their messages through the [`SearchIndex`]
+//! trait, with [`TantivySearchIndex`] as the on-disk implementation.
 
-use tantivy::schema::Schema;
+use std::{fmt::Display, ops::Bound, path::Path, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use parking_lot::Mutex;
+use tantivy::{
+    DateTime, DocAddress, DocId, IndexReader, IndexWriter, ReloadPolicy, Score, Searcher,
+    SegmentReader, TantivyDocument, TantivyError, Term,
+    collector::TopDocs,
+    columnar::Column,
+    query::{
+        BooleanQuery, ConstScoreQuery, EmptyQuery, FuzzyTermQuery, Occur, Query, QueryParser,
+        QueryParserError, RangeQuery, TermQuery,
+    },
+    query_grammar::{self, UserInputAst, UserInputLeaf},
+    schema::{Field, IndexRecordOption, Schema, Value},
+    snippet::SnippetGenerator,
+    tokenizer::{
+        Language, LowerCaser, NgramTokenizer, RemoveLongFilter, SimpleTokenizer, Stemmer,
+        TextAnalyzer, TokenStream,
+    },
+};
+
+use crate::{
+    message::MessageId,
+    server::channel::text::{TextChannelError, TextChannelMessage},
+    user::UserId,
+};
+
+/// The default number of results returned by a search.
+pub const DEFAULT_SEARCH_LIMIT: usize = 25;
+
+/// The maximum number of results returned by a search.
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+/// The maximum number of results that can be paged through in a search.
+///
+/// Every page runs the whole query again, so deep paging is refused
+/// rather than letting a client make the index do unbounded work.
+pub const MAX_SEARCH_OFFSET: usize = 1000;
+
+/// The default Levenshtein distance used by fuzzy searches.
+pub const DEFAULT_FUZZY_DISTANCE: u8 = 1;
+
+/// The maximum Levenshtein distance used by fuzzy searches.
+///
+/// Fuzzy matching gets dramatically more expensive as the distance
+/// grows, so larger distances are clamped to this.
+pub const MAX_FUZZY_DISTANCE: u8 = 2;
+
+/// The maximum number of terms matched fuzzily or by prefix in a search.
+///
+/// Any further terms in the query are ignored.
+pub const MAX_FUZZY_TERMS: usize = 8;
+
+/// How much a message sent just now has it's relevance boosted by in hybrid searches.
+///
+/// The score of a brand new message is multiplied by one plus the boost.
+pub const RECENCY_BOOST: f32 = 1.0;
+
+/// How long it takes for the recency boost of a message to halve.
+pub const RECENCY_HALF_LIFE_MS: u64 = 7 * 24 * 60 * 60 * 1000; // 1 week
+
+/// The default maximum length of highlighted snippets in characters.
+pub const DEFAULT_SNIPPET_CHARS: usize = 150;
+
+/// The maximum length of highlighted snippets in characters.
+pub const MAX_SNIPPET_CHARS: usize = 1000;
 
 // keys used for the full-text schema fields.
 pub const SCHEMA_KEY_TIMESTAMP: &str = "timestamp";
 pub const SCHEMA_KEY_CONTENT: &str = "content";
 pub const SCHEMA_KEY_AUTHOR: &str = "author";
+pub const SCHEMA_KEY_REPLY_TO: &str = "reply_to";
+pub const SCHEMA_KEY_EDITED_AT: &str = "edited_at";
+pub const SCHEMA_KEY_MESSAGE_ID: &str = "message_id";
+
+/// The fields that advanced queries can scope terms to.
+///
+/// Exact queries can only match the message content.
+pub const ADVANCED_QUERY_FIELDS: [&str; 3] =
+    [SCHEMA_KEY_CONTENT, SCHEMA_KEY_AUTHOR, SCHEMA_KEY_TIMESTAMP];
+
+/// Tokens longer than this many bytes are dropped from the index.
+const MAX_TOKEN_BYTES: usize = 40;
+
+/// Name of the tokenizer used by the content field.
+///
+/// The schema refers to the tokenizer by name, so the configured
+/// tokenizer is registered under this name when the index is opened.
+const CONTENT_TOKENIZER: &str = "default";
+
+/// The tokenizer used to split message content into indexed terms.
+///
+/// The same tokenizer is applied to indexed messages and to queries, so
+/// changing it for an existing channel requires reindexing the channel;
+/// messages indexed with the old tokenizer won't match reliably.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchTokenizer {
+    /// Splits on non-alphanumeric characters and lowercases the terms.
+    #[default]
+    Simple,
+    /// Like [`SearchTokenizer::Simple`], also reducing the terms to their
+    /// stems so different forms of a word match each other.
+    Stemmed { language: Language },
+    /// Splits the content into lowercased n-grams, so queries match
+    /// substrings of words at the cost of a much larger index.
+    Ngram { min_gram: usize, max_gram: usize },
+}
+
+impl SearchTokenizer {
+    /// Builds the Tantivy analyzer for the tokenizer.
+    fn analyzer(self) -> Result<TextAnalyzer, TantivyError> {
+        Ok(match self {
+            Self::Simple => TextAnalyzer::builder(SimpleTokenizer::default())
+                .filter(RemoveLongFilter::limit(MAX_TOKEN_BYTES))
+                .filter(LowerCaser)
+                .build(),
+            Self::Stemmed { language } => TextAnalyzer::builder(SimpleTokenizer::default())
+                .filter(RemoveLongFilter::limit(MAX_TOKEN_BYTES))
+                .filter(LowerCaser)
+                .filter(Stemmer::new(language))
+                .build(),
+            Self::Ngram { min_gram, max_gram } => {
+                TextAnalyzer::builder(NgramTokenizer::new(min_gram, max_gram, false)?)
+                    .filter(LowerCaser)
+                    .build()
+            }
+        })
+    }
+}
 
 /// Builds the schema used by the full text search database.
+///
+/// Tantivy refuses to open an index created with a different schema, so
+/// indexes created before a field was added have to be deleted and
+/// rebuilt from the message store with a reindex.
 pub fn text_search_schema() -> Schema {
     let mut schema_builder = Schema::builder();
 
-    // Add the ßtimestamp as an indexed field that we can reference later for retriving
+    // Add the timestamp as an indexed field that we can reference later for retriving
     // (ranges) of messages from the time-series database using text-search query results.
     //
     // Note that we assume the timestamps stored will be in UTC, no timezone conversions are performed.
@@ -20,8 +151,9 @@ pub fn text_search_schema() -> Schema {
         tantivy::schema::DateOptions::from(tantivy::schema::INDEXED)
             .set_stored() // needs to be stored so we can reference it later
             .set_fast() // will be random-accessed lots
-            // TODO: we may want to increase the datetime precision to better-handle fast logging.
-            .set_precision(tantivy::schema::DateTimePrecision::Seconds),
+            // Millisecond precision matches the message timestamps, so date-range
+            // queries stay accurate for messages sent within the same second.
+            .set_precision(tantivy::schema::DateTimePrecision::Milliseconds),
     );
 
     // Add the message body as a tokenized "text" field.
@@ -38,8 +170,1236 @@ pub fn text_search_schema() -> Schema {
     // Add the message autor as a tokenized field.
     schema_builder.add_u64_field(
         SCHEMA_KEY_AUTHOR,
-        tantivy::schema::NumericOptions::from(tantivy::schema::INDEXED).set_fast(), // will be random-accessed lots,
+        tantivy::schema::NumericOptions::from(tantivy::schema::INDEXED)
+            .set_stored() // returned with search results
+            .set_fast(), // will be random-accessed lots,
+    );
+
+    // Add the ID of the replied to message, only set on replies.
+    schema_builder.add_u64_field(
+        SCHEMA_KEY_REPLY_TO,
+        tantivy::schema::NumericOptions::from(tantivy::schema::INDEXED).set_stored(), // returned with search results
+    );
+
+    // Add when the message was last edited, only set on edited messages.
+    schema_builder.add_u64_field(SCHEMA_KEY_EDITED_AT, tantivy::schema::STORED);
+
+    // Add the message ID as an indexed field, so a single message
+    // can be deleted by it's ID when it's edited.
+    schema_builder.add_u64_field(
+        SCHEMA_KEY_MESSAGE_ID,
+        tantivy::schema::NumericOptions::from(tantivy::schema::INDEXED)
+            .set_stored() // returned with search results
+            .set_fast(), // breaks ties when ordering results
     );
 
     schema_builder.build()
 }
+
+/// Handles to the fields of the full-text search schema.
+#[derive(Clone, Copy)]
+pub struct SearchFields {
+    pub timestamp: Field,
+    pub content: Field,
+    pub author: Field,
+    pub reply_to: Field,
+    pub edited_at: Field,
+    pub message_id: Field,
+}
+
+impl SearchFields {
+    /// Resolves the fields from a schema built by [`text_search_schema`].
+    pub fn from_schema(schema: &Schema) -> Self {
+        Self {
+            timestamp: schema.get_field(SCHEMA_KEY_TIMESTAMP).unwrap(),
+            content: schema.get_field(SCHEMA_KEY_CONTENT).unwrap(),
+            author: schema.get_field(SCHEMA_KEY_AUTHOR).unwrap(),
+            reply_to: schema.get_field(SCHEMA_KEY_REPLY_TO).unwrap(),
+            edited_at: schema.get_field(SCHEMA_KEY_EDITED_AT).unwrap(),
+            message_id: schema.get_field(SCHEMA_KEY_MESSAGE_ID).unwrap(),
+        }
+    }
+}
+
+/// How the query text is matched against the message content.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Matches the query using the full query syntax, with exact terms.
+    #[default]
+    Exact,
+    /// Matches terms within a Levenshtein distance of each query term.
+    ///
+    /// The distance is clamped to [`MAX_FUZZY_DISTANCE`].
+    Fuzzy { distance: u8 },
+    /// Matches terms starting with the last query term, for autocomplete.
+    Prefix,
+    /// Matches the query using the full query syntax, with terms scoped
+    /// to any of the [`ADVANCED_QUERY_FIELDS`], such as `author:123`.
+    ///
+    /// Terms without a field are matched against the message content.
+    Advanced,
+}
+
+/// How the results of a search are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchSort {
+    /// Orders the results by their relevance to the query.
+    #[default]
+    Relevance,
+    /// Orders the results by when they were sent, newest first,
+    /// with more relevant results first for messages sent together.
+    Recency,
+    /// Orders the results by their relevance, boosted for recent messages.
+    ///
+    /// The boost decays exponentially with the age of the message,
+    /// see [`RECENCY_BOOST`] and [`RECENCY_HALF_LIFE_MS`].
+    Hybrid,
+}
+
+/// The position of a hit in the order of a search's results.
+///
+/// Results are ordered by their key, highest first. Keys end with the
+/// message ID, which is unique, so every hit has it's own position and
+/// the order doesn't depend on how the index lays out it's documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchKey([u64; 3]);
+
+impl SearchKey {
+    /// Builds the key of a hit in a search with the sort.
+    ///
+    /// Relevance and hybrid searches order hits by score, then timestamp,
+    /// then message ID. Recency searches order them by timestamp, then
+    /// score, then message ID.
+    pub fn new(sort: SearchSort, score: f32, timestamp_ms: u64, message_id: MessageId) -> Self {
+        // The bits of non-negative floats are ordered the same as the floats.
+        let score = score.max(0.0).to_bits() as u64;
+
+        match sort {
+            SearchSort::Relevance | SearchSort::Hybrid => Self([score, timestamp_ms, message_id.0]),
+            SearchSort::Recency => Self([timestamp_ms, score, message_id.0]),
+        }
+    }
+
+    /// Returns the key of a hit in a search with the sort.
+    pub fn of(sort: SearchSort, hit: &SearchHit) -> Self {
+        Self::new(sort, hit.score, hit.timestamp_ms, hit.message_id)
+    }
+
+    /// Returns the ID of the message the key is for.
+    pub fn message_id(&self) -> MessageId {
+        MessageId(self.0[2])
+    }
+}
+
+/// Marks where a page of a search's results ended, to fetch the next page.
+///
+/// The next page starts after the last hit's [`SearchKey`] rather than
+/// at an offset, so messages added or removed between pages don't cause
+/// results to be repeated or skipped. Clients receive cursors as opaque
+/// strings, through the [`Display`] and [`FromStr`] implementations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchCursor {
+    /// The sort of the search the cursor is from.
+    sort: SearchSort,
+    /// The key of the last hit on the page.
+    last: SearchKey,
+    /// The time recent messages are boosted relative to, kept
+    /// for every page so hybrid scores don't shift between pages.
+    now_ms: u64,
+    /// The number of hits on the pages before the next page.
+    depth: usize,
+}
+
+/// Version of the encoded cursor format, so it can change without
+/// misreading cursors that clients held on to.
+const SEARCH_CURSOR_VERSION: u8 = 1;
+
+impl SearchCursor {
+    /// Constructs a cursor for the page after the hit with the key.
+    pub fn new(sort: SearchSort, last: SearchKey, now_ms: u64, depth: usize) -> Self {
+        Self {
+            sort,
+            last,
+            now_ms,
+            depth,
+        }
+    }
+
+    /// Returns the sort of the search the cursor is from.
+    pub fn sort(&self) -> SearchSort {
+        self.sort
+    }
+
+    /// Returns the key of the last hit before the next page.
+    pub fn last(&self) -> SearchKey {
+        self.last
+    }
+
+    /// Returns the time recent messages are boosted relative to.
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// Returns the number of hits on the pages before the next page.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Encodes the cursor as an opaque, URL-safe string.
+impl Display for SearchCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sort: u8 = match self.sort {
+            SearchSort::Relevance => 0,
+            SearchSort::Recency => 1,
+            SearchSort::Hybrid => 2,
+        };
+
+        let mut bytes = vec![SEARCH_CURSOR_VERSION, sort];
+        for part in self.last.0 {
+            bytes.extend(part.to_be_bytes());
+        }
+        bytes.extend(self.now_ms.to_be_bytes());
+        bytes.extend((self.depth as u64).to_be_bytes());
+
+        f.write_str(&URL_SAFE_NO_PAD.encode(bytes))
+    }
+}
+
+/// Indicates a search cursor string wasn't one issued by the server.
+#[derive(Debug)]
+pub struct InvalidSearchCursor;
+
+/// Decodes a cursor encoded by it's [`Display`] implementation.
+impl FromStr for SearchCursor {
+    type Err = InvalidSearchCursor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = URL_SAFE_NO_PAD.decode(s).map_err(|_| InvalidSearchCursor)?;
+        let [version, sort, rest @ ..] = bytes.as_slice() else {
+            return Err(InvalidSearchCursor);
+        };
+        if *version != SEARCH_CURSOR_VERSION || rest.len() != 5 * 8 {
+            return Err(InvalidSearchCursor);
+        }
+
+        let sort = match sort {
+            0 => SearchSort::Relevance,
+            1 => SearchSort::Recency,
+            2 => SearchSort::Hybrid,
+            _ => return Err(InvalidSearchCursor),
+        };
+
+        let mut parts = rest
+            .chunks_exact(8)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()));
+        let mut next = || parts.next().unwrap();
+
+        Ok(Self {
+            sort,
+            last: SearchKey([next(), next(), next()]),
+            now_ms: next(),
+            depth: usize::try_from(next()).map_err(|_| InvalidSearchCursor)?,
+        })
+    }
+}
+
+/// Boosts a relevance score by how recently the message was sent.
+pub fn recency_boosted(score: f32, timestamp_ms: u64, now_ms: u64) -> f32 {
+    let age_ms = now_ms.saturating_sub(timestamp_ms);
+    let decay = 0.5f64.powf(age_ms as f64 / RECENCY_HALF_LIFE_MS as f64);
+
+    score * (1.0 + RECENCY_BOOST * decay as f32)
+}
+
+/// Parameters for searching the messages in a text channel.
+#[derive(Clone)]
+pub struct SearchQuery {
+    /// Full-text query matched against the message content.
+    pub text: String,
+    /// How the query text is matched against the message content.
+    pub mode: SearchMode,
+    /// How the results are ordered.
+    pub sort: SearchSort,
+    /// Only match messages from this author.
+    pub author: Option<UserId>,
+    /// Only match messages sent at or after this timestamp in milliseconds.
+    pub from_ms: Option<u64>,
+    /// Only match messages sent at or before this timestamp in milliseconds.
+    pub to_ms: Option<u64>,
+    /// Maximum number of results to return.
+    ///
+    /// Clamped to [`MAX_SEARCH_LIMIT`].
+    pub limit: usize,
+    /// Where the previous page ended, to fetch the next page.
+    ///
+    /// Must be from a search with the same sort, and at
+    /// most [`MAX_SEARCH_OFFSET`] results deep.
+    pub cursor: Option<SearchCursor>,
+    /// Maximum length of the highlighted snippet in characters.
+    ///
+    /// Clamped to [`MAX_SNIPPET_CHARS`].
+    pub snippet_chars: usize,
+}
+
+/// A message matched by a search.
+pub struct SearchHit {
+    /// Relevance score of the match.
+    ///
+    /// Includes the recency boost in hybrid searches.
+    pub score: f32,
+    /// The ID of the message.
+    pub message_id: MessageId,
+    /// The author of the message.
+    pub author: UserId,
+    /// Timestamp of the message in milliseconds.
+    pub timestamp_ms: u64,
+    /// Text body of the message.
+    pub content: String,
+    /// The message that the message replies to.
+    pub reply_to: Option<MessageId>,
+    /// Timestamp of the last edit in milliseconds, if the message was edited.
+    pub edited_at: Option<u64>,
+    /// Excerpt of the message content with the matched
+    /// terms wrapped in `<b>` tags, escaped as HTML.
+    pub highlight: String,
+}
+
+/// A page of the results of a search.
+pub struct SearchPage {
+    /// The matched messages, ordered by the query's sort.
+    pub hits: Vec<SearchHit>,
+    /// Where the next page starts, if there may be more results.
+    pub next_cursor: Option<SearchCursor>,
+}
+
+/// Indicates there was an error searching a text channel.
+#[derive(Debug)]
+pub enum SearchError {
+    /// Indicates the supplied query text couldn't be parsed.
+    InvalidQuery(QueryParserError),
+    /// Indicates there was an error executing the search.
+    SearchError(TantivyError),
+    /// Indicates the cursor was deeper than [`MAX_SEARCH_OFFSET`].
+    OffsetTooLarge(usize),
+    /// Indicates the cursor is from a search with a different sort.
+    InvalidCursor,
+    /// Indicates the query referred to a field the search mode can't match.
+    FieldNotSearchable(String),
+    /// Indicates the query only had wildcards, which would match every message.
+    WildcardOnly,
+    /// Indicates the query used syntax that isn't supported, such as regexes.
+    UnsupportedSyntax,
+}
+
+/// Full-text index of a text channel's messages.
+///
+/// Only the channel's worker adds and deletes documents, and changes
+/// aren't visible to searches until they're committed, so the worker
+/// can batch commits.
+pub trait SearchIndex: Send + Sync {
+    /// Adds a message to the index.
+    fn add(&self, msg: &TextChannelMessage) -> Result<(), TantivyError>;
+
+    /// Deletes the messages with IDs before the given ID, matching
+    /// the range removed from the store by retention pruning.
+    fn delete_before(&self, before: MessageId) -> Result<(), TantivyError>;
+
+    /// Deletes a message by it's ID, such as before re-adding it after an edit.
+    fn delete_message(&self, msg: &TextChannelMessage) -> Result<(), TantivyError>;
+
+    /// Deletes the messages posted by the author.
+    fn delete_by_author(&self, author: UserId) -> Result<(), TantivyError>;
+
+    /// Deletes the messages sent from `start_ms` up to but not including `end_ms`.
+    fn delete_in_range(&self, start_ms: u64, end_ms: u64) -> Result<(), TantivyError>;
+
+    /// Deletes every message from the index, such as before rebuilding it.
+    ///
+    /// Like the other changes, searches still see the old messages until
+    /// the next commit.
+    fn clear(&self) -> Result<(), TantivyError>;
+
+    /// Commits the pending changes, making them visible to searches.
+    fn commit(&self) -> Result<(), TantivyError>;
+
+    /// Searches the indexed messages, ordered by their [`SearchKey`] for
+    /// the query's sort, starting after the query's cursor.
+    ///
+    /// Hybrid searches boost messages by how recently they were sent
+    /// before `now_ms`, which stays the same for each page of a search.
+    fn search(&self, query: SearchQuery, now_ms: u64) -> Result<Vec<SearchHit>, SearchError>;
+}
+
+/// A search index stored in a Tantivy index directory.
+pub struct TantivySearchIndex {
+    /// Writer used by the channel's worker.
+    ///
+    /// Tantivy only allows a single writer per index, and committing
+    /// requires exclusive access, so it's only locked by the worker.
+    writer: Mutex<IndexWriter>,
+    /// Reader for searching the index.
+    reader: IndexReader,
+    /// Handles to the fields in the search index schema.
+    fields: SearchFields,
+}
+
+impl TantivySearchIndex {
+    /// Opens or creates the search index in the directory.
+    ///
+    /// The content is tokenized with the tokenizer, which must match the
+    /// one the existing documents in the index were added with.
+    pub fn open(
+        dir: &Path,
+        writer_heap_bytes: usize,
+        tokenizer: SearchTokenizer,
+    ) -> Result<Self, TextChannelError> {
+        // Create the text search schema used for querying logs.
+        let schema = text_search_schema();
+
+        // Create the directory for the search index if required.
+        std::fs::create_dir_all(dir).map_err(TextChannelError::SearchIndexPathError)?;
+
+        // Open or create the search index.
+        let index_directory = tantivy::directory::MmapDirectory::open(dir)
+            .map_err(TextChannelError::SearchIndexDirectoryError)?;
+        let index = tantivy::Index::open_or_create(index_directory, schema.clone())
+            .map_err(TextChannelError::SearchError)?;
+
+        // Register the configured tokenizer in place of Tantivy's default, so
+        // both indexing and query parsing use it for the content field.
+        index.tokenizers().register(
+            CONTENT_TOKENIZER,
+            tokenizer
+                .analyzer()
+                .map_err(TextChannelError::SearchError)?,
+        );
+
+        // Create the reader used to search the index, reloading
+        // shortly after the worker commits new messages.
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommitWithDelay)
+            .try_into()
+            .map_err(TextChannelError::SearchError)?;
+
+        // Create the index writer for the channel's message worker task.
+        let writer = index
+            .writer(writer_heap_bytes)
+            .map_err(TextChannelError::SearchError)?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            reader,
+            fields: SearchFields::from_schema(&schema),
+        })
+    }
+}
+
+impl SearchIndex for TantivySearchIndex {
+    fn add(&self, msg: &TextChannelMessage) -> Result<(), TantivyError> {
+        let fields = self.fields;
+
+        // Create a document from the message for search.
+        let mut document = TantivyDocument::default();
+        document.add_date(
+            fields.timestamp,
+            DateTime::from_timestamp_millis(msg.timestamp_ms as i64),
+        );
+        document.add_text(fields.content, msg.content.clone());
+        document.add_u64(fields.author, msg.author.0);
+        document.add_u64(fields.message_id, msg.id.0);
+        if let Some(reply_to) = msg.reply_to {
+            document.add_u64(fields.reply_to, reply_to.0);
+        }
+        if let Some(edited_at) = msg.edited_at {
+            document.add_u64(fields.edited_at, edited_at);
+        }
+
+        self.writer.lock().add_document(document)?;
+
+        Ok(())
+    }
+
+    fn delete_before(&self, before: MessageId) -> Result<(), TantivyError> {
+        let pruned = RangeQuery::new(
+            Bound::Unbounded,
+            Bound::Excluded(Term::from_field_u64(self.fields.message_id, before.0)),
+        );
+
+        self.writer.lock().delete_query(Box::new(pruned))?;
+
+        Ok(())
+    }
+
+    fn delete_message(&self, msg: &TextChannelMessage) -> Result<(), TantivyError> {
+        self.writer
+            .lock()
+            .delete_term(Term::from_field_u64(self.fields.message_id, msg.id.0));
+
+        Ok(())
+    }
+
+    fn delete_by_author(&self, author: UserId) -> Result<(), TantivyError> {
+        self.writer
+            .lock()
+            .delete_term(Term::from_field_u64(self.fields.author, author.0));
+
+        Ok(())
+    }
+
+    fn delete_in_range(&self, start_ms: u64, end_ms: u64) -> Result<(), TantivyError> {
+        let range = RangeQuery::new(
+            Bound::Included(Term::from_field_date(
+                self.fields.timestamp,
+                DateTime::from_timestamp_millis(start_ms as i64),
+            )),
+            Bound::Excluded(Term::from_field_date(
+                self.fields.timestamp,
+                DateTime::from_timestamp_millis(end_ms as i64),
+            )),
+        );
+
+        self.writer.lock().delete_query(Box::new(range))?;
+
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), TantivyError> {
+        self.writer.lock().delete_all_documents()?;
+
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<(), TantivyError> {
+        self.writer.lock().commit()?;
+
+        // Reload right away rather than waiting for the reload policy, so
+        // messages are searchable as soon as the commit returns, which
+        // durable channels rely on before acknowledging a message.
+        self.reader.reload()?;
+
+        Ok(())
+    }
+
+    fn search(&self, query: SearchQuery, now_ms: u64) -> Result<Vec<SearchHit>, SearchError> {
+        search(&self.reader, self.fields, query, now_ms)
+    }
+}
+
+/// Opens the column of message timestamps in a segment of the search index.
+fn timestamp_column(segment: &SegmentReader) -> Option<Column<DateTime>> {
+    match segment.fast_fields().date(SCHEMA_KEY_TIMESTAMP) {
+        Ok(column) => Some(column),
+        Err(err) => {
+            tracing::error!(%err, "failed to open search index timestamp column");
+            None
+        }
+    }
+}
+
+/// Reads a document's timestamp in milliseconds from the timestamp column.
+fn doc_timestamp_ms(timestamps: &Option<Column<DateTime>>, doc: DocId) -> u64 {
+    timestamps
+        .as_ref()
+        .and_then(|column| column.first(doc))
+        .map(|timestamp| timestamp.into_timestamp_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Opens the column of message IDs in a segment of the search index.
+fn message_id_column(segment: &SegmentReader) -> Option<Column<u64>> {
+    match segment.fast_fields().u64(SCHEMA_KEY_MESSAGE_ID) {
+        Ok(column) => Some(column),
+        Err(err) => {
+            tracing::error!(%err, "failed to open search index message ID column");
+            None
+        }
+    }
+}
+
+/// A collected document, with it's key and score unless it was skipped.
+type RankedDoc = (Option<(SearchKey, Score)>, DocAddress);
+
+/// Collects the documents matching a query with the highest [`SearchKey`]s,
+/// skipping those ordered at or before the key they come `after`.
+///
+/// Returns the key and score of each document, with the score boosted in
+/// hybrid searches. Skipped documents rank below every other document,
+/// and are returned without a key if there aren't enough other documents.
+fn search_ranked(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: usize,
+    sort: SearchSort,
+    now_ms: u64,
+    after: Option<SearchKey>,
+) -> Result<Vec<RankedDoc>, TantivyError> {
+    let collector = TopDocs::with_limit(limit).tweak_score(move |segment: &SegmentReader| {
+        let timestamps = timestamp_column(segment);
+        let message_ids = message_id_column(segment);
+
+        move |doc: DocId, score: Score| {
+            let timestamp_ms = doc_timestamp_ms(&timestamps, doc);
+            let message_id = message_ids
+                .as_ref()
+                .and_then(|column| column.first(doc))
+                .unwrap_or_default();
+
+            let score = match sort {
+                SearchSort::Hybrid => recency_boosted(score, timestamp_ms, now_ms),
+                _ => score,
+            };
+
+            let key = SearchKey::new(sort, score, timestamp_ms, MessageId(message_id));
+            after
+                .is_none_or(|after| key < after)
+                .then_some((key, score))
+        }
+    });
+
+    searcher.search(query, &collector)
+}
+
+/// Executes a search against a Tantivy search index.
+fn search(
+    reader: &IndexReader,
+    fields: SearchFields,
+    query: SearchQuery,
+    now_ms: u64,
+) -> Result<Vec<SearchHit>, SearchError> {
+    let searcher = reader.searcher();
+
+    let text_query = match query.mode {
+        // Parse the user-supplied text as a query over the message content,
+        // or over the advanced query fields.
+        SearchMode::Exact | SearchMode::Advanced => {
+            let allowed_fields: &[&str] = match query.mode {
+                SearchMode::Advanced => &ADVANCED_QUERY_FIELDS,
+                _ => &[SCHEMA_KEY_CONTENT],
+            };
+            check_query_syntax(&query.text, allowed_fields)?;
+
+            let parser = QueryParser::for_index(searcher.index(), vec![fields.content]);
+            parser
+                .parse_query(&query.text)
+                .map_err(SearchError::InvalidQuery)?
+        }
+        SearchMode::Fuzzy { .. } | SearchMode::Prefix => {
+            term_match_query(searcher.index(), fields, &query.text, query.mode)?
+        }
+    };
+
+    // Narrow the results down with any of the specified filters.
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, text_query)];
+
+    if let Some(author) = query.author {
+        clauses.push((
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(fields.author, author.0),
+                IndexRecordOption::Basic,
+            )),
+        ));
+    }
+
+    if query.from_ms.is_some() || query.to_ms.is_some() {
+        let bound = |ms: Option<u64>| match ms {
+            Some(ms) => Bound::Included(Term::from_field_date(
+                fields.timestamp,
+                DateTime::from_timestamp_millis(ms as i64),
+            )),
+            None => Bound::Unbounded,
+        };
+
+        clauses.push((
+            Occur::Must,
+            Box::new(RangeQuery::new(bound(query.from_ms), bound(query.to_ms))),
+        ));
+    }
+
+    let limit = query.limit.clamp(1, MAX_SEARCH_LIMIT);
+
+    let search_query = BooleanQuery::new(clauses);
+
+    // Pages start after the last hit of the previous page. Scores shift as
+    // messages are added and removed, so that hit's key is worked out again
+    // against the index as it is now, keeping it in step with the other
+    // hits. The cursor's key is used if the message no longer matches.
+    let after = match query.cursor {
+        Some(cursor) => {
+            let last = cursor.last();
+            // The message ID only picks out the message, without adding to it's score.
+            let last_message = TermQuery::new(
+                Term::from_field_u64(fields.message_id, last.message_id().0),
+                IndexRecordOption::Basic,
+            );
+            let last_query = BooleanQuery::new(vec![
+                (Occur::Must, Box::new(search_query.clone())),
+                (
+                    Occur::Must,
+                    Box::new(ConstScoreQuery::new(Box::new(last_message), 0.0)),
+                ),
+            ]);
+
+            let current = search_ranked(&searcher, &last_query, 1, query.sort, now_ms, None)
+                .map_err(SearchError::SearchError)?;
+            Some(
+                current
+                    .into_iter()
+                    .find_map(|(ranked, _)| ranked)
+                    .map_or(last, |(key, _)| key),
+            )
+        }
+        None => None,
+    };
+
+    let top_docs = search_ranked(&searcher, &search_query, limit, query.sort, now_ms, after)
+        .map_err(SearchError::SearchError)?;
+
+    // Generates excerpts of the message content around the matched terms.
+    let mut snippet_generator = SnippetGenerator::create(&searcher, &search_query, fields.content)
+        .map_err(SearchError::SearchError)?;
+    let snippet_chars = query.snippet_chars.clamp(1, MAX_SNIPPET_CHARS);
+    snippet_generator.set_max_num_chars(snippet_chars);
+
+    let mut hits = Vec::with_capacity(top_docs.len());
+    for (ranked, address) in top_docs {
+        // Documents on earlier pages are only collected when there
+        // aren't enough documents after them to fill the page.
+        let Some((_, score)) = ranked else {
+            continue;
+        };
+
+        let document: TantivyDocument = searcher.doc(address).map_err(SearchError::SearchError)?;
+
+        let content = document
+            .get_first(fields.content)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        // Messages without any highlighted terms produce an empty
+        // snippet, so fall back to the start of the content.
+        let snippet = snippet_generator.snippet_from_doc(&document);
+        let highlight = if snippet.is_empty() {
+            escape_html(&content.chars().take(snippet_chars).collect::<String>())
+        } else {
+            snippet.to_html()
+        };
+
+        hits.push(SearchHit {
+            score,
+            message_id: MessageId(
+                document
+                    .get_first(fields.message_id)
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or_default(),
+            ),
+            author: UserId(
+                document
+                    .get_first(fields.author)
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or_default(),
+            ),
+            timestamp_ms: document
+                .get_first(fields.timestamp)
+                .and_then(|v| v.as_datetime())
+                .map(|d| d.into_timestamp_millis() as u64)
+                .unwrap_or_default(),
+            content,
+            reply_to: document
+                .get_first(fields.reply_to)
+                .and_then(|v| v.as_u64())
+                .map(MessageId),
+            edited_at: document
+                .get_first(fields.edited_at)
+                .and_then(|v| v.as_u64()),
+            highlight,
+        });
+    }
+
+    Ok(hits)
+}
+
+/// Checks a query only refers to the allowed fields, and isn't only wildcards.
+///
+/// The query parser accepts terms for any indexed field, and wildcard
+/// queries that make the index score every message, so the syntax
+/// is checked before the query is parsed.
+fn check_query_syntax(text: &str, allowed_fields: &[&str]) -> Result<(), SearchError> {
+    // Empty queries don't match anything, so they're cheap.
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+
+    // Queries the grammar can't parse are rejected by the query parser.
+    let Ok(ast) = query_grammar::parse_query(text) else {
+        return Ok(());
+    };
+
+    if !check_query_ast(&ast, allowed_fields)? {
+        return Err(SearchError::WildcardOnly);
+    }
+
+    Ok(())
+}
+
+/// Checks the fields of every clause of a parsed query.
+///
+/// Returns true if a clause narrows down the matched messages to those
+/// with a specific term or value, rather than only excluding messages.
+fn check_query_ast(ast: &UserInputAst, allowed_fields: &[&str]) -> Result<bool, SearchError> {
+    match ast {
+        UserInputAst::Clause(clauses) => {
+            let mut narrowed = false;
+            for (occur, clause) in clauses {
+                let clause_narrowed = check_query_ast(clause, allowed_fields)?;
+                if *occur != Some(query_grammar::Occur::MustNot) {
+                    narrowed |= clause_narrowed;
+                }
+            }
+
+            Ok(narrowed)
+        }
+        UserInputAst::Boost(ast, _) => check_query_ast(ast, allowed_fields),
+        UserInputAst::Leaf(leaf) => {
+            let (field, narrowed) = match leaf.as_ref() {
+                UserInputLeaf::Literal(literal) => (
+                    literal.field_name.as_deref(),
+                    literal.phrase.chars().any(|c| c != '*'),
+                ),
+                UserInputLeaf::Range { field, .. } | UserInputLeaf::Set { field, .. } => {
+                    (field.as_deref(), true)
+                }
+                UserInputLeaf::All => (None, false),
+                _ => return Err(SearchError::UnsupportedSyntax),
+            };
+
+            // Terms without a field are matched against the content.
+            if let Some(field) = field
+                && !allowed_fields.contains(&field)
+            {
+                return Err(SearchError::FieldNotSearchable(field.to_string()));
+            }
+
+            Ok(narrowed)
+        }
+    }
+}
+
+/// Builds a query matching every term in the text fuzzily or by prefix.
+///
+/// The text is split into terms with the content field's tokenizer, so
+/// the terms are normalized the same way as the indexed messages.
+fn term_match_query(
+    index: &tantivy::Index,
+    fields: SearchFields,
+    text: &str,
+    mode: SearchMode,
+) -> Result<Box<dyn Query>, SearchError> {
+    let mut analyzer = index
+        .tokenizer_for_field(fields.content)
+        .map_err(SearchError::SearchError)?;
+
+    let mut terms = Vec::new();
+    let mut stream = analyzer.token_stream(text);
+    while stream.advance() && terms.len() < MAX_FUZZY_TERMS {
+        terms.push(Term::from_field_text(fields.content, &stream.token().text));
+    }
+
+    // Nothing to match, such as a query of only punctuation.
+    if terms.is_empty() {
+        return Ok(Box::new(EmptyQuery));
+    }
+
+    let last = terms.len() - 1;
+    let clauses: Vec<(Occur, Box<dyn Query>)> = terms
+        .into_iter()
+        .enumerate()
+        .map(|(i, term)| {
+            let query: Box<dyn Query> = match mode {
+                SearchMode::Fuzzy { distance } => Box::new(FuzzyTermQuery::new(
+                    term,
+                    distance.min(MAX_FUZZY_DISTANCE),
+                    true,
+                )),
+                // Only the last term is still being typed.
+                SearchMode::Prefix if i == last => {
+                    Box::new(FuzzyTermQuery::new_prefix(term, 0, true))
+                }
+                _ => Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)),
+            };
+
+            (Occur::Must, query)
+        })
+        .collect();
+
+    Ok(Box::new(BooleanQuery::new(clauses)))
+}
+
+/// Escapes text for embedding in HTML, matching the escaping used by snippets.
+pub(super) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel::ChannelId,
+        id::now_ms,
+        server::{
+            channel::text::{
+                TextChannel, TextChannelOptions, TextChannelSettings,
+                tests::{test_channel, test_message, test_options, test_query, wait_for_commit},
+            },
+            permission::Permissions,
+        },
+    };
+
+    #[tokio::test]
+    async fn messages_sent_now_match_a_millisecond_range() {
+        let (_dir, channel) = test_channel();
+
+        let created = channel
+            .create_message(
+                test_message(UserId(1), "sparks in the dark"),
+                None,
+                Permissions::NONE,
+            )
+            .await
+            .unwrap();
+        wait_for_commit().await;
+
+        let sent_ms = created.timestamp_ms;
+        let search = |from_ms, to_ms| {
+            let query = SearchQuery {
+                from_ms: Some(from_ms),
+                to_ms: Some(to_ms),
+                ..test_query("sparks")
+            };
+            channel.search(query).unwrap().hits.len()
+        };
+
+        // Bounds are inclusive, to the millisecond.
+        assert_eq!(search(sent_ms, sent_ms), 1);
+        assert_eq!(search(sent_ms - 5, sent_ms + 5), 1);
+        assert_eq!(search(sent_ms - 5, sent_ms - 1), 0);
+        assert_eq!(search(sent_ms + 1, sent_ms + 5), 0);
+    }
+
+    #[tokio::test]
+    async fn fuzzy_and_prefix_modes_match_partial_terms() {
+        let (_dir, channel) = test_channel();
+
+        channel
+            .create_message(
+                test_message(UserId(1), "hello there"),
+                None,
+                Permissions::NONE,
+            )
+            .await
+            .unwrap();
+        wait_for_commit().await;
+
+        let search = |text, mode| {
+            let query = SearchQuery {
+                mode,
+                ..test_query(text)
+            };
+            channel.search(query).unwrap().hits.len()
+        };
+
+        assert_eq!(search("helo", SearchMode::Exact), 0);
+        assert_eq!(search("helo", SearchMode::Fuzzy { distance: 1 }), 1);
+        assert_eq!(search("hel", SearchMode::Exact), 0);
+        assert_eq!(search("hel", SearchMode::Prefix), 1);
+
+        // Only the last term is a prefix, earlier terms must be whole.
+        assert_eq!(search("hel the", SearchMode::Prefix), 0);
+        assert_eq!(search("hello the", SearchMode::Prefix), 1);
+    }
+
+    #[tokio::test]
+    async fn stemmed_searches_match_other_forms_of_a_word() {
+        async fn matches(tokenizer: SearchTokenizer) -> usize {
+            let dir = tempfile::tempdir().unwrap();
+            let db = fjall::Database::builder(dir.path().join("db"))
+                .open()
+                .unwrap();
+            let options = TextChannelOptions {
+                search_tokenizer: tokenizer,
+                ..test_options()
+            };
+            let channel = TextChannel::new(
+                ChannelId(1),
+                &dir.path().join("channel"),
+                db,
+                "general".to_string(),
+                TextChannelSettings::default(),
+                &options,
+            )
+            .unwrap();
+
+            channel
+                .create_message(
+                    test_message(UserId(1), "running to the lake"),
+                    None,
+                    Permissions::NONE,
+                )
+                .await
+                .unwrap();
+            wait_for_commit().await;
+
+            channel.search(test_query("run")).unwrap().hits.len()
+        }
+
+        assert_eq!(matches(SearchTokenizer::Simple).await, 0);
+        assert_eq!(
+            matches(SearchTokenizer::Stemmed {
+                language: Language::English
+            })
+            .await,
+            1
+        );
+    }
+
+    #[test]
+    fn recent_messages_rank_first_when_equally_relevant() {
+        let dir = tempfile::tempdir().unwrap();
+        let index =
+            TantivySearchIndex::open(dir.path(), 15_000_000, SearchTokenizer::Simple).unwrap();
+
+        // The older message is added first, so it would win ties on relevance
+        // alone if the index's document order was used to break them.
+        let now = now_ms();
+        for (id, age_ms) in [(1, 24 * 60 * 60 * 1000), (2, 60 * 1000)] {
+            let msg = TextChannelMessage {
+                id: MessageId(id),
+                timestamp_ms: now - age_ms,
+                ..test_message(UserId(1), "campfire stories")
+            };
+            index.add(&msg).unwrap();
+        }
+        index.commit().unwrap();
+
+        let search = |sort| {
+            let hits = index
+                .search(
+                    SearchQuery {
+                        sort,
+                        ..test_query("campfire")
+                    },
+                    now,
+                )
+                .unwrap();
+            assert_eq!(hits.len(), 2);
+            hits
+        };
+
+        let hits = search(SearchSort::Relevance);
+        assert_eq!(hits[0].score, hits[1].score);
+        assert!(hits[0].timestamp_ms > hits[1].timestamp_ms);
+
+        let hits = search(SearchSort::Recency);
+        assert!(hits[0].timestamp_ms > hits[1].timestamp_ms);
+
+        let hits = search(SearchSort::Hybrid);
+        assert!(hits[0].timestamp_ms > hits[1].timestamp_ms);
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn cursors_page_through_results_while_messages_are_added() {
+        let now = now_ms();
+        let message = |id, timestamp_ms, content| TextChannelMessage {
+            id: MessageId(id),
+            timestamp_ms,
+            ..test_message(UserId(1), content)
+        };
+
+        for sort in [
+            SearchSort::Relevance,
+            SearchSort::Recency,
+            SearchSort::Hybrid,
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let index =
+                TantivySearchIndex::open(dir.path(), 15_000_000, SearchTokenizer::Simple).unwrap();
+
+            // Some of the messages are sent in the same millisecond,
+            // so they're only told apart by their message ID.
+            for id in 1..=7 {
+                index
+                    .add(&message(id, now - 1000 + id / 2, "bonfire"))
+                    .unwrap();
+            }
+            index.commit().unwrap();
+
+            let mut next_id = 100;
+            let query = |cursor| SearchQuery {
+                sort,
+                limit: 2,
+                cursor,
+                ..test_query("bonfire")
+            };
+
+            let mut paged = Vec::new();
+            let mut cursor = None;
+            loop {
+                let hits = index.search(query(cursor), now).unwrap();
+                paged.extend(hits.iter().map(|hit| hit.message_id.0));
+
+                let Some(last) = hits.last().filter(|_| hits.len() == 2) else {
+                    break;
+                };
+                cursor = Some(SearchCursor::new(
+                    sort,
+                    SearchKey::of(sort, last),
+                    now,
+                    paged.len(),
+                ));
+
+                // Matching messages are sent between pages, changing the
+                // scores of every message and the index's document order.
+                for content in ["bonfire", "bonfire by the lake", "bonfire bonfire"] {
+                    index
+                        .add(&message(next_id, now + next_id, content))
+                        .unwrap();
+                    next_id += 1;
+                }
+                index.commit().unwrap();
+            }
+
+            // The earlier messages are each paged through once, newest
+            // first as they're equally relevant. Messages sent after the
+            // search started are only included if they're ordered after
+            // the page they were sent during.
+            let earlier: Vec<_> = paged.iter().copied().filter(|&id| id < 100).collect();
+            assert_eq!(earlier, [7, 6, 5, 4, 3, 2, 1], "{sort:?}");
+
+            let mut unique = paged.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), paged.len(), "{sort:?}");
+        }
+    }
+
+    #[test]
+    fn editing_a_message_keeps_others_sent_in_the_same_millisecond() {
+        let dir = tempfile::tempdir().unwrap();
+        let index =
+            TantivySearchIndex::open(dir.path(), 15_000_000, SearchTokenizer::Simple).unwrap();
+
+        let sent_ms = now_ms();
+        let message = |id, content| TextChannelMessage {
+            id: MessageId(id),
+            timestamp_ms: sent_ms,
+            ..test_message(UserId(1), content)
+        };
+        index.add(&message(1, "first ember")).unwrap();
+        index.add(&message(2, "second ember")).unwrap();
+        index.commit().unwrap();
+
+        // Edit the first message the way the channel worker does.
+        let edited = TextChannelMessage {
+            edited_at: Some(sent_ms + 1),
+            ..message(1, "first spark")
+        };
+        index.delete_message(&edited).unwrap();
+        index.add(&edited).unwrap();
+        index.commit().unwrap();
+
+        let search = |text| index.search(test_query(text), sent_ms).unwrap();
+        let hits = search("ember");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].content, "second ember");
+        assert_eq!(hits[0].message_id, MessageId(2));
+        let hits = search("spark");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].content, "first spark");
+        assert_eq!(hits[0].message_id, MessageId(1));
+    }
+
+    #[test]
+    fn pruning_removes_messages_sent_in_the_cutoff_millisecond() {
+        let dir = tempfile::tempdir().unwrap();
+        let index =
+            TantivySearchIndex::open(dir.path(), 15_000_000, SearchTokenizer::Simple).unwrap();
+
+        let sent_ms = now_ms();
+        let message = |id, content| TextChannelMessage {
+            id: MessageId(id),
+            timestamp_ms: sent_ms,
+            ..test_message(UserId(1), content)
+        };
+        index.add(&message(1, "pruned ember")).unwrap();
+        index.add(&message(2, "kept ember")).unwrap();
+        index.commit().unwrap();
+
+        // Retention removes the store's messages before the cutoff ID,
+        // so the index must drop the same messages.
+        index.delete_before(MessageId(2)).unwrap();
+        index.commit().unwrap();
+
+        let hits = index.search(test_query("ember"), sent_ms).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, MessageId(2));
+    }
+
+    #[tokio::test]
+    async fn advanced_queries_can_be_scoped_to_an_author() {
+        let (_dir, channel) = test_channel();
+        for author in [1, 2] {
+            channel
+                .create_message(
+                    test_message(UserId(author), "hello world"),
+                    None,
+                    Permissions::NONE,
+                )
+                .await
+                .unwrap();
+        }
+        wait_for_commit().await;
+
+        let search = |text: &str, mode| {
+            channel.search(SearchQuery {
+                mode,
+                ..test_query(text)
+            })
+        };
+
+        let hits = search("author:2 AND content:\"hello world\"", SearchMode::Advanced)
+            .unwrap()
+            .hits;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].author, UserId(2));
+
+        // Only advanced queries can scope terms to other fields.
+        assert!(matches!(
+            search("author:2 AND hello", SearchMode::Exact),
+            Err(SearchError::FieldNotSearchable(field)) if field == "author"
+        ));
+        assert!(matches!(
+            search("reply_to:1", SearchMode::Advanced),
+            Err(SearchError::FieldNotSearchable(field)) if field == "reply_to"
+        ));
+        assert!(matches!(
+            search("*", SearchMode::Advanced),
+            Err(SearchError::WildcardOnly)
+        ));
+    }
+}
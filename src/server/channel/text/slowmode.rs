@@ -0,0 +1,47 @@
+//! Slow mode for limiting how often users can post in a text channel.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::user::UserId;
+
+/// Tracks when each user last posted in a channel with slow mode enabled.
+///
+/// Users with recent posts are tracked per channel, so the
+/// cooldown for a user in one channel doesn't affect another.
+#[derive(Default)]
+pub struct SlowMode {
+    /// When each user last posted in the channel.
+    last_posts: Mutex<HashMap<UserId, Instant>>,
+}
+
+impl SlowMode {
+    /// Records a post from the user if they're outside of the cooldown.
+    ///
+    /// Returns how long the user must wait if they posted too recently.
+    pub fn check(&self, user: UserId, cooldown: Duration) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut last_posts = self.last_posts.lock();
+
+        // Forget users that are past the cooldown so the table stays bounded.
+        last_posts.retain(|_, posted| now.duration_since(*posted) < cooldown);
+
+        if let Some(posted) = last_posts.get(&user) {
+            return Err(cooldown - now.duration_since(*posted));
+        }
+
+        last_posts.insert(user, now);
+
+        Ok(())
+    }
+
+    /// Forgets a post recorded by [`Self::check`] that wasn't posted after
+    /// all, so the user doesn't have to wait out the cooldown for it.
+    pub fn refund(&self, user: UserId) {
+        self.last_posts.lock().remove(&user);
+    }
+}
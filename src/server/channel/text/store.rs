@@ -0,0 +1,583 @@
+//! Storage for the messages in a text channel.
+//!
+//! Messages are stored in an FSM-tree keyspace keyed by their
+//! big-endian message ID. Message IDs are snowflakes that start
+//! with the time they were created, so range scans return messages
+//! in the order they were sent, and messages sent within the same
+//! millisecond still have a stable order.
+//!
+//! The number of stored messages is maintained in a metadata
+//! keyspace, so it can be read without scanning the messages.
+//!
+//! Channels access their storage through the [`MessageStore`] trait,
+//! so embedders can swap in another backend, such as the in-memory
+//! store enabled by the `memory-backend` feature.
+
+use std::sync::Arc;
+
+use fjall::{KeyspaceCreateOptions, Slice};
+use parking_lot::Mutex;
+
+use crate::{channel::ChannelId, message::MessageId, server::channel::text::TextChannelMessage};
+
+/// Metadata key storing the number of messages in the channel.
+const META_KEY_MESSAGE_COUNT: &str = "message_count";
+
+/// Storage for a text channel's messages and pins.
+///
+/// Messages are ordered by their ID, so ranges are returned in the
+/// order the messages were sent. Backends report their failures as
+/// [`fjall::Error`], using it's IO variant for errors of their own,
+/// so callers handle the errors of every backend the same way.
+pub trait MessageStore: Send + Sync {
+    /// Returns the number of stored messages.
+    fn message_count(&self) -> u64;
+
+    /// Stores a message, replacing any message with the same ID.
+    fn insert(&self, msg: &TextChannelMessage) -> Result<(), fjall::Error>;
+
+    /// Changes a stored message in place.
+    ///
+    /// Updates are applied one at a time, so concurrent updates of the same
+    /// message don't overwrite each other's changes, and a message removed
+    /// during an update isn't stored again.
+    ///
+    /// Returns the message from before and after the change,
+    /// or `None` if the message isn't stored.
+    fn update(
+        &self,
+        id: MessageId,
+        change: &mut dyn FnMut(&mut TextChannelMessage),
+    ) -> Result<Option<(TextChannelMessage, TextChannelMessage)>, fjall::Error>;
+
+    /// Removes the message with the ID, if it's stored.
+    fn remove(&self, id: MessageId) -> Result<(), fjall::Error>;
+
+    /// Removes up to `limit` of the oldest messages sent before the message.
+    ///
+    /// Returns the number of messages removed.
+    fn remove_before(&self, id: MessageId, limit: usize) -> Result<usize, fjall::Error>;
+
+    /// Returns true if a message with the ID is stored.
+    fn contains(&self, id: MessageId) -> Result<bool, fjall::Error>;
+
+    /// Looks up a message by it's ID.
+    fn get(&self, id: MessageId) -> Result<Option<TextChannelMessage>, fjall::Error>;
+
+    /// Returns the ID of the `n`th oldest message, counting from zero.
+    fn nth_id(&self, n: usize) -> Result<Option<MessageId>, fjall::Error>;
+
+    /// Returns up to `limit` of the messages sent before the message, oldest first.
+    fn messages_before(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error>;
+
+    /// Returns up to `limit` of the messages sent after the message, oldest first.
+    fn messages_after(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error>;
+
+    /// Counts the messages sent after the message, stopping at `max`.
+    fn count_after(&self, id: MessageId, max: u64) -> Result<u64, fjall::Error>;
+
+    /// Returns true if the message is pinned.
+    fn is_pinned(&self, id: MessageId) -> Result<bool, fjall::Error>;
+
+    /// Returns the number of pinned messages.
+    fn pin_count(&self) -> Result<usize, fjall::Error>;
+
+    /// Pins the message.
+    fn pin(&self, id: MessageId) -> Result<(), fjall::Error>;
+
+    /// Unpins the message.
+    fn unpin(&self, id: MessageId) -> Result<(), fjall::Error>;
+
+    /// Returns the IDs of the pinned messages, oldest first.
+    fn pinned_ids(&self) -> Result<Vec<MessageId>, fjall::Error>;
+
+    /// Syncs the written messages to disk, returning once they're durable.
+    fn sync(&self) -> Result<(), fjall::Error>;
+}
+
+/// Handles to the keyspaces storing a text channel's messages.
+///
+/// Fjall keyspaces are synchronized for thread-safe access,
+/// so the store can be cloned and shared between tasks.
+#[derive(Clone)]
+pub struct FjallMessageStore {
+    /// The database the keyspaces belong to, used to sync it's journal.
+    db: fjall::Database,
+    /// Message records keyed by their ID.
+    messages: fjall::Keyspace,
+    /// Metadata about the stored messages.
+    meta: fjall::Keyspace,
+    /// Keys of the pinned messages.
+    pins: fjall::Keyspace,
+
+    /// The number of stored messages.
+    ///
+    /// Locked while the count is persisted so concurrent
+    /// updates are written in the order they're applied.
+    message_count: Arc<Mutex<u64>>,
+    /// Held while messages are updated or removed, so an update's
+    /// read and write of a message aren't interleaved with another change.
+    updates: Arc<Mutex<()>>,
+}
+
+impl FjallMessageStore {
+    /// Opens or creates the keyspaces for the channel's messages.
+    pub fn open(db: &fjall::Database, channel_id: ChannelId) -> Result<Self, fjall::Error> {
+        let messages = db.keyspace(
+            &format!("{}-messages", channel_id.0),
+            keyspace_create_options,
+        )?;
+        let meta = db.keyspace(&format!("{}-meta", channel_id.0), keyspace_create_options)?;
+        let pins = db.keyspace(&format!("{}-pins", channel_id.0), keyspace_create_options)?;
+
+        migrate_timestamp_keys(db, channel_id, &messages)?;
+
+        // Channels created before the count was maintained are counted once.
+        let message_count = match meta.get(META_KEY_MESSAGE_COUNT)? {
+            Some(value) if value.len() == 8 => u64::from_be_bytes(value[..8].try_into().unwrap()),
+            _ => {
+                let count = messages.len()? as u64;
+                meta.insert(META_KEY_MESSAGE_COUNT, count.to_be_bytes())?;
+                count
+            }
+        };
+
+        Ok(Self {
+            db: db.clone(),
+            messages,
+            meta,
+            pins,
+            message_count: Arc::new(Mutex::new(message_count)),
+            updates: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Updates the message count and persists it.
+    fn update_count(&self, update: impl FnOnce(u64) -> u64) -> Result<(), fjall::Error> {
+        let mut count = self.message_count.lock();
+        *count = update(*count);
+
+        self.meta
+            .insert(META_KEY_MESSAGE_COUNT, count.to_be_bytes())
+    }
+}
+
+impl MessageStore for FjallMessageStore {
+    fn message_count(&self) -> u64 {
+        *self.message_count.lock()
+    }
+
+    fn insert(&self, msg: &TextChannelMessage) -> Result<(), fjall::Error> {
+        let record = serde_json::to_vec(msg).expect("messages should always encode");
+        let key = msg.id.0.to_be_bytes();
+
+        // Replacing a message doesn't change the count.
+        let replaced = self.messages.contains_key(key)?;
+
+        self.messages.insert(key, record)?;
+
+        if !replaced {
+            self.update_count(|count| count + 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        id: MessageId,
+        change: &mut dyn FnMut(&mut TextChannelMessage),
+    ) -> Result<Option<(TextChannelMessage, TextChannelMessage)>, fjall::Error> {
+        let _updating = self.updates.lock();
+
+        let Some(original) = self.get(id)? else {
+            return Ok(None);
+        };
+
+        let mut changed = original.clone();
+        change(&mut changed);
+        changed.id = id;
+
+        let record = serde_json::to_vec(&changed).expect("messages should always encode");
+        self.messages.insert(id.0.to_be_bytes(), record)?;
+
+        Ok(Some((original, changed)))
+    }
+
+    fn remove(&self, id: MessageId) -> Result<(), fjall::Error> {
+        let _updating = self.updates.lock();
+
+        let key = id.0.to_be_bytes();
+        if !self.messages.contains_key(key)? {
+            return Ok(());
+        }
+
+        self.messages.remove(key)?;
+        self.update_count(|count| count.saturating_sub(1))
+    }
+
+    fn remove_before(&self, id: MessageId, limit: usize) -> Result<usize, fjall::Error> {
+        let _updating = self.updates.lock();
+
+        // Collect the keys before removing them so the
+        // keyspace isn't modified while it's being iterated.
+        let keys = self
+            .messages
+            .range(..id.0.to_be_bytes())
+            .take(limit)
+            .map(|guard| guard.key())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let removed = keys.len();
+        for key in keys {
+            self.messages.remove(key)?;
+        }
+
+        if removed > 0 {
+            self.update_count(|count| count.saturating_sub(removed as u64))?;
+        }
+
+        Ok(removed)
+    }
+
+    fn contains(&self, id: MessageId) -> Result<bool, fjall::Error> {
+        self.messages.contains_key(id.0.to_be_bytes())
+    }
+
+    fn get(&self, id: MessageId) -> Result<Option<TextChannelMessage>, fjall::Error> {
+        let Some(record) = self.messages.get(id.0.to_be_bytes())? else {
+            return Ok(None);
+        };
+
+        Ok(decode_message(&record, id))
+    }
+
+    fn nth_id(&self, n: usize) -> Result<Option<MessageId>, fjall::Error> {
+        let Some(guard) = self.messages.iter().nth(n) else {
+            return Ok(None);
+        };
+
+        Ok(Some(key_to_id(&guard.key()?)))
+    }
+
+    fn messages_before(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        let entries = self
+            .messages
+            .range(..id.0.to_be_bytes())
+            .rev()
+            .take(limit)
+            .map(|guard| guard.into_inner())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut messages = decode_entries(entries);
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    fn messages_after(
+        &self,
+        id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<TextChannelMessage>, fjall::Error> {
+        // The range is inclusive, so start from the next possible ID.
+        let Some(start) = id.0.checked_add(1) else {
+            return Ok(Vec::new());
+        };
+
+        let entries = self
+            .messages
+            .range(start.to_be_bytes()..)
+            .take(limit)
+            .map(|guard| guard.into_inner())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(decode_entries(entries))
+    }
+
+    fn count_after(&self, id: MessageId, max: u64) -> Result<u64, fjall::Error> {
+        // The range is inclusive, so start from the next possible ID.
+        let Some(start) = id.0.checked_add(1) else {
+            return Ok(0);
+        };
+
+        let mut count = 0;
+        for guard in self.messages.range(start.to_be_bytes()..) {
+            if count >= max {
+                break;
+            }
+
+            guard.key()?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn is_pinned(&self, id: MessageId) -> Result<bool, fjall::Error> {
+        self.pins.contains_key(id.0.to_be_bytes())
+    }
+
+    fn pin_count(&self) -> Result<usize, fjall::Error> {
+        self.pins.len()
+    }
+
+    fn pin(&self, id: MessageId) -> Result<(), fjall::Error> {
+        self.pins.insert(id.0.to_be_bytes(), [])
+    }
+
+    fn unpin(&self, id: MessageId) -> Result<(), fjall::Error> {
+        self.pins.remove(id.0.to_be_bytes())
+    }
+
+    fn pinned_ids(&self) -> Result<Vec<MessageId>, fjall::Error> {
+        self.pins
+            .iter()
+            .map(|guard| Ok(key_to_id(&guard.key()?)))
+            .collect()
+    }
+
+    fn sync(&self) -> Result<(), fjall::Error> {
+        // The journal is shared by every keyspace in the database,
+        // so this also syncs writes from other channels.
+        self.db.persist(fjall::PersistMode::SyncAll)
+    }
+}
+
+/// Decodes a message ID from it's big-endian key.
+fn key_to_id(key: &[u8]) -> MessageId {
+    MessageId(u64::from_be_bytes(key[..8].try_into().unwrap()))
+}
+
+/// Decodes the messages from keyspace entries, skipping any that can't be decoded.
+fn decode_entries(entries: Vec<(Slice, Slice)>) -> Vec<TextChannelMessage> {
+    entries
+        .into_iter()
+        .filter_map(|(key, value)| decode_message(&value, key_to_id(&key)))
+        .collect()
+}
+
+/// Decodes a stored message record, logging records that can't be decoded.
+fn decode_message(record: &[u8], id: MessageId) -> Option<TextChannelMessage> {
+    match serde_json::from_slice::<TextChannelMessage>(record) {
+        Ok(msg) => Some(msg),
+        Err(err) => {
+            tracing::warn!(%err, message_id = ?id, "failed to decode stored message");
+            None
+        }
+    }
+}
+
+/// Moves messages from the keyspaces used before messages were keyed by ID.
+///
+/// Messages used to be keyed by their timestamp, with a secondary
+/// keyspace mapping IDs to timestamps. The legacy keyspaces are left
+/// empty once their messages have been moved.
+fn migrate_timestamp_keys(
+    db: &fjall::Database,
+    channel_id: ChannelId,
+    messages: &fjall::Keyspace,
+) -> Result<(), fjall::Error> {
+    let legacy = db.keyspace(&channel_id.0.to_string(), keyspace_create_options)?;
+    let legacy_ids = db.keyspace(&format!("{}-ids", channel_id.0), keyspace_create_options)?;
+
+    if legacy.is_empty()? {
+        return Ok(());
+    }
+
+    let mut migrated = 0;
+    let mut legacy_keys = Vec::new();
+    for guard in legacy.iter() {
+        let (key, value) = guard.into_inner()?;
+
+        match serde_json::from_slice::<TextChannelMessage>(&value) {
+            Ok(msg) => {
+                messages.insert(msg.id.0.to_be_bytes(), value)?;
+                migrated += 1;
+            }
+            Err(err) => {
+                tracing::warn!(%err, key = ?key, "dropping undecodable message during migration");
+            }
+        }
+
+        legacy_keys.push(key);
+    }
+
+    // Remove the legacy keys once the scan is done, so the
+    // keyspaces aren't modified while they're being iterated.
+    for key in legacy_keys {
+        legacy.remove(key)?;
+    }
+
+    let legacy_id_keys = legacy_ids
+        .iter()
+        .map(|guard| guard.key())
+        .collect::<Result<Vec<_>, _>>()?;
+    for key in legacy_id_keys {
+        legacy_ids.remove(key)?;
+    }
+
+    tracing::info!(channel_id = ?channel_id, migrated, "migrated messages to ID keys");
+
+    Ok(())
+}
+
+/// Options for creating fjall keyspaces for channels.
+fn keyspace_create_options() -> KeyspaceCreateOptions {
+    KeyspaceCreateOptions::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use snowflaked::Snowflake;
+
+    use super::*;
+    use crate::{
+        server::channel::text::{memory::InMemoryMessageStore, tests::test_message},
+        user::UserId,
+    };
+
+    #[test]
+    fn messages_sent_in_the_same_millisecond_keep_their_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        let store = FjallMessageStore::open(&db, ChannelId(1)).unwrap();
+
+        // Both IDs are generated in the same millisecond, so only their sequence differs.
+        let first = MessageId::from_parts(1_000, 0, 0);
+        let second = MessageId::from_parts(1_000, 0, 1);
+
+        // Storing them out of order doesn't change the order they're returned in.
+        for (id, content) in [(second, "second"), (first, "first")] {
+            let mut msg = test_message(UserId(1), content);
+            msg.id = id;
+            msg.timestamp_ms = 1_000;
+            store.insert(&msg).unwrap();
+        }
+
+        let contents = |messages: Vec<TextChannelMessage>| {
+            messages
+                .into_iter()
+                .map(|msg| msg.content)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            contents(store.messages_after(MessageId(0), 10).unwrap()),
+            ["first", "second"]
+        );
+        assert_eq!(
+            contents(store.messages_before(MessageId(u64::MAX), 10).unwrap()),
+            ["first", "second"]
+        );
+        assert_eq!(
+            contents(store.messages_after(first, 10).unwrap()),
+            ["second"]
+        );
+        assert_eq!(
+            contents(store.messages_before(second, 10).unwrap()),
+            ["first"]
+        );
+    }
+
+    #[test]
+    fn message_count_follows_inserts_and_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        let store = FjallMessageStore::open(&db, ChannelId(1)).unwrap();
+
+        let messages: Vec<_> = (1..=4)
+            .map(|i| {
+                let mut msg = test_message(UserId(1), "hello");
+                msg.id = MessageId(i);
+                msg
+            })
+            .collect();
+        for msg in &messages {
+            store.insert(msg).unwrap();
+        }
+        assert_eq!(store.message_count(), 4);
+
+        // Replacing a message or removing one that isn't stored leaves the count alone.
+        store.insert(&messages[0]).unwrap();
+        store.remove(MessageId(99)).unwrap();
+        assert_eq!(store.message_count(), 4);
+
+        store.remove(MessageId(4)).unwrap();
+        assert_eq!(store.message_count(), 3);
+        assert_eq!(store.remove_before(MessageId(3), 10).unwrap(), 2);
+        assert_eq!(store.message_count(), 1);
+
+        // The count is persisted, so it survives reopening the channel.
+        drop(store);
+        let store = FjallMessageStore::open(&db, ChannelId(1)).unwrap();
+        assert_eq!(store.message_count(), 1);
+    }
+
+    /// Exercises a store, so every backend is held to the same behavior.
+    fn check_store(store: &dyn MessageStore) {
+        for i in 1..=5 {
+            let mut msg = test_message(UserId(i), &format!("message {i}"));
+            msg.id = MessageId(i);
+            store.insert(&msg).unwrap();
+        }
+        assert_eq!(store.message_count(), 5);
+        assert!(store.contains(MessageId(3)).unwrap());
+        assert!(!store.contains(MessageId(6)).unwrap());
+        assert_eq!(store.nth_id(1).unwrap(), Some(MessageId(2)));
+        assert_eq!(store.count_after(MessageId(3), 10).unwrap(), 2);
+        assert_eq!(store.count_after(MessageId(1), 3).unwrap(), 3);
+
+        let ids = |messages: Vec<TextChannelMessage>| {
+            messages.into_iter().map(|msg| msg.id.0).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(store.messages_before(MessageId(4), 2).unwrap()), [2, 3]);
+        assert_eq!(ids(store.messages_after(MessageId(2), 2).unwrap()), [3, 4]);
+
+        let (before, after) = store
+            .update(MessageId(3), &mut |msg| msg.content = "edited".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(before.content, "message 3");
+        assert_eq!(after.content, "edited");
+        assert_eq!(store.get(MessageId(3)).unwrap().unwrap().content, "edited");
+        assert!(store.update(MessageId(6), &mut |_| {}).unwrap().is_none());
+
+        store.pin(MessageId(4)).unwrap();
+        store.pin(MessageId(2)).unwrap();
+        assert!(store.is_pinned(MessageId(4)).unwrap());
+        assert_eq!(store.pin_count().unwrap(), 2);
+        assert_eq!(store.pinned_ids().unwrap(), [MessageId(2), MessageId(4)]);
+        store.unpin(MessageId(2)).unwrap();
+        assert_eq!(store.pinned_ids().unwrap(), [MessageId(4)]);
+
+        store.remove(MessageId(5)).unwrap();
+        assert_eq!(store.remove_before(MessageId(3), 10).unwrap(), 2);
+        assert_eq!(store.message_count(), 2);
+        assert_eq!(ids(store.messages_after(MessageId(0), 10).unwrap()), [3, 4]);
+        assert!(store.get(MessageId(1)).unwrap().is_none());
+
+        store.sync().unwrap();
+    }
+
+    #[test]
+    fn backends_behave_the_same() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        check_store(&FjallMessageStore::open(&db, ChannelId(1)).unwrap());
+
+        check_store(&InMemoryMessageStore::new());
+    }
+}
@@ -1,12 +1,90 @@
 //! Provides voice channel and calling functionality.
 
+use std::collections::BTreeMap;
+
+use parking_lot::RwLock;
+use snowflaked::Snowflake;
 use tokio::sync::broadcast;
 
-use crate::server::channel::ChannelId;
+use crate::{id::now_ms, server::channel::ChannelId, user::UserId};
+
+/// The number of events buffered for the channel's subscribers.
+const EVENT_CAPACITY: usize = 25;
 
 /// An event emitted by a voice channel.
-#[derive(Clone)]
-pub enum VoiceChannelEvent {}
+#[derive(Clone, Debug)]
+pub enum VoiceChannelEvent {
+    /// A user joined the channel.
+    ///
+    /// Only emitted when the user's first device joins.
+    ParticipantJoined { user: UserId, joined_at_ms: u64 },
+    /// A user left the channel.
+    ///
+    /// Only emitted when the user's last device leaves.
+    ParticipantLeft { user: UserId },
+    /// A participant's mute or deafen state changed.
+    StateUpdated { user: UserId, state: VoiceState },
+}
+
+/// An action sent to a voice channel.
+pub enum VoiceChannelAction {}
+
+/// Indiciates there's was an error creating a voice channel.
+#[derive(Debug)]
+pub enum VoiceChannelError {
+    /// Indicates that a blank label was supplied.
+    LabelRequired,
+}
+
+/// Indicates a participant's voice state couldn't be changed.
+#[derive(Debug)]
+pub enum VoiceStateError {
+    /// Indicates the user isn't connected to the channel.
+    NotConnected,
+}
+
+/// Whether a participant is muted or deafened.
+///
+/// Participants can mute and deafen themselves, and moderators can
+/// mute and deafen them on the server. Either mutes the participant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VoiceState {
+    /// The participant muted their microphone.
+    pub self_mute: bool,
+    /// The participant muted the channel's audio.
+    pub self_deaf: bool,
+    /// A moderator muted the participant.
+    pub server_mute: bool,
+    /// A moderator deafened the participant.
+    pub server_deaf: bool,
+}
+
+impl VoiceState {
+    /// Returns true if the participant can't be heard.
+    pub fn is_muted(&self) -> bool {
+        self.self_mute || self.server_mute
+    }
+
+    /// Returns true if the participant can't hear the channel.
+    pub fn is_deafened(&self) -> bool {
+        self.self_deaf || self.server_deaf
+    }
+}
+
+/// A user connected to a voice channel.
+#[derive(Clone, Debug)]
+pub struct VoiceParticipant {
+    /// The connected user.
+    pub user: UserId,
+    /// When the user's first device joined, in milliseconds.
+    pub joined_at_ms: u64,
+    /// The number of the user's devices connected to the channel.
+    pub devices: usize,
+    /// Whether the user is muted or deafened.
+    ///
+    /// Shared by all of the user's devices, and reset when they leave.
+    pub state: VoiceState,
+}
 
 /// Provides a voice channel used for voice discussion between users.
 pub struct VoiceChannel {
@@ -15,6 +93,15 @@ pub struct VoiceChannel {
 
     label: String,
 
+    /// Epoch that the channel's snowflake ID timestamps count from.
+    snowflake_epoch_ms: u64,
+
+    /// The users connected to the channel.
+    participants: RwLock<BTreeMap<UserId, VoiceParticipant>>,
+
+    /// Sender for events emitted by the channel.
+    event_sender: broadcast::Sender<VoiceChannelEvent>,
+
     /// Receiver for events emitted by the channel.
     ///
     /// This is typically cloned by a transport (i.e. an HTTP WebSocket
@@ -24,21 +111,159 @@ pub struct VoiceChannel {
 
 impl VoiceChannel {
     /// Constructs a voice channel.
-    pub fn new(id: ChannelId, label: String) -> Self {
-        let (event_sender, event_receiver) = broadcast::channel(25);
+    pub fn new(
+        id: ChannelId,
+        label: String,
+        snowflake_epoch_ms: u64,
+    ) -> Result<Self, VoiceChannelError> {
+        if label.is_empty() {
+            return Err(VoiceChannelError::LabelRequired);
+        }
+
+        let (event_sender, event_receiver) = broadcast::channel(EVENT_CAPACITY);
 
         // TODO: use rustrtc for voice comms
 
-        Self {
+        Ok(Self {
             id,
             label,
+            snowflake_epoch_ms,
+            participants: RwLock::new(BTreeMap::new()),
+            event_sender,
             event_receiver,
+        })
+    }
+
+    /// Returns when the channel was created, in milliseconds since the Unix epoch.
+    ///
+    /// Decoded from the timestamp embedded in the channel's ID,
+    /// which counts from the configured snowflake epoch.
+    pub fn created_at_ms(&self) -> u64 {
+        self.id.timestamp() + self.snowflake_epoch_ms
+    }
+
+    /// Adds one of the user's devices to the channel.
+    ///
+    /// Users can join from multiple devices at once, but they're listed
+    /// once in the roster, and subscribers are only informed when the
+    /// user's first device joins.
+    pub fn join(&self, user: UserId) {
+        let mut participants = self.participants.write();
+
+        if let Some(participant) = participants.get_mut(&user) {
+            participant.devices += 1;
+            return;
+        }
+
+        let joined_at_ms = now_ms();
+        participants.insert(
+            user,
+            VoiceParticipant {
+                user,
+                joined_at_ms,
+                devices: 1,
+                state: VoiceState::default(),
+            },
+        );
+
+        // No subscribers is not an error.
+        let _ = self
+            .event_sender
+            .send(VoiceChannelEvent::ParticipantJoined { user, joined_at_ms });
+    }
+
+    /// Removes one of the user's devices from the channel.
+    ///
+    /// The user is removed from the roster once their last device leaves.
+    pub fn leave(&self, user: UserId) {
+        let mut participants = self.participants.write();
+
+        let Some(participant) = participants.get_mut(&user) else {
+            return;
+        };
+
+        participant.devices -= 1;
+        if participant.devices > 0 {
+            return;
+        }
+
+        participants.remove(&user);
+
+        // No subscribers is not an error.
+        let _ = self
+            .event_sender
+            .send(VoiceChannelEvent::ParticipantLeft { user });
+    }
+
+    /// Sets whether the participant muted or deafened themselves.
+    pub fn set_self_state(
+        &self,
+        user: UserId,
+        self_mute: bool,
+        self_deaf: bool,
+    ) -> Result<VoiceState, VoiceStateError> {
+        self.update_state(user, |state| {
+            state.self_mute = self_mute;
+            state.self_deaf = self_deaf;
+        })
+    }
+
+    /// Sets whether the participant is muted or deafened by a moderator.
+    ///
+    /// Callers must check the moderator holds [`Permissions::MUTE_MEMBERS`].
+    ///
+    /// [`Permissions::MUTE_MEMBERS`]: crate::server::permission::Permissions::MUTE_MEMBERS
+    pub fn set_server_state(
+        &self,
+        user: UserId,
+        server_mute: bool,
+        server_deaf: bool,
+    ) -> Result<VoiceState, VoiceStateError> {
+        self.update_state(user, |state| {
+            state.server_mute = server_mute;
+            state.server_deaf = server_deaf;
+        })
+    }
+
+    /// Updates a participant's voice state, informing
+    /// subscribers if it changed.
+    fn update_state(
+        &self,
+        user: UserId,
+        update: impl FnOnce(&mut VoiceState),
+    ) -> Result<VoiceState, VoiceStateError> {
+        let mut participants = self.participants.write();
+
+        let participant = participants
+            .get_mut(&user)
+            .ok_or(VoiceStateError::NotConnected)?;
+
+        let previous = participant.state;
+        update(&mut participant.state);
+        let state = participant.state;
+
+        if state != previous {
+            // No subscribers is not an error.
+            let _ = self
+                .event_sender
+                .send(VoiceChannelEvent::StateUpdated { user, state });
         }
+
+        Ok(state)
+    }
+
+    /// Returns the users connected to the channel, ordered by when they joined.
+    pub fn participants(&self) -> Vec<VoiceParticipant> {
+        let mut participants: Vec<_> = self.participants.read().values().cloned().collect();
+        participants.sort_by_key(|participant| (participant.joined_at_ms, participant.user));
+
+        participants
     }
 }
 
 impl super::Channel for VoiceChannel {
     type Event = VoiceChannelEvent;
+    type Action = VoiceChannelAction;
 
     fn channel_id(&self) -> super::ChannelId {
         self.id
@@ -55,4 +280,9 @@ impl super::Channel for VoiceChannel {
     fn subscribe(&self) -> broadcast::Receiver<Self::Event> {
         self.event_receiver.resubscribe()
     }
+
+    async fn send_action(&self, action: Self::Action) -> Result<(), super::ChannelClosed> {
+        // Voice channels don't have any actions yet.
+        match action {}
+    }
 }
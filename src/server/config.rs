@@ -0,0 +1,809 @@
+//! Configuration for the application server.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    server::{
+        auth,
+        channel::text::{automod::AutoModConfig, search::SearchTokenizer},
+        gateway::SessionLimitPolicy,
+    },
+    user::UserId,
+};
+
+/// The highest instance ID supported by the snowflake generators.
+///
+/// Snowflake IDs reserve 10 bits for the instance component.
+pub const MAX_INSTANCE_ID: u16 = (1 << 10) - 1;
+
+/// Default memory budget for each channel's search index writer.
+pub const DEFAULT_INDEX_WRITER_HEAP_BYTES: usize = 50_000_000; // 50MB
+
+/// Default interval that gateway clients are asked to send heartbeats at.
+pub const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 30_000; // 30 seconds
+
+/// Default maximum size of a message sent to the gateway.
+pub const DEFAULT_MAX_GATEWAY_MESSAGE_BYTES: usize = 1 << 20; // 1MiB
+
+/// Default maximum number of events retained for resuming each gateway session.
+pub const DEFAULT_RESUME_BUFFER_EVENTS: usize = 256;
+
+/// Default maximum size of the events retained for resuming each gateway session.
+pub const DEFAULT_RESUME_BUFFER_BYTES: usize = 256 << 10; // 256KiB
+
+/// Default duration that a disconnected gateway session can be resumed for.
+pub const DEFAULT_RESUME_WINDOW_SECS: u64 = 60;
+
+/// Default maximum number of messages a user can post per rate limit interval.
+pub const DEFAULT_MESSAGE_RATE_LIMIT: u32 = 5;
+
+/// Default duration of the per-user message rate limit interval.
+pub const DEFAULT_MESSAGE_RATE_INTERVAL_MS: u64 = 5_000; // 5 seconds
+
+/// Default maximum length of a message's content in graphemes.
+pub const DEFAULT_MAX_MESSAGE_GRAPHEMES: usize = 4000;
+
+/// Default maximum size of a message's content in bytes.
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 << 10; // 16KiB
+
+/// Default number of actions that can be queued for each channel's worker.
+pub const DEFAULT_CHANNEL_QUEUE_CAPACITY: usize = 25;
+
+/// Default number of events buffered for each channel's subscribers.
+pub const DEFAULT_CHANNEL_EVENT_CAPACITY: usize = 25;
+
+/// Default number of events buffered for each gateway session.
+pub const DEFAULT_SESSION_EVENT_CAPACITY: usize = 10;
+
+/// Default time gateway clients have to identify after connecting.
+pub const DEFAULT_IDENTIFY_TIMEOUT_MS: u64 = 10_000; // 10 seconds
+
+/// Default epoch of snowflake ID timestamps, in milliseconds since the Unix epoch.
+///
+/// IDs generated before the epoch was configurable count from the Unix epoch.
+pub const DEFAULT_SNOWFLAKE_EPOCH_MS: u64 = 0;
+
+/// Default maximum number of concurrent gateway sessions for each user.
+pub const DEFAULT_MAX_SESSIONS_PER_USER: usize = 16;
+
+/// Default maximum number of concurrent gateway sessions across the server.
+pub const DEFAULT_MAX_SESSIONS: usize = 10_000;
+
+/// Default maximum number of direct channels each user can open.
+pub const DEFAULT_MAX_DIRECT_CHANNELS_PER_USER: usize = 100;
+
+/// Default maximum number of direct channels across the server.
+pub const DEFAULT_MAX_DIRECT_CHANNELS: usize = 10_000;
+
+/// Default maximum number of pinned messages per text channel.
+pub const DEFAULT_MAX_PINS_PER_CHANNEL: usize = 50;
+
+/// The minimum memory budget that Tantivy accepts for an index writer.
+pub const MIN_INDEX_WRITER_HEAP_BYTES: usize = 15_000_000; // 15MB
+
+/// Config for the application server.
+///
+/// Prefer constructing the config with [`Config::builder`],
+/// which applies defaults and validates the supplied values.
+#[derive(Clone)]
+pub struct Config {
+    /// Root directory for storing server data.
+    pub data_dir: PathBuf,
+
+    /// Memory budget in bytes for each channel's search index writer.
+    pub index_writer_heap_bytes: usize,
+
+    /// Tokenizer used for the channels' search indexes.
+    ///
+    /// Changing this doesn't update existing indexes, so channels have
+    /// to be reindexed before searches match reliably again.
+    pub search_tokenizer: SearchTokenizer,
+
+    /// Instance ID embedded in the generated snowflake IDs.
+    ///
+    /// Must be unique for each server sharing an ID space.
+    pub instance_id: u16,
+
+    /// Epoch that snowflake ID timestamps count from, in milliseconds since the Unix epoch.
+    ///
+    /// The time IDs were created at is decoded using this epoch, so changing
+    /// it on an existing deployment breaks decoding the times of existing IDs,
+    /// and can make new IDs collide with or sort before existing ones.
+    pub snowflake_epoch_ms: u64,
+
+    /// Address that the HTTP server listens on.
+    pub bind_addr: SocketAddr,
+
+    /// Public URL that users reach the server at, such as `https://chat.example.com`.
+    ///
+    /// Used to build the OAuth2 callback URLs when the server is
+    /// behind a reverse proxy. Takes precedence over forwarded hosts.
+    pub public_base_url: Option<String>,
+
+    /// Hosts accepted from the `X-Forwarded-Host` and `Forwarded` headers.
+    ///
+    /// Forwarded hosts that aren't listed are rejected, so a client
+    /// can't have the callback URLs point at a host it controls.
+    pub trusted_forwarded_hosts: Vec<String>,
+
+    /// Cross-origin requests allowed to the HTTP API.
+    pub cors: CorsConfig,
+
+    /// Rejects gateway connections from browsers on other origins.
+    ///
+    /// Browsers on the server's own origin, or one of the origins allowed
+    /// by [`CorsConfig`], can connect. Clients that don't send an `Origin`
+    /// header, or authenticate the upgrade with a bot token, aren't
+    /// browsers and skip the check.
+    pub check_gateway_origin: bool,
+
+    /// Level that HTTP access log events are emitted at.
+    pub access_log_level: tracing::Level,
+
+    /// Interval in milliseconds that gateway clients should send heartbeats at.
+    pub heartbeat_interval_ms: u64,
+
+    /// Time in milliseconds that gateway clients have to identify after connecting.
+    ///
+    /// Also limits how long sending the handshake to the client can take.
+    pub identify_timeout_ms: u64,
+
+    /// The maximum size in bytes of a message sent to the gateway.
+    pub max_gateway_message_bytes: usize,
+
+    /// The maximum number of events retained for resuming each gateway session.
+    pub resume_buffer_events: usize,
+
+    /// The maximum total size in bytes of the events retained for resuming each gateway session.
+    pub resume_buffer_bytes: usize,
+
+    /// How long in seconds a disconnected gateway session can be resumed for.
+    pub resume_window_secs: u64,
+
+    /// Users that implicitly hold every permission.
+    pub admin_users: Vec<UserId>,
+
+    /// The maximum number of pinned messages per text channel.
+    pub max_pins_per_channel: usize,
+
+    /// Allows messages to reply to messages that don't exist in the channel.
+    pub allow_dangling_replies: bool,
+
+    /// Maximum number of messages a user can post to a channel per rate limit interval.
+    pub message_rate_limit: u32,
+
+    /// Duration in milliseconds of the per-user message rate limit interval.
+    pub message_rate_interval_ms: u64,
+
+    /// Number of actions that can be queued for each channel's worker.
+    ///
+    /// New messages are rejected as busy once the queue is full.
+    pub channel_queue_capacity: usize,
+
+    /// The maximum length of a message's content in graphemes.
+    pub max_message_graphemes: usize,
+
+    /// The maximum size of a message's content in bytes.
+    ///
+    /// Bounds the memory used to store and index a single message,
+    /// since a few graphemes can be made up of many code points.
+    pub max_message_bytes: usize,
+
+    /// The maximum number of text and voice channels on the server.
+    ///
+    /// Bounds the resources used by channels, since each has it's own
+    /// worker, keyspaces, and search index. Unset for no limit.
+    pub max_channels: Option<usize>,
+
+    /// The maximum number of direct channels each user can open.
+    ///
+    /// Direct channels have the same resources as text channels,
+    /// but are opened by any user, so they're limited separately
+    /// from [`Config::max_channels`].
+    pub max_direct_channels_per_user: usize,
+
+    /// The maximum number of direct channels across the server.
+    pub max_direct_channels: usize,
+
+    /// Rules new messages are checked against, to flag or reject spam.
+    ///
+    /// Auto-moderation is disabled while this is unset.
+    pub auto_moderation: Option<AutoModConfig>,
+
+    /// Number of events buffered for each channel's subscribers.
+    ///
+    /// Subscribers that fall further behind than this skip the missed
+    /// events. Larger buffers tolerate slower consumers in busy channels
+    /// at the cost of memory for every channel.
+    pub channel_event_capacity: usize,
+
+    /// Number of events buffered for each gateway session.
+    ///
+    /// Sessions whose clients fall further behind than this skip the
+    /// missed events. Larger buffers tolerate slower clients at the
+    /// cost of memory for every connected session.
+    pub session_event_capacity: usize,
+
+    /// Maximum number of concurrent gateway sessions for each user.
+    pub max_sessions_per_user: usize,
+
+    /// Maximum number of concurrent gateway sessions across the server.
+    pub max_sessions: usize,
+
+    /// Whether new sessions over a limit are refused or replace the oldest session.
+    pub session_limit_policy: SessionLimitPolicy,
+
+    pub auth: auth::AuthConfig,
+}
+
+impl Config {
+    /// Returns a builder for constructing a validated server config.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// Configures the cross-origin requests browsers allow to the HTTP API.
+///
+/// Web clients hosted on another origin can only call the API when
+/// their origin is allowed. With no allowed origins, browsers only
+/// allow same-origin requests.
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    /// Origins allowed to make requests, such as `https://app.example.com`.
+    pub allowed_origins: Vec<String>,
+    /// HTTP methods allowed in cross-origin requests.
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests.
+    pub allowed_headers: Vec<String>,
+    /// Allows cross-origin requests to include credentials, such as the token cookie.
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: ["authorization", "content-type", "accept"]
+                .map(String::from)
+                .to_vec(),
+            allow_credentials: true,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Checks that the origins, methods, and headers are valid.
+    fn validate(&self) -> Result<(), ConfigError> {
+        for origin in &self.allowed_origins {
+            // Origins are a scheme and host with an optional port, nothing else.
+            let valid = oauth2::url::Url::parse(origin).is_ok_and(|parsed| {
+                matches!(parsed.scheme(), "http" | "https")
+                    && parsed.origin().ascii_serialization() == *origin
+            });
+
+            if !valid {
+                return Err(ConfigError::InvalidCorsOrigin(origin.clone()));
+            }
+        }
+
+        for method in &self.allowed_methods {
+            if axum::http::Method::from_bytes(method.as_bytes()).is_err() {
+                return Err(ConfigError::InvalidCorsMethod(method.clone()));
+            }
+        }
+
+        for header in &self.allowed_headers {
+            if axum::http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return Err(ConfigError::InvalidCorsHeader(header.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Indicates that the server config failed validation.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Indicates that the data directory could not be created or isn't writable.
+    DataDirNotWritable(PathBuf, std::io::Error),
+    /// Indicates that the instance ID doesn't fit in the snowflake instance bits.
+    InstanceIdOutOfRange(u16),
+    /// Indicates that the snowflake epoch is in the future.
+    SnowflakeEpochInFuture(u64),
+    /// Indicates the index writer heap is smaller than Tantivy supports.
+    IndexWriterHeapTooSmall(usize),
+    /// Indicates a CORS origin isn't a valid HTTP or HTTPS origin.
+    InvalidCorsOrigin(String),
+    /// Indicates a CORS method isn't a valid HTTP method.
+    InvalidCorsMethod(String),
+    /// Indicates a CORS header isn't a valid header name.
+    InvalidCorsHeader(String),
+    /// Indicates the token cookie is sent cross-site without being secure,
+    /// which browsers reject.
+    InsecureCrossSiteCookie,
+    /// Indicates the public base URL isn't an absolute HTTP or HTTPS URL.
+    InvalidPublicBaseUrl(String),
+    /// Indicates the n-gram sizes of the search tokenizer are zero or out of order.
+    InvalidNgramRange(usize, usize),
+    /// Indicates the channel queue capacity is zero.
+    ChannelQueueCapacityZero,
+    /// Indicates the channel or session event capacity is zero.
+    EventCapacityZero,
+    /// Indicates a gateway session limit is zero.
+    SessionLimitZero,
+    /// Indicates the auto-moderation capitals ratio isn't between 0 and 1.
+    InvalidCapsRatio(f32),
+}
+
+/// Fluent builder for constructing a server [`Config`].
+pub struct ConfigBuilder {
+    data_dir: PathBuf,
+    index_writer_heap_bytes: usize,
+    search_tokenizer: SearchTokenizer,
+    instance_id: u16,
+    snowflake_epoch_ms: u64,
+    bind_addr: SocketAddr,
+    public_base_url: Option<String>,
+    trusted_forwarded_hosts: Vec<String>,
+    cors: CorsConfig,
+    check_gateway_origin: bool,
+    access_log_level: tracing::Level,
+    heartbeat_interval_ms: u64,
+    identify_timeout_ms: u64,
+    max_gateway_message_bytes: usize,
+    resume_buffer_events: usize,
+    resume_buffer_bytes: usize,
+    resume_window_secs: u64,
+    admin_users: Vec<UserId>,
+    max_pins_per_channel: usize,
+    allow_dangling_replies: bool,
+    message_rate_limit: u32,
+    message_rate_interval_ms: u64,
+    channel_queue_capacity: usize,
+    max_message_graphemes: usize,
+    max_message_bytes: usize,
+    max_channels: Option<usize>,
+    max_direct_channels_per_user: usize,
+    max_direct_channels: usize,
+    auto_moderation: Option<AutoModConfig>,
+    channel_event_capacity: usize,
+    session_event_capacity: usize,
+    max_sessions_per_user: usize,
+    max_sessions: usize,
+    session_limit_policy: SessionLimitPolicy,
+    auth: auth::AuthConfig,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self {
+            data_dir: "data/".into(),
+            index_writer_heap_bytes: DEFAULT_INDEX_WRITER_HEAP_BYTES,
+            search_tokenizer: SearchTokenizer::default(),
+            instance_id: 0,
+            snowflake_epoch_ms: DEFAULT_SNOWFLAKE_EPOCH_MS,
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            public_base_url: None,
+            trusted_forwarded_hosts: vec![],
+            cors: CorsConfig::default(),
+            check_gateway_origin: true,
+            access_log_level: tracing::Level::INFO,
+            heartbeat_interval_ms: DEFAULT_HEARTBEAT_INTERVAL_MS,
+            identify_timeout_ms: DEFAULT_IDENTIFY_TIMEOUT_MS,
+            max_gateway_message_bytes: DEFAULT_MAX_GATEWAY_MESSAGE_BYTES,
+            resume_buffer_events: DEFAULT_RESUME_BUFFER_EVENTS,
+            resume_buffer_bytes: DEFAULT_RESUME_BUFFER_BYTES,
+            resume_window_secs: DEFAULT_RESUME_WINDOW_SECS,
+            admin_users: vec![],
+            max_pins_per_channel: DEFAULT_MAX_PINS_PER_CHANNEL,
+            allow_dangling_replies: false,
+            message_rate_limit: DEFAULT_MESSAGE_RATE_LIMIT,
+            message_rate_interval_ms: DEFAULT_MESSAGE_RATE_INTERVAL_MS,
+            channel_queue_capacity: DEFAULT_CHANNEL_QUEUE_CAPACITY,
+            max_message_graphemes: DEFAULT_MAX_MESSAGE_GRAPHEMES,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            max_channels: None,
+            max_direct_channels_per_user: DEFAULT_MAX_DIRECT_CHANNELS_PER_USER,
+            max_direct_channels: DEFAULT_MAX_DIRECT_CHANNELS,
+            auto_moderation: None,
+            channel_event_capacity: DEFAULT_CHANNEL_EVENT_CAPACITY,
+            session_event_capacity: DEFAULT_SESSION_EVENT_CAPACITY,
+            max_sessions_per_user: DEFAULT_MAX_SESSIONS_PER_USER,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            session_limit_policy: SessionLimitPolicy::default(),
+            auth: auth::AuthConfig {
+                oauth2_clients: vec![],
+                cookie: auth::TokenCookieConfig::default(),
+            },
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// Sets the root directory for storing server data.
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    /// Sets the memory budget for each channel's search index writer.
+    pub fn index_writer_heap_bytes(mut self, bytes: usize) -> Self {
+        self.index_writer_heap_bytes = bytes;
+        self
+    }
+
+    /// Sets the tokenizer used for the channels' search indexes.
+    ///
+    /// Existing channels have to be reindexed after changing the tokenizer.
+    pub fn search_tokenizer(mut self, tokenizer: SearchTokenizer) -> Self {
+        self.search_tokenizer = tokenizer;
+        self
+    }
+
+    /// Sets the instance ID embedded in generated snowflake IDs.
+    pub fn instance_id(mut self, instance_id: u16) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    /// Sets the epoch that snowflake ID timestamps count from, in milliseconds since the Unix epoch.
+    ///
+    /// Changing the epoch on an existing deployment breaks decoding the times of existing IDs.
+    pub fn snowflake_epoch_ms(mut self, epoch_ms: u64) -> Self {
+        self.snowflake_epoch_ms = epoch_ms;
+        self
+    }
+
+    /// Sets the address that the HTTP server listens on.
+    pub fn bind_addr(mut self, bind_addr: SocketAddr) -> Self {
+        self.bind_addr = bind_addr;
+        self
+    }
+
+    /// Sets the public URL that users reach the server at.
+    pub fn public_base_url(mut self, url: impl Into<String>) -> Self {
+        self.public_base_url = Some(url.into());
+        self
+    }
+
+    /// Trusts a host supplied by a reverse proxy in the forwarded headers.
+    pub fn trusted_forwarded_host(mut self, host: impl Into<String>) -> Self {
+        self.trusted_forwarded_hosts.push(host.into());
+        self
+    }
+
+    /// Sets the cross-origin requests allowed to the HTTP API.
+    pub fn cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Allows cross-origin requests to the HTTP API from the origin.
+    pub fn cors_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.cors.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Sets whether gateway connections from browsers on other origins are rejected.
+    pub fn check_gateway_origin(mut self, check: bool) -> Self {
+        self.check_gateway_origin = check;
+        self
+    }
+
+    /// Sets the level that HTTP access log events are emitted at.
+    pub fn access_log_level(mut self, level: tracing::Level) -> Self {
+        self.access_log_level = level;
+        self
+    }
+
+    /// Sets the interval in milliseconds that gateway clients should send heartbeats at.
+    pub fn heartbeat_interval_ms(mut self, interval_ms: u64) -> Self {
+        self.heartbeat_interval_ms = interval_ms;
+        self
+    }
+
+    /// Sets the time in milliseconds that gateway clients have to identify after connecting.
+    pub fn identify_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.identify_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Sets the limits on the events retained for resuming each gateway session.
+    pub fn resume_buffer(mut self, max_events: usize, max_bytes: usize) -> Self {
+        self.resume_buffer_events = max_events;
+        self.resume_buffer_bytes = max_bytes;
+        self
+    }
+
+    /// Sets how long in seconds a disconnected gateway session can be resumed for.
+    pub fn resume_window_secs(mut self, secs: u64) -> Self {
+        self.resume_window_secs = secs;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a message sent to the gateway.
+    pub fn max_gateway_message_bytes(mut self, bytes: usize) -> Self {
+        self.max_gateway_message_bytes = bytes;
+        self
+    }
+
+    /// Adds a user that implicitly holds every permission.
+    pub fn admin_user(mut self, user: UserId) -> Self {
+        self.admin_users.push(user);
+        self
+    }
+
+    /// Sets the maximum number of pinned messages per text channel.
+    pub fn max_pins_per_channel(mut self, max: usize) -> Self {
+        self.max_pins_per_channel = max;
+        self
+    }
+
+    /// Sets whether messages may reply to messages that don't exist in the channel.
+    pub fn allow_dangling_replies(mut self, allow: bool) -> Self {
+        self.allow_dangling_replies = allow;
+        self
+    }
+
+    /// Sets how many messages a user can post to a channel per interval in milliseconds.
+    pub fn message_rate_limit(mut self, limit: u32, interval_ms: u64) -> Self {
+        self.message_rate_limit = limit;
+        self.message_rate_interval_ms = interval_ms;
+        self
+    }
+
+    /// Sets the number of actions that can be queued for each channel's worker.
+    pub fn channel_queue_capacity(mut self, capacity: usize) -> Self {
+        self.channel_queue_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum length of a message's content in graphemes.
+    pub fn max_message_graphemes(mut self, max: usize) -> Self {
+        self.max_message_graphemes = max;
+        self
+    }
+
+    /// Sets the maximum size of a message's content in bytes.
+    pub fn max_message_bytes(mut self, max: usize) -> Self {
+        self.max_message_bytes = max;
+        self
+    }
+
+    /// Sets the maximum number of text and voice channels on the server.
+    pub fn max_channels(mut self, max: usize) -> Self {
+        self.max_channels = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of direct channels per user and across the server.
+    pub fn direct_channel_limits(mut self, per_user: usize, total: usize) -> Self {
+        self.max_direct_channels_per_user = per_user;
+        self.max_direct_channels = total;
+        self
+    }
+
+    /// Enables auto-moderation of new messages with the rules.
+    pub fn auto_moderation(mut self, config: AutoModConfig) -> Self {
+        self.auto_moderation = Some(config);
+        self
+    }
+
+    /// Sets the number of events buffered for each channel's subscribers.
+    pub fn channel_event_capacity(mut self, capacity: usize) -> Self {
+        self.channel_event_capacity = capacity;
+        self
+    }
+
+    /// Sets the number of events buffered for each gateway session.
+    pub fn session_event_capacity(mut self, capacity: usize) -> Self {
+        self.session_event_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum number of concurrent gateway sessions per user and across the server.
+    pub fn session_limits(mut self, per_user: usize, total: usize) -> Self {
+        self.max_sessions_per_user = per_user;
+        self.max_sessions = total;
+        self
+    }
+
+    /// Sets what happens when a new gateway session would exceed a session limit.
+    pub fn session_limit_policy(mut self, policy: SessionLimitPolicy) -> Self {
+        self.session_limit_policy = policy;
+        self
+    }
+
+    /// Sets the user authentication config.
+    pub fn auth(mut self, auth: auth::AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Sets the attributes of the cookie the token is stored in after a web login.
+    pub fn token_cookie(mut self, cookie: auth::TokenCookieConfig) -> Self {
+        self.auth.cookie = cookie;
+        self
+    }
+
+    /// Adds an OAuth2 client that users can authenticate with.
+    pub fn oauth2_client(mut self, client: auth::OauthClient) -> Self {
+        self.auth.oauth2_clients.push(client);
+        self
+    }
+
+    /// Validates the supplied values and builds the config.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        if self.instance_id > MAX_INSTANCE_ID {
+            return Err(ConfigError::InstanceIdOutOfRange(self.instance_id));
+        }
+
+        if self.snowflake_epoch_ms > crate::id::now_ms() {
+            return Err(ConfigError::SnowflakeEpochInFuture(self.snowflake_epoch_ms));
+        }
+
+        if self.index_writer_heap_bytes < MIN_INDEX_WRITER_HEAP_BYTES {
+            return Err(ConfigError::IndexWriterHeapTooSmall(
+                self.index_writer_heap_bytes,
+            ));
+        }
+
+        match self.search_tokenizer {
+            SearchTokenizer::Ngram { min_gram, max_gram }
+                if min_gram == 0 || min_gram > max_gram =>
+            {
+                return Err(ConfigError::InvalidNgramRange(min_gram, max_gram));
+            }
+            _ => {}
+        }
+
+        if let Some(url) = &self.public_base_url {
+            let valid = oauth2::url::Url::parse(url).is_ok_and(|parsed| {
+                matches!(parsed.scheme(), "http" | "https") && parsed.has_host()
+            });
+
+            if !valid {
+                return Err(ConfigError::InvalidPublicBaseUrl(url.clone()));
+            }
+        }
+
+        self.cors.validate()?;
+
+        if self.auth.cookie.same_site == auth::CookieSameSite::None && !self.auth.cookie.secure {
+            return Err(ConfigError::InsecureCrossSiteCookie);
+        }
+
+        if self.channel_queue_capacity == 0 {
+            return Err(ConfigError::ChannelQueueCapacityZero);
+        }
+
+        if self.channel_event_capacity == 0 || self.session_event_capacity == 0 {
+            return Err(ConfigError::EventCapacityZero);
+        }
+
+        if self.max_sessions_per_user == 0 || self.max_sessions == 0 {
+            return Err(ConfigError::SessionLimitZero);
+        }
+
+        if let Some(ratio) = self
+            .auto_moderation
+            .as_ref()
+            .and_then(|config| config.max_caps_ratio)
+            .filter(|ratio| !(0.0..=1.0).contains(ratio))
+        {
+            return Err(ConfigError::InvalidCapsRatio(ratio));
+        }
+
+        check_dir_writable(&self.data_dir)
+            .map_err(|e| ConfigError::DataDirNotWritable(self.data_dir.clone(), e))?;
+
+        Ok(Config {
+            data_dir: self.data_dir,
+            index_writer_heap_bytes: self.index_writer_heap_bytes,
+            search_tokenizer: self.search_tokenizer,
+            instance_id: self.instance_id,
+            snowflake_epoch_ms: self.snowflake_epoch_ms,
+            bind_addr: self.bind_addr,
+            // Paths are appended to the base URL, so drop any trailing slash.
+            public_base_url: self
+                .public_base_url
+                .map(|url| url.trim_end_matches('/').to_string()),
+            trusted_forwarded_hosts: self.trusted_forwarded_hosts,
+            cors: self.cors,
+            check_gateway_origin: self.check_gateway_origin,
+            access_log_level: self.access_log_level,
+            heartbeat_interval_ms: self.heartbeat_interval_ms,
+            identify_timeout_ms: self.identify_timeout_ms,
+            max_gateway_message_bytes: self.max_gateway_message_bytes,
+            resume_buffer_events: self.resume_buffer_events,
+            resume_buffer_bytes: self.resume_buffer_bytes,
+            resume_window_secs: self.resume_window_secs,
+            admin_users: self.admin_users,
+            max_pins_per_channel: self.max_pins_per_channel,
+            allow_dangling_replies: self.allow_dangling_replies,
+            message_rate_limit: self.message_rate_limit,
+            message_rate_interval_ms: self.message_rate_interval_ms,
+            channel_queue_capacity: self.channel_queue_capacity,
+            max_message_graphemes: self.max_message_graphemes,
+            max_message_bytes: self.max_message_bytes,
+            max_channels: self.max_channels,
+            max_direct_channels_per_user: self.max_direct_channels_per_user,
+            max_direct_channels: self.max_direct_channels,
+            auto_moderation: self.auto_moderation,
+            channel_event_capacity: self.channel_event_capacity,
+            session_event_capacity: self.session_event_capacity,
+            max_sessions_per_user: self.max_sessions_per_user,
+            max_sessions: self.max_sessions,
+            session_limit_policy: self.session_limit_policy,
+            auth: self.auth,
+        })
+    }
+}
+
+/// Creates the directory if required and checks that files can be written to it.
+pub(crate) fn check_dir_writable(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+
+    // Write and remove a probe file, as directory permission
+    // bits alone don't account for read-only mounts.
+    let probe = dir.join(".write-probe");
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_data_dirs_are_rejected() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Root ignores the permission bits, so there's nothing to check.
+        if std::fs::write(dir.path().join("probe"), b"").is_ok() {
+            return;
+        }
+
+        let result = ConfigBuilder::default().data_dir(dir.path()).build();
+        assert!(matches!(
+            result,
+            Err(ConfigError::DataDirNotWritable(path, _)) if path == dir.path()
+        ));
+
+        // Missing subdirectories can't be created either.
+        let result = ConfigBuilder::default()
+            .data_dir(dir.path().join("data"))
+            .build();
+        assert!(matches!(result, Err(ConfigError::DataDirNotWritable(..))));
+
+        // Let the temporary directory be removed.
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn snowflake_epochs_in_the_future_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let future_ms = crate::id::now_ms() + 60 * 60 * 1000;
+
+        let result = ConfigBuilder::default()
+            .data_dir(dir.path())
+            .snowflake_epoch_ms(future_ms)
+            .build();
+        assert!(matches!(
+            result,
+            Err(ConfigError::SnowflakeEpochInFuture(epoch)) if epoch == future_ms
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+//! Mirroring of channels from other bonfire servers.
+//!
+//! A [`FederationLink`] connects to a remote server's gateway with the
+//! typed [`client`](crate::client), and re-broadcasts the messages of one
+//! of it's channels into a local channel. Mirroring is one-directional,
+//! messages posted to the local channel aren't sent to the remote server.
+//!
+//! # Trust model
+//!
+//! The link authenticates to the remote server with a bot token issued
+//! by that server, so the remote server decides what the link can read,
+//! and can revoke the token to cut the link off.
+//!
+//! The local server trusts the remote server for the content of the
+//! mirrored messages, but not for identities. Remote user IDs belong to
+//! the remote server and could collide with local ones, so mirrored
+//! messages are attributed to a local user chosen for the link, which
+//! should be a bot user without any permissions on the local server.
+//!
+//! Local users shouldn't be able to post to the mirrored channel, so it
+//! should only be writable by the link's user.
+//!
+//! # Limitations
+//!
+//! Only new messages are mirrored. Edits and deletions on the remote
+//! server aren't, and replies are mirrored as plain messages, since
+//! the remote message IDs don't exist in the local channel.
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+
+use crate::{
+    channel::ChannelId,
+    client::{ClientError, ClientOptions, GatewayClient},
+    message::{MessageFlags, MessageId, MessageMentions},
+    proto::v0::{self, gateway_server_event},
+    server::channel::{
+        Channel,
+        text::{TextChannel, TextChannelAction, TextChannelMessage},
+    },
+    user::UserId,
+};
+
+/// Options for mirroring a remote channel into a local channel.
+#[derive(Clone, Debug)]
+pub struct FederationOptions {
+    /// Options used to connect to the remote server's gateway.
+    pub remote: ClientOptions,
+    /// The channel on the remote server to mirror.
+    pub remote_channel: ChannelId,
+    /// The local user that mirrored messages are attributed to.
+    pub local_author: UserId,
+}
+
+/// A one-directional link mirroring a remote channel into a local channel.
+pub struct FederationLink {
+    client: GatewayClient,
+    remote_channel: ChannelId,
+    local_author: UserId,
+}
+
+impl FederationLink {
+    /// Connects to the remote server's gateway.
+    pub async fn connect(options: FederationOptions) -> Result<Self, ClientError> {
+        let client = GatewayClient::connect(options.remote).await?;
+
+        // Ask the remote gateway to send the mirrored channel's messages.
+        client.send(v0::GatewayClientEvent {
+            event: Some(v0::gateway_client_event::Event::WatchChannel(
+                v0::WatchChannel {
+                    channel_id: options.remote_channel.0,
+                    watch: true,
+                },
+            )),
+        })?;
+
+        tracing::info!(
+            remote_channel = %options.remote_channel,
+            "connected federation link to remote server"
+        );
+
+        Ok(Self {
+            client,
+            remote_channel: options.remote_channel,
+            local_author: options.local_author,
+        })
+    }
+
+    /// Mirrors the remote channel's messages into the local channel
+    /// until the connection to the remote server closes.
+    ///
+    /// Mirrored messages are created in the local channel as usual, so
+    /// they're broadcast to it's subscribers and indexed for search.
+    #[tracing::instrument(
+        skip_all,
+        fields(remote_channel = %self.remote_channel, local_author = %self.local_author)
+    )]
+    pub async fn run(mut self, channel: Arc<TextChannel>) -> Result<(), ClientError> {
+        while let Some(event) = self.client.events().next().await {
+            let Some(message) = self.mirrored_message(event?) else {
+                continue;
+            };
+
+            let action = TextChannelAction::MessageCreated {
+                message,
+                reply: None,
+            };
+
+            if channel.send_action(action).await.is_err() {
+                tracing::warn!("local channel closed, stopping federation link");
+                return Ok(());
+            }
+        }
+
+        Err(ClientError::Closed)
+    }
+
+    /// Converts an event from the remote gateway into
+    /// a message for the local channel, if it's mirrored.
+    ///
+    /// Mirrored messages are attributed to the link's local author,
+    /// with the remote author's name as the display name.
+    fn mirrored_message(&self, event: v0::GatewayServerEvent) -> Option<TextChannelMessage> {
+        let gateway_server_event::Event::MessageCreated(created) = event.event? else {
+            return None;
+        };
+
+        if created.channel_id != self.remote_channel.0 {
+            return None;
+        }
+
+        Some(TextChannelMessage {
+            id: MessageId::default(),
+            author: self.local_author,
+            author_name: Some(
+                created
+                    .author_name
+                    .unwrap_or_else(|| UserId(created.author).to_string()),
+            ),
+            timestamp_ms: created.timestamp_ms,
+            edited_at: None,
+            content: created.content,
+            reply_to: None,
+            mentions: MessageMentions::default(),
+            // Flags like pinned don't carry over to the mirrored message.
+            flags: MessageFlags::default(),
+        })
+    }
+}
@@ -0,0 +1,737 @@
+//! Dispatches channel events to the sessions watching each channel.
+//!
+//! Rather than each session subscribing to the broadcast of every channel
+//! it's interested in, a single dispatcher task runs for each channel and
+//! pushes its events to the watching sessions' own event broadcasts. Each
+//! session only ever holds one receiver, no matter how many channels it
+//! watches or how many channels the server has.
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    channel::ChannelId,
+    message::{MessageId, MessageMentions},
+    proto::v0::{self, GatewayServerEvent, gateway_server_event},
+    server::{
+        channel::{
+            text::{
+                MessageRejection, TextChannelEvent, TextChannelSettings, automod::AutoModReason,
+            },
+            voice::VoiceChannelEvent,
+        },
+        gateway::GatewayService,
+        permission::{PermissionService, Permissions},
+    },
+    user::UserId,
+};
+
+/// The sessions a channel event is delivered to.
+#[derive(PartialEq, Eq, Debug)]
+enum Recipients {
+    /// Every session watching the channel.
+    Watchers,
+    /// The user's sessions, whether or not they're watching the channel.
+    User(UserId),
+    /// The user's sessions, and the sessions of the moderators watching the channel.
+    UserAndModerators(UserId),
+}
+
+/// Dispatcher task that runs for each channel to forward it's events to watching sessions.
+///
+/// Most events go to every session watching the channel, but events about a
+/// user's rejected or flagged messages only go to the user and to moderators.
+///
+/// Watchers are checked against the channel's read permissions for every
+/// event, so a watcher whose permissions are revoked stops receiving them.
+#[tracing::instrument(skip(events, gateway, permissions, settings))]
+pub async fn channel_dispatcher(
+    channel_id: ChannelId,
+    mut events: broadcast::Receiver<TextChannelEvent>,
+    gateway: Arc<RwLock<GatewayService>>,
+    permissions: Arc<RwLock<PermissionService>>,
+    settings: Arc<RwLock<TextChannelSettings>>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(skipped, "channel dispatcher lagged, events were dropped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let (recipients, event) = gateway_event(channel_id, event);
+
+        let read_permissions = settings.read().read_permissions;
+
+        let gateway = gateway.read();
+        match recipients {
+            Recipients::Watchers => {
+                gateway.dispatch_to_channel_where(channel_id, event, |watcher| {
+                    permissions.read().has(watcher, read_permissions)
+                });
+            }
+            Recipients::User(user) => gateway.send_to_user(user, event),
+            Recipients::UserAndModerators(user) => {
+                gateway.send_to_user(user, event.clone());
+                gateway.dispatch_to_channel_where(channel_id, event, |watcher| {
+                    watcher != user
+                        && permissions
+                            .read()
+                            .has(watcher, read_permissions | Permissions::MANAGE_MESSAGES)
+                });
+            }
+        }
+    }
+
+    tracing::info!("channel dispatcher exit");
+}
+
+/// Dispatcher task that runs for each voice channel to forward
+/// changes to it's roster to the sessions watching it.
+#[tracing::instrument(skip(events, gateway))]
+pub async fn voice_dispatcher(
+    channel_id: ChannelId,
+    mut events: broadcast::Receiver<VoiceChannelEvent>,
+    gateway: Arc<RwLock<GatewayService>>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(skipped, "voice dispatcher lagged, events were dropped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        gateway
+            .read()
+            .dispatch_to_channel(channel_id, voice_gateway_event(channel_id, event));
+    }
+
+    tracing::info!("voice dispatcher exit");
+}
+
+/// Converts a voice channel event to the gateway event sent to watching sessions.
+fn voice_gateway_event(channel_id: ChannelId, event: VoiceChannelEvent) -> GatewayServerEvent {
+    let event = match event {
+        VoiceChannelEvent::ParticipantJoined { user, joined_at_ms } => {
+            gateway_server_event::Event::VoiceParticipantJoined(v0::VoiceParticipantJoined {
+                channel_id: channel_id.0,
+                user: user.0,
+                joined_at_ms,
+            })
+        }
+        VoiceChannelEvent::ParticipantLeft { user } => {
+            gateway_server_event::Event::VoiceParticipantLeft(v0::VoiceParticipantLeft {
+                channel_id: channel_id.0,
+                user: user.0,
+            })
+        }
+        VoiceChannelEvent::StateUpdated { user, state } => {
+            gateway_server_event::Event::VoiceStateUpdated(v0::VoiceStateUpdated {
+                channel_id: channel_id.0,
+                user: user.0,
+                self_mute: state.self_mute,
+                self_deaf: state.self_deaf,
+                server_mute: state.server_mute,
+                server_deaf: state.server_deaf,
+            })
+        }
+    };
+
+    GatewayServerEvent {
+        event: Some(event),
+        seq: 0,
+    }
+}
+
+/// Files up to this size are sent inline with their [`v0::AttachmentAdded`] event.
+///
+/// Larger files would bloat the session's replay buffer,
+/// so clients have to fetch them separately.
+pub const MAX_INLINE_ATTACHMENT_BYTES: usize = 64 << 10; // 64KiB
+
+/// Builds the event sent when a file is attached to a message.
+///
+/// The file's contents are only inlined if they're at most
+/// [`MAX_INLINE_ATTACHMENT_BYTES`], otherwise they're left empty.
+///
+/// Nothing emits this event yet, as the server doesn't store attachments.
+/// The wire format is defined ahead of attachment storage so clients
+/// can handle it in both encodings.
+pub fn attachment_added(
+    channel_id: ChannelId,
+    message_id: MessageId,
+    mut attachment: v0::Attachment,
+) -> GatewayServerEvent {
+    attachment.size = attachment.size.max(attachment.data.len() as u64);
+    if attachment.data.len() > MAX_INLINE_ATTACHMENT_BYTES {
+        attachment.data = Vec::new();
+    }
+
+    GatewayServerEvent {
+        event: Some(gateway_server_event::Event::AttachmentAdded(
+            v0::AttachmentAdded {
+                channel_id: channel_id.0,
+                message_id: message_id.0,
+                attachment: Some(attachment),
+            },
+        )),
+        seq: 0,
+    }
+}
+
+/// Converts the mentions parsed from a message's content to their gateway form.
+fn gateway_mentions(mentions: MessageMentions) -> v0::MessageMentions {
+    v0::MessageMentions {
+        users: mentions.users.into_iter().map(|id| id.0).collect(),
+        roles: mentions.roles.into_iter().map(|id| id.0).collect(),
+        channels: mentions.channels.into_iter().map(|id| id.0).collect(),
+    }
+}
+
+/// Describes why a message was rejected, for reporting it to the author's clients.
+pub fn rejection_reason(reason: &MessageRejection) -> &'static str {
+    match reason {
+        MessageRejection::RateLimited { .. } => "rate limited",
+        MessageRejection::SlowMode { .. } => "slow mode",
+        MessageRejection::TooLarge { .. } => "message content is too large",
+        MessageRejection::AutoModerated(_) => "rejected by auto-moderation",
+    }
+}
+
+/// Describes the auto-moderation rule a message broke.
+fn automod_reason(reason: &AutoModReason) -> &'static str {
+    match reason {
+        AutoModReason::Repetition { .. } => "repeated content",
+        AutoModReason::Mentions { .. } => "too many mentions",
+        AutoModReason::Caps { .. } => "too many capital letters",
+    }
+}
+
+/// Converts a channel event to the gateway event sent to clients, and who it's sent to.
+fn gateway_event(
+    channel_id: ChannelId,
+    event: TextChannelEvent,
+) -> (Recipients, GatewayServerEvent) {
+    let mut recipients = Recipients::Watchers;
+
+    let event = match event {
+        TextChannelEvent::NewMessage(message) => {
+            gateway_server_event::Event::MessageCreated(v0::MessageCreated {
+                channel_id: channel_id.0,
+                message_id: message.id.0,
+                author: message.author.0,
+                author_name: message.author_name,
+                content: message.content,
+                timestamp_ms: message.timestamp_ms,
+                reply_to: message.reply_to.map_or(0, |id| id.0),
+                mentions: Some(gateway_mentions(message.mentions)),
+                flags: message.flags.0,
+            })
+        }
+        TextChannelEvent::MessageEdited(message) => {
+            gateway_server_event::Event::MessageEdited(v0::MessageEdited {
+                channel_id: channel_id.0,
+                message_id: message.id.0,
+                content: message.content,
+                mentions: Some(gateway_mentions(message.mentions)),
+                timestamp_ms: message.timestamp_ms,
+                // Edited messages always have an edit time, but fall back to
+                // the send time rather than reporting an edit at the epoch.
+                edited_at_ms: message.edited_at.unwrap_or(message.timestamp_ms),
+                flags: message.flags.0,
+            })
+        }
+        TextChannelEvent::PinsUpdated { pinned } => {
+            gateway_server_event::Event::PinsUpdated(v0::PinsUpdated {
+                channel_id: channel_id.0,
+                pinned: pinned.into_iter().map(|id| id.0).collect(),
+            })
+        }
+        TextChannelEvent::MessagesDeleted { ids } => {
+            gateway_server_event::Event::MessagesDeleted(v0::MessagesDeleted {
+                channel_id: channel_id.0,
+                message_ids: ids.into_iter().map(|id| id.0).collect(),
+            })
+        }
+        TextChannelEvent::MessagesDeletedInRange {
+            start_ms,
+            end_ms,
+            count,
+        } => gateway_server_event::Event::MessagesDeletedInRange(v0::MessagesDeletedInRange {
+            channel_id: channel_id.0,
+            start_ms,
+            end_ms,
+            count: count as u64,
+        }),
+        TextChannelEvent::MessageRejected { author, reason } => {
+            recipients = Recipients::User(author);
+
+            let retry_after_ms = match reason {
+                MessageRejection::RateLimited { retry_after_ms }
+                | MessageRejection::SlowMode { retry_after_ms } => retry_after_ms,
+                _ => 0,
+            };
+
+            gateway_server_event::Event::MessageRejected(v0::MessageRejected {
+                channel_id: channel_id.0,
+                reason: rejection_reason(&reason).to_string(),
+                retry_after_ms,
+            })
+        }
+        TextChannelEvent::MessageFlagged {
+            message_id,
+            author,
+            reason,
+        } => {
+            recipients = Recipients::UserAndModerators(author);
+
+            gateway_server_event::Event::MessageFlagged(v0::MessageFlagged {
+                channel_id: channel_id.0,
+                message_id: message_id.0,
+                author: author.0,
+                reason: automod_reason(&reason).to_string(),
+            })
+        }
+    };
+
+    (
+        recipients,
+        GatewayServerEvent {
+            event: Some(event),
+            seq: 0,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use prost::Message;
+
+    use super::*;
+    use crate::{
+        message::{MessageId, decode_message},
+        proto::v1,
+        server::{
+            channel::{
+                Channel,
+                text::{
+                    TextChannelEvent,
+                    tests::{test_channel, test_message},
+                },
+            },
+            gateway::{ReplayLimits, SessionLimitPolicy, SessionLimits, tests::service},
+        },
+    };
+
+    const CHANNEL: ChannelId = ChannelId(1);
+    const AUTHOR: UserId = UserId(1);
+    const MODERATOR: UserId = UserId(2);
+    const WATCHER: UserId = UserId(3);
+
+    struct Harness {
+        _dir: tempfile::TempDir,
+        events: broadcast::Sender<TextChannelEvent>,
+        gateway: Arc<RwLock<GatewayService>>,
+        permissions: Arc<RwLock<PermissionService>>,
+        settings: Arc<RwLock<TextChannelSettings>>,
+    }
+
+    impl Harness {
+        /// Starts a dispatcher for the channel, with the moderator as an admin.
+        fn start() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let db = fjall::Database::builder(dir.path()).open().unwrap();
+            let permissions = Arc::new(RwLock::new(
+                PermissionService::new(&db, &[MODERATOR]).unwrap(),
+            ));
+            let settings = Arc::new(RwLock::new(TextChannelSettings::default()));
+
+            let gateway = Arc::new(RwLock::new(service(ReplayLimits {
+                max_events: 16,
+                max_bytes: 1 << 10,
+            })));
+
+            let (events, receiver) = broadcast::channel(16);
+            tokio::spawn(channel_dispatcher(
+                CHANNEL,
+                receiver,
+                Arc::clone(&gateway),
+                Arc::clone(&permissions),
+                Arc::clone(&settings),
+            ));
+
+            Self {
+                _dir: dir,
+                events,
+                gateway,
+                permissions,
+                settings,
+            }
+        }
+
+        /// Connects a session for the user that watches the channel.
+        fn watch(&self, user: UserId) -> broadcast::Receiver<GatewayServerEvent> {
+            let session = self
+                .gateway
+                .write()
+                .create_session(user, v0::GatewayIdentify::default())
+                .unwrap();
+
+            let id = session.read().session_id();
+            self.gateway.read().watch_channel(id, CHANNEL);
+
+            session.read().subscribe()
+        }
+
+        fn send(&self, event: TextChannelEvent) {
+            assert!(self.events.send(event).is_ok());
+        }
+    }
+
+    async fn next_event(
+        receiver: &mut broadcast::Receiver<GatewayServerEvent>,
+    ) -> gateway_server_event::Event {
+        tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+            .await
+            .expect("no event was dispatched")
+            .unwrap()
+            .event
+            .unwrap()
+    }
+
+    /// Sends an event every watcher receives, to show nothing was received before it.
+    async fn assert_nothing_received(
+        harness: &Harness,
+        receiver: &mut broadcast::Receiver<GatewayServerEvent>,
+    ) {
+        harness.send(TextChannelEvent::PinsUpdated { pinned: vec![] });
+
+        assert!(matches!(
+            next_event(receiver).await,
+            gateway_server_event::Event::PinsUpdated(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn channel_events_reach_watching_sessions() {
+        let harness = Harness::start();
+        let mut watcher = harness.watch(WATCHER);
+
+        let mut message = test_message(AUTHOR, "hello");
+        message.id = MessageId(10);
+
+        harness.send(TextChannelEvent::NewMessage(message.clone()));
+        let gateway_server_event::Event::MessageCreated(created) = next_event(&mut watcher).await
+        else {
+            panic!("expected a created message");
+        };
+        assert_eq!(created.message_id, 10);
+        assert_eq!(created.content, "hello");
+
+        message.content = "edited".to_string();
+        harness.send(TextChannelEvent::MessageEdited(message));
+        let gateway_server_event::Event::MessageEdited(edited) = next_event(&mut watcher).await
+        else {
+            panic!("expected an edited message");
+        };
+        assert_eq!(edited.message_id, 10);
+        assert_eq!(edited.content, "edited");
+
+        harness.send(TextChannelEvent::PinsUpdated {
+            pinned: vec![MessageId(10)],
+        });
+        let gateway_server_event::Event::PinsUpdated(pins) = next_event(&mut watcher).await else {
+            panic!("expected updated pins");
+        };
+        assert_eq!(pins.pinned, [10]);
+
+        harness.send(TextChannelEvent::MessagesDeleted {
+            ids: vec![MessageId(10), MessageId(11)],
+        });
+        let gateway_server_event::Event::MessagesDeleted(deleted) = next_event(&mut watcher).await
+        else {
+            panic!("expected deleted messages");
+        };
+        assert_eq!(deleted.message_ids, [10, 11]);
+
+        harness.send(TextChannelEvent::MessagesDeletedInRange {
+            start_ms: 100,
+            end_ms: 200,
+            count: 3,
+        });
+        let gateway_server_event::Event::MessagesDeletedInRange(deleted) =
+            next_event(&mut watcher).await
+        else {
+            panic!("expected a deleted range");
+        };
+        assert_eq!(
+            (
+                deleted.channel_id,
+                deleted.start_ms,
+                deleted.end_ms,
+                deleted.count
+            ),
+            (CHANNEL.0, 100, 200, 3)
+        );
+    }
+
+    #[test]
+    fn created_messages_carry_their_mentions() {
+        let mut message = test_message(AUTHOR, "hi <@5> <@&6> <#7>");
+        message.mentions = decode_message(&message.content).mentions();
+
+        let (_, event) = gateway_event(CHANNEL, TextChannelEvent::NewMessage(message));
+        let expected = v0::MessageMentions {
+            users: vec![5],
+            roles: vec![6],
+            channels: vec![7],
+        };
+
+        // The mentions survive both encodings, and the conversion to v1.
+        let decoded = GatewayServerEvent::decode(event.encode_to_vec().as_slice()).unwrap();
+        let json: GatewayServerEvent =
+            serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+        let v1_event = v1::GatewayServerEvent::from(event.clone());
+
+        for event in [event, decoded, json] {
+            let Some(gateway_server_event::Event::MessageCreated(created)) = event.event else {
+                panic!("expected a created message");
+            };
+            assert_eq!(created.mentions.as_ref(), Some(&expected));
+        }
+
+        let Some(v1::gateway_server_event::Event::MessageCreated(created)) = v1_event.event else {
+            panic!("expected a created message");
+        };
+        let mentions = created.mentions.unwrap();
+        assert_eq!(
+            (mentions.users, mentions.roles, mentions.channels),
+            (expected.users, expected.roles, expected.channels)
+        );
+    }
+
+    #[tokio::test]
+    async fn edits_keep_the_created_timestamp_separate() {
+        let (_dir, channel) = test_channel();
+        let mut events = channel.subscribe();
+
+        let created = channel
+            .create_message(test_message(AUTHOR, "hello"), None, Permissions::default())
+            .await
+            .unwrap();
+
+        // Make sure the edit lands in a later millisecond.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        channel
+            .edit_message(created.id, AUTHOR, "edited", None)
+            .await
+            .unwrap();
+
+        let mut next = async || {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            gateway_event(CHANNEL, event).1.event.unwrap()
+        };
+
+        let gateway_server_event::Event::MessageCreated(sent) = next().await else {
+            panic!("expected a created message");
+        };
+        let gateway_server_event::Event::MessageEdited(edited) = next().await else {
+            panic!("expected an edited message");
+        };
+
+        assert_eq!(sent.timestamp_ms, created.timestamp_ms);
+        assert_eq!(edited.timestamp_ms, created.timestamp_ms);
+        assert!(edited.edited_at_ms > created.timestamp_ms);
+    }
+
+    #[tokio::test]
+    async fn rejections_only_reach_the_author() {
+        let harness = Harness::start();
+        let mut author = harness.watch(AUTHOR);
+        let mut watcher = harness.watch(WATCHER);
+
+        harness.send(TextChannelEvent::MessageRejected {
+            author: AUTHOR,
+            reason: MessageRejection::SlowMode {
+                retry_after_ms: 1500,
+            },
+        });
+
+        let gateway_server_event::Event::MessageRejected(rejected) = next_event(&mut author).await
+        else {
+            panic!("expected a rejection");
+        };
+        assert_eq!(rejected.channel_id, CHANNEL.0);
+        assert_eq!(rejected.reason, "slow mode");
+        assert_eq!(rejected.retry_after_ms, 1500);
+
+        assert_nothing_received(&harness, &mut watcher).await;
+    }
+
+    #[tokio::test]
+    async fn flagged_messages_reach_the_author_and_moderators() {
+        let harness = Harness::start();
+        let mut author = harness.watch(AUTHOR);
+        let mut moderator = harness.watch(MODERATOR);
+        let mut watcher = harness.watch(WATCHER);
+
+        harness.send(TextChannelEvent::MessageFlagged {
+            message_id: MessageId(10),
+            author: AUTHOR,
+            reason: AutoModReason::Caps {
+                ratio: 1.0,
+                max: 0.7,
+            },
+        });
+
+        for receiver in [&mut author, &mut moderator] {
+            let gateway_server_event::Event::MessageFlagged(flagged) = next_event(receiver).await
+            else {
+                panic!("expected a flagged message");
+            };
+            assert_eq!((flagged.message_id, flagged.author), (10, AUTHOR.0));
+            assert_eq!(flagged.reason, "too many capital letters");
+        }
+
+        assert_nothing_received(&harness, &mut watcher).await;
+    }
+
+    #[tokio::test]
+    async fn events_only_reach_watchers_that_can_read_the_channel() {
+        let harness = Harness::start();
+        harness.settings.write().read_permissions = Permissions::MANAGE_MESSAGES;
+        let grant = |user, permissions| {
+            harness
+                .permissions
+                .read()
+                .set_permissions(user, permissions)
+                .unwrap();
+        };
+        grant(WATCHER, Permissions::MANAGE_MESSAGES);
+
+        let mut watcher = harness.watch(WATCHER);
+        let mut author = harness.watch(AUTHOR);
+
+        harness.send(TextChannelEvent::PinsUpdated {
+            pinned: vec![MessageId(10)],
+        });
+        let gateway_server_event::Event::PinsUpdated(pins) = next_event(&mut watcher).await else {
+            panic!("expected updated pins");
+        };
+        assert_eq!(pins.pinned, [10]);
+
+        // Revoking the permissions stops the events, even though the channel is still watched.
+        grant(WATCHER, Permissions::NONE);
+        grant(AUTHOR, Permissions::MANAGE_MESSAGES);
+
+        harness.send(TextChannelEvent::PinsUpdated { pinned: vec![] });
+        let gateway_server_event::Event::PinsUpdated(pins) = next_event(&mut author).await else {
+            panic!("expected updated pins");
+        };
+        assert!(pins.pinned.is_empty());
+        assert!(watcher.try_recv().is_err());
+    }
+
+    /// Measures dispatching to many sessions that each watch many channels.
+    ///
+    /// Each channel is forwarded by a single dispatcher holding the only receiver of
+    /// it's events, and each session by a single receiver of it's own, rather than a
+    /// receiver and task for every session and channel pair. Run with
+    /// `cargo test --release dispatch_benchmark -- --ignored --nocapture`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn dispatch_benchmark() {
+        const CHANNELS: usize = 200;
+        const SESSIONS: usize = 500;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        let permissions = Arc::new(RwLock::new(PermissionService::new(&db, &[]).unwrap()));
+        let gateway = Arc::new(RwLock::new(GatewayService::new(
+            ReplayLimits {
+                max_events: 16,
+                max_bytes: 1 << 10,
+            },
+            Duration::from_secs(60),
+            CHANNELS,
+            0,
+            SessionLimits {
+                max_per_user: 1,
+                max_total: SESSIONS,
+                policy: SessionLimitPolicy::RejectNew,
+            },
+        )));
+
+        let channels: Vec<_> = (0..CHANNELS as u64)
+            .map(|id| {
+                let (events, receiver) = broadcast::channel(16);
+                tokio::spawn(channel_dispatcher(
+                    ChannelId(id),
+                    receiver,
+                    Arc::clone(&gateway),
+                    Arc::clone(&permissions),
+                    Arc::new(RwLock::new(TextChannelSettings::default())),
+                ));
+                events
+            })
+            .collect();
+
+        let mut sessions: Vec<_> = (0..SESSIONS as u64)
+            .map(|user| {
+                let mut gateway = gateway.write();
+                let session = gateway
+                    .create_session(UserId(user), v0::GatewayIdentify::default())
+                    .unwrap();
+                let id = session.read().session_id();
+                for channel in 0..CHANNELS as u64 {
+                    gateway.watch_channel(id, ChannelId(channel));
+                }
+
+                session.read().subscribe()
+            })
+            .collect();
+
+        // The number of receivers doesn't depend on the number of sessions.
+        for events in &channels {
+            assert_eq!(events.receiver_count(), 1);
+        }
+
+        let start = std::time::Instant::now();
+
+        for events in &channels {
+            assert!(
+                events
+                    .send(TextChannelEvent::PinsUpdated { pinned: vec![] })
+                    .is_ok()
+            );
+        }
+        for receiver in &mut sessions {
+            for _ in 0..CHANNELS {
+                next_event(receiver).await;
+            }
+        }
+
+        let elapsed = start.elapsed();
+        println!(
+            "dispatched {} events to {SESSIONS} sessions watching {CHANNELS} channels in {elapsed:?} \
+             ({:?} per event), using {CHANNELS} dispatcher tasks and {} receivers",
+            CHANNELS * SESSIONS,
+            elapsed / (CHANNELS * SESSIONS) as u32,
+            CHANNELS + SESSIONS,
+        );
+    }
+}
@@ -4,22 +4,35 @@
 //! For each connection for a client to the server,
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, HashSet},
     hash::{self, Hasher},
-    sync::{Arc, RwLock},
+    sync::Arc,
+    time::Duration,
 };
 
 use chrono::Utc;
-use log::info;
+use parking_lot::RwLock;
 use snowflaked::Snowflake;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{Notify, broadcast, mpsc};
 use tracing::{Instrument, info_span};
 
 use crate::{
-    proto::v0::{self, GatewayClientEvent, GatewayServerEvent},
+    channel::ChannelId,
+    id::id_generator,
+    proto::v0::{self, GatewayClientEvent, GatewayServerEvent, gateway_server_event},
+    server::{ServerEvent, channel::ChannelType, metrics::metrics},
     user::UserId,
 };
 
+pub use dispatch::{
+    MAX_INLINE_ATTACHMENT_BYTES, attachment_added, channel_dispatcher, rejection_reason,
+    voice_dispatcher,
+};
+pub use replay::{ReplayBuffer, ReplayLimits};
+
+mod dispatch;
+mod replay;
+
 /// Concrete type for client session ID's .
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub struct SessionId(pub u64);
@@ -48,6 +61,88 @@ impl Snowflake for SessionId {
     }
 }
 
+/// What happens when a new session would exceed a session limit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SessionLimitPolicy {
+    /// The new session is refused.
+    #[default]
+    RejectNew,
+    /// The oldest session is closed to make room for the new one.
+    CloseOldest,
+}
+
+/// Limits the number of concurrent gateway sessions.
+///
+/// Disconnected sessions that can still be resumed count towards the limits.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionLimits {
+    /// The maximum number of sessions for each user.
+    pub max_per_user: usize,
+    /// The maximum number of sessions across the server.
+    pub max_total: usize,
+    pub policy: SessionLimitPolicy,
+}
+
+/// A disconnected session that was resumed.
+pub struct ResumedSession {
+    pub session: Arc<RwLock<Session>>,
+    /// The events sent after the client's last sequence number.
+    pub replay: Vec<GatewayServerEvent>,
+    /// Receives the events sent after the replayed events.
+    pub events: broadcast::Receiver<GatewayServerEvent>,
+}
+
+/// Indicates a session couldn't be created because a limit was reached.
+#[derive(Debug)]
+pub enum SessionLimitError {
+    /// Indicates the user already has the maximum number of sessions.
+    UserLimitReached,
+    /// Indicates the server already has the maximum number of sessions.
+    ServerLimitReached,
+}
+
+impl From<ChannelType> for v0::ChannelType {
+    fn from(channel_type: ChannelType) -> Self {
+        match channel_type {
+            ChannelType::Text => Self::Text,
+            ChannelType::Voice => Self::Voice,
+        }
+    }
+}
+
+/// Converts server events to the gateway events forwarded to clients.
+impl From<ServerEvent> for GatewayServerEvent {
+    fn from(event: ServerEvent) -> Self {
+        let event = match event {
+            ServerEvent::ChannelCreated {
+                id,
+                label,
+                channel_type,
+            } => gateway_server_event::Event::ChannelCreated(v0::ChannelCreated {
+                id: id.0,
+                label,
+                channel_type: v0::ChannelType::from(channel_type).into(),
+            }),
+            ServerEvent::ChannelUpdated {
+                id,
+                label,
+                channel_type,
+                topic,
+            } => gateway_server_event::Event::ChannelUpdated(v0::ChannelUpdated {
+                id: id.0,
+                label,
+                channel_type: v0::ChannelType::from(channel_type).into(),
+                topic,
+            }),
+        };
+
+        Self {
+            event: Some(event),
+            seq: 0,
+        }
+    }
+}
+
 /// Indicates the connection state of the client.
 pub enum ConnectionState {
     Connected,
@@ -72,12 +167,24 @@ pub struct Session {
     /// connected to the session in seconds.
     last_contact_s: i64,
 
+    /// The sequence number assigned to the next event sent to the client.
+    next_seq: u64,
+    /// Recently sent events, replayed when the session is resumed.
+    replay: ReplayBuffer,
+
     // Channels for sending server events to the session's client.
     server_event_sender: broadcast::Sender<GatewayServerEvent>,
     server_event_subscriber: broadcast::Receiver<GatewayServerEvent>,
 
     /// Ingests client events to the session worker.
     client_event_sender: mpsc::Sender<GatewayClientEvent>,
+
+    /// The voice channels the session joined as one of the user's devices.
+    voice_channels: HashSet<ChannelId>,
+
+    /// Notified when the server closes the session, so the
+    /// connection attached to it can be closed as well.
+    evicted: Arc<Notify>,
 }
 
 impl Session {
@@ -87,10 +194,12 @@ impl Session {
         user: UserId,
         state: ConnectionState,
         identity: v0::GatewayIdentify,
+        replay_limits: ReplayLimits,
+        event_capacity: usize,
     ) -> Self {
         // Channel for sending events generated by
         // the server to it's associated client.
-        let (server_event_sender, server_event_subscriber) = broadcast::channel(10);
+        let (server_event_sender, server_event_subscriber) = broadcast::channel(event_capacity);
 
         // Channel for ingesting events generated by a client.
         let (client_event_sender, client_event_receiver) = mpsc::channel(10);
@@ -100,19 +209,26 @@ impl Session {
             user_ud = ?user,
             "starting client session worker");
 
-        tokio::spawn(session_worker(client_event_receiver));
+        tokio::spawn(session_worker(user, client_event_receiver));
 
         Self {
             id,
             user,
             state,
             identity,
-            last_contact_s: 0,
+            last_contact_s: Utc::now().timestamp(),
+
+            next_seq: 1,
+            replay: ReplayBuffer::new(replay_limits),
 
             server_event_sender,
             server_event_subscriber,
 
             client_event_sender,
+
+            voice_channels: HashSet::new(),
+
+            evicted: Arc::new(Notify::new()),
         }
     }
 
@@ -121,6 +237,66 @@ impl Session {
         self.id
     }
 
+    /// Returns the ID of the user the session is authorized as.
+    ///
+    /// The ID comes from the validated token rather than the client, so
+    /// it's the ID the session's messages and permission checks must use.
+    pub fn user_id(&self) -> UserId {
+        self.user
+    }
+
+    /// Returns the identity the client sent when it created the session.
+    pub fn identity(&self) -> &v0::GatewayIdentify {
+        &self.identity
+    }
+
+    /// Returns true if a client is connected to the session.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, ConnectionState::Connected)
+    }
+
+    /// Returns true if the session should receive events.
+    ///
+    /// Disconnected sessions keep receiving events until they're past the
+    /// resume window, so the events can be replayed if the session is resumed.
+    fn receives_events(&self, resume_cutoff_s: i64) -> bool {
+        self.is_connected() || self.last_contact_s >= resume_cutoff_s
+    }
+
+    /// Assigns the next sequence number to an event for the client,
+    /// and retains it so it can be replayed on resume.
+    ///
+    /// Acknowledgements and other connection-specific events
+    /// aren't meaningful after a reconnect, so they're not retained.
+    fn sequence(&mut self, mut event: GatewayServerEvent) -> GatewayServerEvent {
+        if matches!(
+            event.event,
+            Some(
+                gateway_server_event::Event::HeartbeatAck(_)
+                    | gateway_server_event::Event::SessionReady(_)
+            )
+        ) {
+            return event;
+        }
+
+        event.seq = self.next_seq;
+        self.next_seq += 1;
+
+        self.replay.push(event.clone());
+
+        event
+    }
+
+    /// Returns the events sent after the sequence number, if they can still be replayed.
+    pub fn replay_after(&self, seq: u64) -> Option<Vec<GatewayServerEvent>> {
+        // The client can't have received events that were never sent.
+        if seq >= self.next_seq {
+            return None;
+        }
+
+        self.replay.replay_after(seq)
+    }
+
     /// Updates the last-contacted time for the session.
     pub fn contacted(&mut self) {
         self.last_contact_s = Utc::now().timestamp();
@@ -136,6 +312,44 @@ impl Session {
         self.server_event_subscriber.resubscribe()
     }
 
+    /// Sends an event generated by the server to the session's client.
+    ///
+    /// The event is sequenced and retained before it's sent, so it can be
+    /// replayed if the client is disconnected or falls behind. Events a
+    /// client falls behind on leave a gap in the sequence numbers it sees,
+    /// so it can tell it missed them.
+    pub fn send_event(&mut self, event: GatewayServerEvent) {
+        let event = self.sequence(event);
+
+        // The client may have disconnected, which is fine.
+        let _ = self.server_event_sender.send(event);
+    }
+
+    /// Records that the session joined a voice channel.
+    ///
+    /// Returns false if the session had already joined it.
+    pub fn join_voice(&mut self, channel_id: ChannelId) -> bool {
+        self.voice_channels.insert(channel_id)
+    }
+
+    /// Records that the session left a voice channel.
+    ///
+    /// Returns false if the session hadn't joined it.
+    pub fn leave_voice(&mut self, channel_id: ChannelId) -> bool {
+        self.voice_channels.remove(&channel_id)
+    }
+
+    /// Forgets every voice channel the session joined, returning them
+    /// so the session's device can be removed from each.
+    pub fn take_voice_channels(&mut self) -> Vec<ChannelId> {
+        self.voice_channels.drain().collect()
+    }
+
+    /// Returns a handle that's notified when the server closes the session.
+    pub fn evicted(&self) -> Arc<Notify> {
+        Arc::clone(&self.evicted)
+    }
+
     /// Returns a sender for forwarding events generated
     /// by client endpoints to the server's session worker.
     pub fn client_event_sender(&self) -> mpsc::Sender<GatewayClientEvent> {
@@ -149,25 +363,62 @@ impl Session {
 pub struct GatewayService {
     id_generator: snowflaked::Generator,
 
+    /// Limits the memory used by each session's replay buffer.
+    replay_limits: ReplayLimits,
+    /// How long a disconnected session can be resumed for.
+    resume_window: Duration,
+    /// The number of server events buffered for each session.
+    session_event_capacity: usize,
+    /// Limits the number of concurrent sessions.
+    session_limits: SessionLimits,
+
+    /// IDs of each user's sessions, used to enforce the per-user limit.
+    ///
+    /// Session IDs are snowflakes, so the first ID is the oldest session.
+    user_sessions: HashMap<UserId, BTreeSet<u64>>,
+
     /// Active gateway client sessions.
     sessions: RwLock<HashMap<SessionId, Arc<RwLock<Session>>>>,
+
+    /// IDs of the sessions watching each channel, used to dispatch channel events.
+    channel_watchers: RwLock<HashMap<ChannelId, HashSet<SessionId>>>,
 }
 
 impl GatewayService {
     /// Construct a new instance of the client service.
-    pub fn new() -> Self {
+    pub fn new(
+        replay_limits: ReplayLimits,
+        resume_window: Duration,
+        session_event_capacity: usize,
+        snowflake_epoch_ms: u64,
+        session_limits: SessionLimits,
+    ) -> Self {
         Self {
-            id_generator: snowflaked::Generator::new(0),
+            id_generator: id_generator(0, snowflake_epoch_ms),
+            replay_limits,
+            resume_window,
+            session_event_capacity,
+            session_limits,
+            user_sessions: HashMap::new(),
             sessions: RwLock::new(HashMap::new()),
+            channel_watchers: RwLock::new(HashMap::new()),
         }
     }
 
     /// Creates a new client connection session.
+    ///
+    /// Fails if a session limit is reached and the policy is to refuse
+    /// new sessions, otherwise the oldest session is closed to make room.
     pub fn create_session(
         &mut self,
         user_id: UserId,
         identity: v0::GatewayIdentify,
-    ) -> Arc<RwLock<Session>> {
+    ) -> Result<Arc<RwLock<Session>>, SessionLimitError> {
+        // Sessions that can no longer be resumed are dropped as new ones are created.
+        self.close_expired_sessions();
+
+        self.enforce_session_limits(user_id)?;
+
         // Generate the ID for the new session.
         let id = self.id_generator.generate();
 
@@ -177,30 +428,284 @@ impl GatewayService {
             user_id,
             ConnectionState::Connected,
             identity,
+            self.replay_limits,
+            self.session_event_capacity,
         )));
 
         // Insert the session into the active session table.
+        self.sessions.write().insert(id, Arc::clone(&session));
+        self.user_sessions.entry(user_id).or_default().insert(id.0);
+
+        metrics().gateway_sessions.inc();
+
+        tracing::info!(id = ?id, "created new client session");
+
+        Ok(session)
+    }
+
+    /// Makes room for a new session for the user, or fails if a limit is reached.
+    fn enforce_session_limits(&mut self, user_id: UserId) -> Result<(), SessionLimitError> {
+        let limits = self.session_limits;
+
+        let user_sessions = self.user_sessions.get(&user_id);
+        if user_sessions.map_or(0, BTreeSet::len) >= limits.max_per_user {
+            let oldest = user_sessions.and_then(|ids| ids.first().copied());
+
+            match (limits.policy, oldest) {
+                (SessionLimitPolicy::CloseOldest, Some(oldest)) => {
+                    self.evict_session(SessionId(oldest));
+                }
+                _ => return Err(SessionLimitError::UserLimitReached),
+            }
+        }
+
+        if self.sessions.read().len() >= limits.max_total {
+            let oldest = self.sessions.read().keys().map(|id| id.0).min();
+
+            match (limits.policy, oldest) {
+                (SessionLimitPolicy::CloseOldest, Some(oldest)) => {
+                    self.evict_session(SessionId(oldest));
+                }
+                _ => return Err(SessionLimitError::ServerLimitReached),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Closes a session and the connection attached to it.
+    fn evict_session(&mut self, id: SessionId) {
+        if let Some(session) = self.sessions.read().get(&id) {
+            session.read().evicted.notify_one();
+        }
+
+        tracing::info!(id = ?id, "evicting client session to stay within session limits");
+
+        self.close_session(id);
+    }
+
+    /// Resumes a disconnected session for the user.
+    ///
+    /// Returns the session along with the events sent after the sequence
+    /// number, or `None` if the session can't be resumed and the client
+    /// needs a new session.
+    pub fn resume_session(
+        &mut self,
+        user_id: UserId,
+        id: SessionId,
+        seq: u64,
+    ) -> Option<ResumedSession> {
+        self.close_expired_sessions();
+
+        let session = self.sessions.read().get(&id).cloned()?;
+
+        let (replay, events) = {
+            let mut guard = session.write();
+
+            // Sessions can only be resumed by their own user, and only
+            // one connection can be attached to a session at a time.
+            if guard.user != user_id || guard.is_connected() {
+                return None;
+            }
+
+            let replay = guard.replay_after(seq)?;
+
+            // Subscribed while the session is locked, so no events are
+            // sent between the replayed events and the subscription.
+            let events = guard.subscribe();
+
+            guard.state = ConnectionState::Connected;
+            guard.contacted();
+
+            (replay, events)
+        };
+
+        tracing::info!(id = ?id, replayed = replay.len(), "resumed client session");
+
+        Some(ResumedSession {
+            session,
+            replay,
+            events,
+        })
+    }
+
+    /// Marks a session as disconnected, keeping it so it can be resumed.
+    pub fn disconnect_session(&mut self, id: SessionId) {
+        if let Some(session) = self.sessions.read().get(&id) {
+            let mut session = session.write();
+            session.state = ConnectionState::Disconnected;
+            session.contacted();
+        }
+
+        tracing::info!(id = ?id, "client session disconnected");
+    }
+
+    /// Returns the number of sessions with a connected client.
+    ///
+    /// Disconnected sessions waiting to be resumed aren't counted.
+    pub fn active_session_count(&self) -> usize {
         self.sessions
+            .read()
+            .values()
+            .filter(|session| session.read().is_connected())
+            .count()
+    }
+
+    /// Returns true if the user has a session with a connected client.
+    pub fn is_online(&self, user: UserId) -> bool {
+        self.sessions.read().values().any(|session| {
+            let session = session.read();
+            session.user == user && session.is_connected()
+        })
+    }
+
+    /// Returns the last-contacted time before which disconnected
+    /// sessions can no longer be resumed, in seconds.
+    fn resume_cutoff_s(&self) -> i64 {
+        Utc::now().timestamp() - self.resume_window.as_secs() as i64
+    }
+
+    /// Sends an event to every session of the user.
+    ///
+    /// Disconnected sessions that can still be resumed receive
+    /// the event too, so it's replayed when they're resumed.
+    pub fn send_to_user(&self, user: UserId, event: GatewayServerEvent) {
+        let cutoff_s = self.resume_cutoff_s();
+
+        for session in self.sessions.read().values() {
+            let mut session = session.write();
+            if session.user == user && session.receives_events(cutoff_s) {
+                session.send_event(event.clone());
+            }
+        }
+    }
+
+    /// Sends an event to every session whose user can see it.
+    ///
+    /// Used for server-wide events, so sessions don't each
+    /// need their own subscription to the server's events.
+    pub fn dispatch_to_all(&self, event: GatewayServerEvent, can_view: impl Fn(UserId) -> bool) {
+        let cutoff_s = self.resume_cutoff_s();
+
+        for session in self.sessions.read().values() {
+            let mut session = session.write();
+            if session.receives_events(cutoff_s) && can_view(session.user) {
+                session.send_event(event.clone());
+            }
+        }
+    }
+
+    /// Starts sending a channel's events to the session.
+    ///
+    /// Watched channels are kept while the session is disconnected,
+    /// so they don't need to be watched again after a resume.
+    pub fn watch_channel(&self, id: SessionId, channel_id: ChannelId) {
+        if !self.sessions.read().contains_key(&id) {
+            return;
+        }
+
+        self.channel_watchers
             .write()
-            .unwrap()
-            .insert(id, Arc::clone(&session));
+            .entry(channel_id)
+            .or_default()
+            .insert(id);
+    }
 
-        tracing::info!(id = ?id, "created new client session");
+    /// Stops sending a channel's events to the session.
+    pub fn unwatch_channel(&self, id: SessionId, channel_id: ChannelId) {
+        let mut watchers = self.channel_watchers.write();
+        if let Some(ids) = watchers.get_mut(&channel_id) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                watchers.remove(&channel_id);
+            }
+        }
+    }
+
+    /// Sends a channel event to every session watching the channel.
+    pub fn dispatch_to_channel(&self, channel_id: ChannelId, event: GatewayServerEvent) {
+        self.dispatch_to_channel_where(channel_id, event, |_| true);
+    }
+
+    /// Sends a channel event to the sessions watching the
+    /// channel, for the users the filter returns true for.
+    pub fn dispatch_to_channel_where(
+        &self,
+        channel_id: ChannelId,
+        event: GatewayServerEvent,
+        filter: impl Fn(UserId) -> bool,
+    ) {
+        // Copy the watchers out, so the membership isn't locked while sending.
+        let ids: Vec<SessionId> = match self.channel_watchers.read().get(&channel_id) {
+            Some(ids) => ids.iter().copied().collect(),
+            None => return,
+        };
+
+        let cutoff_s = self.resume_cutoff_s();
+
+        let sessions = self.sessions.read();
+        for session in ids.iter().filter_map(|id| sessions.get(id)) {
+            let mut session = session.write();
+            if session.receives_events(cutoff_s) && filter(session.user) {
+                session.send_event(event.clone());
+            }
+        }
+    }
 
-        session
+    /// Closes the disconnected sessions that are past the resume window.
+    fn close_expired_sessions(&mut self) {
+        let cutoff_s = self.resume_cutoff_s();
+
+        let expired: Vec<SessionId> = self
+            .sessions
+            .read()
+            .iter()
+            .filter(|(_, session)| {
+                let session = session.read();
+                !session.is_connected() && session.last_contact_s < cutoff_s
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            self.close_session(id);
+        }
     }
 
     /// Closes an open client session.
     pub fn close_session(&mut self, id: SessionId) {
         // Remove the session from the active session table.
-        self.sessions.write().unwrap().remove(&id);
+        if let Some(session) = self.sessions.write().remove(&id) {
+            let user = session.read().user;
+            if let Some(ids) = self.user_sessions.get_mut(&user) {
+                ids.remove(&id.0);
+                if ids.is_empty() {
+                    self.user_sessions.remove(&user);
+                }
+            }
+
+            metrics().gateway_sessions.dec();
+        }
+
+        // Stop dispatching channel events to the closed session.
+        self.channel_watchers.write().retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
 
         tracing::info!(id = ?id, "closing client session");
     }
 }
 
 /// Worker task spawned for each client session.
-async fn session_worker(mut client_event_receiver: mpsc::Receiver<GatewayClientEvent>) {
+///
+/// Events are attributed to the user the session was authorized as
+/// when the client identified, never to a user named by the client.
+#[tracing::instrument(skip(client_event_receiver))]
+async fn session_worker(
+    user: UserId,
+    mut client_event_receiver: mpsc::Receiver<GatewayClientEvent>,
+) {
     loop {
         // Wait to receive the next event from the client.
         let Some(event) = client_event_receiver
@@ -220,3 +725,299 @@ async fn session_worker(mut client_event_receiver: mpsc::Receiver<GatewayClientE
 
     tracing::info!("client session worker exited");
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A gateway service with room for a few sessions.
+    pub(crate) fn service(replay_limits: ReplayLimits) -> GatewayService {
+        GatewayService::new(
+            replay_limits,
+            Duration::from_secs(60),
+            16,
+            0,
+            SessionLimits {
+                max_per_user: 4,
+                max_total: 16,
+                policy: SessionLimitPolicy::RejectNew,
+            },
+        )
+    }
+
+    fn channel_deleted(id: u64) -> GatewayServerEvent {
+        GatewayServerEvent {
+            event: Some(gateway_server_event::Event::ChannelDeleted(
+                v0::ChannelDeleted { id },
+            )),
+            seq: 0,
+        }
+    }
+
+    /// Waits for the next event sent to the session.
+    async fn next_event(
+        events: &mut broadcast::Receiver<GatewayServerEvent>,
+    ) -> Result<GatewayServerEvent, broadcast::error::RecvError> {
+        tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("timed out waiting for an event")
+    }
+
+    #[tokio::test]
+    async fn resume_replays_events_missed_while_disconnected() {
+        let mut gateway = service(ReplayLimits {
+            max_events: 16,
+            max_bytes: 1 << 10,
+        });
+
+        let session = gateway
+            .create_session(UserId(1), v0::GatewayIdentify::default())
+            .unwrap();
+        let id = session.read().session_id();
+        let mut events = session.read().subscribe();
+        gateway.watch_channel(id, ChannelId(1));
+
+        // The client receives the first event before the connection drops.
+        gateway.dispatch_to_channel(ChannelId(1), channel_deleted(1));
+        let received = next_event(&mut events).await.unwrap();
+        gateway.disconnect_session(id);
+
+        // Events dispatched while the client is away, through each of the dispatch paths.
+        gateway.dispatch_to_channel(ChannelId(1), channel_deleted(2));
+        gateway.dispatch_to_all(channel_deleted(3), |_| true);
+        gateway.send_to_user(UserId(1), channel_deleted(4));
+
+        let resumed = gateway.resume_session(UserId(1), id, received.seq).unwrap();
+
+        assert!(Arc::ptr_eq(&resumed.session, &session));
+        assert!(resumed.session.read().is_connected());
+        assert_eq!(
+            resumed.replay,
+            (2..=4)
+                .map(|seq| GatewayServerEvent {
+                    seq,
+                    ..channel_deleted(seq)
+                })
+                .collect::<Vec<_>>()
+        );
+
+        // Events after the resume continue the sequence on the new subscription.
+        let mut events = resumed.events;
+        gateway.dispatch_to_channel(ChannelId(1), channel_deleted(5));
+        assert_eq!(next_event(&mut events).await.unwrap().seq, 5);
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers_are_contiguous_until_events_are_dropped() {
+        let mut gateway = service(ReplayLimits {
+            max_events: 64,
+            max_bytes: 4 << 10,
+        });
+
+        let session = gateway
+            .create_session(UserId(1), v0::GatewayIdentify::default())
+            .unwrap();
+        let mut events = session.read().subscribe();
+
+        for channel in 1..=5 {
+            gateway.send_to_user(UserId(1), channel_deleted(channel));
+        }
+        for seq in 1..=5 {
+            assert_eq!(next_event(&mut events).await.unwrap().seq, seq);
+        }
+
+        // Acknowledgements aren't numbered, so they don't leave a gap.
+        session.write().send_event(GatewayServerEvent {
+            event: Some(gateway_server_event::Event::HeartbeatAck(
+                v0::HeartbeatAck { seq: 1 },
+            )),
+            seq: 0,
+        });
+        assert_eq!(next_event(&mut events).await.unwrap().seq, 0);
+        gateway.send_to_user(UserId(1), channel_deleted(6));
+        assert_eq!(next_event(&mut events).await.unwrap().seq, 6);
+
+        // Overflow the session's event buffer, so the client falls behind.
+        for channel in 7..=30 {
+            gateway.send_to_user(UserId(1), channel_deleted(channel));
+        }
+        assert!(matches!(
+            next_event(&mut events).await,
+            Err(broadcast::error::RecvError::Lagged(_))
+        ));
+
+        // The dropped events leave a gap the client can see, and can still be replayed.
+        let next = next_event(&mut events).await.unwrap().seq;
+        assert!(next > 7);
+        let replay = session.read().replay_after(6).unwrap();
+        assert_eq!(
+            replay.iter().map(|event| event.seq).collect::<Vec<_>>(),
+            (7..=30).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_from_an_evicted_sequence_requires_a_new_session() {
+        let mut gateway = service(ReplayLimits {
+            max_events: 2,
+            max_bytes: 1 << 10,
+        });
+
+        let session = gateway
+            .create_session(UserId(1), v0::GatewayIdentify::default())
+            .unwrap();
+        let id = session.read().session_id();
+        let mut events = session.read().subscribe();
+
+        gateway.send_to_user(UserId(1), channel_deleted(1));
+        let received = next_event(&mut events).await.unwrap();
+        gateway.disconnect_session(id);
+
+        // The event after the client's last one is evicted from the buffer.
+        for channel in 2..=4 {
+            gateway.dispatch_to_all(channel_deleted(channel), |_| true);
+        }
+
+        assert!(
+            gateway
+                .resume_session(UserId(1), id, received.seq)
+                .is_none()
+        );
+        assert!(!session.read().is_connected());
+    }
+
+    #[tokio::test]
+    async fn sessions_cant_be_resumed_by_another_user() {
+        let mut gateway = service(ReplayLimits {
+            max_events: 16,
+            max_bytes: 1 << 10,
+        });
+
+        let session = gateway
+            .create_session(UserId(1), v0::GatewayIdentify::default())
+            .unwrap();
+        let id = session.read().session_id();
+        gateway.disconnect_session(id);
+
+        assert!(gateway.resume_session(UserId(2), id, 0).is_none());
+    }
+
+    #[tokio::test]
+    async fn sessions_are_authorized_as_the_validated_user() {
+        let mut gateway = service(ReplayLimits {
+            max_events: 16,
+            max_bytes: 1 << 10,
+        });
+
+        // Whatever the client sends when identifying, the session
+        // belongs to the user its token was validated as.
+        let identity = v0::GatewayIdentify {
+            token: "another user's token".to_string(),
+            ..Default::default()
+        };
+        let session = gateway.create_session(UserId(1), identity).unwrap();
+
+        assert_eq!(session.read().user_id(), UserId(1));
+    }
+
+    /// A gateway service with the session limits.
+    fn limited_service(
+        max_per_user: usize,
+        max_total: usize,
+        policy: SessionLimitPolicy,
+    ) -> GatewayService {
+        GatewayService::new(
+            ReplayLimits {
+                max_events: 16,
+                max_bytes: 1 << 10,
+            },
+            Duration::from_secs(60),
+            16,
+            0,
+            SessionLimits {
+                max_per_user,
+                max_total,
+                policy,
+            },
+        )
+    }
+
+    /// Returns the users of each session the gateway holds, in order.
+    fn session_users(gateway: &GatewayService) -> Vec<UserId> {
+        let mut users: Vec<UserId> = gateway
+            .sessions
+            .read()
+            .values()
+            .map(|session| session.read().user)
+            .collect();
+        users.sort();
+        users
+    }
+
+    #[tokio::test]
+    async fn sessions_are_capped_per_user() {
+        let mut gateway = limited_service(2, 16, SessionLimitPolicy::RejectNew);
+        for _ in 0..2 {
+            gateway
+                .create_session(UserId(1), v0::GatewayIdentify::default())
+                .unwrap();
+        }
+
+        assert!(matches!(
+            gateway.create_session(UserId(1), v0::GatewayIdentify::default()),
+            Err(SessionLimitError::UserLimitReached)
+        ));
+
+        // Other users have their own allowance.
+        gateway
+            .create_session(UserId(2), v0::GatewayIdentify::default())
+            .unwrap();
+        assert_eq!(session_users(&gateway), [UserId(1), UserId(1), UserId(2)]);
+
+        // Disconnected sessions can still be resumed, so they count too.
+        let mut gateway = limited_service(2, 16, SessionLimitPolicy::CloseOldest);
+        let oldest = gateway
+            .create_session(UserId(1), v0::GatewayIdentify::default())
+            .unwrap();
+        let oldest_id = oldest.read().session_id();
+        gateway.disconnect_session(oldest_id);
+        for _ in 0..2 {
+            gateway
+                .create_session(UserId(1), v0::GatewayIdentify::default())
+                .unwrap();
+        }
+
+        // The oldest session was closed to make room.
+        let evicted = oldest.read().evicted();
+        tokio::time::timeout(Duration::from_secs(1), evicted.notified())
+            .await
+            .unwrap();
+        assert!(gateway.resume_session(UserId(1), oldest_id, 0).is_none());
+        assert_eq!(session_users(&gateway), [UserId(1), UserId(1)]);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_capped_across_the_server() {
+        let mut gateway = limited_service(4, 2, SessionLimitPolicy::RejectNew);
+        for user in 1..=2 {
+            gateway
+                .create_session(UserId(user), v0::GatewayIdentify::default())
+                .unwrap();
+        }
+
+        assert!(matches!(
+            gateway.create_session(UserId(3), v0::GatewayIdentify::default()),
+            Err(SessionLimitError::ServerLimitReached)
+        ));
+
+        // The oldest session on the server is closed, whichever user it belongs to.
+        let mut gateway = limited_service(4, 2, SessionLimitPolicy::CloseOldest);
+        for user in 1..=3 {
+            gateway
+                .create_session(UserId(user), v0::GatewayIdentify::default())
+                .unwrap();
+        }
+
+        assert_eq!(session_users(&gateway), [UserId(2), UserId(3)]);
+    }
+}
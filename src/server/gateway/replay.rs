@@ -0,0 +1,145 @@
+//! Buffers recently sent events so a session can be resumed.
+
+use std::collections::VecDeque;
+
+use prost::Message;
+
+use crate::proto::v0::GatewayServerEvent;
+
+/// Limits the memory used by a session's replay buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayLimits {
+    /// The maximum number of events retained.
+    pub max_events: usize,
+    /// The maximum total encoded size in bytes of the retained events.
+    pub max_bytes: usize,
+}
+
+/// Ring buffer of the most recent events sent to a session's client.
+///
+/// The oldest events are evicted once either of the limits is reached.
+/// Events are measured by their encoded Protobuf size, so the buffer
+/// stays within its memory budget no matter how large the events are.
+pub struct ReplayBuffer {
+    limits: ReplayLimits,
+
+    /// Buffered events along with their encoded size, oldest first.
+    events: VecDeque<(GatewayServerEvent, usize)>,
+    /// Total encoded size of the buffered events.
+    bytes: usize,
+
+    /// The sequence number of the newest event no longer in the buffer.
+    ///
+    /// Zero if no events have been evicted.
+    evicted_seq: u64,
+}
+
+impl ReplayBuffer {
+    /// Constructs an empty replay buffer.
+    pub fn new(limits: ReplayLimits) -> Self {
+        Self {
+            limits,
+            events: VecDeque::new(),
+            bytes: 0,
+            evicted_seq: 0,
+        }
+    }
+
+    /// Records an event for the client, evicting the oldest events as needed.
+    ///
+    /// Events are expected to be pushed in sequence order.
+    pub fn push(&mut self, event: GatewayServerEvent) {
+        let size = event.encoded_len();
+
+        // An event that can never fit evicts everything, so a resume
+        // from before it fails instead of silently skipping it.
+        if size > self.limits.max_bytes || self.limits.max_events == 0 {
+            self.evicted_seq = event.seq;
+            self.events.clear();
+            self.bytes = 0;
+            return;
+        }
+
+        while self.events.len() >= self.limits.max_events
+            || self.bytes + size > self.limits.max_bytes
+        {
+            let Some((evicted, evicted_size)) = self.events.pop_front() else {
+                break;
+            };
+
+            self.evicted_seq = evicted.seq;
+            self.bytes -= evicted_size;
+        }
+
+        self.bytes += size;
+        self.events.push_back((event, size));
+    }
+
+    /// Returns the events sent after the sequence number.
+    ///
+    /// Returns `None` if any of those events have been evicted,
+    /// in which case the client can't resume the session.
+    pub fn replay_after(&self, seq: u64) -> Option<Vec<GatewayServerEvent>> {
+        if seq < self.evicted_seq {
+            return None;
+        }
+
+        Some(
+            self.events
+                .iter()
+                .filter(|(event, _)| event.seq > seq)
+                .map(|(event, _)| event.clone())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::v0::{ChannelDeleted, ChannelUpdated, gateway_server_event::Event};
+
+    fn channel_updated(seq: u64, label: &str) -> GatewayServerEvent {
+        GatewayServerEvent {
+            event: Some(Event::ChannelUpdated(ChannelUpdated {
+                label: label.to_string(),
+                ..Default::default()
+            })),
+            seq,
+        }
+    }
+
+    #[test]
+    fn buffer_stays_within_the_byte_limit() {
+        let mut buffer = ReplayBuffer::new(ReplayLimits {
+            max_events: 100,
+            max_bytes: 256,
+        });
+
+        for seq in 1..=10 {
+            buffer.push(channel_updated(seq, &"a".repeat(100)));
+        }
+
+        assert!(buffer.bytes <= 256);
+        assert_eq!(buffer.replay_after(10), Some(vec![]));
+        assert_eq!(buffer.replay_after(9).unwrap().len(), 1);
+        assert!(buffer.replay_after(1).is_none());
+    }
+
+    #[test]
+    fn oversized_events_make_earlier_sequences_unresumable() {
+        let mut buffer = ReplayBuffer::new(ReplayLimits {
+            max_events: 100,
+            max_bytes: 64,
+        });
+
+        buffer.push(GatewayServerEvent {
+            event: Some(Event::ChannelDeleted(ChannelDeleted { id: 1 })),
+            seq: 1,
+        });
+        buffer.push(channel_updated(2, &"a".repeat(100)));
+
+        assert!(buffer.replay_after(1).is_none());
+        assert_eq!(buffer.replay_after(2), Some(vec![]));
+    }
+}
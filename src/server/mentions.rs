@@ -0,0 +1,156 @@
+//! Resolving the targets of message mentions to display names.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    channel::ChannelId,
+    message::MessageMentions,
+    role::RoleId,
+    server::{Server, channel::Channel},
+    user::UserId,
+};
+
+/// Name shown for mentioned users.
+///
+/// The server doesn't keep user profiles yet, so users are never resolved.
+pub const UNKNOWN_USER_NAME: &str = "unknown-user";
+
+/// Name shown for mentioned roles.
+///
+/// The server doesn't keep a directory of roles yet, so roles are never resolved.
+pub const UNKNOWN_ROLE_NAME: &str = "unknown-role";
+
+/// Name shown for mentioned channels that don't exist.
+pub const DELETED_CHANNEL_NAME: &str = "deleted-channel";
+
+/// A mentioned user, role, or channel along with it's display name.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct ResolvedMention<Id> {
+    pub id: Id,
+    /// The name to display for the mention.
+    pub name: String,
+    /// False if the target couldn't be found, in which case the name is a placeholder.
+    pub resolved: bool,
+}
+
+impl<Id> ResolvedMention<Id> {
+    /// A mention of a target that couldn't be found.
+    fn placeholder(id: Id, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            resolved: false,
+        }
+    }
+}
+
+/// The display names of the targets mentioned in a message.
+#[derive(Clone, Debug, Default, Serialize, JsonSchema)]
+pub struct ResolvedMentions {
+    pub users: Vec<ResolvedMention<UserId>>,
+    pub roles: Vec<ResolvedMention<RoleId>>,
+    pub channels: Vec<ResolvedMention<ChannelId>>,
+}
+
+impl Server {
+    /// Resolves the display names of the targets mentioned in a message.
+    ///
+    /// Each kind of target is looked up with a single lock of it's
+    /// service, however many times it's mentioned. Direct channels are
+    /// private, so mentions of them are resolved as deleted channels.
+    pub fn resolve_mentions(&self, mentions: &MessageMentions) -> ResolvedMentions {
+        let users = mentions
+            .users
+            .iter()
+            .map(|&id| ResolvedMention::placeholder(id, UNKNOWN_USER_NAME))
+            .collect();
+
+        let roles = mentions
+            .roles
+            .iter()
+            .map(|&id| ResolvedMention::placeholder(id, UNKNOWN_ROLE_NAME))
+            .collect();
+
+        let text_channels = self.text_channels.read();
+        let voice_channels = self.voice_channels.read();
+        let channels = mentions
+            .channels
+            .iter()
+            .map(|&id| {
+                let label = match (text_channels.get(&id), voice_channels.get(&id)) {
+                    (Some(channel), _) => Some(channel.get_label()),
+                    (None, Some(channel)) => Some(channel.get_label()),
+                    (None, None) => None,
+                };
+
+                match label {
+                    Some(label) => ResolvedMention {
+                        id,
+                        name: label.to_string(),
+                        resolved: true,
+                    },
+                    None => ResolvedMention::placeholder(id, DELETED_CHANNEL_NAME),
+                }
+            })
+            .collect();
+
+        ResolvedMentions {
+            users,
+            roles,
+            channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::tests::server, server::channel::text::TextChannelSettings};
+
+    #[tokio::test]
+    async fn mentions_are_resolved_to_channel_labels() {
+        let server = server();
+        let state = &server.state;
+        let text = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let voice = state.create_voice_channel("lounge".to_string()).unwrap();
+        let direct = server.direct_channel(&[UserId(1), UserId(2)]);
+
+        let resolved = state.resolve_mentions(&MessageMentions {
+            users: vec![UserId(1)],
+            roles: vec![RoleId(2)],
+            channels: vec![
+                text.channel_id(),
+                voice.channel_id(),
+                direct.channel().channel_id(),
+                ChannelId(404),
+            ],
+        });
+
+        let channels: Vec<_> = resolved
+            .channels
+            .iter()
+            .map(|mention| (mention.id, mention.name.as_str(), mention.resolved))
+            .collect();
+        assert_eq!(
+            channels,
+            [
+                (text.channel_id(), "general", true),
+                (voice.channel_id(), "lounge", true),
+                // Direct channels are private, so they're hidden like deleted ones.
+                (direct.channel().channel_id(), DELETED_CHANNEL_NAME, false),
+                (ChannelId(404), DELETED_CHANNEL_NAME, false),
+            ]
+        );
+
+        // Users and roles aren't kept by the server yet, so they're placeholders.
+        assert_eq!(resolved.users.len(), 1);
+        assert_eq!(resolved.users[0].name, UNKNOWN_USER_NAME);
+        assert!(!resolved.users[0].resolved);
+        assert_eq!(resolved.roles.len(), 1);
+        assert_eq!(resolved.roles[0].name, UNKNOWN_ROLE_NAME);
+        assert!(!resolved.roles[0].resolved);
+    }
+}
@@ -0,0 +1,171 @@
+//! Prometheus metrics for observing the server.
+//!
+//! The metrics are registered in a global registry on first
+//! access, and exported in the Prometheus text format by the
+//! HTTP `/metrics` endpoint.
+
+use std::sync::LazyLock;
+
+use crate::server::stats::ServerStats;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+/// The metrics collected by the server.
+pub struct Metrics {
+    registry: Registry,
+
+    /// Count of messages created, labeled by channel.
+    pub messages_created: IntCounterVec,
+    /// Count of messages edited, labeled by channel.
+    pub messages_edited: IntCounterVec,
+    /// Count of messages deleted, labeled by channel.
+    pub messages_deleted: IntCounterVec,
+
+    /// Number of currently active gateway sessions.
+    pub gateway_sessions: IntGauge,
+
+    /// Number of channels in the channel list.
+    pub text_channels: IntGauge,
+    /// Number of messages stored across every channel.
+    pub messages_stored: IntGauge,
+
+    /// Count of full-text search queries executed.
+    pub search_queries: IntCounter,
+
+    /// Count of OAuth2 logins, labeled by provider and result.
+    pub oauth_logins: IntCounterVec,
+
+    /// Time taken by a channel worker to ingest a new message.
+    pub message_ingest_seconds: Histogram,
+    /// Time between a client sending a message and the server receiving it,
+    /// as reported by the client's clock.
+    pub message_client_latency_seconds: Histogram,
+    /// Time taken to execute a full-text search query.
+    pub search_seconds: Histogram,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Returns the global server metrics.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("bonfire".to_string()), None)
+            .expect("metrics registry prefix should be valid");
+
+        let messages_created = IntCounterVec::new(
+            Opts::new("messages_created_total", "Messages created per channel."),
+            &["channel"],
+        )
+        .unwrap();
+        let messages_edited = IntCounterVec::new(
+            Opts::new("messages_edited_total", "Messages edited per channel."),
+            &["channel"],
+        )
+        .unwrap();
+        let messages_deleted = IntCounterVec::new(
+            Opts::new("messages_deleted_total", "Messages deleted per channel."),
+            &["channel"],
+        )
+        .unwrap();
+
+        let gateway_sessions =
+            IntGauge::new("gateway_sessions", "Active gateway client sessions.").unwrap();
+
+        let text_channels =
+            IntGauge::new("text_channels", "Channels in the channel list.").unwrap();
+        let messages_stored =
+            IntGauge::new("messages_stored", "Messages stored across every channel.").unwrap();
+
+        let search_queries =
+            IntCounter::new("search_queries_total", "Full-text search queries executed.").unwrap();
+
+        let oauth_logins = IntCounterVec::new(
+            Opts::new(
+                "oauth_logins_total",
+                "OAuth2 logins per provider and result.",
+            ),
+            &["provider", "result"],
+        )
+        .unwrap();
+
+        let message_ingest_seconds = Histogram::with_opts(HistogramOpts::new(
+            "message_ingest_seconds",
+            "Time taken by a channel worker to ingest a message.",
+        ))
+        .unwrap();
+        let message_client_latency_seconds = Histogram::with_opts(HistogramOpts::new(
+            "message_client_latency_seconds",
+            "Time between a client sending a message and the server receiving it.",
+        ))
+        .unwrap();
+        let search_seconds = Histogram::with_opts(HistogramOpts::new(
+            "search_seconds",
+            "Time taken to execute a full-text search query.",
+        ))
+        .unwrap();
+
+        registry
+            .register(Box::new(messages_created.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(messages_edited.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(messages_deleted.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(gateway_sessions.clone()))
+            .unwrap();
+        registry.register(Box::new(text_channels.clone())).unwrap();
+        registry
+            .register(Box::new(messages_stored.clone()))
+            .unwrap();
+        registry.register(Box::new(search_queries.clone())).unwrap();
+        registry.register(Box::new(oauth_logins.clone())).unwrap();
+        registry
+            .register(Box::new(message_ingest_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(message_client_latency_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(search_seconds.clone())).unwrap();
+
+        Self {
+            registry,
+            messages_created,
+            messages_edited,
+            messages_deleted,
+            gateway_sessions,
+            text_channels,
+            messages_stored,
+            search_queries,
+            oauth_logins,
+            message_ingest_seconds,
+            message_client_latency_seconds,
+            search_seconds,
+        }
+    }
+
+    /// Updates the gauges that are sampled from a stats snapshot.
+    pub fn record_stats(&self, stats: &ServerStats) {
+        self.text_channels.set(stats.text_channels as i64);
+        self.messages_stored.set(stats.messages as i64);
+    }
+
+    /// Encodes the current value of all metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            tracing::error!(%err, "failed to encode metrics");
+        }
+
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
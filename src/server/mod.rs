@@ -1,67 +1,255 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
+    io,
+    panic::{self, AssertUnwindSafe},
     path::PathBuf,
-    sync::{Arc, RwLock},
+    sync::Arc,
+    time::Duration,
 };
 
 use fjall::Database;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, task::JoinHandle};
 
 use crate::{
     channel::ChannelId,
+    id::id_generator,
     server::{
-        auth::AuthService,
-        channel::text::{TextChannel, TextChannelError},
-        gateway::GatewayService,
+        auth::{AuthService, AuthServiceError},
+        category::CategoryService,
+        channel::{
+            Channel, ChannelType,
+            direct::{DirectChannel, DirectChannelError, MAX_DIRECT_PARTICIPANTS},
+            text::{
+                SEARCH_INDEX_DIR, TextChannel, TextChannelError, TextChannelOptions,
+                TextChannelSettings,
+            },
+            voice::{VoiceChannel, VoiceChannelError},
+        },
+        gateway::{GatewayService, ReplayLimits, SessionLimits},
+        notification::NotificationSettingsService,
+        permission::PermissionService,
+        presence::PresenceService,
+        read_state::{MAX_UNREAD_COUNT, ReadStateService},
+        webhook::WebhookService,
     },
+    user::{SYSTEM_USER_ID, UserId},
 };
 
 pub mod auth;
+pub mod bot_token;
+pub mod category;
 pub mod channel;
+pub mod config;
+#[cfg(feature = "federation")]
+pub mod federation;
 pub mod gateway;
+pub mod mentions;
+pub mod metrics;
+pub mod notification;
+pub mod notify;
+pub mod oauth2_account;
+pub mod permission;
+pub mod presence;
+pub mod read_state;
+pub mod search;
+pub mod session_token;
+pub mod stats;
+pub mod usage;
 pub mod user;
+pub mod webhook;
+
+pub use config::{Config, ConfigBuilder, ConfigError, CorsConfig};
+
+use config::check_dir_writable;
+
+/// Directory in the data directory containing the database.
+const DATABASE_DIR: &str = "data";
+
+/// Directory in the data directory containing each channel's files.
+const CHANNELS_DIR: &str = "channels";
+
+/// The number of server events buffered for slow subscribers.
+pub const SERVER_EVENT_CAPACITY: usize = 64;
 
 /// An event that occures on a server.
+///
+/// Private direct channels don't emit events, since
+/// they never appear in the server's channel list.
+#[derive(Clone, Debug)]
 pub enum ServerEvent {
     /// Emitted when a new channel is created.
-    ChannelCreated,
+    ChannelCreated {
+        id: ChannelId,
+        label: String,
+        channel_type: ChannelType,
+    },
+    /// Emitted when a channel's label or settings change.
+    ChannelUpdated {
+        id: ChannelId,
+        label: String,
+        channel_type: ChannelType,
+        topic: Option<String>,
+    },
 }
 
-/// Config for the application server.
-pub struct Config {
-    /// Root directory for storing server data.
-    pub data_dir: PathBuf,
-
-    pub auth: auth::AuthConfig,
+impl ServerEvent {
+    /// Returns the ID of the channel the event is about.
+    pub fn channel_id(&self) -> ChannelId {
+        match self {
+            Self::ChannelCreated { id, .. } | Self::ChannelUpdated { id, .. } => *id,
+        }
+    }
 }
 
 /// Application server.
 pub struct Server {
     config: Config,
 
-    id_generator: snowflaked::Generator,
+    /// Generates IDs for new channels.
+    ///
+    /// Uses the thread-safe generator so IDs can be
+    /// generated through a shared reference to the server.
+    id_generator: snowflaked::sync::Generator,
 
     /// FSM-tree database for storing the time-series channel messages.
     db: fjall::Database,
 
+    /// Keyspace persisting the channel list, keyed by channel ID.
+    channel_list: fjall::Keyspace,
+
     /// Service for managing user authentication.
     auth: Arc<RwLock<AuthService>>,
     /// Service for managing connections to clients.
     gateway: Arc<RwLock<GatewayService>>,
+    /// Service for managing channel webhooks.
+    webhooks: Arc<RwLock<WebhookService>>,
+    /// Service for managing channel categories and ordering.
+    categories: Arc<RwLock<CategoryService>>,
+    /// Service for resolving user permissions.
+    permissions: Arc<RwLock<PermissionService>>,
+    /// Service for tracking how far users have read in each channel.
+    read_states: Arc<RwLock<ReadStateService>>,
+    /// Service for storing user notification preferences.
+    notification_settings: Arc<RwLock<NotificationSettingsService>>,
+    /// Service for tracking when users were last online.
+    presence: Arc<RwLock<PresenceService>>,
 
     /// A hashmap of the available channels on the server.
     text_channels: RwLock<HashMap<ChannelId, Arc<TextChannel>>>,
+
+    /// A hashmap of the available voice channels on the server.
+    voice_channels: RwLock<HashMap<ChannelId, Arc<VoiceChannel>>>,
+
+    /// A hashmap of the private direct channels between users.
+    ///
+    /// These are kept separate so they never appear in the channel list.
+    direct_channels: RwLock<HashMap<ChannelId, Arc<DirectChannel>>>,
+
+    /// Held while a text or voice channel is created, so
+    /// concurrent creations can't exceed the channel limit.
+    channel_creation: Mutex<()>,
+
+    /// Held while a direct channel is created, so concurrent creations
+    /// can't create duplicate channels or exceed the direct channel limits.
+    ///
+    /// Separate from the direct channel map, so looking up direct
+    /// channels isn't blocked while a new channel is opened on disk.
+    direct_channel_creation: Mutex<()>,
+
+    /// The persisted channels that couldn't be loaded.
+    ///
+    /// These are unavailable until an admin reloads them with
+    /// [`Server::reload_failed_channel`], or the server is restarted.
+    failed_channels: RwLock<Vec<FailedChannel>>,
+
+    /// Sender for events that occur on the server.
+    event_sender: broadcast::Sender<ServerEvent>,
+
+    /// Handles of the tasks forwarding channel events, stopped when the server shuts down.
+    tasks: Mutex<Vec<JoinHandle<()>>>,
 }
 
 #[derive(Debug)]
 pub enum Error {
+    /// Indicates a directory in the data directory couldn't be created or isn't writable.
+    DataDirError(PathBuf, io::Error),
     DatabaseError(fjall::Error),
+    AuthServiceError(AuthServiceError),
+    /// Indicates a persisted channel record couldn't be decoded.
+    CorruptChannelRecord(ChannelId, serde_json::Error),
+    /// Indicates a persisted channel couldn't be reopened.
+    TextChannelError(ChannelId, TextChannelError),
+    /// Indicates a persisted voice channel couldn't be reopened.
+    VoiceChannelError(ChannelId, VoiceChannelError),
+    /// Indicates reopening a persisted channel panicked.
+    ChannelPanicked(ChannelId),
+}
+
+impl Error {
+    /// Returns true if the error may have been caused by a channel's
+    /// search index, which can be recreated from the stored messages.
+    fn may_be_search_index(&self) -> bool {
+        matches!(
+            self,
+            Self::TextChannelError(
+                _,
+                TextChannelError::SearchIndexPathError(_)
+                    | TextChannelError::SearchIndexDirectoryError(_)
+                    | TextChannelError::SearchError(_)
+            ) | Self::ChannelPanicked(_)
+        )
+    }
+}
+
+/// A text channel reopened from it's persisted record.
+struct LoadedChannel {
+    channel: Arc<TextChannel>,
+    /// True if the channel's search index was recreated empty.
+    index_recreated: bool,
+}
+
+/// A persisted channel that couldn't be loaded when the server started.
+#[derive(Clone, Debug)]
+pub struct FailedChannel {
+    pub channel_id: ChannelId,
+    /// Description of the error that stopped the channel from loading.
+    pub reason: String,
 }
 
+#[derive(Debug)]
 pub enum CreateChannelError {
-    /// Indicates that the R/W lock on the internal
-    /// channel list has become poisoned somehow.
-    PoisonedChannelLock,
     TextChannelError(TextChannelError),
+    VoiceChannelError(VoiceChannelError),
+    DirectChannelError(DirectChannelError),
+    /// Indicates the channel couldn't be persisted to the channel list.
+    DatabaseError(fjall::Error),
+    /// Indicates the server already has the maximum number of channels.
+    LimitReached {
+        max: usize,
+    },
+}
+
+/// A channel persisted in the channel list.
+///
+/// Channels are reopened from their records when the server starts.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChannelRecord {
+    Text {
+        label: String,
+        settings: TextChannelSettings,
+    },
+    Voice {
+        label: String,
+    },
+    Direct {
+        participants: BTreeSet<UserId>,
+        /// Missing from the records of channels opened before it was recorded.
+        #[serde(default)]
+        creator: Option<UserId>,
+    },
 }
 
 impl From<TextChannelError> for CreateChannelError {
@@ -73,26 +261,360 @@ impl From<TextChannelError> for CreateChannelError {
 impl Server {
     /// Construct a new instance of the application.
     pub fn new(config: Config) -> Result<Self, Error> {
+        // Create the layout of the data directory up front, so problems with
+        // it are reported now rather than when the first channel is created.
+        let database_dir = config.data_dir.join(DATABASE_DIR);
+        for dir in [
+            &config.data_dir,
+            &database_dir,
+            &config.data_dir.join(CHANNELS_DIR),
+        ] {
+            check_dir_writable(dir).map_err(|e| Error::DataDirError(dir.clone(), e))?;
+        }
+
         // Open or create the database for the server.
-        let database_dir: PathBuf = config.data_dir.clone().join("data");
         let db = Database::builder(database_dir)
             .open()
-            .map_err(|e| Error::DatabaseError(e))?;
+            .map_err(Error::DatabaseError)?;
 
         // Construct the service for managing user authentication.
-        let auth = Arc::new(RwLock::new(AuthService::new(config.auth.clone())));
+        let auth = Arc::new(RwLock::new(
+            AuthService::new(
+                config.auth.clone(),
+                &db,
+                config.instance_id,
+                config.snowflake_epoch_ms,
+            )
+            .map_err(Error::AuthServiceError)?,
+        ));
 
         // Construct the service for managing connected client sessions.
-        let gateway = Arc::new(RwLock::new(GatewayService::new()));
+        let gateway = Arc::new(RwLock::new(GatewayService::new(
+            ReplayLimits {
+                max_events: config.resume_buffer_events,
+                max_bytes: config.resume_buffer_bytes,
+            },
+            Duration::from_secs(config.resume_window_secs),
+            config.session_event_capacity,
+            config.snowflake_epoch_ms,
+            SessionLimits {
+                max_per_user: config.max_sessions_per_user,
+                max_total: config.max_sessions,
+                policy: config.session_limit_policy,
+            },
+        )));
 
-        Ok(Self {
-            config,
-            id_generator: snowflaked::Generator::new(0),
+        // Construct the service for managing channel webhooks.
+        let webhooks = Arc::new(RwLock::new(
+            WebhookService::new(&db, config.instance_id, config.snowflake_epoch_ms)
+                .map_err(Error::DatabaseError)?,
+        ));
+
+        // Construct the service for managing channel categories.
+        let categories = Arc::new(RwLock::new(
+            CategoryService::new(&db, config.instance_id, config.snowflake_epoch_ms)
+                .map_err(Error::DatabaseError)?,
+        ));
+
+        // Construct the service for resolving user permissions.
+        let permissions = Arc::new(RwLock::new(
+            PermissionService::new(&db, &config.admin_users).map_err(Error::DatabaseError)?,
+        ));
+
+        // Construct the service for tracking what users have read.
+        let read_states = Arc::new(RwLock::new(
+            ReadStateService::new(&db).map_err(Error::DatabaseError)?,
+        ));
+
+        // Construct the service for storing notification preferences.
+        let notification_settings = Arc::new(RwLock::new(
+            NotificationSettingsService::new(&db).map_err(Error::DatabaseError)?,
+        ));
+
+        // Construct the service for tracking when users were last online.
+        let presence = Arc::new(RwLock::new(
+            PresenceService::new(&db).map_err(Error::DatabaseError)?,
+        ));
+
+        // Open the keyspace persisting the channel list.
+        let channel_list = db
+            .keyspace("channels", fjall::KeyspaceCreateOptions::default)
+            .map_err(Error::DatabaseError)?;
+
+        let server = Self {
+            id_generator: id_generator(config.instance_id, config.snowflake_epoch_ms),
             db,
+            channel_list,
             auth,
             gateway,
+            webhooks,
+            categories,
+            permissions,
+            read_states,
+            notification_settings,
+            presence,
             text_channels: RwLock::new(HashMap::new()),
-        })
+            voice_channels: RwLock::new(HashMap::new()),
+            direct_channels: RwLock::new(HashMap::new()),
+            channel_creation: Mutex::new(()),
+            direct_channel_creation: Mutex::new(()),
+            failed_channels: RwLock::new(Vec::new()),
+            event_sender: broadcast::channel(SERVER_EVENT_CAPACITY).0,
+            tasks: Mutex::new(Vec::new()),
+            config,
+        };
+
+        // Reopen the channels that were created in previous runs.
+        server.load_channels()?;
+
+        Ok(server)
+    }
+
+    /// Reopens the channels persisted in the channel list.
+    ///
+    /// A channel that can't be reopened, such as one with a corrupted
+    /// record or keyspace, is logged and left unavailable rather than
+    /// stopping the rest of the channels from loading. Channels whose
+    /// search index was recreated are reindexed in the background.
+    fn load_channels(&self) -> Result<(), Error> {
+        for guard in self.channel_list.iter() {
+            let (key, value) = guard.into_inner().map_err(Error::DatabaseError)?;
+            let id = ChannelId(u64::from_be_bytes(key[..8].try_into().unwrap()));
+
+            match self.load_channel(id, &value) {
+                Ok(Some(LoadedChannel {
+                    channel,
+                    index_recreated: true,
+                })) => self.spawn_reindex(channel),
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::error!(
+                        channel_id = ?id,
+                        ?err,
+                        "failed to load channel, it will be unavailable"
+                    );
+
+                    self.failed_channels.write().push(FailedChannel {
+                        channel_id: id,
+                        reason: format!("{err:?}"),
+                    });
+                }
+            }
+        }
+
+        tracing::info!(
+            text_channels = self.text_channels.read().len(),
+            voice_channels = self.voice_channels.read().len(),
+            direct_channels = self.direct_channels.read().len(),
+            failed_channels = self.failed_channels.read().len(),
+            "loaded persisted channels"
+        );
+
+        Ok(())
+    }
+
+    /// Reopens a channel from it's persisted record.
+    ///
+    /// The search index only holds copies of the stored messages, so if
+    /// it can't be opened it's recreated empty, to be rebuilt by the caller.
+    ///
+    /// Returns the reopened text channel, or `None` for voice channels.
+    fn load_channel(&self, id: ChannelId, value: &[u8]) -> Result<Option<LoadedChannel>, Error> {
+        let record = serde_json::from_slice::<ChannelRecord>(value)
+            .map_err(|e| Error::CorruptChannelRecord(id, e))?;
+
+        let (label, settings) = match &record {
+            ChannelRecord::Text { label, settings } => (label.clone(), settings.clone()),
+            ChannelRecord::Direct { .. } => (format!("dm-{id}"), TextChannelSettings::default()),
+            // Voice channels don't have any storage to open.
+            ChannelRecord::Voice { label } => {
+                let channel = VoiceChannel::new(id, label.clone(), self.config.snowflake_epoch_ms)
+                    .map_err(|e| Error::VoiceChannelError(id, e))?;
+                self.add_voice_channel(Arc::new(channel));
+
+                return Ok(None);
+            }
+        };
+
+        // Corrupted files can make the storage libraries panic
+        // rather than return an error, so contain those too.
+        let open = || {
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                self.open_text_channel(id, label.clone(), settings.clone())
+            }))
+            .map_err(|_| Error::ChannelPanicked(id))?
+            .map_err(|e| Error::TextChannelError(id, e))
+        };
+
+        let (channel, index_recreated) = match open() {
+            Ok(channel) => (channel, false),
+            Err(err) if err.may_be_search_index() => {
+                tracing::warn!(
+                    channel_id = ?id,
+                    ?err,
+                    "failed to open channel, recreating it's search index"
+                );
+
+                let index_dir = self.channel_dir(id).join(SEARCH_INDEX_DIR);
+                if index_dir.exists() {
+                    std::fs::remove_dir_all(&index_dir)
+                        .map_err(|e| Error::DataDirError(index_dir, e))?;
+                }
+
+                (open()?, true)
+            }
+            Err(err) => return Err(err),
+        };
+        let channel = Arc::new(channel);
+
+        match record {
+            ChannelRecord::Text { .. } => {
+                self.text_channels.write().insert(id, Arc::clone(&channel));
+            }
+            ChannelRecord::Voice { .. } => unreachable!("voice channels are loaded above"),
+            ChannelRecord::Direct {
+                participants,
+                creator,
+            } => {
+                self.direct_channels.write().insert(
+                    id,
+                    Arc::new(DirectChannel::new(
+                        participants,
+                        creator,
+                        Arc::clone(&channel),
+                    )),
+                );
+            }
+        }
+
+        Ok(Some(LoadedChannel {
+            channel,
+            index_recreated,
+        }))
+    }
+
+    /// Rebuilds a channel's recreated search index in the background.
+    fn spawn_reindex(&self, channel: Arc<TextChannel>) {
+        let task = tokio::spawn(async move {
+            let channel_id = channel.channel_id();
+            match channel.reindex().await {
+                Ok(count) => tracing::info!(?channel_id, count, "reindexed channel"),
+                Err(err) => tracing::error!(?channel_id, ?err, "failed to reindex channel"),
+            }
+        });
+
+        self.tasks.lock().push(task);
+    }
+
+    /// Returns the persisted channels that couldn't be loaded.
+    pub fn failed_channels(&self) -> Vec<FailedChannel> {
+        self.failed_channels.read().clone()
+    }
+
+    /// Tries loading a persisted channel that couldn't be loaded again,
+    /// such as after the problem with it's files was fixed.
+    ///
+    /// The channel's search index is recreated if it can't be opened, in
+    /// which case the caller should reindex the channel. Returns `None` if
+    /// the channel isn't one that failed to load, or is a voice channel.
+    pub fn reload_failed_channel(&self, id: ChannelId) -> Result<Option<Arc<TextChannel>>, Error> {
+        let _creating = self.channel_creation.lock();
+
+        if !self
+            .failed_channels
+            .read()
+            .iter()
+            .any(|failed| failed.channel_id == id)
+        {
+            return Ok(None);
+        }
+
+        let Some(value) = self
+            .channel_list
+            .get(id.0.to_be_bytes())
+            .map_err(Error::DatabaseError)?
+        else {
+            return Ok(None);
+        };
+
+        let loaded = self.load_channel(id, &value);
+
+        let mut failed_channels = self.failed_channels.write();
+        match &loaded {
+            Ok(_) => failed_channels.retain(|failed| failed.channel_id != id),
+            Err(err) => {
+                for failed in failed_channels.iter_mut().filter(|f| f.channel_id == id) {
+                    failed.reason = format!("{err:?}");
+                }
+            }
+        }
+
+        Ok(loaded?.map(|loaded| loaded.channel))
+    }
+
+    /// Persists a channel's record in the channel list.
+    fn save_channel(&self, id: ChannelId, record: &ChannelRecord) -> Result<(), fjall::Error> {
+        let value = serde_json::to_vec(record).expect("channel records should always encode");
+
+        self.channel_list.insert(id.0.to_be_bytes(), value)
+    }
+
+    /// Removes the record and files of a channel that couldn't be opened
+    /// after it was saved, so it isn't loaded when the server restarts.
+    fn forget_channel(&self, id: ChannelId) {
+        if let Err(err) = self.channel_list.remove(id.0.to_be_bytes()) {
+            tracing::error!(%err, channel_id = %id, "failed to remove channel record");
+        }
+
+        let data_dir = self.channel_dir(id);
+        if !data_dir.exists() {
+            return;
+        }
+
+        if let Err(err) = std::fs::remove_dir_all(&data_dir) {
+            tracing::error!(%err, channel_id = %id, "failed to remove channel directory");
+        }
+    }
+
+    /// Subscribes to the events that occur on the server.
+    ///
+    /// Only events emitted after subscribing are received.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ServerEvent> {
+        self.event_sender.subscribe()
+    }
+
+    /// Emits an event to the server's subscribers.
+    ///
+    /// The event is also dispatched to the gateway sessions
+    /// of the users that can see the channel it's about.
+    fn emit(&self, event: ServerEvent) {
+        let channel_id = event.channel_id();
+        self.gateway
+            .read()
+            .dispatch_to_all(event.clone().into(), |user| {
+                self.can_view_channel(user, channel_id)
+            });
+
+        // No subscribers is not an error.
+        let _ = self.event_sender.send(event);
+    }
+
+    /// Returns the directory containing the server's database.
+    fn database_dir(&self) -> PathBuf {
+        self.config.data_dir.join(DATABASE_DIR)
+    }
+
+    /// Returns the directory containing a channel's files, such as it's search index.
+    fn channel_dir(&self, id: ChannelId) -> PathBuf {
+        self.config
+            .data_dir
+            .join(CHANNELS_DIR)
+            .join(id.0.to_string())
+    }
+
+    /// Returns the config the server was constructed with.
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
     /// Returns a handle to the auth service.
@@ -105,39 +627,751 @@ impl Server {
         Arc::clone(&self.gateway)
     }
 
+    /// Returns a handle to the webhook service.
+    pub fn webhooks(&self) -> Arc<RwLock<WebhookService>> {
+        Arc::clone(&self.webhooks)
+    }
+
+    /// Returns a handle to the category service.
+    pub fn categories(&self) -> Arc<RwLock<CategoryService>> {
+        Arc::clone(&self.categories)
+    }
+
+    /// Returns a handle to the permission service.
+    pub fn permissions(&self) -> Arc<RwLock<PermissionService>> {
+        Arc::clone(&self.permissions)
+    }
+
+    /// Returns a handle to the read state service.
+    pub fn read_states(&self) -> Arc<RwLock<ReadStateService>> {
+        Arc::clone(&self.read_states)
+    }
+
+    /// Returns a handle to the presence service.
+    pub fn presence(&self) -> Arc<RwLock<PresenceService>> {
+        Arc::clone(&self.presence)
+    }
+
+    /// Returns a handle to the notification settings service.
+    pub fn notification_settings(&self) -> Arc<RwLock<NotificationSettingsService>> {
+        Arc::clone(&self.notification_settings)
+    }
+
+    /// Returns the number of messages in the channel the user hasn't read,
+    /// up to [`MAX_UNREAD_COUNT`].
+    ///
+    /// Counting reads the channel's keyspace with blocking IO.
+    pub fn unread_count(&self, user: UserId, channel: &TextChannel) -> Result<u64, fjall::Error> {
+        match self
+            .read_states
+            .read()
+            .last_read(user, channel.channel_id())
+        {
+            Some(last_read) => channel.count_after(last_read, MAX_UNREAD_COUNT),
+            None => Ok(channel.message_count().min(MAX_UNREAD_COUNT)),
+        }
+    }
+
     /// Create a new text channel on the server.
     ///
     /// Returns a handle to the created text channel.
     pub fn create_text_channel(
-        &mut self,
+        &self,
         label: String,
+        settings: TextChannelSettings,
     ) -> Result<Arc<TextChannel>, CreateChannelError> {
+        let _creating = self.channel_creation.lock();
+        self.check_channel_limit()?;
+
         // Generate a channel ID.
         let id: ChannelId = self.id_generator.generate();
 
+        let record = ChannelRecord::Text {
+            label: label.clone(),
+            settings: settings.clone(),
+        };
+
+        // Persist the channel so it's reopened when the server restarts.
+        //
+        // The channel is saved before it's opened, so a failed save
+        // doesn't leave behind a running worker and the channel's files.
+        self.save_channel(id, &record)
+            .map_err(CreateChannelError::DatabaseError)?;
+
+        let channel = match self.open_text_channel(id, label, settings) {
+            Ok(channel) => Arc::new(channel),
+            Err(err) => {
+                self.forget_channel(id);
+                return Err(err.into());
+            }
+        };
+
+        // Add the channel to the global channel list.
+        self.text_channels.write().insert(id, Arc::clone(&channel));
+
+        self.emit(ServerEvent::ChannelCreated {
+            id,
+            label: channel.get_label().to_string(),
+            channel_type: channel.channel_type(),
+        });
+
+        Ok(channel)
+    }
+
+    pub fn create_voice_channel(
+        &self,
+        label: String,
+    ) -> Result<Arc<VoiceChannel>, CreateChannelError> {
+        let _creating = self.channel_creation.lock();
+        self.check_channel_limit()?;
+
+        let id: ChannelId = self.id_generator.generate();
+
+        let record = ChannelRecord::Voice {
+            label: label.clone(),
+        };
+
+        let channel = Arc::new(
+            VoiceChannel::new(id, label, self.config.snowflake_epoch_ms)
+                .map_err(CreateChannelError::VoiceChannelError)?,
+        );
+
+        // Persist the channel so it's reopened when the server restarts.
+        self.save_channel(id, &record)
+            .map_err(CreateChannelError::DatabaseError)?;
+
+        self.add_voice_channel(Arc::clone(&channel));
+
+        self.emit(ServerEvent::ChannelCreated {
+            id,
+            label: channel.get_label().to_string(),
+            channel_type: channel.channel_type(),
+        });
+
+        Ok(channel)
+    }
+
+    /// Adds a voice channel to the server, and starts
+    /// dispatching it's events to watching sessions.
+    fn add_voice_channel(&self, channel: Arc<VoiceChannel>) {
+        let dispatcher = tokio::spawn(gateway::voice_dispatcher(
+            channel.channel_id(),
+            channel.subscribe(),
+            Arc::clone(&self.gateway),
+        ));
+        self.tasks.lock().push(dispatcher);
+
+        self.voice_channels
+            .write()
+            .insert(channel.channel_id(), channel);
+    }
+
+    /// Returns an error if the server already has the maximum number of channels.
+    ///
+    /// Direct channels are created by users rather than the server's
+    /// admins, and never appear in the channel list, so they aren't counted.
+    fn check_channel_limit(&self) -> Result<(), CreateChannelError> {
+        let Some(max) = self.config.max_channels else {
+            return Ok(());
+        };
+
+        let count = self.text_channels.read().len() + self.voice_channels.read().len();
+        if count >= max {
+            return Err(CreateChannelError::LimitReached { max });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the direct channel between the participants,
+    /// creating it if the participants don't have one yet.
+    ///
+    /// The creator must be one of the participants, and the rest must be
+    /// users known to the server. New channels count towards the creator's
+    /// and the server's direct channel limits.
+    pub fn create_direct_channel(
+        &self,
+        creator: UserId,
+        participants: BTreeSet<UserId>,
+    ) -> Result<Arc<DirectChannel>, CreateChannelError> {
+        if participants.len() < 2
+            || participants.len() > MAX_DIRECT_PARTICIPANTS
+            || !participants.contains(&creator)
+            || participants.contains(&SYSTEM_USER_ID)
+        {
+            return Err(CreateChannelError::DirectChannelError(
+                DirectChannelError::InvalidParticipants,
+            ));
+        }
+
+        if let Some(&unknown) = participants
+            .iter()
+            .find(|&&user| user != creator && !self.is_known_user(user))
+        {
+            return Err(CreateChannelError::DirectChannelError(
+                DirectChannelError::UnknownUser(unknown),
+            ));
+        }
+
+        // Held while checking for an existing channel so concurrent
+        // requests can't create duplicate channels or exceed the limits.
+        let _creation = self.direct_channel_creation.lock();
+
+        {
+            let direct_channels = self.direct_channels.read();
+
+            // Reuse the existing channel if the participants already have one.
+            if let Some(existing) = direct_channels
+                .values()
+                .find(|c| c.participants() == &participants)
+            {
+                return Ok(Arc::clone(existing));
+            }
+
+            let max = self.config.max_direct_channels;
+            if direct_channels.len() >= max {
+                return Err(CreateChannelError::DirectChannelError(
+                    DirectChannelError::ServerLimitReached { max },
+                ));
+            }
+
+            let max = self.config.max_direct_channels_per_user;
+            let created = direct_channels
+                .values()
+                .filter(|c| c.creator() == Some(creator))
+                .count();
+            if created >= max {
+                return Err(CreateChannelError::DirectChannelError(
+                    DirectChannelError::UserLimitReached { max },
+                ));
+            }
+        }
+
+        let id: ChannelId = self.id_generator.generate();
+
+        // Persist the channel so it's reopened when the server restarts,
+        // before opening it like server channels.
+        self.save_channel(
+            id,
+            &ChannelRecord::Direct {
+                participants: participants.clone(),
+                creator: Some(creator),
+            },
+        )
+        .map_err(CreateChannelError::DatabaseError)?;
+
+        // Opened without holding the direct channel map's lock, since opening
+        // the search index on disk would stall every direct channel lookup.
+        let label = format!("dm-{id}");
+        let channel = match self.open_text_channel(id, label, TextChannelSettings::default()) {
+            Ok(channel) => channel,
+            Err(err) => {
+                self.forget_channel(id);
+                return Err(err.into());
+            }
+        };
+
+        let direct = Arc::new(DirectChannel::new(
+            participants,
+            Some(creator),
+            Arc::new(channel),
+        ));
+
+        self.direct_channels.write().insert(id, Arc::clone(&direct));
+
+        Ok(direct)
+    }
+
+    /// Returns true if the user is known to the server.
+    ///
+    /// There's no directory of users, so users are known once they've
+    /// connected to the gateway, or if they're one of the server's admins.
+    pub fn is_known_user(&self, user: UserId) -> bool {
+        if user == SYSTEM_USER_ID {
+            return false;
+        }
+
+        self.config.admin_users.contains(&user)
+            || self.gateway.read().is_online(user)
+            || self.presence.read().last_seen(user).is_some()
+    }
+
+    /// Returns the direct channel with the specified ID if the user is a participant.
+    pub fn direct_channel(
+        &self,
+        user: UserId,
+        id: ChannelId,
+    ) -> Result<Arc<DirectChannel>, DirectChannelError> {
+        let Some(channel) = self.direct_channels.read().get(&id).cloned() else {
+            return Err(DirectChannelError::NotFound);
+        };
+
+        if !channel.is_participant(user) {
+            return Err(DirectChannelError::Forbidden);
+        }
+
+        Ok(channel)
+    }
+
+    /// Returns true if the user can see the channel.
+    ///
+    /// Direct channels are only visible to their participants, and server
+    /// text channels to the users that can read them. Voice channels are
+    /// visible to every user.
+    pub fn can_view_channel(&self, user: UserId, id: ChannelId) -> bool {
+        if let Some(channel) = self.direct_channels.read().get(&id) {
+            return channel.is_participant(user);
+        }
+
+        self.text_channel(id)
+            .is_none_or(|channel| self.can_read(user, &channel))
+    }
+
+    /// Returns the direct channels that the user is a participant in.
+    pub fn direct_channels(&self, user: UserId) -> Vec<Arc<DirectChannel>> {
+        self.direct_channels
+            .read()
+            .values()
+            .filter(|c| c.is_participant(user))
+            .cloned()
+            .collect()
+    }
+
+    /// Opens the storage for a text channel and starts it's worker.
+    fn open_text_channel(
+        &self,
+        id: ChannelId,
+        label: String,
+        settings: TextChannelSettings,
+    ) -> Result<TextChannel, TextChannelError> {
         // Construct the data directory for the channel.
-        let data_dir = self.config.data_dir.join("channels").join(id.0.to_string());
+        let data_dir = self.channel_dir(id);
 
         // SAFETY: Fjall database is syncronized for thread-safe
         //  access and can be cloned without external locks.
-        let channel = Arc::new(TextChannel::new(id, &data_dir, self.db.clone(), label)?);
+        let channel = TextChannel::new(
+            id,
+            &data_dir,
+            self.db.clone(),
+            label,
+            settings,
+            &TextChannelOptions {
+                index_writer_heap_bytes: self.config.index_writer_heap_bytes,
+                search_tokenizer: self.config.search_tokenizer,
+                instance_id: self.config.instance_id,
+                snowflake_epoch_ms: self.config.snowflake_epoch_ms,
+                max_pins: self.config.max_pins_per_channel,
+                allow_dangling_replies: self.config.allow_dangling_replies,
+                message_rate_limit: self.config.message_rate_limit,
+                message_rate_interval: Duration::from_millis(self.config.message_rate_interval_ms),
+                queue_capacity: self.config.channel_queue_capacity,
+                max_content_graphemes: self.config.max_message_graphemes,
+                max_content_bytes: self.config.max_message_bytes,
+                event_capacity: self.config.channel_event_capacity,
+                auto_moderation: self.config.auto_moderation.clone(),
+            },
+        )?;
 
-        // Add the channel to the global channel list.
-        self.text_channels
-            .write()
-            .map_err(|_| CreateChannelError::PoisonedChannelLock)?
-            .insert(id, Arc::clone(&channel));
+        // Spawn the task that notifies users mentioned in the channel.
+        let notifier = tokio::spawn(notify::mention_notifier(
+            id,
+            channel.subscribe(),
+            Arc::clone(&self.gateway),
+            Arc::clone(&self.notification_settings),
+        ));
+
+        // Spawn the task that dispatches the channel's events to watching sessions.
+        let dispatcher = tokio::spawn(gateway::channel_dispatcher(
+            id,
+            channel.subscribe(),
+            Arc::clone(&self.gateway),
+            Arc::clone(&self.permissions),
+            channel.shared_settings(),
+        ));
+        self.tasks.lock().extend([notifier, dispatcher]);
 
         Ok(channel)
     }
 
+    /// Replaces a text channel's settings and persists them.
+    pub fn set_text_channel_settings(
+        &self,
+        channel: &TextChannel,
+        settings: TextChannelSettings,
+    ) -> Result<(), fjall::Error> {
+        self.save_channel(
+            channel.channel_id(),
+            &ChannelRecord::Text {
+                label: channel.get_label().to_string(),
+                settings: settings.clone(),
+            },
+        )?;
+
+        channel.set_settings(settings);
+
+        self.emit(ServerEvent::ChannelUpdated {
+            id: channel.channel_id(),
+            label: channel.get_label().to_string(),
+            channel_type: channel.channel_type(),
+            topic: channel.settings().topic,
+        });
+
+        Ok(())
+    }
+
+    /// Returns a handle to the text channel with the specified ID.
+    pub fn text_channel(&self, id: ChannelId) -> Option<Arc<TextChannel>> {
+        self.text_channels.read().get(&id).cloned()
+    }
+
+    /// Returns a handle to the voice channel with the specified ID.
+    pub fn voice_channel(&self, id: ChannelId) -> Option<Arc<VoiceChannel>> {
+        self.voice_channels.read().get(&id).cloned()
+    }
+
+    /// Returns a list of handles to all the available voice channels.
+    pub fn voice_channels(&self) -> Vec<Arc<VoiceChannel>> {
+        self.voice_channels.read().values().cloned().collect()
+    }
+
     /// Returns a list of handles to all the available channels.
     pub fn text_channels(&self) -> Vec<Arc<TextChannel>> {
-        self.text_channels
+        self.text_channels.read().values().map(Arc::clone).collect()
+    }
+
+    /// Stops the workers of every text and direct channel, and the
+    /// tasks forwarding the events of every channel.
+    ///
+    /// Returns once every channel's messages are committed to it's search
+    /// index and synced to disk, so nothing is lost when the process exits.
+    /// None of the server's tasks hold the database open afterwards.
+    pub async fn shutdown(&self) {
+        let mut channels = self.text_channels();
+        channels.extend(
+            self.direct_channels
+                .read()
+                .values()
+                .map(|direct| direct.channel()),
+        );
+
+        futures::future::join_all(channels.iter().map(|channel| channel.shutdown())).await;
+
+        // Nothing is left to forward once the channels are shut down.
+        let tasks = std::mem::take(&mut *self.tasks.lock());
+        for task in &tasks {
+            task.abort();
+        }
+        futures::future::join_all(tasks).await;
+
+        tracing::info!(channels = channels.len(), "channels shut down");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::{server, server_with};
+
+    #[tokio::test]
+    async fn subscribers_receive_channel_list_changes() {
+        let server = server();
+        let state = &server.state;
+        let mut events = state.subscribe_events();
+
+        let channel = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        match events.try_recv().unwrap() {
+            ServerEvent::ChannelCreated { id, label, .. } => {
+                assert_eq!(id, channel.channel_id());
+                assert_eq!(label, "general");
+            }
+            event => panic!("expected a created event, got {event:?}"),
+        }
+
+        let settings = TextChannelSettings {
+            slow_mode_secs: Some(30),
+            ..Default::default()
+        };
+        state.set_text_channel_settings(&channel, settings).unwrap();
+        match events.try_recv().unwrap() {
+            ServerEvent::ChannelUpdated { id, label, .. } => {
+                assert_eq!(id, channel.channel_id());
+                assert_eq!(label, "general");
+            }
+            event => panic!("expected an updated event, got {event:?}"),
+        }
+
+        // Direct channels aren't in the channel list.
+        server.direct_channel(&[UserId(1), UserId(2)]);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn channels_are_only_visible_to_users_that_can_read_them() {
+        use crate::server::permission::Permissions;
+
+        let server = server();
+        let state = &server.state;
+        let (member, moderator) = (UserId(1), UserId(2));
+        state
+            .permissions()
             .read()
+            .set_permissions(moderator, Permissions::MANAGE_MESSAGES)
+            .unwrap();
+
+        let general = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
             .unwrap()
-            .values()
-            .map(|c| Arc::clone(c))
-            .collect()
+            .channel_id();
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let moderators = state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap()
+            .channel_id();
+
+        assert!(state.can_view_channel(member, general));
+        assert!(state.can_view_channel(moderator, general));
+        assert!(!state.can_view_channel(member, moderators));
+        assert!(state.can_view_channel(moderator, moderators));
+
+        // Direct channels are only visible to their participants.
+        let direct = server
+            .direct_channel(&[member, UserId(3)])
+            .channel()
+            .channel_id();
+        assert!(state.can_view_channel(member, direct));
+        assert!(!state.can_view_channel(moderator, direct));
+    }
+
+    #[tokio::test]
+    async fn created_at_counts_from_the_configured_epoch() {
+        // Recent enough that IDs from it decode to a different
+        // time than they would from the Unix or default epochs.
+        let epoch_ms = crate::id::now_ms() - 24 * 60 * 60 * 1000;
+        let server = server_with(|config| config.snowflake_epoch_ms(epoch_ms));
+
+        let before = crate::id::now_ms();
+        let channel = server
+            .state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let after = crate::id::now_ms();
+
+        let created_at_ms = channel.created_at_ms();
+        assert!(
+            (before..=after).contains(&created_at_ms),
+            "{created_at_ms} isn't between {before} and {after}"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_corrupted_index_is_recreated_and_reindexed() {
+        use crate::server::{
+            channel::text::tests::{test_message, test_query},
+            permission::Permissions,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = ConfigBuilder::default()
+            .data_dir(dir.path())
+            .build()
+            .unwrap();
+
+        let server = Server::new(config.clone()).unwrap();
+        // Only the IDs are kept, so the channels' storage is closed with the server.
+        let healthy = server
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap()
+            .channel_id();
+        let channel = server
+            .create_text_channel("random".to_string(), TextChannelSettings::default())
+            .unwrap();
+        channel
+            .create_message(test_message(UserId(1), "kindling"), None, Permissions::NONE)
+            .await
+            .unwrap();
+        let corrupted = channel.channel_id();
+        drop(channel);
+
+        let index_dir = server.channel_dir(corrupted).join(SEARCH_INDEX_DIR);
+        server.shutdown().await;
+        drop(server);
+
+        // Truncate every file in the index, as a crash mid-write might.
+        for entry in std::fs::read_dir(&index_dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                std::fs::File::create(&path).unwrap();
+            }
+        }
+
+        let server = Server::new(config).unwrap();
+        assert!(server.text_channel(healthy).is_some());
+        assert!(server.stats().failed_channels.is_empty());
+
+        // The stored message is added back to the recreated index in the background.
+        let channel = server.text_channel(corrupted).unwrap();
+        let mut hits = 0;
+        for _ in 0..50 {
+            hits = channel.search(test_query("kindling")).unwrap().hits.len();
+            if hits > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(hits, 1);
+    }
+
+    #[tokio::test]
+    async fn failed_channels_can_be_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ConfigBuilder::default()
+            .data_dir(dir.path())
+            .build()
+            .unwrap();
+
+        let server = Server::new(config.clone()).unwrap();
+        let healthy = server
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap()
+            .channel_id();
+        let broken = server
+            .create_text_channel("random".to_string(), TextChannelSettings::default())
+            .unwrap()
+            .channel_id();
+
+        let index_dir = server.channel_dir(broken).join(SEARCH_INDEX_DIR);
+        server.shutdown().await;
+        drop(server);
+
+        // A file in the way of the index can't be removed
+        // as a directory, so the index can't be recreated.
+        std::fs::remove_dir_all(&index_dir).unwrap();
+        std::fs::write(&index_dir, b"").unwrap();
+
+        let server = Server::new(config).unwrap();
+        assert!(server.text_channel(healthy).is_some());
+        assert!(server.text_channel(broken).is_none());
+
+        let failed = server.stats().failed_channels;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].channel_id, broken);
+
+        // Only failed channels are reloaded.
+        assert!(server.reload_failed_channel(healthy).unwrap().is_none());
+        assert!(server.reload_failed_channel(broken).is_err());
+        assert_eq!(server.failed_channels().len(), 1);
+
+        std::fs::remove_file(&index_dir).unwrap();
+        let reloaded = server.reload_failed_channel(broken).unwrap().unwrap();
+        assert_eq!(reloaded.channel_id(), broken);
+        assert!(server.text_channel(broken).is_some());
+        assert!(server.failed_channels().is_empty());
+    }
+
+    #[tokio::test]
+    async fn channels_can_be_created_up_to_the_limit() {
+        let server = server_with(|config| config.max_channels(2));
+        let state = &server.state;
+
+        // Text and voice channels both count towards the limit.
+        state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        state.create_voice_channel("lounge".to_string()).unwrap();
+
+        assert!(matches!(
+            state.create_text_channel("overflow".to_string(), TextChannelSettings::default()),
+            Err(CreateChannelError::LimitReached { max: 2 })
+        ));
+        assert!(matches!(
+            state.create_voice_channel("overflow".to_string()),
+            Err(CreateChannelError::LimitReached { max: 2 })
+        ));
+        assert_eq!(
+            state.text_channels().len() + state.voice_channels().len(),
+            2
+        );
+
+        // Direct channels aren't counted.
+        server.direct_channel(&[UserId(1), UserId(2)]);
+    }
+
+    #[tokio::test]
+    async fn direct_channels_are_limited_per_user_and_across_the_server() {
+        let server = server_with(|config| config.direct_channel_limits(2, 3));
+        let state = &server.state;
+        for user in 1..=5 {
+            state
+                .presence()
+                .read()
+                .record_seen(UserId(user), 0)
+                .unwrap();
+        }
+
+        let open = |creator: u64, other: u64| {
+            state.create_direct_channel(
+                UserId(creator),
+                BTreeSet::from([UserId(creator), UserId(other)]),
+            )
+        };
+
+        let first = open(1, 2).unwrap();
+        open(1, 3).unwrap();
+        assert!(matches!(
+            open(1, 4),
+            Err(CreateChannelError::DirectChannelError(
+                DirectChannelError::UserLimitReached { max: 2 }
+            ))
+        ));
+
+        // Existing channels are still returned, whoever asks for them.
+        assert!(Arc::ptr_eq(&open(1, 2).unwrap(), &first));
+        assert!(Arc::ptr_eq(&open(2, 1).unwrap(), &first));
+
+        // Channels count towards the user that opened them, not every participant.
+        open(2, 3).unwrap();
+        assert!(matches!(
+            open(3, 4),
+            Err(CreateChannelError::DirectChannelError(
+                DirectChannelError::ServerLimitReached { max: 3 }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn direct_channels_are_only_opened_with_known_users() {
+        let server = server();
+        let state = &server.state;
+        state.presence().read().record_seen(UserId(2), 0).unwrap();
+
+        assert!(matches!(
+            state.create_direct_channel(UserId(1), BTreeSet::from([UserId(1), UserId(9)])),
+            Err(CreateChannelError::DirectChannelError(
+                DirectChannelError::UnknownUser(UserId(9))
+            ))
+        ));
+
+        // The creator has to be a participant, and the system user can't be one.
+        for participants in [
+            BTreeSet::from([UserId(2), UserId(3)]),
+            BTreeSet::from([UserId(1), SYSTEM_USER_ID]),
+        ] {
+            assert!(matches!(
+                state.create_direct_channel(UserId(1), participants),
+                Err(CreateChannelError::DirectChannelError(
+                    DirectChannelError::InvalidParticipants
+                ))
+            ));
+        }
+
+        state
+            .create_direct_channel(UserId(1), BTreeSet::from([UserId(1), UserId(2)]))
+            .unwrap();
+        assert_eq!(state.direct_channels(UserId(2)).len(), 1);
     }
 }
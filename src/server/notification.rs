@@ -0,0 +1,147 @@
+//! User notification preferences.
+//!
+//! Users pick a notification level for the server as a whole, and can
+//! override it for individual channels. Muting a channel suppresses its
+//! notifications without affecting the messages delivered to clients
+//! watching the channel.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{channel::ChannelId, user::UserId};
+
+/// Which messages a user is notified of.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+    /// Notified of every message.
+    #[default]
+    All,
+    /// Only notified when mentioned.
+    MentionsOnly,
+    /// Never notified.
+    None,
+}
+
+/// A user's notification preferences for a channel or the server.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize, schemars::JsonSchema,
+)]
+pub struct NotificationSettings {
+    /// Suppresses every notification while set.
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub level: NotificationLevel,
+}
+
+impl NotificationSettings {
+    /// Returns true if the user should be notified when they're mentioned.
+    pub fn notifies_mentions(&self) -> bool {
+        !self.muted && self.level != NotificationLevel::None
+    }
+}
+
+/// Indicates there was an error accessing notification settings.
+#[derive(Debug)]
+pub enum NotificationSettingsError {
+    /// Indicates a stored settings record couldn't be encoded or decoded.
+    CorruptRecord(serde_json::Error),
+    /// Indicates there was an error accessing the settings keyspace.
+    DatabaseError(fjall::Error),
+}
+
+/// Service for storing user notification preferences.
+pub struct NotificationSettingsService {
+    /// Keyspace storing the settings records.
+    ///
+    /// Server-wide settings are keyed by the user ID, and channel
+    /// overrides by the user ID followed by the channel ID.
+    keyspace: fjall::Keyspace,
+}
+
+impl NotificationSettingsService {
+    /// Constructs the service, opening or creating the notification settings keyspace.
+    pub fn new(db: &fjall::Database) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace(
+            "notification_settings",
+            fjall::KeyspaceCreateOptions::default,
+        )?;
+
+        Ok(Self { keyspace })
+    }
+
+    /// Returns the settings that apply to the user in the channel.
+    ///
+    /// Channels without an override use the user's server-wide settings.
+    /// Errors reading the keyspace are logged and treated as the defaults.
+    pub fn settings(&self, user: UserId, channel: ChannelId) -> NotificationSettings {
+        self.get(&channel_key(user, channel))
+            .or_else(|| self.get(&user.0.to_be_bytes()))
+            .unwrap_or_default()
+    }
+
+    /// Returns the user's server-wide settings.
+    pub fn server_settings(&self, user: UserId) -> NotificationSettings {
+        self.get(&user.0.to_be_bytes()).unwrap_or_default()
+    }
+
+    /// Replaces the user's settings for the channel.
+    pub fn set_channel_settings(
+        &self,
+        user: UserId,
+        channel: ChannelId,
+        settings: NotificationSettings,
+    ) -> Result<(), NotificationSettingsError> {
+        self.set(&channel_key(user, channel), settings)
+    }
+
+    /// Replaces the user's server-wide settings.
+    pub fn set_server_settings(
+        &self,
+        user: UserId,
+        settings: NotificationSettings,
+    ) -> Result<(), NotificationSettingsError> {
+        self.set(&user.0.to_be_bytes(), settings)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<NotificationSettings> {
+        let bytes = match self.keyspace.get(key) {
+            Ok(bytes) => bytes?,
+            Err(err) => {
+                tracing::error!(%err, "failed to read notification settings");
+                return None;
+            }
+        };
+
+        match serde_json::from_slice(&bytes) {
+            Ok(settings) => Some(settings),
+            Err(err) => {
+                tracing::error!(%err, "corrupt notification settings record");
+                None
+            }
+        }
+    }
+
+    fn set(
+        &self,
+        key: &[u8],
+        settings: NotificationSettings,
+    ) -> Result<(), NotificationSettingsError> {
+        let record =
+            serde_json::to_vec(&settings).map_err(NotificationSettingsError::CorruptRecord)?;
+
+        self.keyspace
+            .insert(key, record)
+            .map_err(NotificationSettingsError::DatabaseError)
+    }
+}
+
+/// Builds the keyspace key for a user's settings in a channel.
+fn channel_key(user: UserId, channel: ChannelId) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&user.0.to_be_bytes());
+    key[8..].copy_from_slice(&channel.0.to_be_bytes());
+    key
+}
@@ -0,0 +1,189 @@
+//! Notifies users when they're mentioned in a message.
+//!
+//! A notifier task runs alongside each channel's worker, watching for new
+//! messages and sending a mention event to the sessions of each mentioned
+//! user. Users receive the notification even if their client isn't
+//! watching the channel the message was sent in, unless their
+//! notification settings for the channel suppress it.
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    channel::ChannelId,
+    proto::v0::{self, GatewayServerEvent, gateway_server_event},
+    server::{
+        channel::text::{TextChannelEvent, TextChannelMessage},
+        gateway::GatewayService,
+        notification::NotificationSettingsService,
+    },
+    user::UserId,
+};
+
+/// Notification task that runs for each channel to deliver mention events.
+#[tracing::instrument(skip(events, gateway, settings))]
+pub async fn mention_notifier(
+    channel_id: ChannelId,
+    mut events: broadcast::Receiver<TextChannelEvent>,
+    gateway: Arc<RwLock<GatewayService>>,
+    settings: Arc<RwLock<NotificationSettingsService>>,
+) {
+    loop {
+        let message = match events.recv().await {
+            Ok(TextChannelEvent::NewMessage(message)) => message,
+            Ok(_) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    skipped,
+                    "mention notifier lagged, notifications were dropped"
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let gateway = gateway.read();
+        let settings = settings.read();
+        for user in notified_users(&message) {
+            if settings.settings(user, channel_id).notifies_mentions() {
+                gateway.send_to_user(user, mentioned_event(channel_id, &message));
+            }
+        }
+    }
+
+    tracing::info!("mention notifier exit");
+}
+
+/// Returns the users that should be notified of a message.
+///
+/// Users aren't notified when they mention themselves. Role mentions
+/// aren't resolved, since users can't be assigned roles yet.
+fn notified_users(message: &TextChannelMessage) -> impl Iterator<Item = UserId> + '_ {
+    message
+        .mentions
+        .users
+        .iter()
+        .copied()
+        .filter(|&user| user != message.author)
+}
+
+/// Builds the gateway event notifying a user they were mentioned.
+fn mentioned_event(channel_id: ChannelId, message: &TextChannelMessage) -> GatewayServerEvent {
+    GatewayServerEvent {
+        event: Some(gateway_server_event::Event::Mentioned(v0::Mentioned {
+            channel_id: channel_id.0,
+            message_id: message.id.0,
+        })),
+        seq: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        http::tests::server,
+        message::format_user,
+        server::{
+            channel::{
+                Channel,
+                text::{TextChannelSettings, tests::test_message},
+            },
+            notification::{NotificationLevel, NotificationSettings},
+            permission::Permissions,
+        },
+    };
+
+    /// Returns the IDs of the messages the session was notified of
+    /// within a short wait, ignoring other events.
+    async fn mentions(events: &mut broadcast::Receiver<GatewayServerEvent>) -> Vec<u64> {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut mentioned = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let Some(gateway_server_event::Event::Mentioned(mention)) = event.event {
+                mentioned.push(mention.message_id);
+            }
+        }
+
+        mentioned
+    }
+
+    #[tokio::test]
+    async fn mentioned_users_are_notified() {
+        let server = server();
+        let state = &server.state;
+        let channel = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+
+        let subscribe = |user| {
+            let session = state
+                .gateway()
+                .write()
+                .create_session(user, Default::default())
+                .unwrap();
+            let events = session.read().subscribe();
+            (session, events)
+        };
+        let (_mentioned, mut mentioned_events) = subscribe(UserId(2));
+        let (_uninvolved, mut uninvolved_events) = subscribe(UserId(3));
+
+        // Neither user is watching the channel.
+        let content = format!("hey {}", format_user(UserId(2)));
+        let message = channel
+            .create_message(test_message(UserId(1), &content), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        assert_eq!(mentions(&mut mentioned_events).await, [message.id.0]);
+        assert!(mentions(&mut uninvolved_events).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn muted_channels_dont_notify_mentions() {
+        let server = server();
+        let state = &server.state;
+        let channel = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+
+        let subscribe = |user, settings| {
+            state
+                .notification_settings()
+                .read()
+                .set_channel_settings(user, channel.channel_id(), settings)
+                .unwrap();
+            let session = state
+                .gateway()
+                .write()
+                .create_session(user, Default::default())
+                .unwrap();
+            let events = session.read().subscribe();
+            (session, events)
+        };
+        let muted = NotificationSettings {
+            muted: true,
+            ..Default::default()
+        };
+        let mentions_only = NotificationSettings {
+            level: NotificationLevel::MentionsOnly,
+            ..Default::default()
+        };
+        let (_muted, mut muted_events) = subscribe(UserId(2), muted);
+        let (_mentions_only, mut mentions_only_events) = subscribe(UserId(3), mentions_only);
+
+        let content = format!("hey {} {}", format_user(UserId(2)), format_user(UserId(3)));
+        let message = channel
+            .create_message(test_message(UserId(1), &content), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        assert!(mentions(&mut muted_events).await.is_empty());
+        assert_eq!(mentions(&mut mentions_only_events).await, [message.id.0]);
+    }
+}
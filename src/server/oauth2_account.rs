@@ -0,0 +1,101 @@
+//! Links the accounts users log in with at OAuth2 providers to local users.
+//!
+//! The first login with a provider account creates a new local user, and
+//! later logins with the same account authenticate as that user again.
+
+use parking_lot::Mutex;
+
+use crate::{id::id_generator, user::UserId};
+
+/// Indicates there was an error looking up or linking a provider account.
+#[derive(Debug)]
+pub enum OAuth2AccountError {
+    /// Indicates the stored user ID isn't a valid ID.
+    CorruptRecord,
+    /// Indicates there was an error accessing the account keyspace.
+    DatabaseError(fjall::Error),
+}
+
+/// Storage for the provider accounts linked to local users.
+pub struct OAuth2AccountStore {
+    id_generator: snowflaked::sync::Generator,
+
+    /// Keyspace storing the local user IDs keyed by provider and account ID.
+    keyspace: fjall::Keyspace,
+
+    /// Held while linking an account, so concurrent first logins
+    /// with the same account can't create two users.
+    linking: Mutex<()>,
+}
+
+impl OAuth2AccountStore {
+    /// Opens or creates the OAuth2 account keyspace.
+    pub fn open(
+        db: &fjall::Database,
+        instance_id: u16,
+        snowflake_epoch_ms: u64,
+    ) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("oauth2_accounts", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self {
+            id_generator: id_generator(instance_id, snowflake_epoch_ms),
+            keyspace,
+            linking: Mutex::new(()),
+        })
+    }
+
+    /// Returns the local user linked to the provider's account,
+    /// creating a new user if the account hasn't logged in before.
+    pub fn user(&self, provider: &str, account: &str) -> Result<UserId, OAuth2AccountError> {
+        let key = account_key(provider, account);
+        let _linking = self.linking.lock();
+
+        let existing = self
+            .keyspace
+            .get(&key)
+            .map_err(OAuth2AccountError::DatabaseError)?;
+        if let Some(bytes) = existing {
+            let id = bytes
+                .as_ref()
+                .try_into()
+                .map_err(|_| OAuth2AccountError::CorruptRecord)?;
+            return Ok(UserId(u64::from_be_bytes(id)));
+        }
+
+        let user = UserId(self.id_generator.generate());
+        self.keyspace
+            .insert(key, user.0.to_be_bytes())
+            .map_err(OAuth2AccountError::DatabaseError)?;
+
+        tracing::info!(provider, user_id = ?user, "created user for oauth2 account");
+
+        Ok(user)
+    }
+}
+
+/// Builds the key of a provider's account.
+///
+/// The provider and account are separated by a NUL, which
+/// isn't expected in either, so keys can't be ambiguous.
+fn account_key(provider: &str, account: &str) -> Vec<u8> {
+    [provider.as_bytes(), &[0], account.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_log_in_as_the_same_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        let accounts = OAuth2AccountStore::open(&db, 0, 0).unwrap();
+
+        let user = accounts.user("github", "42").unwrap();
+        assert_eq!(accounts.user("github", "42").unwrap(), user);
+
+        // The same account ID at another provider is another person.
+        assert_ne!(accounts.user("gitlab", "42").unwrap(), user);
+        assert_ne!(accounts.user("github", "43").unwrap(), user);
+    }
+}
@@ -0,0 +1,120 @@
+//! User permissions for privileged server actions.
+//!
+//! Permissions are granted to users individually and stored in the
+//! permissions keyspace. Users configured as server admins implicitly
+//! hold every permission.
+
+use std::{
+    collections::HashSet,
+    ops::{BitOr, BitOrAssign},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::user::UserId;
+
+/// A set of permissions held by a user.
+///
+/// Serialized as the bitfield of the permissions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Permissions(pub u64);
+
+impl Permissions {
+    /// No permissions.
+    pub const NONE: Permissions = Permissions(0);
+    /// Allows pinning and deleting other users' messages.
+    pub const MANAGE_MESSAGES: Permissions = Permissions(1 << 0);
+    /// Allows creating, editing, and deleting channels.
+    pub const MANAGE_CHANNELS: Permissions = Permissions(1 << 1);
+    /// Exempts the user from channel slow mode.
+    pub const BYPASS_SLOW_MODE: Permissions = Permissions(1 << 2);
+    /// Allows muting and deafening other users in voice channels.
+    pub const MUTE_MEMBERS: Permissions = Permissions(1 << 3);
+    /// Every permission, held by server admins.
+    pub const ALL: Permissions = Permissions(u64::MAX);
+
+    /// Returns true if every permission in `other` is held.
+    pub fn contains(self, other: Permissions) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Permissions(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Permissions {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Service for resolving and granting user permissions.
+pub struct PermissionService {
+    /// Keyspace storing the permissions granted to each user.
+    keyspace: fjall::Keyspace,
+
+    /// Users that implicitly hold every permission.
+    admins: HashSet<UserId>,
+}
+
+impl PermissionService {
+    /// Constructs the permission service, opening or creating the permissions keyspace.
+    pub fn new(db: &fjall::Database, admins: &[UserId]) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("permissions", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self {
+            keyspace,
+            admins: admins.iter().copied().collect(),
+        })
+    }
+
+    /// Returns true if the user is configured as a server admin.
+    pub fn is_admin(&self, user: UserId) -> bool {
+        self.admins.contains(&user)
+    }
+
+    /// Returns the permissions held by the user.
+    ///
+    /// Errors reading the keyspace are logged and treated as no permissions.
+    pub fn permissions(&self, user: UserId) -> Permissions {
+        if self.admins.contains(&user) {
+            return Permissions::ALL;
+        }
+
+        match self.keyspace.get(user.0.to_be_bytes()) {
+            Ok(Some(bytes)) => match bytes.as_ref().try_into() {
+                Ok(bits) => Permissions(u64::from_be_bytes(bits)),
+                Err(_) => {
+                    tracing::error!(user_id = ?user, "corrupt permissions record");
+                    Permissions::NONE
+                }
+            },
+            Ok(None) => Permissions::NONE,
+            Err(err) => {
+                tracing::error!(%err, user_id = ?user, "failed to read permissions");
+                Permissions::NONE
+            }
+        }
+    }
+
+    /// Returns true if the user holds every permission in `required`.
+    pub fn has(&self, user: UserId, required: Permissions) -> bool {
+        self.permissions(user).contains(required)
+    }
+
+    /// Replaces the permissions granted to the user.
+    pub fn set_permissions(
+        &self,
+        user: UserId,
+        permissions: Permissions,
+    ) -> Result<(), fjall::Error> {
+        self.keyspace
+            .insert(user.0.to_be_bytes(), permissions.0.to_be_bytes())
+    }
+}
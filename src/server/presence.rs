@@ -0,0 +1,47 @@
+//! Tracks when users were last connected to the gateway.
+//!
+//! The last seen time is recorded when a user's gateway connection
+//! closes, so clients can show how long ago an offline user was online.
+
+use crate::user::UserId;
+
+/// Service for storing when users were last seen.
+pub struct PresenceService {
+    /// Keyspace storing the last seen time in seconds, keyed by user ID.
+    keyspace: fjall::Keyspace,
+}
+
+impl PresenceService {
+    /// Constructs the presence service, opening or creating the last seen keyspace.
+    pub fn new(db: &fjall::Database) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("last_seen", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self { keyspace })
+    }
+
+    /// Returns when the user was last seen, in seconds since the Unix epoch.
+    ///
+    /// Errors reading the keyspace are logged and treated as never seen.
+    pub fn last_seen(&self, user: UserId) -> Option<i64> {
+        match self.keyspace.get(user.0.to_be_bytes()) {
+            Ok(Some(bytes)) => match bytes.as_ref().try_into() {
+                Ok(seen) => Some(i64::from_be_bytes(seen)),
+                Err(_) => {
+                    tracing::error!(user_id = ?user, "corrupt last seen record");
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(%err, user_id = ?user, "failed to read last seen");
+                None
+            }
+        }
+    }
+
+    /// Records that the user was seen at the time, in seconds since the Unix epoch.
+    pub fn record_seen(&self, user: UserId, seen_s: i64) -> Result<(), fjall::Error> {
+        self.keyspace
+            .insert(user.0.to_be_bytes(), seen_s.to_be_bytes())
+    }
+}
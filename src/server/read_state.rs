@@ -0,0 +1,78 @@
+//! Tracks how far each user has read in each channel.
+//!
+//! The read state is the ID of the last message the user has read in a
+//! channel. Messages after it are unread. Channels the user hasn't read
+//! yet have no read state, so every message in them is unread.
+
+use crate::{channel::ChannelId, message::MessageId, user::UserId};
+
+/// The most unread messages counted in a channel.
+///
+/// Counting scans the channel's messages, so counts stop here rather than
+/// scanning a busy channel's whole history. Clients should show a count
+/// this high as "999+".
+pub const MAX_UNREAD_COUNT: u64 = 1000;
+
+/// Service for storing the read state of users in channels.
+pub struct ReadStateService {
+    /// Keyspace storing the last read message ID, keyed by user and channel ID.
+    keyspace: fjall::Keyspace,
+}
+
+impl ReadStateService {
+    /// Constructs the read state service, opening or creating the read state keyspace.
+    pub fn new(db: &fjall::Database) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("read_states", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self { keyspace })
+    }
+
+    /// Returns the ID of the last message the user read in the channel.
+    ///
+    /// Errors reading the keyspace are logged and treated as nothing read.
+    pub fn last_read(&self, user: UserId, channel: ChannelId) -> Option<MessageId> {
+        match self.keyspace.get(read_state_key(user, channel)) {
+            Ok(Some(bytes)) => match bytes.as_ref().try_into() {
+                Ok(id) => Some(MessageId(u64::from_be_bytes(id))),
+                Err(_) => {
+                    tracing::error!(user_id = ?user, channel_id = ?channel, "corrupt read state record");
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(err) => {
+                tracing::error!(%err, user_id = ?user, channel_id = ?channel, "failed to read read state");
+                None
+            }
+        }
+    }
+
+    /// Marks the messages in the channel up to and including the message as read.
+    ///
+    /// The read state only moves forward, acknowledging an older message
+    /// than the one already read leaves it unchanged. Returns the message
+    /// the user has now read up to.
+    pub fn ack(
+        &self,
+        user: UserId,
+        channel: ChannelId,
+        message: MessageId,
+    ) -> Result<MessageId, fjall::Error> {
+        if let Some(last_read) = self.last_read(user, channel).filter(|id| id.0 >= message.0) {
+            return Ok(last_read);
+        }
+
+        self.keyspace
+            .insert(read_state_key(user, channel), message.0.to_be_bytes())?;
+
+        Ok(message)
+    }
+}
+
+/// Builds the keyspace key for a user's read state in a channel.
+fn read_state_key(user: UserId, channel: ChannelId) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&user.0.to_be_bytes());
+    key[8..].copy_from_slice(&channel.0.to_be_bytes());
+    key
+}
@@ -0,0 +1,176 @@
+//! Searching the messages across every channel a user can read.
+
+use std::{cmp::Reverse, sync::Arc};
+
+use futures::future::join_all;
+
+use crate::{
+    channel::ChannelId,
+    server::{
+        Server,
+        channel::{
+            Channel,
+            text::{
+                TextChannel,
+                search::{MAX_SEARCH_LIMIT, SearchError, SearchHit, SearchKey, SearchQuery},
+            },
+        },
+    },
+    user::UserId,
+};
+
+/// A message matched by a search across multiple channels.
+pub struct ChannelSearchHit {
+    /// The channel the matched message is in.
+    pub channel_id: ChannelId,
+    /// The matched message.
+    pub hit: SearchHit,
+}
+
+impl Server {
+    /// Returns true if the user can read the messages in the channel.
+    ///
+    /// Direct channels can only be read by their participants, and server
+    /// channels by the users holding the channel's read permissions.
+    pub fn can_read(&self, user: UserId, channel: &TextChannel) -> bool {
+        let channel_id = channel.channel_id();
+        if self.text_channel(channel_id).is_none() {
+            return self.direct_channel(user, channel_id).is_ok();
+        }
+
+        self.permissions()
+            .read()
+            .has(user, channel.settings().read_permissions)
+    }
+
+    /// Returns the text channels that the user can read.
+    ///
+    /// These are the server channels the user holds the read permissions
+    /// of, along with the direct channels the user participates in.
+    pub fn readable_channels(&self, user: UserId) -> Vec<Arc<TextChannel>> {
+        let mut channels: Vec<_> = self
+            .text_channels()
+            .into_iter()
+            .filter(|channel| self.can_read(user, channel))
+            .collect();
+        channels.extend(
+            self.direct_channels(user)
+                .iter()
+                .map(|direct| direct.channel()),
+        );
+
+        channels
+    }
+
+    /// Searches the messages in every channel that the user can read.
+    ///
+    /// Each channel has it's own search index, so the query is run
+    /// against every channel concurrently and the results merged in the
+    /// query's order, the same order as a search of a single channel.
+    pub async fn search_all(
+        &self,
+        user: UserId,
+        query: SearchQuery,
+        limit: usize,
+    ) -> Result<Vec<ChannelSearchHit>, SearchError> {
+        let limit = limit.clamp(1, MAX_SEARCH_LIMIT);
+
+        let searches = self.readable_channels(user).into_iter().map(|channel| {
+            // Any channel could hold all of the top results.
+            // Results across channels aren't paged.
+            let query = SearchQuery {
+                limit,
+                cursor: None,
+                ..query.clone()
+            };
+
+            // Searching is blocking, so run each one on the blocking pool.
+            tokio::task::spawn_blocking(move || {
+                let channel_id = channel.channel_id();
+                channel.search(query).map(|page| {
+                    page.hits
+                        .into_iter()
+                        .map(|hit| ChannelSearchHit { channel_id, hit })
+                        .collect::<Vec<_>>()
+                })
+            })
+        });
+
+        let mut hits = Vec::new();
+        for result in join_all(searches).await {
+            match result {
+                Ok(channel_hits) => hits.extend(channel_hits?),
+                Err(err) => tracing::error!(%err, "channel search task failed"),
+            }
+        }
+
+        // Message IDs are unique across the server, so
+        // the merged order doesn't depend on the channels.
+        hits.sort_by_key(|hit| Reverse(SearchKey::of(query.sort, &hit.hit)));
+        hits.truncate(limit);
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::tests::server,
+        server::{
+            channel::text::{
+                TextChannelSettings,
+                tests::{test_message, test_query, wait_for_commit},
+            },
+            permission::Permissions,
+        },
+    };
+
+    #[tokio::test]
+    async fn search_all_skips_channels_the_user_cant_read() {
+        let server = server();
+        let state = &server.state;
+
+        let public = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let settings = TextChannelSettings {
+            read_permissions: Permissions::MANAGE_MESSAGES,
+            ..Default::default()
+        };
+        let restricted = state
+            .create_text_channel("moderators".to_string(), settings)
+            .unwrap();
+        state
+            .permissions()
+            .read()
+            .set_permissions(UserId(2), Permissions::MANAGE_MESSAGES)
+            .unwrap();
+
+        for channel in [&public, &restricted] {
+            channel
+                .create_message(
+                    test_message(UserId(2), "bonfire tonight"),
+                    None,
+                    Permissions::NONE,
+                )
+                .await
+                .unwrap();
+        }
+        wait_for_commit().await;
+
+        let hits = state
+            .search_all(UserId(1), test_query("bonfire"), 10)
+            .await
+            .unwrap();
+        let channels: Vec<_> = hits.iter().map(|hit| hit.channel_id).collect();
+        assert_eq!(channels, [public.channel_id()]);
+
+        let hits = state
+            .search_all(UserId(2), test_query("bonfire"), 10)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+    }
+}
@@ -0,0 +1,324 @@
+//! Expiring tokens issued to users when they log in.
+//!
+//! Session tokens have the same `<token id>.<secret>` form as bot
+//! tokens, but stop being accepted once they expire. A token that
+//! hasn't expired yet can be refreshed, which replaces it with a
+//! new token that expires a full session lifetime later.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use parking_lot::Mutex;
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    id::id_generator,
+    server::auth::constant_time_eq,
+    user::{SYSTEM_USER_ID, UserId},
+};
+
+/// Length of the generated session token secrets.
+const SESSION_TOKEN_SECRET_LEN: usize = 48;
+
+/// A newly issued session token.
+pub struct IssuedSessionToken {
+    /// The full token to present when authenticating.
+    pub token: String,
+    /// The user the token authenticates as.
+    pub user: UserId,
+    /// When the token expires in milliseconds since the Unix epoch.
+    pub expires_at_ms: i64,
+}
+
+/// The session token as stored in the session token keyspace.
+#[derive(Serialize, Deserialize)]
+struct SessionTokenRecord {
+    user: UserId,
+    secret: String,
+    expires_at_ms: i64,
+}
+
+/// Indicates there was an error issuing, refreshing, or revoking a session token.
+#[derive(Debug)]
+pub enum SessionTokenError {
+    /// Indicates the token would authenticate as the reserved [`SYSTEM_USER_ID`].
+    ReservedUser,
+    /// Indicates the stored token record couldn't be encoded.
+    CorruptRecord(serde_json::Error),
+    /// Indicates there was an error accessing the session token keyspace.
+    DatabaseError(fjall::Error),
+}
+
+/// Storage for the session tokens issued to users.
+pub struct SessionTokenStore {
+    id_generator: snowflaked::sync::Generator,
+
+    /// Keyspace storing the token records keyed by token ID.
+    keyspace: fjall::Keyspace,
+
+    /// How long issued tokens are accepted for.
+    lifetime: Duration,
+
+    /// Held while a token is checked and removed, so concurrent
+    /// refreshes of the same token can't both succeed.
+    removing: Mutex<()>,
+}
+
+impl SessionTokenStore {
+    /// Opens or creates the session token keyspace.
+    ///
+    /// Tokens are issued to expire after `lifetime`.
+    pub fn open(
+        db: &fjall::Database,
+        instance_id: u16,
+        snowflake_epoch_ms: u64,
+        lifetime: Duration,
+    ) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("session_tokens", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self {
+            id_generator: id_generator(instance_id, snowflake_epoch_ms),
+            keyspace,
+            lifetime,
+            removing: Mutex::new(()),
+        })
+    }
+
+    /// Issues a new token that authenticates as the user.
+    ///
+    /// Tokens can't be issued for [`SYSTEM_USER_ID`], so nobody can post as the
+    /// server's system author. Tokens that have expired since the last token
+    /// was issued are removed.
+    pub fn issue(&self, user: UserId) -> Result<IssuedSessionToken, SessionTokenError> {
+        if user == SYSTEM_USER_ID {
+            return Err(SessionTokenError::ReservedUser);
+        }
+
+        self.remove_expired()
+            .map_err(SessionTokenError::DatabaseError)?;
+
+        let id: u64 = self.id_generator.generate();
+
+        let secret: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(SESSION_TOKEN_SECRET_LEN)
+            .map(char::from)
+            .collect();
+
+        let record = SessionTokenRecord {
+            user,
+            secret: secret.clone(),
+            expires_at_ms: Utc::now().timestamp_millis() + self.lifetime.as_millis() as i64,
+        };
+
+        self.keyspace
+            .insert(
+                id.to_be_bytes(),
+                serde_json::to_vec(&record).map_err(SessionTokenError::CorruptRecord)?,
+            )
+            .map_err(SessionTokenError::DatabaseError)?;
+
+        Ok(IssuedSessionToken {
+            token: format!("{id}.{secret}"),
+            user,
+            expires_at_ms: record.expires_at_ms,
+        })
+    }
+
+    /// Returns the user a session token authenticates as, if it's valid and unexpired.
+    pub fn validate(&self, token: &str) -> Option<UserId> {
+        self.lookup(token).map(|(_, user)| user)
+    }
+
+    /// Replaces an unexpired token with a new token for the same user.
+    ///
+    /// The old token is revoked, so each token can only be refreshed once.
+    ///
+    /// Returns `None` if the token isn't a valid session token, or has expired.
+    pub fn refresh(&self, token: &str) -> Result<Option<IssuedSessionToken>, SessionTokenError> {
+        // The old token is removed before the new one is issued, so
+        // only one of several concurrent refreshes gets a new token.
+        let Some((id, user)) = self.take(token)? else {
+            return Ok(None);
+        };
+
+        let issued = self.issue(user)?;
+
+        tracing::info!(token_id = id, user_id = ?user, "refreshed session token");
+
+        Ok(Some(issued))
+    }
+
+    /// Revokes a session token so it can no longer be used to authenticate.
+    ///
+    /// Returns false if the token wasn't a valid session token.
+    pub fn revoke(&self, token: &str) -> Result<bool, SessionTokenError> {
+        let Some((id, user)) = self.take(token)? else {
+            return Ok(false);
+        };
+
+        tracing::info!(token_id = id, user_id = ?user, "revoked session token");
+
+        Ok(true)
+    }
+
+    /// Removes a session token if it's valid, returning it's ID and the user it authenticated as.
+    fn take(&self, token: &str) -> Result<Option<(u64, UserId)>, SessionTokenError> {
+        let _removing = self.removing.lock();
+
+        let Some((id, user)) = self.lookup(token) else {
+            return Ok(None);
+        };
+
+        self.keyspace
+            .remove(id.to_be_bytes())
+            .map_err(SessionTokenError::DatabaseError)?;
+
+        Ok(Some((id, user)))
+    }
+
+    /// Removes the tokens that have expired.
+    ///
+    /// Token IDs are snowflakes and every token is issued with the same
+    /// lifetime, so tokens expire in the order of their keys. The scan
+    /// stops at the first unexpired token, rather than reading every token.
+    ///
+    /// Returns the number of tokens removed.
+    fn remove_expired(&self) -> Result<usize, fjall::Error> {
+        let now_ms = Utc::now().timestamp_millis();
+
+        // Collect the keys before removing them so the
+        // keyspace isn't modified while it's being iterated.
+        let mut expired = Vec::new();
+        for guard in self.keyspace.iter() {
+            let (key, bytes) = guard.into_inner()?;
+
+            // Corrupt records can never be used, so they're removed too.
+            let expires_at_ms = serde_json::from_slice::<SessionTokenRecord>(&bytes)
+                .map_or(i64::MIN, |record| record.expires_at_ms);
+            if expires_at_ms > now_ms {
+                break;
+            }
+
+            expired.push(key);
+        }
+
+        let removed = expired.len();
+        for key in expired {
+            self.keyspace.remove(key)?;
+        }
+
+        if removed > 0 {
+            tracing::debug!(removed, "removed expired session tokens");
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns the ID of a session token and the user it authenticates as, if it's valid.
+    ///
+    /// Expired tokens are treated as invalid.
+    fn lookup(&self, token: &str) -> Option<(u64, UserId)> {
+        let (id, secret) = token.split_once('.')?;
+        let id: u64 = id.parse().ok()?;
+
+        let bytes = match self.keyspace.get(id.to_be_bytes()) {
+            Ok(bytes) => bytes?,
+            Err(err) => {
+                tracing::error!(%err, token_id = id, "failed to read session token");
+                return None;
+            }
+        };
+
+        let record: SessionTokenRecord = match serde_json::from_slice(&bytes) {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::error!(%err, token_id = id, "corrupt session token record");
+                return None;
+            }
+        };
+
+        if !constant_time_eq(record.secret.as_bytes(), secret.as_bytes()) {
+            return None;
+        }
+
+        if record.expires_at_ms <= Utc::now().timestamp_millis() {
+            return None;
+        }
+
+        Some((id, record.user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &tempfile::TempDir, lifetime: Duration) -> SessionTokenStore {
+        let db = fjall::Database::builder(dir.path()).open().unwrap();
+        SessionTokenStore::open(&db, 0, 0, lifetime).unwrap()
+    }
+
+    #[test]
+    fn refresh_extends_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir, Duration::from_secs(60));
+
+        let issued = store.issue(UserId(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let refreshed = store.refresh(&issued.token).unwrap().unwrap();
+
+        assert_eq!(refreshed.user, UserId(1));
+        assert!(refreshed.expires_at_ms > issued.expires_at_ms);
+        assert_eq!(store.validate(&refreshed.token), Some(UserId(1)));
+
+        // The refreshed token replaces the old one.
+        assert_eq!(store.validate(&issued.token), None);
+        assert!(store.refresh(&issued.token).unwrap().is_none());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir, Duration::ZERO);
+
+        let issued = store.issue(UserId(1)).unwrap();
+
+        assert_eq!(store.validate(&issued.token), None);
+        assert!(store.refresh(&issued.token).unwrap().is_none());
+    }
+
+    #[test]
+    fn concurrent_refreshes_of_a_token_only_succeed_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir, Duration::from_secs(60));
+
+        let issued = store.issue(UserId(1)).unwrap();
+
+        let refreshed = std::thread::scope(|scope| {
+            let refreshes: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| store.refresh(&issued.token).unwrap()))
+                .collect();
+
+            refreshes
+                .into_iter()
+                .filter_map(|refresh| refresh.join().unwrap())
+                .count()
+        });
+
+        assert_eq!(refreshed, 1);
+    }
+
+    #[test]
+    fn expired_tokens_are_removed_when_a_token_is_issued() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir, Duration::ZERO);
+
+        store.issue(UserId(1)).unwrap();
+        store.issue(UserId(2)).unwrap();
+
+        // Issuing the second token removed the first, which had expired.
+        assert_eq!(store.keyspace.len().unwrap(), 1);
+    }
+}
@@ -0,0 +1,145 @@
+//! Point-in-time statistics about the server for operator dashboards.
+
+use crate::{
+    channel::ChannelId,
+    server::{FailedChannel, Server, channel::Channel},
+};
+
+/// A snapshot of the server's activity.
+pub struct ServerStats {
+    /// Number of channels in the channel list.
+    pub text_channels: usize,
+    /// Number of private direct channels.
+    pub direct_channels: usize,
+    /// Number of messages stored across every channel.
+    pub messages: u64,
+    /// Number of gateway sessions with a connected client.
+    pub active_sessions: usize,
+    /// Statistics for each channel in the channel list.
+    pub channels: Vec<ChannelStats>,
+    /// The persisted channels that couldn't be loaded.
+    pub failed_channels: Vec<FailedChannel>,
+}
+
+/// A snapshot of a channel's activity.
+pub struct ChannelStats {
+    pub channel_id: ChannelId,
+    /// Number of messages stored in the channel.
+    pub message_count: u64,
+    /// Number of transports subscribed to the channel's events.
+    pub subscribers: usize,
+}
+
+impl Server {
+    /// Takes a snapshot of the server's activity.
+    ///
+    /// The channel maps and session table are only locked long enough to
+    /// copy out what's needed, and channel workers aren't touched, so
+    /// taking a snapshot doesn't hold up message ingest.
+    pub fn stats(&self) -> ServerStats {
+        let text_channels = self.text_channels();
+        let direct_channels = self
+            .direct_channels
+            .read()
+            .values()
+            .map(|direct| direct.channel())
+            .collect::<Vec<_>>();
+
+        let active_sessions = self.gateway.read().active_session_count();
+
+        let messages = text_channels
+            .iter()
+            .chain(direct_channels.iter())
+            .map(|channel| channel.message_count())
+            .sum();
+
+        let channels = text_channels
+            .iter()
+            .map(|channel| ChannelStats {
+                channel_id: channel.channel_id(),
+                message_count: channel.message_count(),
+                subscribers: channel.subscriber_count(),
+            })
+            .collect();
+
+        ServerStats {
+            text_channels: text_channels.len(),
+            direct_channels: direct_channels.len(),
+            messages,
+            active_sessions,
+            channels,
+            failed_channels: self.failed_channels(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        http::tests::server,
+        server::{
+            channel::{
+                Channel,
+                text::{TextChannelSettings, tests::test_message},
+            },
+            permission::Permissions,
+        },
+        user::UserId,
+    };
+
+    #[tokio::test]
+    async fn stats_count_the_channels_messages_and_sessions() {
+        let server = server();
+        let state = &server.state;
+
+        let general = state
+            .create_text_channel("general".to_string(), TextChannelSettings::default())
+            .unwrap();
+        state
+            .create_text_channel("random".to_string(), TextChannelSettings::default())
+            .unwrap();
+        let direct = server.direct_channel(&[UserId(1), UserId(2)]);
+
+        for content in ["hello", "anyone here?"] {
+            general
+                .create_message(test_message(UserId(1), content), None, Permissions::NONE)
+                .await
+                .unwrap();
+        }
+        direct
+            .channel()
+            .create_message(test_message(UserId(2), "hi"), None, Permissions::NONE)
+            .await
+            .unwrap();
+
+        let subscribers = general.subscriber_count();
+        let _events = general.subscribe();
+
+        let gateway = state.gateway();
+        gateway
+            .write()
+            .create_session(UserId(1), Default::default())
+            .unwrap();
+        let disconnected = gateway
+            .write()
+            .create_session(UserId(2), Default::default())
+            .unwrap();
+        let disconnected = disconnected.read().session_id();
+        gateway.write().disconnect_session(disconnected);
+
+        let stats = state.stats();
+        assert_eq!(stats.text_channels, 2);
+        assert_eq!(stats.direct_channels, 1);
+        assert_eq!(stats.messages, 3);
+        assert_eq!(stats.active_sessions, 1);
+        assert!(stats.failed_channels.is_empty());
+
+        let general_stats = stats
+            .channels
+            .iter()
+            .find(|channel| channel.channel_id == general.channel_id())
+            .unwrap();
+        assert_eq!(general_stats.message_count, 2);
+        assert_eq!(general_stats.subscribers, subscribers + 1);
+    }
+}
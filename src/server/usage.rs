@@ -0,0 +1,87 @@
+//! Reports the disk space used by the server for capacity planning.
+
+use std::{io, path::Path};
+
+use crate::{
+    channel::ChannelId,
+    server::{Server, channel::Channel},
+};
+
+/// Disk space used by the server's data directory.
+pub struct DataUsage {
+    /// Size in bytes of the database shared by every channel.
+    ///
+    /// Holds the messages of every channel along with the server's
+    /// other records, so it isn't broken down per channel.
+    pub database_bytes: u64,
+    /// Disk space used by each channel's own files.
+    pub channels: Vec<ChannelDataUsage>,
+}
+
+/// Disk space used by a channel's own files.
+pub struct ChannelDataUsage {
+    pub channel_id: ChannelId,
+    /// Number of messages stored for the channel in the database.
+    pub message_count: u64,
+    /// Size in bytes of the channel's data directory, including it's search index.
+    pub bytes: u64,
+}
+
+impl Server {
+    /// Reports the disk space used by the server's data directory.
+    ///
+    /// This walks the data directory, so it performs blocking IO
+    /// and should be called from a blocking task.
+    pub fn data_usage(&self) -> io::Result<DataUsage> {
+        let database_bytes = dir_size(&self.database_dir())?;
+
+        let mut channels = self.text_channels();
+        channels.extend(
+            self.direct_channels
+                .read()
+                .values()
+                .map(|direct| direct.channel()),
+        );
+
+        let channels = channels
+            .iter()
+            .map(|channel| {
+                Ok(ChannelDataUsage {
+                    channel_id: channel.channel_id(),
+                    message_count: channel.message_count(),
+                    bytes: dir_size(&self.channel_dir(channel.channel_id()))?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(DataUsage {
+            database_bytes,
+            channels,
+        })
+    }
+}
+
+/// Returns the total size in bytes of the files in a directory and it's subdirectories.
+///
+/// Directories that don't exist are treated as empty.
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
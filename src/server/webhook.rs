@@ -0,0 +1,227 @@
+//! Webhooks allow external systems to post messages to
+//! a channel without maintaining a gateway connection.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    hash::{self, Hasher},
+    num::ParseIntError,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use snowflaked::Snowflake;
+
+use crate::{channel::ChannelId, id::id_generator, server::auth::constant_time_eq};
+
+/// Length of the generated webhook secret tokens.
+const WEBHOOK_TOKEN_LEN: usize = 48;
+
+/// Maximum number of messages a webhook can post per rate limit window.
+pub const WEBHOOK_RATE_LIMIT: u32 = 5;
+
+/// Duration of the rate limiting window for webhooks.
+pub const WEBHOOK_RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Concrete type for webhook ID's.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct WebhookId(pub u64);
+
+/// Enables for using the ID's for keys in HashMaps.
+impl hash::Hash for WebhookId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0);
+    }
+}
+
+/// Enables extracting the snowflake ID components from IDs.
+impl Snowflake for WebhookId {
+    fn from_parts(timestamp: u64, instance: u64, sequence: u64) -> Self {
+        Self(u64::from_parts(timestamp, instance, sequence))
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.0.timestamp()
+    }
+
+    fn instance(&self) -> u64 {
+        self.0.instance()
+    }
+
+    fn sequence(&self) -> u64 {
+        self.0.sequence()
+    }
+}
+
+/// Display IDs in logs and formatting strings.
+impl Display for WebhookId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+/// Allows for calling parse() on strings to convert them to a webhook ID.
+impl FromStr for WebhookId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(WebhookId(u64::from_str(s)?))
+    }
+}
+
+/// A webhook that can post messages to a channel.
+#[derive(Clone)]
+pub struct Webhook {
+    /// Unique ID of the webhook.
+    pub id: WebhookId,
+    /// The channel the webhook posts messages to.
+    pub channel_id: ChannelId,
+    /// Secret token that must be supplied to execute the webhook.
+    pub token: String,
+}
+
+/// The webhook definition as stored in the webhook keyspace.
+#[derive(Serialize, Deserialize)]
+struct WebhookRecord {
+    channel_id: u64,
+    token: String,
+}
+
+/// Indicates there was an error managing or executing a webhook.
+#[derive(Debug)]
+pub enum WebhookError {
+    /// Indicates the webhook doesn't exist.
+    NotFound,
+    /// Indicates the supplied webhook token was incorrect.
+    InvalidToken,
+    /// Indicates the webhook has posted too many messages recently.
+    RateLimited,
+    /// Indicates the stored webhook definition couldn't be decoded.
+    CorruptRecord(serde_json::Error),
+    /// Indicates there was an error accessing the webhook keyspace.
+    DatabaseError(fjall::Error),
+}
+
+/// Tracks the number of messages posted in the current rate limit window.
+struct RateWindow {
+    started: Instant,
+    count: u32,
+}
+
+/// Service for managing channel webhooks.
+pub struct WebhookService {
+    id_generator: snowflaked::Generator,
+
+    /// Keyspace storing the webhook definitions keyed by webhook ID.
+    keyspace: fjall::Keyspace,
+
+    /// Rate limiting windows for recently executed webhooks.
+    rate_windows: HashMap<WebhookId, RateWindow>,
+}
+
+impl WebhookService {
+    /// Constructs the webhook service, opening or creating the webhook keyspace.
+    pub fn new(
+        db: &fjall::Database,
+        instance_id: u16,
+        snowflake_epoch_ms: u64,
+    ) -> Result<Self, fjall::Error> {
+        let keyspace = db.keyspace("webhooks", fjall::KeyspaceCreateOptions::default)?;
+
+        Ok(Self {
+            id_generator: id_generator(instance_id, snowflake_epoch_ms),
+            keyspace,
+            rate_windows: HashMap::new(),
+        })
+    }
+
+    /// Creates a new webhook for the specified channel.
+    ///
+    /// Returns the webhook including it's secret token, which
+    /// must be supplied by the caller to execute the webhook.
+    pub fn create_webhook(&mut self, channel_id: ChannelId) -> Result<Webhook, WebhookError> {
+        let id: WebhookId = self.id_generator.generate();
+
+        let token: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(WEBHOOK_TOKEN_LEN)
+            .map(char::from)
+            .collect();
+
+        let record = WebhookRecord {
+            channel_id: channel_id.0,
+            token: token.clone(),
+        };
+
+        self.keyspace
+            .insert(
+                id.0.to_be_bytes(),
+                serde_json::to_vec(&record).map_err(WebhookError::CorruptRecord)?,
+            )
+            .map_err(WebhookError::DatabaseError)?;
+
+        tracing::info!(webhook_id = ?id, channel_id = ?channel_id, "created webhook");
+
+        Ok(Webhook {
+            id,
+            channel_id,
+            token,
+        })
+    }
+
+    /// Looks up a webhook by it's ID.
+    pub fn webhook(&self, id: WebhookId) -> Result<Webhook, WebhookError> {
+        let Some(bytes) = self
+            .keyspace
+            .get(id.0.to_be_bytes())
+            .map_err(WebhookError::DatabaseError)?
+        else {
+            return Err(WebhookError::NotFound);
+        };
+
+        let record: WebhookRecord =
+            serde_json::from_slice(&bytes).map_err(WebhookError::CorruptRecord)?;
+
+        Ok(Webhook {
+            id,
+            channel_id: ChannelId(record.channel_id),
+            token: record.token,
+        })
+    }
+
+    /// Verifies the token and rate limit for executing a webhook.
+    ///
+    /// Returns the verified webhook if it can post a message.
+    pub fn authorize_execute(
+        &mut self,
+        id: WebhookId,
+        token: &str,
+    ) -> Result<Webhook, WebhookError> {
+        let webhook = self.webhook(id)?;
+
+        if !constant_time_eq(webhook.token.as_bytes(), token.as_bytes()) {
+            return Err(WebhookError::InvalidToken);
+        }
+
+        let now = Instant::now();
+
+        // Drop windows that have expired so the table stays bounded.
+        self.rate_windows
+            .retain(|_, window| now.duration_since(window.started) < WEBHOOK_RATE_WINDOW);
+
+        let window = self.rate_windows.entry(id).or_insert(RateWindow {
+            started: now,
+            count: 0,
+        });
+
+        if window.count >= WEBHOOK_RATE_LIMIT {
+            return Err(WebhookError::RateLimited);
+        }
+
+        window.count += 1;
+
+        Ok(webhook)
+    }
+}
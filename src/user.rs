@@ -7,12 +7,20 @@ use std::{
     str::FromStr,
 };
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use snowflaked::Snowflake;
 
 /// Concrete type for user ID's.
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
 pub struct UserId(pub u64);
 
+/// The reserved author of messages that weren't posted by a user, such as
+/// announcements from the server and messages posted through webhooks.
+///
+/// User IDs are snowflakes, which are never zero, so no user has this ID.
+/// Tokens can't be minted or issued for it, so nobody can authenticate as it.
+pub const SYSTEM_USER_ID: UserId = UserId(0);
+
 /// Enables for using the ID's for keys in HashMaps.
 impl hash::Hash for UserId {
     fn hash<H: Hasher>(&self, state: &mut H) {
@@ -54,3 +62,28 @@ impl FromStr for UserId {
         Ok(UserId(u64::from_str(s)?))
     }
 }
+
+/// Serializes IDs as strings for API payloads.
+impl Serialize for UserId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::id::serialize_id(self.0, serializer)
+    }
+}
+
+/// Deserializes IDs from either strings or integers.
+impl<'de> Deserialize<'de> for UserId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::id::deserialize_id(deserializer).map(UserId)
+    }
+}
+
+/// Describes IDs as strings in generated schemas.
+impl schemars::JsonSchema for UserId {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "UserId".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        crate::id::id_schema()
+    }
+}